@@ -352,7 +352,7 @@ function take_update(player_id, json) {
 
 let last_update = '';
 
-function check() {
+function refresh() {
     let player_id = get_player_id();
 
     return fetch(`player.json?id=${player_id}`, {
@@ -366,7 +366,6 @@ function check() {
     }).then((json) => {
         let this_str = JSON.stringify(json);
 
-        setTimeout(check, 500);
         if (this_str === last_update) {
             return;
         }
@@ -386,4 +385,27 @@ function check() {
     });
 }
 
+function check() {
+    return refresh().then(() => {
+        setTimeout(check, 500);
+    });
+}
+
+// The server pushes this player's events over a websocket as they happen, so
+// refresh right away instead of waiting for the next poll.
+function listen() {
+    let player_id = get_player_id();
+    let socket = new WebSocket(`ws://${window.location.host}/ws/${player_id}`);
+    socket.onmessage = (msg) => {
+        let event = JSON.parse(msg.data);
+        if (event.type !== 'reply' && event.type !== 'error') {
+            refresh();
+        }
+    };
+    socket.onclose = () => {
+        setTimeout(listen, 2000);
+    };
+}
+
 check();
+listen();
@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc as std_mpsc;
+use std::thread::{self, JoinHandle};
+
+use log::debug;
+use rand_chacha::ChaCha8Rng;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::common::standard_coin::ChiaIdentity;
+use crate::common::types::{
+    AllocEncoder, Amount, CoinString, Error, GameID, Hash, Program, PuzzleHash, Timeout,
+};
+use crate::peer_container::{
+    CradleEvent, CradleEventCollector, GameCradle, SynchronousGameCradle,
+    SynchronousGameCradleConfig, WatchReport,
+};
+use crate::potato_handler::{GameStart, GameType, LivenessPolicy};
+
+// How many commands or inbound peer messages may wait for the cradle before
+// senders have to wait.
+const COMMAND_QUEUE_DEPTH: usize = 64;
+
+/// Owned version of SynchronousGameCradleConfig, since the cradle is built on
+/// its own thread.
+pub struct AsyncGameCradleConfig {
+    pub game_types: BTreeMap<GameType, Program>,
+    pub have_potato: bool,
+    pub identity: ChiaIdentity,
+    pub my_contribution: Amount,
+    pub their_contribution: Amount,
+    pub channel_timeout: Timeout,
+    pub unroll_timeout: Timeout,
+    pub reward_puzzle_hash: PuzzleHash,
+    pub liveness: LivenessPolicy,
+    pub validate_outbound: bool,
+    pub rng: ChaCha8Rng,
+}
+
+type Reply<T> = oneshot::Sender<Result<T, Error>>;
+
+enum CradleCommand {
+    OpeningCoin(CoinString, Reply<()>),
+    StartGames(bool, GameStart, Reply<Vec<GameID>>),
+    MakeMove(GameID, Vec<u8>, Hash, Reply<()>),
+    Accept(GameID, Reply<()>),
+    ProposeGame(GameStart, Reply<Vec<GameID>>),
+    AcceptProposal(GameID, Reply<()>),
+    DeclineProposal(GameID, Reply<()>),
+    CancelProposal(GameID, Reply<()>),
+    TopUp(CoinString, Reply<()>),
+    Withdraw(Amount, Reply<()>),
+    OfferSettlement(GameID, Amount, Reply<()>),
+    AcceptSettlement(GameID, Reply<()>),
+    DeclineSettlement(GameID, Reply<()>),
+    ShutDown(Reply<()>),
+    NewBlock(usize, WatchReport, Reply<()>),
+    ReportPuzzleAndSolution(CoinString, Option<(Program, Program)>, Reply<()>),
+    GoOnChain(Reply<()>),
+    Resume(Reply<()>),
+    IsOnChain(Reply<bool>),
+    HandshakeFinished(Reply<bool>),
+    Snapshot(Reply<Vec<u8>>),
+}
+
+fn cradle_stopped() -> Error {
+    Error::StrErr("game cradle has stopped".to_string())
+}
+
+// The state living on the cradle's thread.
+struct CradleTask {
+    cradle: SynchronousGameCradle,
+    allocator: AllocEncoder,
+    rng: ChaCha8Rng,
+    events: mpsc::UnboundedSender<CradleEvent>,
+    outbound: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl CradleTask {
+    fn run_command(&mut self, command: CradleCommand) {
+        let allocator = &mut self.allocator;
+        let rng = &mut self.rng;
+        let cradle = &mut self.cradle;
+        // A dropped reply means the caller stopped waiting; that's fine.
+        match command {
+            CradleCommand::OpeningCoin(coin, reply) => {
+                let _ = reply.send(cradle.opening_coin(allocator, rng, coin));
+            }
+            CradleCommand::StartGames(i_initiated, game, reply) => {
+                let _ = reply.send(cradle.start_games(allocator, rng, i_initiated, &game));
+            }
+            CradleCommand::MakeMove(id, readable, new_entropy, reply) => {
+                let _ = reply.send(cradle.make_move(allocator, rng, &id, readable, new_entropy));
+            }
+            CradleCommand::Accept(id, reply) => {
+                let _ = reply.send(cradle.accept(allocator, rng, &id));
+            }
+            CradleCommand::ProposeGame(game, reply) => {
+                let _ = reply.send(cradle.propose_game(allocator, rng, &game));
+            }
+            CradleCommand::AcceptProposal(id, reply) => {
+                let _ = reply.send(cradle.accept_proposal(allocator, rng, &id));
+            }
+            CradleCommand::DeclineProposal(id, reply) => {
+                let _ = reply.send(cradle.decline_proposal(allocator, rng, &id));
+            }
+            CradleCommand::CancelProposal(id, reply) => {
+                let _ = reply.send(cradle.cancel_proposal(allocator, rng, &id));
+            }
+            CradleCommand::TopUp(coin, reply) => {
+                let _ = reply.send(cradle.top_up(allocator, rng, coin));
+            }
+            CradleCommand::Withdraw(amount, reply) => {
+                let _ = reply.send(cradle.withdraw(allocator, rng, &amount));
+            }
+            CradleCommand::OfferSettlement(id, my_share, reply) => {
+                let _ = reply.send(cradle.offer_settlement(allocator, rng, &id, &my_share));
+            }
+            CradleCommand::AcceptSettlement(id, reply) => {
+                let _ = reply.send(cradle.accept_settlement(allocator, rng, &id));
+            }
+            CradleCommand::DeclineSettlement(id, reply) => {
+                let _ = reply.send(cradle.decline_settlement(allocator, rng, &id));
+            }
+            CradleCommand::ShutDown(reply) => {
+                let _ = reply.send(cradle.shut_down(allocator, rng));
+            }
+            CradleCommand::NewBlock(height, report, reply) => {
+                let _ = reply.send(cradle.new_block(allocator, rng, height, &report));
+            }
+            CradleCommand::ReportPuzzleAndSolution(coin, puzzle_and_solution, reply) => {
+                let _ = reply.send(cradle.report_puzzle_and_solution(
+                    allocator,
+                    rng,
+                    &coin,
+                    puzzle_and_solution.as_ref().map(|(p, s)| (p, s)),
+                ));
+            }
+            CradleCommand::GoOnChain(reply) => {
+                let mut collector = CradleEventCollector::default();
+                let _ = reply.send(cradle.go_on_chain(allocator, rng, &mut collector));
+                for event in collector.take_events() {
+                    self.emit(event);
+                }
+            }
+            CradleCommand::Resume(reply) => {
+                let _ = reply.send(cradle.resume());
+            }
+            CradleCommand::IsOnChain(reply) => {
+                let _ = reply.send(Ok(cradle.is_on_chain()));
+            }
+            CradleCommand::HandshakeFinished(reply) => {
+                let _ = reply.send(Ok(cradle.handshake_finished()));
+            }
+            CradleCommand::Snapshot(reply) => {
+                let _ = reply.send(cradle.to_snapshot_bytes(allocator));
+            }
+        }
+    }
+
+    fn emit(&mut self, event: CradleEvent) {
+        // Nobody listening isn't a reason to stop the channel.
+        let sent = if let CradleEvent::OutboundMessage(msg) = event {
+            self.outbound.send(msg).is_ok()
+        } else {
+            self.events.send(event).is_ok()
+        };
+        if !sent {
+            debug!("async cradle output dropped, no receiver");
+        }
+    }
+
+    fn deliver_message(&mut self, msg: &[u8]) {
+        if let Err(e) = self.cradle.deliver_message(msg) {
+            self.emit(CradleEvent::Error(e));
+        }
+    }
+
+    // Run idle until the cradle has nothing more to do.
+    fn drive(&mut self) {
+        for event in self.cradle.idle_events(&mut self.allocator, &mut self.rng) {
+            self.emit(event);
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut commands: mpsc::Receiver<CradleCommand>,
+        mut inbound: mpsc::Receiver<Vec<u8>>,
+    ) {
+        let mut inbound_open = true;
+        self.drive();
+        loop {
+            // Peer messages go first, so a command sees every message that
+            // was delivered before it was sent.
+            tokio::select! {
+                biased;
+                msg = inbound.recv(), if inbound_open => {
+                    match msg {
+                        Some(msg) => self.deliver_message(&msg),
+                        None => inbound_open = false,
+                    }
+                }
+                command = commands.recv() => {
+                    match command {
+                        Some(command) => self.run_command(command),
+                        // Every handle is gone.
+                        None => break,
+                    }
+                }
+            }
+            self.drive();
+        }
+    }
+}
+
+/// Sends the peer's messages into the cradle.  The transport holds one of
+/// these, and can be cloned if it reads from several places.
+#[derive(Clone)]
+pub struct PeerSink {
+    inbound: mpsc::Sender<Vec<u8>>,
+}
+
+impl PeerSink {
+    pub async fn deliver(&self, msg: Vec<u8>) -> Result<(), Error> {
+        self.inbound.send(msg).await.map_err(|_| cradle_stopped())
+    }
+}
+
+/// The peer transport's side of the cradle: messages to write to the peer
+/// and a sink for those read from it.
+pub struct PeerTransport {
+    pub sink: PeerSink,
+    outbound: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl PeerTransport {
+    /// The next message to send to the peer, or None once the cradle stopped.
+    pub async fn next_outbound(&mut self) -> Option<Vec<u8>> {
+        self.outbound.recv().await
+    }
+}
+
+/// The stream of CradleEvent, in the order the cradle produced them.
+/// Outbound peer messages go to PeerTransport instead.
+pub struct CradleEvents {
+    events: mpsc::UnboundedReceiver<CradleEvent>,
+}
+
+impl CradleEvents {
+    /// The next event, or None once the cradle stopped.
+    pub async fn next(&mut self) -> Option<CradleEvent> {
+        self.events.recv().await
+    }
+}
+
+/// A game cradle driven by its own task.  SynchronousGameCradle holds state
+/// that can't move between threads, so the cradle is built and run on a
+/// dedicated thread with a single threaded runtime.  Each command or inbound
+/// message is followed by calling idle until continue_on is false, so
+/// callers never spin idle themselves.  Peer messages are handled ahead of
+/// commands, so once a command returns, everything the cradle did for the
+/// messages delivered before it has already been emitted.  The cradle stops once every
+/// AsyncGameCradle handle is dropped.
+#[derive(Clone)]
+pub struct AsyncGameCradle {
+    commands: mpsc::Sender<CradleCommand>,
+}
+
+type SpawnedCradle = (AsyncGameCradle, CradleEvents, PeerTransport, JoinHandle<()>);
+
+impl AsyncGameCradle {
+    pub fn spawn(config: AsyncGameCradleConfig) -> Result<SpawnedCradle, Error> {
+        let rng = config.rng;
+        AsyncGameCradle::spawn_with(rng, move |_allocator, rng| {
+            Ok(SynchronousGameCradle::new(
+                rng,
+                SynchronousGameCradleConfig {
+                    game_types: config.game_types,
+                    have_potato: config.have_potato,
+                    identity: &config.identity,
+                    my_contribution: config.my_contribution,
+                    their_contribution: config.their_contribution,
+                    channel_timeout: config.channel_timeout,
+                    unroll_timeout: config.unroll_timeout,
+                    reward_puzzle_hash: config.reward_puzzle_hash,
+                    liveness: config.liveness,
+                    validate_outbound: config.validate_outbound,
+                },
+            ))
+        })
+    }
+
+    /// Run a cradle rebuilt from bytes given by AsyncGameCradle::snapshot.
+    pub fn restore(snapshot: Vec<u8>, rng: ChaCha8Rng) -> Result<SpawnedCradle, Error> {
+        AsyncGameCradle::spawn_with(rng, move |allocator, _rng| {
+            SynchronousGameCradle::from_snapshot_bytes(allocator, &snapshot)
+        })
+    }
+
+    fn spawn_with<F>(rng: ChaCha8Rng, build: F) -> Result<SpawnedCradle, Error>
+    where
+        F: FnOnce(&mut AllocEncoder, &mut ChaCha8Rng) -> Result<SynchronousGameCradle, Error>
+            + Send
+            + 'static,
+    {
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_QUEUE_DEPTH);
+        let (inbound_tx, inbound_rx) = mpsc::channel(COMMAND_QUEUE_DEPTH);
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let (ready_tx, ready_rx) = std_mpsc::channel();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .map_err(Error::IoErr)?;
+        let thread = thread::Builder::new()
+            .name("game-cradle".to_string())
+            .spawn(move || {
+                let mut rng = rng;
+                let mut allocator = AllocEncoder::new();
+                let cradle = match build(&mut allocator, &mut rng) {
+                    Ok(cradle) => {
+                        let _ = ready_tx.send(Ok(()));
+                        cradle
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let task = CradleTask {
+                    cradle,
+                    allocator,
+                    rng,
+                    events: event_tx,
+                    outbound: outbound_tx,
+                };
+                runtime.block_on(task.run(command_rx, inbound_rx));
+            })
+            .map_err(Error::IoErr)?;
+
+        ready_rx.recv().map_err(|_| cradle_stopped())??;
+
+        Ok((
+            AsyncGameCradle {
+                commands: command_tx,
+            },
+            CradleEvents { events: event_rx },
+            PeerTransport {
+                sink: PeerSink {
+                    inbound: inbound_tx,
+                },
+                outbound: outbound_rx,
+            },
+            thread,
+        ))
+    }
+
+    async fn call<T>(&self, command: impl FnOnce(Reply<T>) -> CradleCommand) -> Result<T, Error> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.commands
+            .send(command(reply_tx))
+            .await
+            .map_err(|_| cradle_stopped())?;
+        reply_rx.await.map_err(|_| cradle_stopped())?
+    }
+
+    /// See GameCradle::opening_coin.
+    pub async fn opening_coin(&self, coin: CoinString) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::OpeningCoin(coin, reply))
+            .await
+    }
+
+    pub async fn handshake_finished(&self) -> Result<bool, Error> {
+        self.call(CradleCommand::HandshakeFinished).await
+    }
+
+    pub async fn is_on_chain(&self) -> Result<bool, Error> {
+        self.call(CradleCommand::IsOnChain).await
+    }
+
+    pub async fn start_games(
+        &self,
+        i_initiated: bool,
+        game: GameStart,
+    ) -> Result<Vec<GameID>, Error> {
+        self.call(|reply| CradleCommand::StartGames(i_initiated, game, reply))
+            .await
+    }
+
+    pub async fn make_move(
+        &self,
+        id: GameID,
+        readable: Vec<u8>,
+        new_entropy: Hash,
+    ) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::MakeMove(id, readable, new_entropy, reply))
+            .await
+    }
+
+    pub async fn accept(&self, id: GameID) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::Accept(id, reply)).await
+    }
+
+    pub async fn propose_game(&self, game: GameStart) -> Result<Vec<GameID>, Error> {
+        self.call(|reply| CradleCommand::ProposeGame(game, reply))
+            .await
+    }
+
+    pub async fn accept_proposal(&self, id: GameID) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::AcceptProposal(id, reply))
+            .await
+    }
+
+    pub async fn decline_proposal(&self, id: GameID) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::DeclineProposal(id, reply))
+            .await
+    }
+
+    pub async fn cancel_proposal(&self, id: GameID) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::CancelProposal(id, reply))
+            .await
+    }
+
+    pub async fn top_up(&self, coin: CoinString) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::TopUp(coin, reply)).await
+    }
+
+    pub async fn withdraw(&self, amount: Amount) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::Withdraw(amount, reply))
+            .await
+    }
+
+    pub async fn offer_settlement(&self, id: GameID, my_share: Amount) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::OfferSettlement(id, my_share, reply))
+            .await
+    }
+
+    pub async fn accept_settlement(&self, id: GameID) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::AcceptSettlement(id, reply))
+            .await
+    }
+
+    pub async fn decline_settlement(&self, id: GameID) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::DeclineSettlement(id, reply))
+            .await
+    }
+
+    pub async fn shut_down(&self) -> Result<(), Error> {
+        self.call(CradleCommand::ShutDown).await
+    }
+
+    pub async fn new_block(&self, height: usize, report: WatchReport) -> Result<(), Error> {
+        self.call(|reply| CradleCommand::NewBlock(height, report, reply))
+            .await
+    }
+
+    /// Answer a CradleEvent::PuzzleSolutionRequest.
+    pub async fn report_puzzle_and_solution(
+        &self,
+        coin_id: CoinString,
+        puzzle_and_solution: Option<(Program, Program)>,
+    ) -> Result<(), Error> {
+        self.call(|reply| {
+            CradleCommand::ReportPuzzleAndSolution(coin_id, puzzle_and_solution, reply)
+        })
+        .await
+    }
+
+    pub async fn go_on_chain(&self) -> Result<(), Error> {
+        self.call(CradleCommand::GoOnChain).await
+    }
+
+    /// See GameCradle::resume.  Call after the peer transport reconnects.
+    pub async fn resume(&self) -> Result<(), Error> {
+        self.call(CradleCommand::Resume).await
+    }
+
+    /// See SynchronousGameCradle::to_snapshot_bytes.  Everything the cradle
+    /// has emitted so far is reflected in the result.
+    pub async fn snapshot(&self) -> Result<Vec<u8>, Error> {
+        self.call(CradleCommand::Snapshot).await
+    }
+}
@@ -69,6 +69,18 @@ impl GameHandler {
             serialized: Program::from_nodeptr(allocator, *nodeptr)?,
         })
     }
+
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatGameHandler,
+    ) -> Result<GameHandler, Error> {
+        let nodeptr = serializable.serialized.to_nodeptr(allocator)?;
+        if serializable.my_turn {
+            Ok(GameHandler::MyTurnHandler(nodeptr))
+        } else {
+            Ok(GameHandler::TheirTurnHandler(nodeptr))
+        }
+    }
 }
 
 pub struct MyTurnInputs<'a> {
@@ -14,12 +14,16 @@ use clvm_tools_rs::classic::clvm_tools::binutils::disassemble;
 use clvm_traits::ToClvm;
 use clvmr::allocator::NodePtr;
 
+use serde::{Deserialize, Serialize};
+
 use crate::channel_handler::types::{
-    CachedPotatoRegenerateLastHop, ChannelCoin, ChannelCoinInfo, ChannelCoinSpendInfo,
-    ChannelCoinSpentResult, ChannelHandlerEnv, ChannelHandlerInitiationData,
+    curry_unroll_puzzle, CachedPotatoRegenerateLastHop, ChannelCoin, ChannelCoinInfo,
+    ChannelCoinSpendInfo, ChannelCoinSpentResult, ChannelHandlerEnv, ChannelHandlerInitiationData,
     ChannelHandlerInitiationResult, ChannelHandlerPrivateKeys, ChannelHandlerUnrollSpendInfo,
     CoinDataForReward, CoinSpentAccept, CoinSpentDisposition, CoinSpentMoveUp, CoinSpentResult,
-    DispositionResult, GameStartInfo, HandshakeResult, LiveGame, MoveResult, OnChainGameCoin,
+    DispositionResult, FlatCachedPotatoRegenerateLastHop, FlatChannelCoinInfo,
+    FlatChannelHandlerUnrollSpendInfo, FlatLiveGame, FlatPendingSplice, GameStartInfo,
+    HandshakeResult, LiveGame, MoveResult, OnChainGameCoin, OnChainMoveResult, PendingSplice,
     PotatoAcceptCachedData, PotatoMoveCachedData, PotatoSignatures, ReadableMove, UnrollCoin,
     UnrollCoinConditionInputs, UnrollTarget,
 };
@@ -29,11 +33,11 @@ use crate::common::standard_coin::{
     puzzle_hash_for_synthetic_public_key, sign_agg_sig_me, standard_solution_unsafe, ChiaIdentity,
 };
 use crate::common::types::{
-    usize_from_atom, Aggsig, Amount, BrokenOutCoinSpendInfo, CoinCondition, CoinID, CoinSpend,
-    CoinString, Error, GameID, Hash, IntoErr, Node, PrivateKey, Program, PublicKey, Puzzle,
-    PuzzleHash, Sha256tree, Spend, SpendRewardResult, Timeout, ToQuotedProgram,
+    usize_from_atom, Aggsig, AllocEncoder, Amount, BrokenOutCoinSpendInfo, CoinCondition, CoinID,
+    CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Node, PrivateKey, Program, ProtocolError,
+    PublicKey, Puzzle, PuzzleHash, Sha256tree, Spend, SpendRewardResult, Timeout, ToQuotedProgram,
 };
-use crate::referee::RefereeMaker;
+use crate::referee::{RefereeMaker, TheirTurnCoinSpentResult};
 
 /// A channel handler runs the game by facilitating the phases of game startup
 /// and passing on move information as well as termination to other layers.
@@ -112,9 +116,151 @@ pub struct ChannelHandler {
 
     // Live games
     live_games: Vec<LiveGame>,
+
+    // A top-up or withdrawal waiting for its spend to confirm.
+    pending_splice: Option<PendingSplice>,
+}
+
+/// Serializable form of a ChannelHandler.  All clvm data is flattened so that
+/// the handler can be rebuilt in a fresh allocator.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlatChannelHandler {
+    private_keys: ChannelHandlerPrivateKeys,
+
+    their_channel_coin_public_key: PublicKey,
+    their_unroll_coin_public_key: PublicKey,
+    their_referee_puzzle_hash: PuzzleHash,
+
+    my_out_of_game_balance: Amount,
+    their_out_of_game_balance: Amount,
+
+    my_allocated_balance: Amount,
+    their_allocated_balance: Amount,
+
+    have_potato: bool,
+    initiated_on_chain: bool,
+    unroll_advance_timeout: Timeout,
+
+    cached_last_action: Option<FlatCachedPotatoRegenerateLastHop>,
+
+    current_state_number: usize,
+    next_nonce_number: usize,
+
+    state_channel: FlatChannelCoinInfo,
+
+    unroll: FlatChannelHandlerUnrollSpendInfo,
+    timeout: Option<FlatChannelHandlerUnrollSpendInfo>,
+
+    live_games: Vec<FlatLiveGame>,
+
+    #[serde(default)]
+    pending_splice: Option<FlatPendingSplice>,
 }
 
 impl ChannelHandler {
+    pub fn to_serializable(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<FlatChannelHandler, Error> {
+        let cached_last_action = if let Some(cached) = &self.cached_last_action {
+            Some(cached.to_serializable(allocator)?)
+        } else {
+            None
+        };
+        let timeout = if let Some(timeout) = &self.timeout {
+            Some(timeout.to_serializable(allocator)?)
+        } else {
+            None
+        };
+        let mut live_games = Vec::new();
+        for live_game in self.live_games.iter() {
+            live_games.push(live_game.to_serializable(allocator)?);
+        }
+        let pending_splice = if let Some(pending) = &self.pending_splice {
+            Some(pending.to_serializable(allocator)?)
+        } else {
+            None
+        };
+
+        Ok(FlatChannelHandler {
+            private_keys: self.private_keys.clone(),
+            their_channel_coin_public_key: self.their_channel_coin_public_key.clone(),
+            their_unroll_coin_public_key: self.their_unroll_coin_public_key.clone(),
+            their_referee_puzzle_hash: self.their_referee_puzzle_hash.clone(),
+            my_out_of_game_balance: self.my_out_of_game_balance.clone(),
+            their_out_of_game_balance: self.their_out_of_game_balance.clone(),
+            my_allocated_balance: self.my_allocated_balance.clone(),
+            their_allocated_balance: self.their_allocated_balance.clone(),
+            have_potato: self.have_potato,
+            initiated_on_chain: self.initiated_on_chain,
+            unroll_advance_timeout: self.unroll_advance_timeout.clone(),
+            cached_last_action,
+            current_state_number: self.current_state_number,
+            next_nonce_number: self.next_nonce_number,
+            state_channel: self.state_channel.to_serializable(),
+            unroll: self.unroll.to_serializable(allocator)?,
+            timeout,
+            live_games,
+            pending_splice,
+        })
+    }
+
+    /// Rebuild a channel handler from its serialized form.  The clvm data held
+    /// by the channel handler and its referees is placed in the given allocator.
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatChannelHandler,
+    ) -> Result<ChannelHandler, Error> {
+        let cached_last_action = if let Some(cached) = &serializable.cached_last_action {
+            Some(CachedPotatoRegenerateLastHop::from_serializable(
+                allocator, cached,
+            )?)
+        } else {
+            None
+        };
+        let timeout = if let Some(timeout) = &serializable.timeout {
+            Some(ChannelHandlerUnrollSpendInfo::from_serializable(
+                allocator, timeout,
+            )?)
+        } else {
+            None
+        };
+        let mut live_games = Vec::new();
+        for live_game in serializable.live_games.iter() {
+            live_games.push(LiveGame::from_serializable(allocator, live_game)?);
+        }
+        let pending_splice = if let Some(pending) = &serializable.pending_splice {
+            Some(PendingSplice::from_serializable(allocator, pending)?)
+        } else {
+            None
+        };
+
+        Ok(ChannelHandler {
+            private_keys: serializable.private_keys.clone(),
+            their_channel_coin_public_key: serializable.their_channel_coin_public_key.clone(),
+            their_unroll_coin_public_key: serializable.their_unroll_coin_public_key.clone(),
+            their_referee_puzzle_hash: serializable.their_referee_puzzle_hash.clone(),
+            my_out_of_game_balance: serializable.my_out_of_game_balance.clone(),
+            their_out_of_game_balance: serializable.their_out_of_game_balance.clone(),
+            my_allocated_balance: serializable.my_allocated_balance.clone(),
+            their_allocated_balance: serializable.their_allocated_balance.clone(),
+            have_potato: serializable.have_potato,
+            initiated_on_chain: serializable.initiated_on_chain,
+            unroll_advance_timeout: serializable.unroll_advance_timeout.clone(),
+            cached_last_action,
+            current_state_number: serializable.current_state_number,
+            next_nonce_number: serializable.next_nonce_number,
+            state_channel: ChannelCoinInfo::from_serializable(&serializable.state_channel),
+            unroll: ChannelHandlerUnrollSpendInfo::from_serializable(
+                allocator,
+                &serializable.unroll,
+            )?,
+            timeout,
+            live_games,
+            pending_splice,
+        })
+    }
+
     pub fn is_initial_potato(&self) -> bool {
         self.unroll.coin.started_with_potato
     }
@@ -147,15 +293,6 @@ impl ChannelHandler {
         &self.unroll
     }
 
-    fn make_curried_unroll_puzzle<R: Rng>(
-        &self,
-        env: &mut ChannelHandlerEnv<R>,
-    ) -> Result<NodePtr, Error> {
-        self.unroll
-            .coin
-            .make_curried_unroll_puzzle(env, &self.get_aggregate_unroll_public_key())
-    }
-
     fn unroll_coin_condition_inputs(
         &self,
         my_ending_game_value: Amount,
@@ -173,6 +310,7 @@ impl ChannelHandler {
                 - self.their_allocated_balance.clone()
                 + their_ending_game_value,
             puzzle_hashes_and_amounts: puzzle_hashes_and_amounts.to_vec(),
+            unroll_timeout: self.unroll_advance_timeout.clone(),
         }
     }
 
@@ -190,6 +328,39 @@ impl ChannelHandler {
         self.my_out_of_game_balance.clone()
     }
 
+    /// Conditions for a clean shutdown paying each side its balance at its
+    /// referee puzzle hash, which is what the receiving side checks for.
+    pub fn clean_shutdown_conditions<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+    ) -> Result<NodePtr, Error> {
+        let my_referee_public_key =
+            private_to_public_key(&self.private_keys.my_referee_private_key);
+        let my_referee_puzzle_hash = puzzle_hash_for_pk(env.allocator, &my_referee_public_key)?;
+        [
+            (
+                CREATE_COIN,
+                (
+                    my_referee_puzzle_hash,
+                    (self.my_out_of_game_balance.clone(), ()),
+                ),
+            ),
+            (
+                CREATE_COIN,
+                (
+                    self.their_referee_puzzle_hash.clone(),
+                    (self.their_out_of_game_balance.clone(), ()),
+                ),
+            ),
+        ]
+        .to_clvm(env.allocator)
+        .into_gen()
+    }
+
+    pub fn live_game_ids(&self) -> Vec<GameID> {
+        self.live_games.iter().map(|g| g.game_id.clone()).collect()
+    }
+
     fn get_just_created_games(&self) -> Vec<GameID> {
         if let Some(CachedPotatoRegenerateLastHop::PotatoCreatedGame(games, _, _)) =
             &self.cached_last_action
@@ -296,6 +467,7 @@ impl ChannelHandler {
             timeout: None,
 
             live_games: Vec::new(),
+            pending_splice: None,
 
             private_keys,
         };
@@ -480,12 +652,35 @@ impl ChannelHandler {
         &mut self,
         env: &mut ChannelHandlerEnv<R>,
     ) -> Result<PotatoSignatures, Error> {
+        self.check_no_splice("empty potato")?;
         // We let them spend a state number 1 higher but nothing else changes.
         self.update_cache_for_potato_send(None);
 
         self.update_cached_unroll_state(env)
     }
 
+    /// Messages that spend the potato can only come from the peer holding it.
+    fn check_peer_has_potato(&self, what: &str) -> Result<(), Error> {
+        if self.have_potato {
+            return Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+                "received {what} while we hold the potato"
+            ))));
+        }
+
+        Ok(())
+    }
+
+    /// The channel state can't change while a splice is waiting to confirm.
+    fn check_no_splice(&self, what: &str) -> Result<(), Error> {
+        if self.pending_splice.is_some() {
+            return Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+                "{what} while a splice is in progress"
+            ))));
+        }
+
+        Ok(())
+    }
+
     pub fn verify_channel_coin_from_peer_signatures<R: Rng>(
         &self,
         env: &mut ChannelHandlerEnv<R>,
@@ -510,7 +705,9 @@ impl ChannelHandler {
                 ..channel_coin_spend
             })
         } else {
-            Err(Error::StrErr("failed to verify signature".to_string()))
+            Err(Error::Protocol(ProtocolError::BadSignature(
+                "channel coin half signature".to_string(),
+            )))
         }
     }
 
@@ -539,7 +736,9 @@ impl ChannelHandler {
             &self.get_aggregate_unroll_public_key(),
             &signatures.my_unroll_half_signature_peer,
         )? {
-            return Err(Error::StrErr("bad unroll signature verify".to_string()));
+            return Err(Error::Protocol(ProtocolError::BadSignature(
+                "unroll coin half signature".to_string(),
+            )));
         }
 
         // State coin section
@@ -579,6 +778,7 @@ impl ChannelHandler {
         env: &mut ChannelHandlerEnv<R>,
         signatures: &PotatoSignatures,
     ) -> Result<ChannelCoinSpendInfo, Error> {
+        self.check_no_splice("empty potato")?;
         let unroll_data = self.compute_unroll_data_for_games(&[], None, &self.live_games)?;
 
         let spend = self.received_potato_verify_signatures(
@@ -647,6 +847,7 @@ impl ChannelHandler {
         env: &mut ChannelHandlerEnv<R>,
         start_info_list: &[GameStartInfo],
     ) -> Result<PotatoSignatures, Error> {
+        self.check_no_splice("game start")?;
         debug!("SEND POTATO START GAME");
         let (my_full_contribution, their_full_contribution) =
             self.start_game_contributions(start_info_list);
@@ -684,6 +885,7 @@ impl ChannelHandler {
         signatures: &PotatoSignatures,
         start_info_list: &[GameStartInfo],
     ) -> Result<ChannelCoinSpendInfo, Error> {
+        self.check_no_splice("game start")?;
         debug!(
             "RECEIVED_POTATO_START_GAME: our state is {}, unroll state is {}",
             self.current_state_number, self.unroll.coin.state_number
@@ -752,6 +954,20 @@ impl ChannelHandler {
             })
     }
 
+    /// The amount at stake in a live game.
+    pub fn game_amount(&self, game_id: &GameID) -> Result<Amount, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        Ok(self.live_games[game_idx].referee_maker.get_amount())
+    }
+
+    /// Our share of a live game if it were accepted now.
+    pub fn game_our_current_share(&self, game_id: &GameID) -> Result<Amount, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        Ok(self.live_games[game_idx]
+            .referee_maker
+            .get_our_current_share())
+    }
+
     pub fn send_potato_move<R: Rng>(
         &mut self,
         env: &mut ChannelHandlerEnv<R>,
@@ -759,19 +975,22 @@ impl ChannelHandler {
         readable_move: &ReadableMove,
         new_entropy: Hash,
     ) -> Result<MoveResult, Error> {
+        self.check_no_splice("move")?;
         debug!("SEND_POTATO_MOVE");
         let game_idx = self.get_game_by_id(game_id)?;
 
+        // If the channel unrolls before this move is acknowledged, the game
+        // coin will be the one from before the move and we replay it on chain.
+        let puzzle_hash = self.live_games[game_idx].last_referee_puzzle_hash.clone();
+
         let referee_maker: &mut RefereeMaker = self.live_games[game_idx].referee_maker.borrow_mut();
+        let amount = referee_maker.get_amount();
         let referee_result =
             referee_maker.my_turn_make_move(env.allocator, readable_move, new_entropy)?;
         debug!("move_result {referee_result:?}");
         self.live_games[game_idx].last_referee_puzzle_hash =
             referee_result.puzzle_hash_for_unroll.clone();
 
-        let puzzle_hash = referee_result.puzzle_hash_for_unroll;
-        let amount = referee_result.details.basic.mover_share.clone();
-
         // We let them spend a state number 1 higher but nothing else changes.
         self.update_cache_for_potato_send(Some(
             CachedPotatoRegenerateLastHop::PotatoMoveHappening(PotatoMoveCachedData {
@@ -795,6 +1014,7 @@ impl ChannelHandler {
         game_id: &GameID,
         move_result: &MoveResult,
     ) -> Result<(ChannelCoinSpendInfo, NodePtr, Vec<u8>), Error> {
+        self.check_no_splice("move")?;
         debug!("RECEIVED_POTATO_MOVE");
         let game_idx = self.get_game_by_id(game_id)?;
 
@@ -850,6 +1070,7 @@ impl ChannelHandler {
         env: &mut ChannelHandlerEnv<R>,
         game_id: &GameID,
     ) -> Result<(PotatoSignatures, Amount), Error> {
+        self.check_no_splice("accept")?;
         assert!(self.have_potato);
         debug!("SEND_POTATO_ACCEPT");
         let game_idx = self.get_game_by_id(game_id)?;
@@ -888,7 +1109,8 @@ impl ChannelHandler {
         signatures: &PotatoSignatures,
         game_id: &GameID,
     ) -> Result<ChannelCoinSpendInfo, Error> {
-        assert!(!self.have_potato);
+        self.check_no_splice("accept")?;
+        self.check_peer_has_potato("accept")?;
         debug!("RECEIVED_POTATO_ACCEPT");
         let game_idx = self.get_game_by_id(game_id)?;
         let unroll_data = self.compute_unroll_data_for_games(
@@ -930,6 +1152,112 @@ impl ChannelHandler {
         })
     }
 
+    /// Check a settlement of a live game where we receive my_share of what's
+    /// at stake, returning the game's index and the peer's share.
+    fn settlement_split(
+        &self,
+        game_id: &GameID,
+        my_share: &Amount,
+    ) -> Result<(usize, Amount), Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        let at_stake = self.game_amount(game_id)?;
+        if *my_share > at_stake {
+            return Err(Error::Protocol(ProtocolError::Malformed(format!(
+                "settlement share {my_share:?} is more than the {at_stake:?} at stake"
+            ))));
+        }
+
+        Ok((game_idx, at_stake - my_share.clone()))
+    }
+
+    // Like accept, except each side is paid its agreed share rather than
+    // getting its contribution back.
+    fn remove_settled_game(
+        &mut self,
+        game_idx: usize,
+        my_share: &Amount,
+        their_share: &Amount,
+    ) -> LiveGame {
+        let live_game = self.live_games.remove(game_idx);
+        self.my_allocated_balance -= live_game.my_contribution.clone();
+        self.their_allocated_balance -= live_game.their_contribution.clone();
+        self.my_out_of_game_balance = self.my_out_of_game_balance.clone()
+            - live_game.my_contribution.clone()
+            + my_share.clone();
+        self.their_out_of_game_balance = self.their_out_of_game_balance.clone()
+            - live_game.their_contribution.clone()
+            + their_share.clone();
+        live_game
+    }
+
+    /// End a game at a split both sides agreed to, with us receiving my_share.
+    ///
+    /// The split only binds once the peer countersigns it.  If the channel
+    /// goes on chain with the state before this one, the game coin is
+    /// claimed like an accepted game at our share of its current state,
+    /// which can differ from my_share.
+    pub fn send_potato_settle<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        game_id: &GameID,
+        my_share: &Amount,
+    ) -> Result<PotatoSignatures, Error> {
+        self.check_no_splice("settlement")?;
+        if !self.have_potato {
+            return Err(Error::StrErr("settlement without the potato".to_string()));
+        }
+        debug!("SEND_POTATO_SETTLE");
+        let (game_idx, their_share) = self.settlement_split(game_id, my_share)?;
+        let live_game = self.remove_settled_game(game_idx, my_share, &their_share);
+        let at_stake = live_game.referee_maker.get_amount();
+        let on_chain_share = live_game.referee_maker.get_our_current_share();
+
+        // What the chain pays is decided by the game, not by the split.
+        self.update_cache_for_potato_send(if on_chain_share == Amount::default() {
+            None
+        } else {
+            Some(CachedPotatoRegenerateLastHop::PotatoAccept(
+                PotatoAcceptCachedData {
+                    game_id: game_id.clone(),
+                    puzzle_hash: live_game.last_referee_puzzle_hash.clone(),
+                    live_game,
+                    at_stake_amount: at_stake,
+                    our_share_amount: on_chain_share,
+                },
+            ))
+        });
+
+        self.update_cached_unroll_state(env)
+    }
+
+    pub fn received_potato_settle<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        signatures: &PotatoSignatures,
+        game_id: &GameID,
+        my_share: &Amount,
+    ) -> Result<ChannelCoinSpendInfo, Error> {
+        self.check_no_splice("settlement")?;
+        self.check_peer_has_potato("settlement")?;
+        debug!("RECEIVED_POTATO_SETTLE");
+        let (game_idx, their_share) = self.settlement_split(game_id, my_share)?;
+        let unroll_data =
+            self.compute_unroll_data_for_games(&[game_id.clone()], None, &self.live_games)?;
+
+        let unroll_condition_inputs =
+            self.unroll_coin_condition_inputs(my_share.clone(), their_share.clone(), &unroll_data);
+        let spend =
+            self.received_potato_verify_signatures(env, signatures, &unroll_condition_inputs)?;
+
+        self.remove_settled_game(game_idx, my_share, &their_share);
+
+        Ok(ChannelCoinSpendInfo {
+            aggsig: spend.signature,
+            solution: spend.solution,
+            conditions: spend.conditions,
+        })
+    }
+
     pub fn state_channel_coin_solution_and_signature<R: Rng>(
         &self,
         env: &mut ChannelHandlerEnv<R>,
@@ -985,7 +1313,7 @@ impl ChannelHandler {
         conditions: NodePtr,
     ) -> Result<BrokenOutCoinSpendInfo, Error> {
         debug!("RECEIVED_POTATO_CLEAN_SHUTDOWN");
-        assert!(!self.have_potato);
+        self.check_peer_has_potato("clean shutdown")?;
         let channel_spend = self.verify_channel_coin_from_peer_signatures(
             env,
             their_channel_half_signature,
@@ -1000,6 +1328,409 @@ impl ChannelHandler {
         Ok(channel_spend)
     }
 
+    pub fn has_potato(&self) -> bool {
+        self.have_potato
+    }
+
+    pub fn splice_in_progress(&self) -> bool {
+        self.pending_splice.is_some()
+    }
+
+    pub fn pending_splice(&self) -> Option<&PendingSplice> {
+        self.pending_splice.as_ref()
+    }
+
+    /// The channel coin a pending splice will create.
+    pub fn pending_splice_coin(&self) -> Option<&CoinString> {
+        self.pending_splice
+            .as_ref()
+            .map(|p| p.state_channel.coin.coin_string())
+    }
+
+    // The channel coin a splice creates and its first unroll, with our half
+    // signatures for it.
+    fn splice_state<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        my_addition: &Amount,
+        their_addition: &Amount,
+        my_withdrawal: &Amount,
+        their_withdrawal: &Amount,
+        payout_puzzle_hash: Option<PuzzleHash>,
+    ) -> Result<(PendingSplice, PotatoSignatures), Error> {
+        let overflow = || {
+            Error::Protocol(ProtocolError::Malformed(
+                "splice overflows the channel amount".to_string(),
+            ))
+        };
+        let amount = self
+            .state_channel
+            .amount
+            .to_u64()
+            .checked_add(my_addition.to_u64())
+            .and_then(|a| a.checked_add(their_addition.to_u64()))
+            .ok_or_else(overflow)?;
+        let unallocated = |balance: &Amount, allocated: &Amount, addition: &Amount| {
+            balance
+                .to_u64()
+                .checked_add(addition.to_u64())
+                .and_then(|b| b.checked_sub(allocated.to_u64()))
+                .ok_or_else(overflow)
+        };
+        let my_available = unallocated(
+            &self.my_out_of_game_balance,
+            &self.my_allocated_balance,
+            my_addition,
+        )?;
+        let their_available = unallocated(
+            &self.their_out_of_game_balance,
+            &self.their_allocated_balance,
+            their_addition,
+        )?;
+        if my_withdrawal.to_u64() > my_available || their_withdrawal.to_u64() > their_available {
+            return Err(Error::Protocol(ProtocolError::Malformed(
+                "withdrawal is more than the unallocated balance".to_string(),
+            )));
+        }
+        let amount = Amount::new(amount - my_withdrawal.to_u64() - their_withdrawal.to_u64());
+
+        let old_coin = self.state_channel_coin();
+        let puzzle_hash = if let Some((_, puzzle_hash, _)) = old_coin.coin_string().to_parts() {
+            puzzle_hash
+        } else {
+            return Err(Error::StrErr("no channel coin".to_string()));
+        };
+        let coin = ChannelCoin::new(CoinString::from_parts(
+            &old_coin.to_coin_id(),
+            &puzzle_hash,
+            &amount,
+        ));
+
+        let unroll_data = self.compute_unroll_data_for_games(&[], None, &self.live_games)?;
+        let mut inputs =
+            self.unroll_coin_condition_inputs(Amount::default(), Amount::default(), &unroll_data);
+        inputs.my_balance = Amount::new(my_available - my_withdrawal.to_u64());
+        inputs.their_balance = Amount::new(their_available - their_withdrawal.to_u64());
+        let mut unroll_coin = self.unroll.coin.clone();
+        unroll_coin.state_number = self.current_state_number + 1;
+        unroll_coin.update(
+            env,
+            &self.private_keys.my_unroll_coin_private_key,
+            &self.their_unroll_coin_public_key,
+            &inputs,
+        )?;
+
+        let channel_coin_spend = coin.get_solution_and_signature(
+            env,
+            &self.private_keys.my_channel_coin_private_key,
+            &self.get_aggregate_channel_public_key(),
+            &self.get_aggregate_unroll_public_key(),
+            &amount,
+            &unroll_coin,
+        )?;
+        let signatures = PotatoSignatures {
+            my_channel_half_signature_peer: channel_coin_spend.signature,
+            my_unroll_half_signature_peer: unroll_coin.get_unroll_coin_signature()?,
+        };
+
+        Ok((
+            PendingSplice {
+                my_addition: my_addition.clone(),
+                their_addition: their_addition.clone(),
+                my_withdrawal: my_withdrawal.clone(),
+                their_withdrawal: their_withdrawal.clone(),
+                payout_puzzle_hash,
+                state_channel: ChannelCoinInfo {
+                    coin,
+                    amount,
+                    spend: Spend::default(),
+                },
+                unroll: ChannelHandlerUnrollSpendInfo {
+                    coin: unroll_coin,
+                    signatures: PotatoSignatures::default(),
+                },
+                countersigned: false,
+            },
+            signatures,
+        ))
+    }
+
+    // Check the peer's half signatures for a splice's first unroll and keep
+    // the fully signed spend of the new channel coin.
+    fn countersign_splice<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        pending: &mut PendingSplice,
+        signatures: &PotatoSignatures,
+    ) -> Result<(), Error> {
+        if !pending.unroll.coin.verify(
+            env,
+            &self.get_aggregate_unroll_public_key(),
+            &signatures.my_unroll_half_signature_peer,
+        )? {
+            return Err(Error::Protocol(ProtocolError::BadSignature(
+                "splice unroll half signature".to_string(),
+            )));
+        }
+
+        let aggregate_public_key = self.get_aggregate_channel_public_key();
+        let channel_coin_spend = pending.state_channel.coin.get_solution_and_signature(
+            env,
+            &self.private_keys.my_channel_coin_private_key,
+            &aggregate_public_key,
+            &self.get_aggregate_unroll_public_key(),
+            &pending.state_channel.amount,
+            &pending.unroll.coin,
+        )?;
+        let full_signature =
+            channel_coin_spend.signature + signatures.my_channel_half_signature_peer.clone();
+        if !full_signature.verify(&aggregate_public_key, &channel_coin_spend.message) {
+            return Err(Error::Protocol(ProtocolError::BadSignature(
+                "splice channel coin half signature".to_string(),
+            )));
+        }
+
+        pending.state_channel.spend = Spend {
+            puzzle: puzzle_for_synthetic_public_key(
+                env.allocator,
+                &env.standard_puzzle,
+                &aggregate_public_key,
+            )?,
+            solution: Program::from_nodeptr(env.allocator, channel_coin_spend.solution)?,
+            signature: full_signature,
+        };
+        pending.unroll.signatures = signatures.clone();
+        pending.countersigned = true;
+        Ok(())
+    }
+
+    // The old channel coin spends to the new one, paying out any withdrawal.
+    // For a top-up, the added coin spent in the same bundle makes up the
+    // difference.
+    fn splice_conditions<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        pending: &PendingSplice,
+    ) -> Result<NodePtr, Error> {
+        let (_, puzzle_hash, amount) =
+            if let Some(parts) = pending.state_channel.coin.coin_string().to_parts() {
+                parts
+            } else {
+                return Err(Error::StrErr("no splice channel coin".to_string()));
+            };
+        let withdrawal = pending.my_withdrawal.clone() + pending.their_withdrawal.clone();
+        match (&pending.payout_puzzle_hash, withdrawal > Amount::default()) {
+            (Some(payout_puzzle_hash), true) => [
+                (CREATE_COIN, (puzzle_hash, (amount, ()))),
+                (CREATE_COIN, (payout_puzzle_hash.clone(), (withdrawal, ()))),
+            ]
+            .to_clvm(env.allocator)
+            .into_gen(),
+            (None, true) => Err(Error::StrErr(
+                "withdrawal without a payout puzzle hash".to_string(),
+            )),
+            _ => [(CREATE_COIN, (puzzle_hash, (amount, ())))]
+                .to_clvm(env.allocator)
+                .into_gen(),
+        }
+    }
+
+    fn check_can_splice(&self, what: &str, amount: &Amount) -> Result<(), Error> {
+        if !self.have_potato {
+            return Err(Error::StrErr(format!("{what} without the potato")));
+        }
+        self.check_no_splice(what)?;
+        if *amount == Amount::default() {
+            return Err(Error::StrErr(format!("{what} of nothing")));
+        }
+
+        Ok(())
+    }
+
+    // The peer's half of a splice it started.  Returns our signatures for the
+    // new channel coin's unroll and our half signature of the spend creating
+    // it.
+    fn receive_splice<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        mut pending: PendingSplice,
+        signatures: &PotatoSignatures,
+        our_signatures: PotatoSignatures,
+    ) -> Result<(PotatoSignatures, Aggsig), Error> {
+        self.countersign_splice(env, &mut pending, signatures)?;
+        let conditions = self.splice_conditions(env, &pending)?;
+        let (_, splice_half_signature) =
+            self.state_channel_coin_solution_and_signature(env, conditions)?;
+
+        self.pending_splice = Some(pending);
+        Ok((our_signatures, splice_half_signature))
+    }
+
+    fn check_peer_can_splice(&self, what: &str, amount: &Amount) -> Result<(), Error> {
+        self.check_peer_has_potato(what)?;
+        self.check_no_splice(what)?;
+        if *amount == Amount::default() {
+            return Err(Error::Protocol(ProtocolError::Malformed(format!(
+                "{what} of nothing"
+            ))));
+        }
+
+        Ok(())
+    }
+
+    /// Start adding amount from our side to the channel.  Like any potato
+    /// send this passes the potato, but it only takes effect once the top-up
+    /// spend confirms.
+    pub fn send_potato_top_up<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        amount: &Amount,
+    ) -> Result<PotatoSignatures, Error> {
+        self.check_can_splice("top-up", amount)?;
+        let nothing = Amount::default();
+        let (pending, signatures) =
+            self.splice_state(env, amount, &nothing, &nothing, &nothing, None)?;
+        self.pending_splice = Some(pending);
+        Ok(signatures)
+    }
+
+    /// Countersign the peer's top-up.  Returns our signatures for the new
+    /// channel coin's unroll and our half signature of the spend creating it.
+    pub fn received_potato_top_up<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        amount: &Amount,
+        signatures: &PotatoSignatures,
+    ) -> Result<(PotatoSignatures, Aggsig), Error> {
+        self.check_peer_can_splice("top-up", amount)?;
+        let nothing = Amount::default();
+        let (pending, our_signatures) =
+            self.splice_state(env, &nothing, amount, &nothing, &nothing, None)?;
+        self.receive_splice(env, pending, signatures, our_signatures)
+    }
+
+    /// Start taking amount of our unallocated balance out of the channel to
+    /// payout_puzzle_hash.  As with a top-up, this passes the potato once the
+    /// splice confirms.
+    pub fn send_potato_withdraw<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        amount: &Amount,
+        payout_puzzle_hash: &PuzzleHash,
+    ) -> Result<PotatoSignatures, Error> {
+        self.check_can_splice("withdrawal", amount)?;
+        let nothing = Amount::default();
+        let (pending, signatures) = self.splice_state(
+            env,
+            &nothing,
+            &nothing,
+            amount,
+            &nothing,
+            Some(payout_puzzle_hash.clone()),
+        )?;
+        self.pending_splice = Some(pending);
+        Ok(signatures)
+    }
+
+    /// Countersign the peer's withdrawal.  Returns the same as
+    /// received_potato_top_up.
+    pub fn received_potato_withdraw<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        amount: &Amount,
+        payout_puzzle_hash: &PuzzleHash,
+        signatures: &PotatoSignatures,
+    ) -> Result<(PotatoSignatures, Aggsig), Error> {
+        self.check_peer_can_splice("withdrawal", amount)?;
+        let nothing = Amount::default();
+        let (pending, our_signatures) = self.splice_state(
+            env,
+            &nothing,
+            &nothing,
+            &nothing,
+            amount,
+            Some(payout_puzzle_hash.clone()),
+        )?;
+        self.receive_splice(env, pending, signatures, our_signatures)
+    }
+
+    /// The peer countersigned our splice.  Returns the fully signed spend of
+    /// the old channel coin.  A top-up needs the added coin spent with it.
+    pub fn received_potato_splice_accept<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        signatures: &PotatoSignatures,
+        their_half_signature: &Aggsig,
+    ) -> Result<Spend, Error> {
+        let mut pending = match self.pending_splice.take() {
+            Some(pending) if !pending.countersigned && pending.is_ours() => pending,
+            pending => {
+                self.pending_splice = pending;
+                return Err(Error::Protocol(ProtocolError::StateMismatch(
+                    "splice accept without our splice".to_string(),
+                )));
+            }
+        };
+
+        let result = self
+            .countersign_splice(env, &mut pending, signatures)
+            .and_then(|_| {
+                let conditions = self.splice_conditions(env, &pending)?;
+                self.verify_channel_coin_from_peer_signatures(env, their_half_signature, conditions)
+            })
+            .and_then(|spend| {
+                Ok(Spend {
+                    puzzle: puzzle_for_pk(env.allocator, &self.get_aggregate_channel_public_key())?,
+                    solution: Program::from_nodeptr(env.allocator, spend.solution)?,
+                    signature: spend.signature,
+                })
+            });
+
+        if result.is_err() {
+            // Countersigning happens all at once or not at all.
+            pending.countersigned = false;
+        }
+        self.pending_splice = Some(pending);
+        result
+    }
+
+    /// The splice confirmed: move to the new channel coin and its balances.
+    /// Returns whether we now hold the potato and the fully signed spend of
+    /// the new channel coin to its unroll.
+    pub fn splice_confirmed(&mut self) -> Result<(bool, Spend), Error> {
+        let pending = match self.pending_splice.take() {
+            Some(pending) if pending.countersigned => pending,
+            pending => {
+                self.pending_splice = pending;
+                return Err(Error::StrErr(
+                    "splice confirmed before it was countersigned".to_string(),
+                ));
+            }
+        };
+
+        self.my_out_of_game_balance =
+            self.my_out_of_game_balance.clone() + pending.my_addition - pending.my_withdrawal;
+        self.their_out_of_game_balance = self.their_out_of_game_balance.clone()
+            + pending.their_addition
+            - pending.their_withdrawal;
+        self.state_channel = pending.state_channel;
+        self.current_state_number = pending.unroll.coin.state_number;
+        self.unroll = pending.unroll.clone();
+        self.timeout = Some(pending.unroll);
+        self.cached_last_action = None;
+        // The splice passed the potato from whoever started it.
+        self.have_potato = !self.have_potato;
+
+        Ok((self.have_potato, self.state_channel.spend.clone()))
+    }
+
+    pub fn out_of_game_balances(&self) -> (Amount, Amount) {
+        (
+            self.my_out_of_game_balance.clone(),
+            self.their_out_of_game_balance.clone(),
+        )
+    }
+
     fn break_out_conditions_for_spent_coin<R: Rng>(
         &self,
         env: &mut ChannelHandlerEnv<R>,
@@ -1028,42 +1759,72 @@ impl ChannelHandler {
         Ok(rem_conditions)
     }
 
+    /// Spend an unroll coin we created to its default conditions once the
+    /// timeout has passed.
     pub fn get_create_unroll_coin_transaction<R: Rng>(
         &self,
         env: &mut ChannelHandlerEnv<R>,
         use_unroll: &ChannelHandlerUnrollSpendInfo,
-        add_sigs: bool,
     ) -> Result<ChannelCoinSpentResult, Error> {
-        assert!(self.timeout.is_some());
-
         debug!("channel handler at {}", self.current_state_number);
 
-        // Superceding state no timeout
-        // Provide a reveal of the unroll puzzle.
-        // Provide last unroll conditions
-        // Should have a cached signature for unrolling
-
         // Full unroll puzzle reveal includes the curried info,
         let curried_unroll_puzzle = use_unroll
             .coin
             .make_curried_unroll_puzzle(env, &self.get_aggregate_unroll_public_key())?;
-        let unroll_puzzle_solution = use_unroll
-            .coin
-            .make_unroll_puzzle_solution(env, &self.get_aggregate_unroll_public_key())?;
+        let unroll_puzzle_solution = use_unroll.coin.make_unroll_puzzle_timeout_solution(env)?;
         let solution_program = Program::from_nodeptr(env.allocator, unroll_puzzle_solution)?;
 
         debug!(
             "get_unroll_coin_transaction {:?}",
             solution_program.to_hex()
         );
-        let mut signature = use_unroll.coin.get_unroll_coin_signature()?;
-        if add_sigs {
-            signature += use_unroll.signatures.my_unroll_half_signature_peer.clone();
-        }
         Ok(ChannelCoinSpentResult {
             transaction: Spend {
                 puzzle: Puzzle::from_nodeptr(env.allocator, curried_unroll_puzzle)?,
                 solution: solution_program,
+                signature: Aggsig::default(),
+            },
+            timeout: true,
+            games_canceled: self.get_just_created_games(),
+        })
+    }
+
+    /// Supercede an unroll coin created from an older state with our most
+    /// recent fully signed state.
+    ///
+    /// The unroll puzzle we're spending is rebuilt from the state number and
+    /// default conditions hash revealed in the channel coin spend.
+    fn get_supercede_unroll_coin_transaction<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        old_state_number: usize,
+        old_conditions_hash: &PuzzleHash,
+    ) -> Result<ChannelCoinSpentResult, Error> {
+        let use_unroll = if let Some(t) = self.timeout.as_ref() {
+            t
+        } else {
+            return Err(Error::StrErr(
+                "no fully signed state to supercede with".to_string(),
+            ));
+        };
+
+        let curried_unroll_puzzle = curry_unroll_puzzle(
+            env,
+            &self.get_aggregate_unroll_public_key(),
+            old_state_number,
+            old_conditions_hash,
+        )?;
+        let unroll_puzzle_solution = use_unroll
+            .coin
+            .make_unroll_puzzle_solution(env, &self.get_aggregate_unroll_public_key())?;
+        let signature = use_unroll.coin.get_unroll_coin_signature()?
+            + use_unroll.signatures.my_unroll_half_signature_peer.clone();
+
+        Ok(ChannelCoinSpentResult {
+            transaction: Spend {
+                puzzle: Puzzle::from_nodeptr(env.allocator, curried_unroll_puzzle)?,
+                solution: Program::from_nodeptr(env.allocator, unroll_puzzle_solution)?,
                 signature,
             },
             timeout: false,
@@ -1071,6 +1832,25 @@ impl ChannelHandler {
         })
     }
 
+    fn get_unroll_for_state(
+        &self,
+        state_number: usize,
+    ) -> Result<&ChannelHandlerUnrollSpendInfo, Error> {
+        if let Some(t) = self.timeout.as_ref() {
+            if t.coin.state_number == state_number {
+                return Ok(t);
+            }
+        }
+
+        if self.unroll.coin.state_number == state_number {
+            return Ok(&self.unroll);
+        }
+
+        Err(Error::StrErr(format!(
+            "no unroll data for state {state_number}"
+        )))
+    }
+
     /// Ensure that we include the last state sequence number in a memo so we can
     /// possibly supercede an earlier unroll.
     ///
@@ -1117,7 +1897,7 @@ impl ChannelHandler {
         conditions: NodePtr,
     ) -> Result<ChannelCoinSpentResult, Error> {
         let rem_conditions = self.break_out_conditions_for_spent_coin(env, conditions)?;
-        let full_coin = self.get_unroll_coin();
+        let full_coin = self.get_finished_unroll_coin();
 
         let state_number = usize_from_atom(&rem_conditions[0])
             .ok_or_else(|| Error::StrErr("Unconvertible state number".to_string()))?;
@@ -1136,41 +1916,63 @@ impl ChannelHandler {
         match (myself, state_number.cmp(&self.current_state_number)) {
             (true, _) | (_, Ordering::Equal) => {
                 // Timeout
-                let curried_unroll_puzzle = self
-                    .unroll
-                    .coin
-                    .make_curried_unroll_puzzle(env, &self.get_aggregate_unroll_public_key())?;
-                let unroll_puzzle_solution = self
-                    .unroll
-                    .coin
-                    .make_unroll_puzzle_solution(env, &self.get_aggregate_unroll_public_key())?;
-
-                Ok(ChannelCoinSpentResult {
-                    transaction: Spend {
-                        puzzle: Puzzle::from_nodeptr(env.allocator, curried_unroll_puzzle)?,
-                        solution: Program::from_nodeptr(env.allocator, unroll_puzzle_solution)?,
-                        signature: self.unroll.coin.get_unroll_coin_signature()?,
-                    },
-                    timeout: true,
-                    games_canceled: self.get_just_created_games(),
-                })
+                let use_unroll = self.get_unroll_for_state(state_number)?;
+                self.get_create_unroll_coin_transaction(env, use_unroll)
             }
             (_, Ordering::Greater) => Err(Error::StrErr(format!(
                 "Reply from the future onchain {} (me {}) vs {}",
                 state_number, self.current_state_number, full_coin.coin.state_number
             ))),
             (_, Ordering::Less) => {
-                if our_parity == their_parity {
+                // The state we last sent is theirs to unroll: they redo their
+                // reply to it on chain once the unroll times out.
+                if our_parity == their_parity
+                    || full_coin.coin.state_number < state_number
+                    || state_number == self.unroll.coin.state_number
+                {
                     return Err(Error::StrErr(
                         "We're superceding ourselves from the past?".to_string(),
                     ));
                 }
 
-                self.get_create_unroll_coin_transaction(env, full_coin, true)
+                if rem_conditions.len() < 2 {
+                    return Err(Error::StrErr(
+                        "channel coin spend didn't reveal the unroll conditions hash".to_string(),
+                    ));
+                }
+
+                let old_conditions_hash = PuzzleHash::from_bytes(
+                    rem_conditions[1]
+                        .clone()
+                        .try_into()
+                        .map_err(|_| Error::StrErr("bad unroll conditions hash".to_string()))?,
+                );
+                self.get_supercede_unroll_coin_transaction(env, state_number, &old_conditions_hash)
             }
         }
     }
 
+    /// Whether a spend of the channel coin unrolls a state older than the one
+    /// our peer is entitled to, so that we should supercede it with the newest
+    /// state we hold rather than let it time out.
+    ///
+    /// Our peer may always unroll the last state we sent them, since they may
+    /// not have our signatures on their reply yet.
+    pub fn is_stale_unroll<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        conditions: NodePtr,
+    ) -> Result<bool, Error> {
+        let rem_conditions = self.break_out_conditions_for_spent_coin(env, conditions)?;
+        let state_number = usize_from_atom(&rem_conditions[0])
+            .ok_or_else(|| Error::StrErr("Unconvertible state number".to_string()))?;
+
+        Ok(
+            state_number < self.get_finished_unroll_coin().coin.state_number
+                && state_number != self.unroll.coin.state_number,
+        )
+    }
+
     // 5 cases
     //
     // 1 last potato nil (nothing changed)
@@ -1195,9 +1997,13 @@ impl ChannelHandler {
         unroll_coin: &CoinString,
         state_number: usize,
     ) -> Result<Option<DispositionResult>, Error> {
+        // The only older state that can end up on chain is the one before our
+        // last potato send, in which case we redo what we sent on chain.
         if state_number == self.current_state_number {
             return Ok(None);
-        } else if state_number != self.unroll.coin.state_number {
+        } else if state_number + 1 != self.current_state_number
+            || self.unroll.coin.state_number != self.current_state_number
+        {
             return Err(Error::StrErr("Bad state number".to_string()));
         }
 
@@ -1302,7 +2108,8 @@ impl ChannelHandler {
         &self,
         env: &mut ChannelHandlerEnv<R>,
     ) -> Result<Vec<OnChainGameCoin>, Error> {
-        let state_number = self.unroll.coin.state_number;
+        let finished_unroll = self.get_finished_unroll_coin();
+        let state_number = finished_unroll.coin.state_number;
         // We need the view of the system as of the most recent timeout.
         // I made a move, they have the potato, so we need to reconstruct the
         // game states from the most recent their turn.  If there's a move in the
@@ -1310,7 +2117,9 @@ impl ChannelHandler {
         // it uses the one from the live game object.  Once on chain, we'll need
         // the actual puzzle, but that's a problem for a comment other than this
         // one.
-        let unroll_puzzle = self.make_curried_unroll_puzzle(env)?;
+        let unroll_puzzle = finished_unroll
+            .coin
+            .make_curried_unroll_puzzle(env, &self.get_aggregate_unroll_public_key())?;
         let unroll_puzzle_hash = Node(unroll_puzzle).sha256tree(env.allocator);
         let parent_coin = self.state_channel_coin().coin_string();
         let unroll_coin = CoinString::from_parts(
@@ -1348,7 +2157,10 @@ impl ChannelHandler {
             } else {
                 // XXX Used to exist, needs ressurection from the cache to potentially replay
                 // the accept.
-                todo!();
+                return Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+                    "game coin for unknown game {:?}",
+                    game_coin.game_id_up
+                ))));
             }
         }
 
@@ -1433,6 +2245,143 @@ impl ChannelHandler {
         })
     }
 
+    /// Tell whether the referee coin holding this game on chain is waiting on
+    /// our move.
+    pub fn game_is_my_turn_on_chain(&self, game_id: &GameID) -> Result<bool, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        Ok(self.live_games[game_idx]
+            .referee_maker
+            .is_my_turn_on_chain())
+    }
+
+    pub fn get_game_timeout(&self, game_id: &GameID) -> Result<Timeout, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        Ok(self.live_games[game_idx].referee_maker.timeout.clone())
+    }
+
+    #[cfg(test)]
+    pub fn cheat_on_next_move(
+        &mut self,
+        game_id: &GameID,
+        mover_share: Amount,
+    ) -> Result<(), Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        self.live_games[game_idx]
+            .referee_maker
+            .cheat_on_next_move(mover_share);
+        Ok(())
+    }
+
+    /// Make our move in a game that's being played on chain.  The referee
+    /// advances just as it does off chain, and we return the spend of the
+    /// current game coin along with the game coin it makes.
+    pub fn on_chain_our_move<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        game_id: &GameID,
+        readable_move: &ReadableMove,
+        new_entropy: Hash,
+        existing_coin: &CoinString,
+    ) -> Result<OnChainMoveResult, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+
+        let referee_maker: &mut RefereeMaker = self.live_games[game_idx].referee_maker.borrow_mut();
+        let referee_result =
+            referee_maker.my_turn_make_move(env.allocator, readable_move, new_entropy)?;
+        let transaction = referee_maker.get_transaction_for_move(
+            env.allocator,
+            existing_coin,
+            &env.agg_sig_me_additional_data,
+        )?;
+        self.live_games[game_idx].last_referee_puzzle_hash =
+            referee_result.puzzle_hash_for_unroll.clone();
+
+        Ok(OnChainMoveResult {
+            game_move: referee_result.details,
+            spend: CoinSpend {
+                coin: existing_coin.clone(),
+                bundle: transaction.bundle,
+            },
+            new_game_coin: transaction.reward_coin,
+        })
+    }
+
+    /// A game coin waiting on their move was spent.  Conditions are the result
+    /// of running the spend's puzzle and solution.
+    pub fn game_coin_spent<R: Rng>(
+        &mut self,
+        env: &mut ChannelHandlerEnv<R>,
+        game_id: &GameID,
+        coin_string: &CoinString,
+        conditions: NodePtr,
+    ) -> Result<TheirTurnCoinSpentResult, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        self.live_games[game_idx]
+            .referee_maker
+            .their_turn_coin_spent(
+                env.allocator,
+                &env.agg_sig_me_additional_data,
+                coin_string,
+                &conditions,
+            )
+    }
+
+    /// Whether a spend of a game coin moved the game on rather than ending it.
+    pub fn game_coin_spend_is_move<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        game_id: &GameID,
+        conditions: NodePtr,
+    ) -> Result<bool, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        self.live_games[game_idx]
+            .referee_maker
+            .is_move_spend(env.allocator, conditions)
+    }
+
+    /// The spend timing out a game coin, if we're the one that should claim it.
+    pub fn get_game_timeout_transaction<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        game_id: &GameID,
+        coin_string: &CoinString,
+    ) -> Result<Option<CoinSpend>, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        let referee_maker = &self.live_games[game_idx].referee_maker;
+        if !referee_maker.should_claim_on_chain_timeout() {
+            return Ok(None);
+        }
+
+        Ok(referee_maker
+            .get_transaction_for_on_chain_timeout(env.allocator, coin_string)?
+            .map(|transaction| CoinSpend {
+                coin: coin_string.clone(),
+                bundle: transaction.bundle,
+            }))
+    }
+
+    /// Our reward coin among the conditions of a spend that ended a game on
+    /// chain.
+    pub fn get_game_reward_coin<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        game_id: &GameID,
+        coin_string: &CoinString,
+        conditions: NodePtr,
+    ) -> Result<Option<CoinString>, Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        Ok(self.live_games[game_idx]
+            .referee_maker
+            .get_my_reward_coin_from_conditions(env.allocator, coin_string, conditions))
+    }
+
+    /// Forget a game that has ended on chain.
+    pub fn game_finished_on_chain(&mut self, game_id: &GameID) -> Result<(), Error> {
+        let game_idx = self.get_game_by_id(game_id)?;
+        self.live_games.remove(game_idx);
+        Ok(())
+    }
+
     // the vanilla coin we get and each reward coin are all sent to the referee
     // this returns spends which allow them to be consolidated by spending the
     // reward coins.
@@ -1457,7 +2406,11 @@ impl ChannelHandler {
 
         for c in coins.iter() {
             if let Some((_parent, ph, amount)) = c.to_parts() {
-                assert_eq!(ph, referee_puzzle_hash);
+                if ph != referee_puzzle_hash {
+                    return Err(Error::StrErr(
+                        "reward coin isn't paid to our referee puzzle hash".to_string(),
+                    ));
+                }
                 total_amount += amount.clone();
                 exploded_coins.push(CoinDataForReward {
                     coin_string: c.clone(),
@@ -15,19 +15,19 @@ use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::channel_handler::game_handler::{FlatGameHandler, GameHandler};
-use crate::common::constants::{CREATE_COIN, REM};
+use crate::common::constants::{ASSERT_HEIGHT_RELATIVE, CREATE_COIN, REM};
 use crate::common::standard_coin::{
     private_to_public_key, puzzle_hash_for_pk, read_hex_puzzle, standard_solution_partial,
     unsafe_sign_partial,
 };
 use crate::common::types::{
     atom_from_clvm, usize_from_atom, Aggsig, AllocEncoder, Amount, BrokenOutCoinSpendInfo, CoinID,
-    CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Node, PrivateKey, Program, PublicKey,
-    Puzzle, PuzzleHash, Sha256Input, Sha256tree, Spend, Timeout,
+    CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Node, PrivateKey, Program, ProtocolError,
+    PublicKey, Puzzle, PuzzleHash, Sha256Input, Sha256tree, Spend, Timeout,
 };
-use crate::referee::{GameMoveDetails, LiveGameReplay, RefereeMaker};
+use crate::referee::{FlatRefereeMaker, GameMoveDetails, LiveGameReplay, RefereeMaker};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ChannelHandlerPrivateKeys {
     pub my_channel_coin_private_key: PrivateKey,
     pub my_unroll_coin_private_key: PrivateKey,
@@ -305,12 +305,21 @@ pub struct MoveResult {
     pub game_move: GameMoveDetails,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OnChainGameCoin {
     pub game_id_up: GameID,
     pub coin_string_up: Option<CoinString>,
 }
 
+/// Our move made against a game coin that's on chain: the spend of the coin
+/// and the game coin it creates, which awaits their move.
+#[derive(Debug, Clone)]
+pub struct OnChainMoveResult {
+    pub game_move: GameMoveDetails,
+    pub spend: CoinSpend,
+    pub new_game_coin: CoinString,
+}
+
 #[derive(Debug, Clone)]
 pub struct CoinSpentMoveUp {
     pub game_id: GameID,
@@ -413,6 +422,7 @@ pub struct PotatoAcceptCachedData {
     pub our_share_amount: Amount,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PotatoMoveCachedData {
     pub game_id: GameID,
     pub puzzle_hash: PuzzleHash,
@@ -641,8 +651,17 @@ impl ChannelCoin {
                 .into_gen()?,
         )];
         let create_conditions_obj = create_conditions.to_clvm(env.allocator).into_gen()?;
+        // The second rem lets the other player rebuild the unroll puzzle we're
+        // spending to so they can supercede it with a newer state.
+        let conditions_hash_rem = (
+            REM,
+            (unroll_coin.get_conditions_hash_for_unroll_puzzle()?, ()),
+        );
+        let create_conditions_with_hash = (conditions_hash_rem, Node(create_conditions_obj))
+            .to_clvm(env.allocator)
+            .into_gen()?;
         let create_conditions_with_rem =
-            prepend_rem_conditions(env, unroll_coin.state_number, create_conditions_obj)?;
+            prepend_rem_conditions(env, unroll_coin.state_number, create_conditions_with_hash)?;
         self.get_solution_and_signature_from_conditions(
             env,
             private_key,
@@ -666,6 +685,7 @@ pub struct UnrollCoinConditionInputs {
     pub my_balance: Amount,
     pub their_balance: Amount,
     pub puzzle_hashes_and_amounts: Vec<(PuzzleHash, Amount)>,
+    pub unroll_timeout: Timeout,
 }
 
 #[derive(Clone, Debug)]
@@ -696,14 +716,16 @@ pub struct UnrollCoinOutcome {
 /// At the end of the day update and verify should produce the same conditions for
 /// a specific generation and verify the same message.
 ///
-/// UnrollCoin is responsible for enforcing that a time lock (ASSERT_RELATIVE ...) etc
-/// so that the other player has an opportunity to challenge the unroll.
+/// UnrollCoin is responsible for enforcing a time lock (ASSERT_HEIGHT_RELATIVE) on
+/// the default conditions so that the other player has an opportunity to challenge
+/// the unroll.
 ///
 /// The unrolling player will have to trigger the "reveal" part as below after a time
 /// if the other player doesn't successfully challenge by providing another program that
 /// produces new conditions that match the parity criteria.
 ///
-/// XXX TODO: Add time lock
+/// The conditions signed for the metapuzzle don't carry the time lock, since a
+/// challenge has to land before the timeout.
 #[derive(Default, Clone)]
 pub struct UnrollCoin {
     pub started_with_potato: bool,
@@ -727,6 +749,37 @@ fn prepend_state_number_rem_to_conditions<R: Rng>(
         .into_gen()
 }
 
+/// Build the unroll puzzle for a channel state given the state number and
+/// default conditions hash revealed when the channel coin was spent.
+///
+/// The unroll coin can be spent with its default conditions after the timeout
+/// or superceded with any later state of the opposite parity.
+pub fn curry_unroll_puzzle<R: Rng>(
+    env: &mut ChannelHandlerEnv<R>,
+    aggregate_public_key: &PublicKey,
+    old_state_number: usize,
+    default_conditions_hash: &PuzzleHash,
+) -> Result<NodePtr, Error> {
+    let shared_puzzle = CurriedProgram {
+        program: env.unroll_metapuzzle.clone(),
+        args: clvm_curried_args!(aggregate_public_key.clone()),
+    }
+    .to_clvm(env.allocator)
+    .into_gen()?;
+    let shared_puzzle_hash = Node(shared_puzzle).sha256tree(env.allocator);
+
+    CurriedProgram {
+        program: env.unroll_puzzle.clone(),
+        args: clvm_curried_args!(
+            shared_puzzle_hash,
+            old_state_number,
+            default_conditions_hash.clone()
+        ),
+    }
+    .to_clvm(env.allocator)
+    .into_gen()
+}
+
 pub fn prepend_rem_conditions<R: Rng>(
     env: &mut ChannelHandlerEnv<R>,
     state_number: usize,
@@ -786,24 +839,25 @@ impl UnrollCoin {
         aggregate_public_key: &PublicKey,
     ) -> Result<NodePtr, Error> {
         let conditions_hash = self.get_conditions_hash_for_unroll_puzzle()?;
-        let shared_puzzle = CurriedProgram {
-            program: env.unroll_metapuzzle.clone(),
-            args: clvm_curried_args!(aggregate_public_key.clone()),
-        }
-        .to_clvm(env.allocator)
-        .into_gen()?;
-        let shared_puzzle_hash = Node(shared_puzzle).sha256tree(env.allocator);
-
-        CurriedProgram {
-            program: env.unroll_puzzle.clone(),
-            args: clvm_curried_args!(
-                shared_puzzle_hash,
-                self.get_old_state_number()? - 1,
-                conditions_hash
-            ),
-        }
-        .to_clvm(env.allocator)
-        .into_gen()
+        curry_unroll_puzzle(
+            env,
+            aggregate_public_key,
+            self.get_old_state_number()?,
+            &conditions_hash,
+        )
+    }
+
+    /// Solution for spending our own unroll coin to its default conditions.
+    /// No signature is needed as the puzzle checks the conditions against the
+    /// curried hash.  The conditions assert the unroll timeout, so the spend
+    /// is refused until the coin is that many blocks old.
+    pub fn make_unroll_puzzle_timeout_solution<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+    ) -> Result<NodePtr, Error> {
+        (Node(self.get_conditions_for_unroll_coin_spend()?), ())
+            .to_clvm(env.allocator)
+            .into_gen()
     }
 
     pub fn make_unroll_puzzle_solution<R: Rng>(
@@ -820,7 +874,10 @@ impl UnrollCoin {
 
         let unroll_puzzle_solution = (
             Node(unroll_inner_puzzle),
-            (Node(self.get_conditions_for_unroll_coin_spend()?), ()),
+            (
+                Node(self.get_internal_conditions_for_unroll_coin_spend()?),
+                (),
+            ),
         )
             .to_clvm(env.allocator)
             .into_gen()?;
@@ -888,21 +945,30 @@ impl UnrollCoin {
         inputs: &UnrollCoinConditionInputs,
     ) -> Result<Aggsig, Error> {
         let unroll_conditions = self.compute_unroll_coin_conditions(env, inputs)?;
-        let conditions_hash = Node(unroll_conditions).sha256tree(env.allocator);
+        let signed_hash = Node(unroll_conditions).sha256tree(env.allocator);
+        // The default conditions only apply once the other player has had the
+        // unroll timeout to supercede them.
+        let default_conditions = (
+            (ASSERT_HEIGHT_RELATIVE, (inputs.unroll_timeout.clone(), ())),
+            Node(unroll_conditions),
+        )
+            .to_clvm(env.allocator)
+            .into_gen()?;
+        let conditions_hash = Node(default_conditions).sha256tree(env.allocator);
         let unroll_public_key = private_to_public_key(unroll_private_key);
         let unroll_aggregate_key = unroll_public_key.clone() + their_unroll_coin_public_key.clone();
         debug!(
             "conditions {}",
-            disassemble(env.allocator.allocator(), unroll_conditions, None)
+            disassemble(env.allocator.allocator(), default_conditions, None)
         );
         debug!("conditions_hash {conditions_hash:?}");
         let unroll_signature = unsafe_sign_partial(
             unroll_private_key,
             &unroll_aggregate_key,
-            conditions_hash.bytes(),
+            signed_hash.bytes(),
         );
         self.outcome = Some(UnrollCoinOutcome {
-            conditions: unroll_conditions,
+            conditions: default_conditions,
             conditions_without_hash: unroll_conditions,
             state_number: self.state_number,
             hash: conditions_hash,
@@ -970,7 +1036,10 @@ impl LiveGame {
                 ph.clone()
             } else {
                 // No coin string given so this game was ended.  We need to ressurect it.
-                todo!();
+                return Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+                    "no game coin for ended game {:?}",
+                    self.game_id
+                ))));
             };
 
         let referee_puzzle_hash = self
@@ -987,10 +1056,315 @@ impl LiveGame {
                 .curried_referee_puzzle_hash_for_validator(allocator, true)?;
 
             if new_puzzle_hash == want_ph {
-                todo!();
+                // The referee now generates the puzzle for the coin on chain.
+                return Ok(vec![]);
             }
         }
 
-        todo!();
+        Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+            "game coin for {:?} doesn't match any state we had",
+            self.game_id
+        ))))
+    }
+}
+
+/// Serializable form of a LiveGame.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlatLiveGame {
+    pub game_id: GameID,
+    pub last_referee_puzzle_hash: PuzzleHash,
+    pub referee_maker: FlatRefereeMaker,
+    pub my_contribution: Amount,
+    pub their_contribution: Amount,
+}
+
+impl LiveGame {
+    pub fn to_serializable(&self, allocator: &mut AllocEncoder) -> Result<FlatLiveGame, Error> {
+        Ok(FlatLiveGame {
+            game_id: self.game_id.clone(),
+            last_referee_puzzle_hash: self.last_referee_puzzle_hash.clone(),
+            referee_maker: self.referee_maker.to_serializable(allocator)?,
+            my_contribution: self.my_contribution.clone(),
+            their_contribution: self.their_contribution.clone(),
+        })
+    }
+
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatLiveGame,
+    ) -> Result<LiveGame, Error> {
+        Ok(LiveGame {
+            game_id: serializable.game_id.clone(),
+            last_referee_puzzle_hash: serializable.last_referee_puzzle_hash.clone(),
+            referee_maker: Box::new(RefereeMaker::from_serializable(
+                allocator,
+                &serializable.referee_maker,
+            )?),
+            my_contribution: serializable.my_contribution.clone(),
+            their_contribution: serializable.their_contribution.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlatPotatoAcceptCachedData {
+    pub game_id: GameID,
+    pub puzzle_hash: PuzzleHash,
+    pub live_game: FlatLiveGame,
+    pub at_stake_amount: Amount,
+    pub our_share_amount: Amount,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum FlatCachedPotatoRegenerateLastHop {
+    PotatoCreatedGame(Vec<GameID>, Amount, Amount),
+    PotatoAccept(FlatPotatoAcceptCachedData),
+    PotatoMoveHappening(PotatoMoveCachedData),
+}
+
+impl CachedPotatoRegenerateLastHop {
+    pub fn to_serializable(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<FlatCachedPotatoRegenerateLastHop, Error> {
+        match self {
+            CachedPotatoRegenerateLastHop::PotatoCreatedGame(ids, my_amount, their_amount) => {
+                Ok(FlatCachedPotatoRegenerateLastHop::PotatoCreatedGame(
+                    ids.clone(),
+                    my_amount.clone(),
+                    their_amount.clone(),
+                ))
+            }
+            CachedPotatoRegenerateLastHop::PotatoAccept(accept) => Ok(
+                FlatCachedPotatoRegenerateLastHop::PotatoAccept(FlatPotatoAcceptCachedData {
+                    game_id: accept.game_id.clone(),
+                    puzzle_hash: accept.puzzle_hash.clone(),
+                    live_game: accept.live_game.to_serializable(allocator)?,
+                    at_stake_amount: accept.at_stake_amount.clone(),
+                    our_share_amount: accept.our_share_amount.clone(),
+                }),
+            ),
+            CachedPotatoRegenerateLastHop::PotatoMoveHappening(move_data) => Ok(
+                FlatCachedPotatoRegenerateLastHop::PotatoMoveHappening(move_data.clone()),
+            ),
+        }
+    }
+
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatCachedPotatoRegenerateLastHop,
+    ) -> Result<CachedPotatoRegenerateLastHop, Error> {
+        match serializable {
+            FlatCachedPotatoRegenerateLastHop::PotatoCreatedGame(ids, my_amount, their_amount) => {
+                Ok(CachedPotatoRegenerateLastHop::PotatoCreatedGame(
+                    ids.clone(),
+                    my_amount.clone(),
+                    their_amount.clone(),
+                ))
+            }
+            FlatCachedPotatoRegenerateLastHop::PotatoAccept(accept) => Ok(
+                CachedPotatoRegenerateLastHop::PotatoAccept(PotatoAcceptCachedData {
+                    game_id: accept.game_id.clone(),
+                    puzzle_hash: accept.puzzle_hash.clone(),
+                    live_game: LiveGame::from_serializable(allocator, &accept.live_game)?,
+                    at_stake_amount: accept.at_stake_amount.clone(),
+                    our_share_amount: accept.our_share_amount.clone(),
+                }),
+            ),
+            FlatCachedPotatoRegenerateLastHop::PotatoMoveHappening(move_data) => Ok(
+                CachedPotatoRegenerateLastHop::PotatoMoveHappening(move_data.clone()),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FlatUnrollCoinOutcome {
+    pub conditions: Program,
+    pub conditions_without_hash: Program,
+    pub state_number: usize,
+    pub hash: PuzzleHash,
+    pub signature: Aggsig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FlatUnrollCoin {
+    pub started_with_potato: bool,
+    pub state_number: usize,
+    pub outcome: Option<FlatUnrollCoinOutcome>,
+}
+
+impl UnrollCoin {
+    pub fn to_serializable(&self, allocator: &mut AllocEncoder) -> Result<FlatUnrollCoin, Error> {
+        let outcome = if let Some(outcome) = &self.outcome {
+            Some(FlatUnrollCoinOutcome {
+                conditions: Program::from_nodeptr(allocator, outcome.conditions)?,
+                conditions_without_hash: Program::from_nodeptr(
+                    allocator,
+                    outcome.conditions_without_hash,
+                )?,
+                state_number: outcome.state_number,
+                hash: outcome.hash.clone(),
+                signature: outcome.signature.clone(),
+            })
+        } else {
+            None
+        };
+
+        Ok(FlatUnrollCoin {
+            started_with_potato: self.started_with_potato,
+            state_number: self.state_number,
+            outcome,
+        })
+    }
+
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatUnrollCoin,
+    ) -> Result<UnrollCoin, Error> {
+        let outcome = if let Some(outcome) = &serializable.outcome {
+            Some(UnrollCoinOutcome {
+                conditions: outcome.conditions.to_nodeptr(allocator)?,
+                conditions_without_hash: outcome.conditions_without_hash.to_nodeptr(allocator)?,
+                state_number: outcome.state_number,
+                hash: outcome.hash.clone(),
+                signature: outcome.signature.clone(),
+            })
+        } else {
+            None
+        };
+
+        Ok(UnrollCoin {
+            started_with_potato: serializable.started_with_potato,
+            state_number: serializable.state_number,
+            outcome,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FlatChannelHandlerUnrollSpendInfo {
+    pub coin: FlatUnrollCoin,
+    pub signatures: PotatoSignatures,
+}
+
+impl ChannelHandlerUnrollSpendInfo {
+    pub fn to_serializable(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<FlatChannelHandlerUnrollSpendInfo, Error> {
+        Ok(FlatChannelHandlerUnrollSpendInfo {
+            coin: self.coin.to_serializable(allocator)?,
+            signatures: self.signatures.clone(),
+        })
+    }
+
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatChannelHandlerUnrollSpendInfo,
+    ) -> Result<ChannelHandlerUnrollSpendInfo, Error> {
+        Ok(ChannelHandlerUnrollSpendInfo {
+            coin: UnrollCoin::from_serializable(allocator, &serializable.coin)?,
+            signatures: serializable.signatures.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FlatChannelCoinInfo {
+    pub coin: CoinString,
+    pub amount: Amount,
+    pub spend: Spend,
+}
+
+impl ChannelCoinInfo {
+    pub fn to_serializable(&self) -> FlatChannelCoinInfo {
+        FlatChannelCoinInfo {
+            coin: self.coin.coin_string().clone(),
+            amount: self.amount.clone(),
+            spend: self.spend.clone(),
+        }
+    }
+
+    pub fn from_serializable(serializable: &FlatChannelCoinInfo) -> ChannelCoinInfo {
+        ChannelCoinInfo {
+            coin: ChannelCoin::new(serializable.coin.clone()),
+            amount: serializable.amount.clone(),
+            spend: serializable.spend.clone(),
+        }
+    }
+}
+
+/// A splice in flight: from the signature exchange until the spend moving the
+/// old channel coin into a new channel coin confirms.  A top-up spends an
+/// added coin alongside; a withdrawal creates a payout coin.  The new coin's
+/// first unroll is signed against the new balances, so the channel state
+/// can't move on until then.
+pub struct PendingSplice {
+    pub my_addition: Amount,
+    pub their_addition: Amount,
+    pub my_withdrawal: Amount,
+    pub their_withdrawal: Amount,
+    /// Where a withdrawal is paid.
+    pub payout_puzzle_hash: Option<PuzzleHash>,
+    /// The channel coin the splice creates.  Its spend is the unroll below
+    /// once countersigned.
+    pub state_channel: ChannelCoinInfo,
+    pub unroll: ChannelHandlerUnrollSpendInfo,
+    /// Whether unroll holds the peer's half signatures yet.
+    pub countersigned: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FlatPendingSplice {
+    pub my_addition: Amount,
+    pub their_addition: Amount,
+    pub my_withdrawal: Amount,
+    pub their_withdrawal: Amount,
+    pub payout_puzzle_hash: Option<PuzzleHash>,
+    pub state_channel: FlatChannelCoinInfo,
+    pub unroll: FlatChannelHandlerUnrollSpendInfo,
+    pub countersigned: bool,
+}
+
+impl PendingSplice {
+    /// Whether we started this splice.
+    pub fn is_ours(&self) -> bool {
+        self.my_addition > Amount::default() || self.my_withdrawal > Amount::default()
+    }
+
+    pub fn to_serializable(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<FlatPendingSplice, Error> {
+        Ok(FlatPendingSplice {
+            my_addition: self.my_addition.clone(),
+            their_addition: self.their_addition.clone(),
+            my_withdrawal: self.my_withdrawal.clone(),
+            their_withdrawal: self.their_withdrawal.clone(),
+            payout_puzzle_hash: self.payout_puzzle_hash.clone(),
+            state_channel: self.state_channel.to_serializable(),
+            unroll: self.unroll.to_serializable(allocator)?,
+            countersigned: self.countersigned,
+        })
+    }
+
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatPendingSplice,
+    ) -> Result<PendingSplice, Error> {
+        Ok(PendingSplice {
+            my_addition: serializable.my_addition.clone(),
+            their_addition: serializable.their_addition.clone(),
+            my_withdrawal: serializable.my_withdrawal.clone(),
+            their_withdrawal: serializable.their_withdrawal.clone(),
+            payout_puzzle_hash: serializable.payout_puzzle_hash.clone(),
+            state_channel: ChannelCoinInfo::from_serializable(&serializable.state_channel),
+            unroll: ChannelHandlerUnrollSpendInfo::from_serializable(
+                allocator,
+                &serializable.unroll,
+            )?,
+            countersigned: serializable.countersigned,
+        })
     }
 }
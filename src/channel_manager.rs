@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use log::debug;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::common::types::{AllocEncoder, Amount, CoinString, Error, Program, SpendBundle};
+use crate::peer_container::{GameCradle, SynchronousGameCradle, WatchReport};
+use crate::potato_handler::ToLocalUI;
+
+/// Names the opponent on the other end of one channel.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct PeerId(pub String);
+
+/// A channel as listed by ChannelManager::channels.
+#[derive(Debug, Clone)]
+pub struct ChannelSummary {
+    pub peer: PeerId,
+    pub handshake_finished: bool,
+    pub is_on_chain: bool,
+    pub amount: Amount,
+}
+
+/// Everything the channels asked for during ChannelManager::idle.
+#[derive(Default)]
+pub struct ChannelManagerIdleResult {
+    pub outbound_transactions: VecDeque<SpendBundle>,
+    /// Coins whose puzzle and solution are wanted, once each.  Answer with
+    /// ChannelManager::report_puzzle_and_solution.
+    pub coin_solution_requests: VecDeque<CoinString>,
+    /// Coins a channel started watching, for registering with the block feed.
+    pub coins_to_watch: VecDeque<CoinString>,
+    pub outbound_messages: VecDeque<(PeerId, Vec<u8>)>,
+    /// A channel failing doesn't stop the others from running.
+    pub errors: Vec<(PeerId, Error)>,
+}
+
+struct ManagedChannel<U: ToLocalUI> {
+    cradle: SynchronousGameCradle,
+    local_ui: U,
+}
+
+/// Owns the channels to many peers at once.  One block feed serves every
+/// channel and inbound messages are routed by peer id.  Each channel keeps its
+/// own ToLocalUI, which receives the callbacks for that channel only.
+pub struct ChannelManager<U: ToLocalUI> {
+    channels: BTreeMap<PeerId, ManagedChannel<U>>,
+    // Which channels asked for each coin's puzzle and solution.
+    solution_requests: HashMap<CoinString, BTreeSet<PeerId>>,
+}
+
+impl<U: ToLocalUI> Default for ChannelManager<U> {
+    fn default() -> Self {
+        ChannelManager {
+            channels: BTreeMap::default(),
+            solution_requests: HashMap::default(),
+        }
+    }
+}
+
+impl<U: ToLocalUI> ChannelManager<U> {
+    pub fn add_channel(
+        &mut self,
+        peer: PeerId,
+        cradle: SynchronousGameCradle,
+        local_ui: U,
+    ) -> Result<(), Error> {
+        if self.channels.contains_key(&peer) {
+            return Err(Error::StrErr(format!(
+                "already have a channel with {peer:?}"
+            )));
+        }
+
+        self.channels
+            .insert(peer, ManagedChannel { cradle, local_ui });
+        Ok(())
+    }
+
+    /// Drop a channel, returning its cradle and UI.  Shut it down first with
+    /// close_channel unless the channel is being abandoned.
+    pub fn remove_channel(&mut self, peer: &PeerId) -> Option<(SynchronousGameCradle, U)> {
+        for peers in self.solution_requests.values_mut() {
+            peers.remove(peer);
+        }
+        self.solution_requests.retain(|_, peers| !peers.is_empty());
+        self.channels
+            .remove(peer)
+            .map(|channel| (channel.cradle, channel.local_ui))
+    }
+
+    pub fn channels(&self) -> Vec<ChannelSummary> {
+        self.channels
+            .iter()
+            .map(|(peer, channel)| ChannelSummary {
+                peer: peer.clone(),
+                handshake_finished: channel.cradle.handshake_finished(),
+                is_on_chain: channel.cradle.is_on_chain(),
+                amount: channel.cradle.amount(),
+            })
+            .collect()
+    }
+
+    pub fn cradle(&self, peer: &PeerId) -> Option<&SynchronousGameCradle> {
+        self.channels.get(peer).map(|channel| &channel.cradle)
+    }
+
+    /// The cradle for a peer, for starting games, moving and so on.
+    pub fn cradle_mut(&mut self, peer: &PeerId) -> Option<&mut SynchronousGameCradle> {
+        self.channels
+            .get_mut(peer)
+            .map(|channel| &mut channel.cradle)
+    }
+
+    pub fn local_ui(&self, peer: &PeerId) -> Option<&U> {
+        self.channels.get(peer).map(|channel| &channel.local_ui)
+    }
+
+    pub fn local_ui_mut(&mut self, peer: &PeerId) -> Option<&mut U> {
+        self.channels
+            .get_mut(peer)
+            .map(|channel| &mut channel.local_ui)
+    }
+
+    fn channel_mut(&mut self, peer: &PeerId) -> Result<&mut ManagedChannel<U>, Error> {
+        self.channels
+            .get_mut(peer)
+            .ok_or_else(|| Error::StrErr(format!("no channel with {peer:?}")))
+    }
+
+    /// Start a clean shutdown of the channel with peer.  Its UI hears
+    /// shutdown_complete once the channel coin is spent.
+    pub fn close_channel<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        peer: &PeerId,
+    ) -> Result<(), Error> {
+        self.channel_mut(peer)?.cradle.shut_down(allocator, rng)
+    }
+
+    pub fn deliver_message(&mut self, peer: &PeerId, inbound_message: &[u8]) -> Result<(), Error> {
+        self.channel_mut(peer)?
+            .cradle
+            .deliver_message(inbound_message)
+    }
+
+    /// Every coin any channel is watching, once each, for registering with
+    /// the block feed.
+    pub fn watched_coins(&self) -> HashSet<CoinString> {
+        self.channels
+            .values()
+            .flat_map(|channel| channel.cradle.watched_coins().cloned())
+            .collect()
+    }
+
+    /// Give a block's watch report to every channel.  Each channel only acts
+    /// on the coins it watches.
+    pub fn new_block<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        height: usize,
+        report: &WatchReport,
+    ) -> Vec<(PeerId, Error)> {
+        let mut errors = Vec::new();
+        for (peer, channel) in self.channels.iter_mut() {
+            if let Err(e) = channel.cradle.new_block(allocator, rng, height, report) {
+                errors.push((peer.clone(), e));
+            }
+        }
+        errors
+    }
+
+    /// Answer a coin_solution_requests entry.  Only the channels that asked
+    /// for the coin are told.
+    pub fn report_puzzle_and_solution<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        coin_id: &CoinString,
+        puzzle_and_solution: Option<(&Program, &Program)>,
+    ) -> Vec<(PeerId, Error)> {
+        let mut errors = Vec::new();
+        let peers = self.solution_requests.remove(coin_id).unwrap_or_default();
+        for peer in peers.iter() {
+            if let Some(channel) = self.channels.get_mut(peer) {
+                if let Err(e) = channel.cradle.report_puzzle_and_solution(
+                    allocator,
+                    rng,
+                    coin_id,
+                    puzzle_and_solution,
+                ) {
+                    errors.push((peer.clone(), e));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Run every channel's idle until it has nothing more to do, collecting
+    /// what they produced.
+    pub fn idle<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+    ) -> ChannelManagerIdleResult {
+        let mut result = ChannelManagerIdleResult::default();
+        for (peer, channel) in self.channels.iter_mut() {
+            loop {
+                let idle_result = match channel.cradle.idle(allocator, rng, &mut channel.local_ui) {
+                    Ok(idle_result) => idle_result,
+                    Err(e) => {
+                        result.errors.push((peer.clone(), e));
+                        break;
+                    }
+                };
+
+                result
+                    .outbound_transactions
+                    .extend(idle_result.outbound_transactions);
+                for coin in idle_result.coin_solution_requests {
+                    let peers = self.solution_requests.entry(coin.clone()).or_default();
+                    if peers.is_empty() {
+                        result.coin_solution_requests.push_back(coin);
+                    }
+                    peers.insert(peer.clone());
+                }
+                result.coins_to_watch.extend(idle_result.coins_to_watch);
+                result.outbound_messages.extend(
+                    idle_result
+                        .outbound_messages
+                        .into_iter()
+                        .map(|msg| (peer.clone(), msg)),
+                );
+                if let Some(e) = idle_result.receive_error {
+                    debug!("channel {peer:?} receive error {e:?}");
+                    result.errors.push((peer.clone(), e));
+                }
+
+                if !idle_result.continue_on {
+                    break;
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,429 @@
+use std::sync::mpsc as std_mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+
+use chia_gaming::common::standard_coin::ChiaIdentity;
+use chia_gaming::common::types::{
+    AllocEncoder, Amount, CoinSpend, CoinString, Error, IntoErr, PrivateKey, Program, PuzzleHash,
+};
+use chia_gaming::simulator::Simulator;
+
+pub const DEFAULT_CHAIN_ADDR: &str = "127.0.0.1:5810";
+
+// How long a client waits before trying the block source again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// One line of json sent to the block source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum ChainRequest {
+    /// The current height and every unspent coin.
+    Block,
+    /// Pay amount to puzzle_hash from the block source's own coins.  A block
+    /// is farmed so the coin exists when the reply arrives.
+    Fund {
+        puzzle_hash: PuzzleHash,
+        amount: Amount,
+    },
+    PushTx {
+        spends: Vec<CoinSpend>,
+    },
+    PuzzleAndSolution {
+        coin: CoinString,
+    },
+}
+
+/// The block source's answer, also one line of json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum ChainReply {
+    Block {
+        height: usize,
+        coins: Vec<CoinString>,
+    },
+    Funded {
+        coin: CoinString,
+    },
+    Included {
+        code: u32,
+    },
+    PuzzleAndSolution {
+        puzzle_and_solution: Option<(Program, Program)>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+type PendingRequest = (ChainRequest, oneshot::Sender<ChainReply>);
+
+/// The inclusion code for a transaction the chain refused, numbered as chia
+/// does.
+pub const INCLUSION_FAILED: u32 = 3;
+
+fn simulator_error<E: std::fmt::Debug>(e: E) -> Error {
+    Error::StrErr(format!("simulator: {e:?}"))
+}
+
+fn chain_stopped() -> Error {
+    Error::StrErr("block source has stopped".to_string())
+}
+
+// The simulator and the coins it hands out.  Lives on its own thread, since
+// every connection shares it.
+struct ChainState {
+    allocator: AllocEncoder,
+    simulator: Simulator,
+    identity: ChiaIdentity,
+}
+
+impl ChainState {
+    fn new() -> Result<Self, Error> {
+        let mut allocator = AllocEncoder::new();
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        let pk: PrivateKey = rng.gen();
+        let identity = ChiaIdentity::new(&mut allocator, pk)?;
+        let simulator = Simulator::default();
+        simulator.farm_block(&identity.puzzle_hash);
+        Ok(ChainState {
+            allocator,
+            simulator,
+            identity,
+        })
+    }
+
+    fn farm(&self) {
+        self.simulator.farm_block(&self.identity.puzzle_hash);
+    }
+
+    fn fund(&mut self, puzzle_hash: PuzzleHash, amount: Amount) -> Result<CoinString, Error> {
+        let coins = self
+            .simulator
+            .get_my_coins(&self.identity.puzzle_hash)
+            .map_err(simulator_error)?;
+        let (source, source_amount) = coins
+            .iter()
+            .filter_map(|c| c.to_parts().map(|(_, _, amt)| (c.clone(), amt)))
+            .find(|(_, amt)| *amt > amount)
+            .ok_or_else(|| Error::StrErr(format!("no coin to fund {amount:?} from")))?;
+        let created = self.simulator.spend_coin_to_puzzle_hash(
+            &mut self.allocator,
+            &self.identity,
+            &self.identity.puzzle,
+            &source,
+            &[
+                (puzzle_hash, amount.clone()),
+                (self.identity.puzzle_hash.clone(), source_amount - amount),
+            ],
+        )?;
+        self.farm();
+        Ok(created[0].clone())
+    }
+
+    fn handle(&mut self, request: ChainRequest) -> Result<ChainReply, Error> {
+        match request {
+            ChainRequest::Block => Ok(ChainReply::Block {
+                height: self.simulator.get_current_height(),
+                coins: self.simulator.get_all_coins().map_err(simulator_error)?,
+            }),
+            ChainRequest::Fund {
+                puzzle_hash,
+                amount,
+            } => Ok(ChainReply::Funded {
+                coin: self.fund(puzzle_hash, amount)?,
+            }),
+            ChainRequest::PushTx { spends } => {
+                let included = self
+                    .simulator
+                    .push_tx(&mut self.allocator, &spends)
+                    .map_err(simulator_error)?;
+                Ok(ChainReply::Included {
+                    code: included.code,
+                })
+            }
+            ChainRequest::PuzzleAndSolution { coin } => Ok(ChainReply::PuzzleAndSolution {
+                puzzle_and_solution: self
+                    .simulator
+                    .get_puzzle_and_solution(&coin)
+                    .map_err(simulator_error)?,
+            }),
+        }
+    }
+}
+
+// Answer requests as they come, farming a block whenever block_interval
+// passes.
+fn run_chain(requests: std_mpsc::Receiver<PendingRequest>, block_interval: Duration) {
+    let mut chain = match ChainState::new() {
+        Ok(chain) => chain,
+        Err(e) => {
+            error!("could not start the simulator: {e:?}");
+            return;
+        }
+    };
+
+    let mut next_block = Instant::now() + block_interval;
+    loop {
+        let wait = next_block.saturating_duration_since(Instant::now());
+        match requests.recv_timeout(wait) {
+            Ok((request, reply)) => {
+                debug!("chain request {request:?}");
+                let result = chain.handle(request).unwrap_or_else(|e| ChainReply::Error {
+                    error: format!("{e:?}"),
+                });
+                // The connection may have gone away meanwhile.
+                let _ = reply.send(result);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                chain.farm();
+                next_block = Instant::now() + block_interval;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return;
+            }
+        }
+    }
+}
+
+async fn write_line<T: Serialize>(writer: &mut OwnedWriteHalf, value: &T) -> Result<(), Error> {
+    let mut line = serde_json::to_string(value).into_gen()?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .await
+        .map_err(Error::IoErr)
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    requests: std_mpsc::Sender<PendingRequest>,
+) -> Result<(), Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await.map_err(Error::IoErr)? {
+        let reply = match serde_json::from_str::<ChainRequest>(&line) {
+            Ok(request) => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                requests
+                    .send((request, reply_tx))
+                    .map_err(|_| chain_stopped())?;
+                reply_rx.await.map_err(|_| chain_stopped())?
+            }
+            Err(e) => ChainReply::Error {
+                error: format!("{e:?}"),
+            },
+        };
+        write_line(&mut writer, &reply).await?;
+    }
+    Ok(())
+}
+
+/// Serve one simulator to every process that connects, farming a block each
+/// block_interval.
+pub async fn serve_chain(addr: String, block_interval: Duration) -> Result<(), Error> {
+    let listener = TcpListener::bind(&addr).await.map_err(Error::IoErr)?;
+    let (request_tx, request_rx) = std_mpsc::channel();
+    thread::Builder::new()
+        .name("chain".to_string())
+        .spawn(move || run_chain(request_rx, block_interval))
+        .map_err(Error::IoErr)?;
+
+    info!("block source on {addr}");
+    loop {
+        let (stream, from) = listener.accept().await.map_err(Error::IoErr)?;
+        let requests = request_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, requests).await {
+                debug!("block source connection from {from}: {e:?}");
+            }
+        });
+    }
+}
+
+/// A connection to serve_chain.  If the connection drops, the request is
+/// sent again once it's back.
+pub struct ChainClient {
+    addr: String,
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+fn unexpected_reply(reply: ChainReply) -> Error {
+    Error::StrErr(format!("unexpected reply from the block source: {reply:?}"))
+}
+
+impl ChainClient {
+    /// Connect, waiting for the block source to come up if it isn't yet.
+    pub async fn connect(addr: &str) -> Result<ChainClient, Error> {
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    let (reader, writer) = stream.into_split();
+                    return Ok(ChainClient {
+                        addr: addr.to_string(),
+                        lines: BufReader::new(reader).lines(),
+                        writer,
+                    });
+                }
+                Err(e) => {
+                    debug!("waiting for the block source at {addr}: {e:?}");
+                    sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+
+    // Send request and read the reply, or None if the connection broke.
+    async fn exchange(&mut self, request: &ChainRequest) -> Result<Option<String>, Error> {
+        match write_line(&mut self.writer, request).await {
+            Ok(()) => {}
+            Err(Error::IoErr(e)) => {
+                debug!("block source write failed: {e:?}");
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+        match self.lines.next_line().await {
+            Ok(line) => Ok(line),
+            Err(e) => {
+                debug!("block source read failed: {e:?}");
+                Ok(None)
+            }
+        }
+    }
+
+    async fn call(&mut self, request: &ChainRequest) -> Result<ChainReply, Error> {
+        let line = loop {
+            if let Some(line) = self.exchange(request).await? {
+                break line;
+            }
+            warn!("lost the block source at {}, reconnecting", self.addr);
+            let addr = self.addr.clone();
+            *self = ChainClient::connect(&addr).await?;
+        };
+        match serde_json::from_str(&line).into_gen()? {
+            ChainReply::Error { error } => Err(Error::StrErr(error)),
+            reply => Ok(reply),
+        }
+    }
+
+    pub async fn block(&mut self) -> Result<(usize, Vec<CoinString>), Error> {
+        match self.call(&ChainRequest::Block).await? {
+            ChainReply::Block { height, coins } => Ok((height, coins)),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    pub async fn fund(
+        &mut self,
+        puzzle_hash: &PuzzleHash,
+        amount: Amount,
+    ) -> Result<CoinString, Error> {
+        let request = ChainRequest::Fund {
+            puzzle_hash: puzzle_hash.clone(),
+            amount,
+        };
+        match self.call(&request).await? {
+            ChainReply::Funded { coin } => Ok(coin),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    /// Returns the simulator's inclusion code.
+    pub async fn push_tx(&mut self, spends: Vec<CoinSpend>) -> Result<u32, Error> {
+        match self.call(&ChainRequest::PushTx { spends }).await? {
+            ChainReply::Included { code } => Ok(code),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+
+    pub async fn puzzle_and_solution(
+        &mut self,
+        coin: &CoinString,
+    ) -> Result<Option<(Program, Program)>, Error> {
+        let request = ChainRequest::PuzzleAndSolution { coin: coin.clone() };
+        match self.call(&request).await? {
+            ChainReply::PuzzleAndSolution {
+                puzzle_and_solution,
+            } => Ok(puzzle_and_solution),
+            reply => Err(unexpected_reply(reply)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chia_gaming::common::types::{CoinID, Hash};
+
+    // Serialize, read back and serialize again, which must agree.
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> String {
+        let json = serde_json::to_string(value).expect("should serialize");
+        let back: T = serde_json::from_str(&json).expect("should deserialize");
+        assert_eq!(
+            serde_json::to_string(&back).expect("should serialize"),
+            json
+        );
+        json
+    }
+
+    fn test_coin() -> CoinString {
+        CoinString::from_parts(
+            &CoinID::new(Hash::from_bytes([1; 32])),
+            &PuzzleHash::from_bytes([2; 32]),
+            &Amount::new(100),
+        )
+    }
+
+    #[test]
+    fn test_chain_requests_round_trip() {
+        assert_eq!(round_trip(&ChainRequest::Block), r#"{"request":"block"}"#);
+        round_trip(&ChainRequest::Fund {
+            puzzle_hash: PuzzleHash::from_bytes([3; 32]),
+            amount: Amount::new(200),
+        });
+        round_trip(&ChainRequest::PushTx { spends: vec![] });
+        round_trip(&ChainRequest::PuzzleAndSolution { coin: test_coin() });
+    }
+
+    #[test]
+    fn test_chain_replies_round_trip() {
+        round_trip(&ChainReply::Block {
+            height: 7,
+            coins: vec![test_coin()],
+        });
+        round_trip(&ChainReply::Funded { coin: test_coin() });
+        round_trip(&ChainReply::Included { code: 1 });
+        round_trip(&ChainReply::PuzzleAndSolution {
+            puzzle_and_solution: Some((Program::from_bytes(&[0x80]), Program::from_bytes(&[0x01]))),
+        });
+        round_trip(&ChainReply::PuzzleAndSolution {
+            puzzle_and_solution: None,
+        });
+        let json = round_trip(&ChainReply::Error {
+            error: "no coin".to_string(),
+        });
+        assert_eq!(json, r#"{"reply":"error","error":"no coin"}"#);
+    }
+
+    #[test]
+    fn test_chain_rejects_unknown_requests() {
+        assert!(serde_json::from_str::<ChainRequest>(r#"{"request":"mint"}"#).is_err());
+    }
+}
@@ -4,9 +4,11 @@ use std::ffi::OsString;
 use std::fs;
 use std::io::stdin;
 use std::mem::swap;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Mutex;
+use std::time::Duration;
 
 use clvm_tools_rs::classic::clvm_tools::binutils::disassemble;
 use clvm_traits::{ClvmEncoder, ToClvm};
@@ -14,7 +16,7 @@ use clvmr::allocator::SExp;
 use clvmr::serde::node_to_bytes;
 
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, error};
 
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
@@ -22,8 +24,10 @@ use rand_chacha::ChaCha8Rng;
 use salvo::http::ResBody;
 use salvo::hyper::body::Bytes;
 use salvo::prelude::*;
+use salvo::websocket::{Message, WebSocketUpgrade};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use tokio::sync::broadcast;
 
 use chia_gaming::channel_handler::types::ReadableMove;
 use chia_gaming::common::standard_coin::ChiaIdentity;
@@ -38,15 +42,46 @@ use chia_gaming::games::poker_collection;
 use chia_gaming::peer_container::{
     FullCoinSetAdapter, GameCradle, SynchronousGameCradle, SynchronousGameCradleConfig,
 };
-use chia_gaming::potato_handler::{GameStart, GameType, ToLocalUI};
+use chia_gaming::potato_handler::{
+    GameStart, GameType, GoingOnChainReason, LivenessPolicy, ToLocalUI,
+};
 use chia_gaming::simulator::Simulator;
 
+/// A block source several processes can share.
+mod chain;
+/// One player of a game run across two processes.
+mod peer;
+
+use chain::{serve_chain, DEFAULT_CHAIN_ADDR};
+use peer::{run_peer, PeerConfig, DEFAULT_PEER_ADDR};
+
+// Pushed to a player's websocket as soon as GameRunner::idle sees it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PlayerEvent {
+    SelfMove { game_id: String, readable: String },
+    OpponentMoved { game_id: String, readable: String },
+    GameMessage { game_id: String, readable: String },
+    GameFinished { game_id: String, my_share: u64 },
+    GameCancelled { game_id: String },
+    ShutdownComplete { reward_coin: String },
+    GoingOnChain { reason: GoingOnChainReason },
+    OnChain,
+    BlockHeight { height: usize },
+}
+
+fn readable_hex(allocator: &mut AllocEncoder, readable: &ReadableMove) -> Result<String, Error> {
+    let bytes = node_to_bytes(allocator.allocator(), readable.to_nodeptr()).into_gen()?;
+    Ok(hex::encode(bytes))
+}
+
 #[derive(Debug)]
 struct UIReceiver {
     received_moves: usize,
     our_readable_move: Vec<u8>,
     remote_message: ReadableMove,
     opponent_readable_move: ReadableMove,
+    events: Vec<PlayerEvent>,
 }
 
 impl UIReceiver {
@@ -57,52 +92,78 @@ impl UIReceiver {
             our_readable_move: Vec::default(),
             remote_message: nil_readable.clone(),
             opponent_readable_move: nil_readable,
+            events: Vec::default(),
         }
     }
 }
 
 impl ToLocalUI for UIReceiver {
-    fn self_move(&mut self, _id: &GameID, readable: &[u8]) -> Result<(), Error> {
+    fn self_move(&mut self, id: &GameID, readable: &[u8]) -> Result<(), Error> {
         self.our_readable_move = readable.to_vec();
+        self.events.push(PlayerEvent::SelfMove {
+            game_id: hex::encode(id.to_bytes()),
+            readable: hex::encode(readable),
+        });
         Ok(())
     }
 
     fn opponent_moved(
         &mut self,
-        _allocator: &mut AllocEncoder,
-        _id: &GameID,
+        allocator: &mut AllocEncoder,
+        id: &GameID,
         readable: ReadableMove,
     ) -> Result<(), Error> {
         self.received_moves += 1;
         self.our_readable_move = Vec::default();
+        self.events.push(PlayerEvent::OpponentMoved {
+            game_id: hex::encode(id.to_bytes()),
+            readable: readable_hex(allocator, &readable)?,
+        });
         self.opponent_readable_move = readable;
         Ok(())
     }
 
     fn game_message(
         &mut self,
-        _allocator: &mut AllocEncoder,
-        _id: &GameID,
+        allocator: &mut AllocEncoder,
+        id: &GameID,
         readable: ReadableMove,
     ) -> Result<(), Error> {
+        self.events.push(PlayerEvent::GameMessage {
+            game_id: hex::encode(id.to_bytes()),
+            readable: readable_hex(allocator, &readable)?,
+        });
         self.remote_message = readable;
         Ok(())
     }
 
-    fn game_finished(&mut self, _id: &GameID, _my_share: Amount) -> Result<(), Error> {
+    fn game_finished(&mut self, id: &GameID, my_share: Amount) -> Result<(), Error> {
+        self.events.push(PlayerEvent::GameFinished {
+            game_id: hex::encode(id.to_bytes()),
+            my_share: my_share.to_u64(),
+        });
         Ok(())
     }
 
-    fn game_cancelled(&mut self, _id: &GameID) -> Result<(), Error> {
-        todo!();
+    fn game_cancelled(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events.push(PlayerEvent::GameCancelled {
+            game_id: hex::encode(id.to_bytes()),
+        });
+        Ok(())
     }
 
-    fn shutdown_complete(&mut self, _reward_coin_string: &CoinString) -> Result<(), Error> {
-        todo!();
+    fn shutdown_complete(&mut self, reward_coin_string: &CoinString) -> Result<(), Error> {
+        self.events.push(PlayerEvent::ShutdownComplete {
+            reward_coin: hex::encode(reward_coin_string.to_bytes()),
+        });
+        Ok(())
     }
 
-    fn going_on_chain(&mut self) -> Result<(), Error> {
-        todo!();
+    fn going_on_chain(&mut self, reason: &GoingOnChainReason) -> Result<(), Error> {
+        self.events.push(PlayerEvent::GoingOnChain {
+            reason: reason.clone(),
+        });
+        Ok(())
     }
 }
 
@@ -190,6 +251,7 @@ pub struct PerPlayerInfo {
     num_incoming_actions: usize,
     game_outcome: CalpokerResult,
     known_cards: (Vec<Card>, Vec<Card>),
+    reported_on_chain: bool,
 }
 
 struct ReleaseObject<'a, T: Clone> {
@@ -264,6 +326,20 @@ impl PerPlayerInfo {
             incoming_actions: VecDeque::default(),
             allow_remote_message,
             num_incoming_actions: 0,
+            reported_on_chain: false,
+        }
+    }
+
+    // Send everything the cradle reported since the last call to whoever is
+    // listening on this player's websocket.
+    fn publish_events(&mut self) {
+        if !self.reported_on_chain && self.cradle.is_on_chain() {
+            self.reported_on_chain = true;
+            self.local_ui.events.push(PlayerEvent::OnChain);
+        }
+
+        for event in self.local_ui.events.drain(..) {
+            publish_event(self.player_id, &event);
         }
     }
 
@@ -491,6 +567,7 @@ struct GameRunner {
     funded: bool,
 
     tick_count: usize,
+    reported_height: Option<usize>,
 
     auto: bool,
 }
@@ -526,6 +603,49 @@ enum WebRequest {
     AllowMessage,
 }
 
+// Commands a player can send over their websocket instead of posting to the
+// matching http endpoint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum SocketCommand {
+    Idle,
+    Reset,
+    Player,
+    WordHash { word: String },
+    Picks { picks: Vec<bool> },
+    Finish,
+    SetAuto { auto: bool },
+    AllowMessage,
+}
+
+impl SocketCommand {
+    fn into_request(self, player_id: bool) -> WebRequest {
+        match self {
+            SocketCommand::Idle => WebRequest::Idle,
+            SocketCommand::Reset => WebRequest::Reset,
+            SocketCommand::Player => WebRequest::Player(player_id),
+            SocketCommand::WordHash { word } => {
+                let hash = Sha256Input::Bytes(word.as_bytes()).hash();
+                WebRequest::WordHash(player_id, hash.bytes().to_vec())
+            }
+            SocketCommand::Picks { picks } => WebRequest::Picks(player_id, picks),
+            SocketCommand::Finish => WebRequest::FinishMove(player_id),
+            SocketCommand::SetAuto { auto } => WebRequest::SetAuto(auto),
+            SocketCommand::AllowMessage => WebRequest::AllowMessage,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SocketReply {
+    Reply { result: Value },
+    Error { error: String },
+}
+
+// Events a slow socket can fall behind by before it starts missing them.
+const EVENT_BACKLOG: usize = 256;
+
 type StringWithError = Result<String, Error>;
 
 lazy_static! {
@@ -543,6 +663,17 @@ lazy_static! {
         let (tx, rx) = mpsc::channel();
         (tx.into(), rx.into())
     };
+    static ref PLAYER_EVENTS: [broadcast::Sender<String>; 2] = [
+        broadcast::channel(EVENT_BACKLOG).0,
+        broadcast::channel(EVENT_BACKLOG).0
+    ];
+}
+
+fn publish_event(player_id: bool, event: &PlayerEvent) {
+    if let Ok(json) = serde_json::to_string(event) {
+        // Nobody listening isn't an error; the http endpoints still work.
+        PLAYER_EVENTS[player_id as usize].send(json).ok();
+    }
 }
 
 #[derive(Serialize)]
@@ -600,6 +731,8 @@ impl GameRunner {
                 channel_timeout: Timeout::new(100),
                 reward_puzzle_hash: id1.puzzle_hash.clone(),
                 unroll_timeout: Timeout::new(5),
+                liveness: LivenessPolicy::default(),
+                validate_outbound: false,
             },
         );
         let cradle2 = SynchronousGameCradle::new(
@@ -613,6 +746,8 @@ impl GameRunner {
                 channel_timeout: Timeout::new(100),
                 reward_puzzle_hash: id2.puzzle_hash.clone(),
                 unroll_timeout: Timeout::new(5),
+                liveness: LivenessPolicy::default(),
+                validate_outbound: false,
             },
         );
         let game_ids = Vec::default();
@@ -649,6 +784,7 @@ impl GameRunner {
             funded: false,
             auto: false,
             tick_count: 0,
+            reported_height: None,
             player_info: [player1, player2],
         })
     }
@@ -723,6 +859,18 @@ impl GameRunner {
         }
 
         let current_height = self.simulator.get_current_height();
+        if self.reported_height != Some(current_height) {
+            self.reported_height = Some(current_height);
+            for player_id in [false, true] {
+                publish_event(
+                    player_id,
+                    &PlayerEvent::BlockHeight {
+                        height: current_height,
+                    },
+                );
+            }
+        }
+
         let current_coins = self.simulator.get_all_coins().expect("should work");
         let watch_report = self
             .coinset_adapter
@@ -772,6 +920,8 @@ impl GameRunner {
                     break;
                 }
             }
+
+            self.player_info[i].publish_events();
         }
 
         if !self.funded {
@@ -895,7 +1045,9 @@ fn pass_on_request(wr: WebRequest) -> Result<String, Error> {
     }
 
     let from_web = FROM_WEB.1.lock().unwrap();
-    let result = (*from_web).recv().unwrap();
+    let result = (*from_web)
+        .recv()
+        .map_err(|_| Error::StrErr("game runner has stopped".to_string()))?;
     drop(locked);
 
     result
@@ -982,6 +1134,78 @@ fn allow_message(_req: &mut Request) -> Result<String, String> {
     pass_on_request(WebRequest::AllowMessage).report_err()
 }
 
+// Requests wait on the game runner, so they're kept off the socket's task.
+async fn socket_reply(text: &str, player_id: bool) -> String {
+    let reply = match serde_json::from_str::<SocketCommand>(text) {
+        Ok(command) => match tokio::task::spawn_blocking(move || {
+            pass_on_request(command.into_request(player_id))
+        })
+        .await
+        .unwrap_or_else(|e| Err(Error::StrErr(format!("request failed: {e:?}"))))
+        {
+            Ok(result) => SocketReply::Reply {
+                result: serde_json::from_str(&result).unwrap_or(Value::String(result)),
+            },
+            Err(e) => SocketReply::Error {
+                error: format!("{e:?}"),
+            },
+        },
+        Err(e) => SocketReply::Error {
+            error: format!("{e:?}"),
+        },
+    };
+    serde_json::to_string(&reply).unwrap_or_else(|_| "\"bad json conversion\"".to_string())
+}
+
+// Pushes the player's cradle events as they happen and answers commands sent
+// on the same socket.
+#[handler]
+async fn player_ws(req: &mut Request, res: &mut Response) -> Result<(), StatusError> {
+    let player_id = match req.param::<String>("id").as_deref() {
+        Some("1") => false,
+        Some("2") => true,
+        _ => return Err(StatusError::not_found()),
+    };
+    let mut events = PLAYER_EVENTS[player_id as usize].subscribe();
+    WebSocketUpgrade::new()
+        .upgrade(req, res, move |mut ws| async move {
+            loop {
+                tokio::select! {
+                    msg = ws.recv() => {
+                        let msg = if let Some(Ok(msg)) = msg {
+                            msg
+                        } else {
+                            return;
+                        };
+                        if msg.is_close() {
+                            return;
+                        }
+                        if let Ok(text) = msg.to_str() {
+                            let reply = socket_reply(text, player_id).await;
+                            if ws.send(Message::text(reply)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    event = events.recv() => {
+                        match event {
+                            Ok(json) => {
+                                if ws.send(Message::text(json)).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Err(broadcast::error::RecvError::Lagged(_)) => {}
+                            Err(broadcast::error::RecvError::Closed) => {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+        })
+        .await
+}
+
 fn reset_sim(sim: &mut GameRunner, auto: bool) -> Result<String, Error> {
     let empty_simulator = Simulator::default();
     let empty_coinset_adapter = FullCoinSetAdapter::default();
@@ -1000,6 +1224,80 @@ fn detect_run_as_python(args: &[String]) -> bool {
     args.iter().any(|x: &String| x == "-c")
 }
 
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|a| a.as_str())
+}
+
+fn arg_millis(args: &[String], name: &str, default: u64) -> Duration {
+    Duration::from_millis(
+        arg_value(args, name)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default),
+    )
+}
+
+// chia-gaming chain [--listen addr] [--block-ms n]
+//
+// chia-gaming peer --player 1|2 [--peer addr] [--chain addr] [--serve-chain]
+//     [--state file] [--delay-ms n] [--poll-ms n] [--block-ms n]
+//
+// Player 1 listens on the peer address and player 2 connects to it.  Both use
+// the block source at the chain address, which either runs as its own process
+// or inside player 1 with --serve-chain.
+fn run_separate_process(
+    rt: &tokio::runtime::Runtime,
+    args: &[String],
+) -> Option<Result<(), Error>> {
+    let chain_addr = arg_value(args, "--chain")
+        .unwrap_or(DEFAULT_CHAIN_ADDR)
+        .to_string();
+    let block_interval = arg_millis(args, "--block-ms", 2000);
+    match args.get(1).map(|a| a.as_str()) {
+        Some("chain") => {
+            let listen = arg_value(args, "--listen")
+                .unwrap_or(DEFAULT_CHAIN_ADDR)
+                .to_string();
+            Some(rt.block_on(serve_chain(listen, block_interval)))
+        }
+        Some("peer") => {
+            let player = match arg_value(args, "--player") {
+                Some("1") => 1,
+                Some("2") => 2,
+                other => {
+                    return Some(Err(Error::StrErr(format!(
+                        "--player must be 1 or 2, not {other:?}"
+                    ))));
+                }
+            };
+            let config = PeerConfig {
+                player,
+                peer_addr: arg_value(args, "--peer")
+                    .unwrap_or(DEFAULT_PEER_ADDR)
+                    .to_string(),
+                chain_addr: chain_addr.clone(),
+                state_file: arg_value(args, "--state").map(PathBuf::from),
+                delay: arg_millis(args, "--delay-ms", 0),
+                poll_interval: arg_millis(args, "--poll-ms", 250),
+            };
+            let serve = args.iter().any(|a| a == "--serve-chain");
+            Some(rt.block_on(async move {
+                if serve {
+                    tokio::spawn(async move {
+                        if let Err(e) = serve_chain(chain_addr, block_interval).await {
+                            error!("block source failed: {e:?}");
+                        }
+                    });
+                }
+                run_peer(config).await
+            }))
+        }
+        _ => None,
+    }
+}
+
 fn main() {
     let args = std::env::args();
     let args_vec: Vec<String> = args.collect();
@@ -1023,6 +1321,14 @@ fn main() {
     }
     let rt = tokio::runtime::Runtime::new().unwrap();
 
+    if let Some(result) = run_separate_process(&rt, &args_vec) {
+        if let Err(e) = result {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     rt.block_on(async {
         let mut auto = args_vec.iter().any(|x| x == "auto");
 
@@ -1040,6 +1346,7 @@ fn main() {
             .push(Router::with_path("picks").post(do_picks))
             .push(Router::with_path("set_auto").post(set_auto))
             .push(Router::with_path("allow_message").post(allow_message))
+            .push(Router::with_path("ws/<id>").goal(player_ws))
             .push(Router::with_path("finish").post(finish));
         let acceptor = TcpListener::new("127.0.0.1:5800").bind().await;
 
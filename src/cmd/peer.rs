@@ -0,0 +1,624 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+
+use rand::seq::index::sample;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{interval, sleep, sleep_until, Instant};
+
+use chia_gaming::async_cradle::{AsyncGameCradle, AsyncGameCradleConfig, PeerSink};
+use chia_gaming::common::standard_coin::ChiaIdentity;
+use chia_gaming::common::types::{
+    AllocEncoder, Amount, CoinString, Error, GameID, Hash, IntoErr, PrivateKey, Sha256Input,
+    Timeout,
+};
+use chia_gaming::games::calpoker::{encode_calpoker_commit, encode_calpoker_picks};
+use chia_gaming::games::poker_collection;
+use chia_gaming::peer_container::{CradleEvent, FullCoinSetAdapter};
+use chia_gaming::potato_handler::{GameStart, GameType, LivenessPolicy};
+
+use crate::chain::{ChainClient, INCLUSION_FAILED};
+
+pub const DEFAULT_PEER_ADDR: &str = "127.0.0.1:5811";
+
+// How long player 2 waits before dialing player 1 again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// Anything bigger than this on the wire means the stream is out of step.
+const MAX_FRAME: u32 = 16 * 1024 * 1024;
+
+/// One player of a two process game.
+pub struct PeerConfig {
+    /// 1 plays alice and listens for the other player, 2 plays bob and
+    /// connects to it.
+    pub player: usize,
+    pub peer_addr: String,
+    pub chain_addr: String,
+    /// Where to keep our state between runs.  Without it a restarted player
+    /// starts a new channel.
+    pub state_file: Option<PathBuf>,
+    /// Held back from each message we send, to mimic a slow network.
+    pub delay: Duration,
+    /// How often to ask the block source for a new block.
+    pub poll_interval: Duration,
+}
+
+// How far through the scripted calpoker game we are.
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct PeerProgress {
+    fund_coin: Option<CoinString>,
+    funded: bool,
+    game_ids: Vec<GameID>,
+    moves_made: usize,
+    moves_received: usize,
+    accepted: bool,
+    finished: bool,
+}
+
+// What's written to the state file.  The cradle is its snapshot bytes.
+#[derive(Serialize, Deserialize)]
+struct PeerSave {
+    cradle: Vec<u8>,
+    progress: PeerProgress,
+}
+
+fn load_state(path: &Option<PathBuf>) -> Result<Option<PeerSave>, Error> {
+    let path = match path {
+        Some(path) if path.exists() => path,
+        _ => {
+            return Ok(None);
+        }
+    };
+    let data = fs::read(path).map_err(Error::IoErr)?;
+    let doc = bson::Document::from_reader(&mut &data[..]).into_gen()?;
+    bson::from_bson(bson::Bson::Document(doc))
+        .into_gen()
+        .map(Some)
+}
+
+// The same player number always gets the same identity, so a restarted
+// process still owns its coins.
+fn player_identity(allocator: &mut AllocEncoder, player: usize) -> Result<ChiaIdentity, Error> {
+    let mut rng = ChaCha8Rng::from_seed([player as u8; 32]);
+    let pk: PrivateKey = rng.gen();
+    ChiaIdentity::new(allocator, pk)
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let len = reader.read_u32().await.map_err(Error::IoErr)?;
+    if len > MAX_FRAME {
+        return Err(Error::StrErr(format!("peer sent a {len} byte frame")));
+    }
+    let mut msg = vec![0; len as usize];
+    reader.read_exact(&mut msg).await.map_err(Error::IoErr)?;
+    Ok(msg)
+}
+
+async fn read_frames(mut reader: OwnedReadHalf, sink: PeerSink) -> Result<(), Error> {
+    loop {
+        let msg = read_frame(&mut reader).await?;
+        sink.deliver(msg).await?;
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, msg: &[u8]) -> Result<(), Error> {
+    let len = match u32::try_from(msg.len()) {
+        Ok(len) if len <= MAX_FRAME => len,
+        _ => {
+            return Err(Error::StrErr(format!(
+                "can't send a {} byte frame",
+                msg.len()
+            )));
+        }
+    };
+    writer.write_u32(len).await.map_err(Error::IoErr)?;
+    writer.write_all(msg).await.map_err(Error::IoErr)
+}
+
+// Keep a connection to the other player, reconnecting whenever it drops.
+// Every new connection is reported on connected so the cradle can resume;
+// whatever failed to send is retransmitted then.
+async fn run_link(
+    listen: bool,
+    addr: String,
+    delay: Duration,
+    mut outbound: mpsc::UnboundedReceiver<(Instant, Vec<u8>)>,
+    sink: PeerSink,
+    connected: mpsc::UnboundedSender<()>,
+) -> Result<(), Error> {
+    let listener = if listen {
+        Some(TcpListener::bind(&addr).await.map_err(Error::IoErr)?)
+    } else {
+        None
+    };
+
+    loop {
+        let stream = if let Some(listener) = &listener {
+            listener.accept().await.map_err(Error::IoErr)?.0
+        } else {
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("connect to {addr}: {e:?}");
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            }
+        };
+        stream.set_nodelay(true).map_err(Error::IoErr)?;
+        info!("connected to the other player via {addr}");
+        if connected.send(()).is_err() {
+            return Ok(());
+        }
+
+        let (reader, mut writer) = stream.into_split();
+        let mut reading = tokio::spawn(read_frames(reader, sink.clone()));
+        loop {
+            tokio::select! {
+                result = &mut reading => {
+                    debug!("peer reader stopped: {result:?}");
+                    break;
+                }
+                msg = outbound.recv() => {
+                    let (queued, msg) = if let Some(msg) = msg {
+                        msg
+                    } else {
+                        reading.abort();
+                        return Ok(());
+                    };
+                    sleep_until(queued + delay).await;
+                    if let Err(e) = write_frame(&mut writer, &msg).await {
+                        debug!("peer write failed: {e:?}");
+                        break;
+                    }
+                }
+            }
+        }
+        reading.abort();
+        warn!("lost the connection to the other player");
+    }
+}
+
+struct PeerPlayer {
+    player: usize,
+    alice: bool,
+    cradle: AsyncGameCradle,
+    chain: ChainClient,
+    coin_set: FullCoinSetAdapter,
+    allocator: AllocEncoder,
+    rng: ChaCha8Rng,
+    identity: ChiaIdentity,
+    last_height: Option<usize>,
+    state_file: Option<PathBuf>,
+    link: mpsc::UnboundedSender<(Instant, Vec<u8>)>,
+    progress: PeerProgress,
+}
+
+impl PeerPlayer {
+    async fn save(&mut self) -> Result<(), Error> {
+        let path = if let Some(path) = &self.state_file {
+            path.clone()
+        } else {
+            return Ok(());
+        };
+        let save = PeerSave {
+            cradle: self.cradle.snapshot().await?,
+            progress: self.progress.clone(),
+        };
+        let bson_doc = bson::to_bson(&save).map_err(|e| Error::StrErr(format!("{e:?}")))?;
+        let data = bson::to_vec(&bson_doc).map_err(|e| Error::StrErr(format!("{e:?}")))?;
+        // Replace the old state in one step so a kill mid-write can't lose it.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data).map_err(Error::IoErr)?;
+        fs::rename(&tmp, &path).map_err(Error::IoErr)
+    }
+
+    // Save before the message leaves, so the other player never sees
+    // anything we'd forget by restarting.
+    async fn send(&mut self, msg: Vec<u8>) -> Result<(), Error> {
+        self.save().await?;
+        self.link
+            .send((Instant::now(), msg))
+            .map_err(|_| Error::StrErr("peer link stopped".to_string()))
+    }
+
+    async fn new_block(&mut self) -> Result<(), Error> {
+        let (height, coins) = self.chain.block().await?;
+        if self.last_height == Some(height) {
+            return Ok(());
+        }
+
+        self.last_height = Some(height);
+        let report = self
+            .coin_set
+            .make_report_from_coin_set_update(height as u64, &coins)?;
+        self.cradle.new_block(height, report).await?;
+
+        if !self.progress.funded {
+            let coin = if let Some(coin) = &self.progress.fund_coin {
+                coin.clone()
+            } else {
+                let coin = self
+                    .chain
+                    .fund(&self.identity.puzzle_hash, Amount::new(100))
+                    .await?;
+                self.progress.fund_coin = Some(coin.clone());
+                self.save().await?;
+                coin
+            };
+            if coins.contains(&coin) {
+                info!("player {}: funding the channel", self.player);
+                self.cradle.opening_coin(coin).await?;
+                self.progress.funded = true;
+                self.save().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn start_games(&mut self) -> Result<(), Error> {
+        if !self.progress.game_ids.is_empty() {
+            return Ok(());
+        }
+
+        self.progress.game_ids = self
+            .cradle
+            .start_games(
+                self.alice,
+                GameStart {
+                    amount: Amount::new(200),
+                    my_contribution: Amount::new(100),
+                    game_type: GameType(b"calpoker".to_vec()),
+                    timeout: Timeout::new(10),
+                    my_turn: self.alice,
+                    parameters: vec![0x80],
+                },
+            )
+            .await?;
+        info!("player {}: started calpoker", self.player);
+        Ok(())
+    }
+
+    fn next_move(&mut self) -> Result<Vec<u8>, Error> {
+        let readable = match (self.alice, self.progress.moves_made) {
+            (true, 0) => {
+                let word: [u8; 16] = self.rng.gen();
+                let commit = Sha256Input::Bytes(&word).hash();
+                encode_calpoker_commit(&mut self.allocator, &commit.bytes())?
+            }
+            (false, 0) => {
+                let word: [u8; 16] = self.rng.gen();
+                encode_calpoker_commit(&mut self.allocator, &word)?
+            }
+            (_, 1) => {
+                let picks = sample(&mut self.rng, 8, 4).into_vec();
+                encode_calpoker_picks(&mut self.allocator, &picks)?
+            }
+            _ => {
+                return Ok(vec![0x80]);
+            }
+        };
+        Ok(readable.bytes().to_vec())
+    }
+
+    // Alice makes three moves and bob two, each after hearing the other's
+    // last one.  Bob accepts once alice's final move arrives.
+    async fn take_turn(&mut self) -> Result<(), Error> {
+        let game_id = if let Some(game_id) = self.progress.game_ids.first() {
+            game_id.clone()
+        } else {
+            return Ok(());
+        };
+
+        let made = self.progress.moves_made;
+        let received = self.progress.moves_received;
+        let my_turn = if self.alice {
+            made < 3 && received == made
+        } else {
+            made < 2 && received == made + 1
+        };
+
+        if my_turn {
+            let readable = self.next_move()?;
+            let new_entropy: Hash = self.rng.gen();
+            self.cradle
+                .make_move(game_id, readable, new_entropy)
+                .await?;
+            self.progress.moves_made += 1;
+            info!("player {}: made move {}", self.player, made + 1);
+        } else if !self.alice && !self.progress.accepted && received == 3 {
+            self.cradle.accept(game_id).await?;
+            self.progress.accepted = true;
+            info!("player {}: accepted", self.player);
+        }
+
+        Ok(())
+    }
+
+    async fn game_over(&mut self) -> Result<(), Error> {
+        if self.cradle.is_on_chain().await? {
+            self.progress.finished = true;
+        } else if self.alice {
+            self.cradle.shut_down().await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_event(&mut self, event: CradleEvent) -> Result<(), Error> {
+        let player = self.player;
+        match event {
+            CradleEvent::HandshakeFinished => {
+                info!("player {player}: handshake finished");
+                self.start_games().await?;
+            }
+            CradleEvent::OpponentMoved(_, readable) => {
+                self.progress.moves_received += 1;
+                info!("player {player}: opponent moved {}", readable.to_hex());
+            }
+            CradleEvent::GameFinished(_, my_share) => {
+                info!("player {player}: game finished, our share {my_share:?}");
+                self.game_over().await?;
+            }
+            CradleEvent::GameCancelled(_) => {
+                info!("player {player}: game cancelled");
+                self.game_over().await?;
+            }
+            CradleEvent::ShutdownComplete(reward_coin) => {
+                info!("player {player}: shut down, reward coin {reward_coin:?}");
+                self.progress.finished = true;
+            }
+            CradleEvent::GoingOnChain(reason) => {
+                info!("player {player}: going on chain: {reason:?}");
+            }
+            CradleEvent::Transaction(bundle) => {
+                let code = self.chain.push_tx(bundle.spends).await?;
+                if code == INCLUSION_FAILED {
+                    warn!("player {player}: transaction was refused");
+                }
+            }
+            CradleEvent::PuzzleSolutionRequest(coin) => {
+                let puzzle_and_solution = self.chain.puzzle_and_solution(&coin).await?;
+                self.cradle
+                    .report_puzzle_and_solution(coin, puzzle_and_solution)
+                    .await?;
+            }
+            CradleEvent::OutboundMessage(msg) => {
+                self.send(msg).await?;
+            }
+            CradleEvent::Error(e) => {
+                error!("player {player}: error {e:?}");
+            }
+            // We report the whole coin set each block.
+            CradleEvent::WatchCoin(_) => {}
+            event => {
+                debug!("player {player}: {event:?}");
+            }
+        }
+
+        self.take_turn().await?;
+        self.save().await
+    }
+}
+
+/// Play calpoker as one player, with the other player in another process.
+/// Peer messages go over tcp, framed with a length, and blocks come from
+/// serve_chain.  With a state file the process can be killed and restarted
+/// at any point and carries on where it left off, as long as the block
+/// source keeps running.
+pub async fn run_peer(config: PeerConfig) -> Result<(), Error> {
+    let alice = config.player == 1;
+    let mut allocator = AllocEncoder::new();
+    let identity = player_identity(&mut allocator, config.player)?;
+
+    let (cradle, mut events, mut transport, progress) = if let Some(saved) =
+        load_state(&config.state_file)?
+    {
+        info!(
+            "player {}: restoring from {:?}",
+            config.player, config.state_file
+        );
+        let (cradle, events, transport, _thread) =
+            AsyncGameCradle::restore(saved.cradle, ChaCha8Rng::from_entropy())?;
+        (cradle, events, transport, saved.progress)
+    } else {
+        let game_types = poker_collection(&mut allocator);
+        let (cradle, events, transport, _thread) = AsyncGameCradle::spawn(AsyncGameCradleConfig {
+            game_types,
+            have_potato: alice,
+            identity: identity.clone(),
+            my_contribution: Amount::new(100),
+            their_contribution: Amount::new(100),
+            channel_timeout: Timeout::new(100),
+            unroll_timeout: Timeout::new(5),
+            reward_puzzle_hash: identity.puzzle_hash.clone(),
+            liveness: LivenessPolicy::default(),
+            validate_outbound: false,
+            rng: ChaCha8Rng::from_entropy(),
+        })?;
+        (cradle, events, transport, PeerProgress::default())
+    };
+
+    if progress.finished {
+        info!("player {}: already finished", config.player);
+        return Ok(());
+    }
+
+    let chain = ChainClient::connect(&config.chain_addr).await?;
+
+    let (link_tx, link_rx) = mpsc::unbounded_channel();
+    let (connected_tx, mut connected_rx) = mpsc::unbounded_channel();
+    let peer_addr = config.peer_addr.clone();
+    let delay = config.delay;
+    let sink = transport.sink.clone();
+    tokio::spawn(async move {
+        if let Err(e) = run_link(alice, peer_addr, delay, link_rx, sink, connected_tx).await {
+            error!("peer link failed: {e:?}");
+        }
+    });
+
+    let mut player = PeerPlayer {
+        player: config.player,
+        alice,
+        cradle,
+        chain,
+        coin_set: FullCoinSetAdapter::default(),
+        allocator,
+        rng: ChaCha8Rng::from_entropy(),
+        identity,
+        last_height: None,
+        state_file: config.state_file,
+        link: link_tx,
+        progress,
+    };
+
+    // A restart can land between the handshake and starting the game.
+    if player.cradle.handshake_finished().await? {
+        player.start_games().await?;
+    }
+    player.save().await?;
+
+    let mut ticker = interval(config.poll_interval);
+    while !player.progress.finished {
+        tokio::select! {
+            event = events.next() => {
+                match event {
+                    Some(event) => player.handle_event(event).await?,
+                    None => break,
+                }
+            }
+            msg = transport.next_outbound() => {
+                match msg {
+                    Some(msg) => player.send(msg).await?,
+                    None => break,
+                }
+            }
+            Some(()) = connected_rx.recv() => {
+                player.cradle.resume().await?;
+            }
+            _ = ticker.tick() => {
+                player.new_block().await?;
+            }
+        }
+    }
+
+    player.save().await?;
+    info!("player {}: done", player.player);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::time::timeout;
+
+    use crate::chain::serve_chain;
+
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("should bind");
+        listener
+            .local_addr()
+            .expect("should have an address")
+            .to_string()
+    }
+
+    fn test_config(player: usize, peer_addr: &str, chain_addr: &str, dir: &Path) -> PeerConfig {
+        PeerConfig {
+            player,
+            peer_addr: peer_addr.to_string(),
+            chain_addr: chain_addr.to_string(),
+            state_file: Some(dir.join(format!("p{player}.state"))),
+            delay: Duration::ZERO,
+            poll_interval: Duration::from_millis(20),
+        }
+    }
+
+    fn saved_progress(config: &PeerConfig) -> Option<PeerProgress> {
+        load_state(&config.state_file)
+            .ok()
+            .flatten()
+            .map(|save| save.progress)
+    }
+
+    // Play a whole game over loopback.  Player 2 is killed once it has made
+    // a move and restarted from its state file, so player 1 also has to take
+    // a new connection.
+    #[tokio::test]
+    async fn test_peers_finish_a_game_across_a_restart() {
+        let chain_addr = free_addr();
+        let peer_addr = free_addr();
+        let dir = std::env::temp_dir().join(format!("chia-gaming-peers-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("should make a state dir");
+
+        let chain = tokio::spawn(serve_chain(chain_addr.clone(), Duration::from_millis(50)));
+
+        let alice = run_peer(test_config(1, &peer_addr, &chain_addr, &dir));
+        tokio::pin!(alice);
+        let bob_config = test_config(2, &peer_addr, &chain_addr, &dir);
+
+        let finished = timeout(Duration::from_secs(120), async {
+            let bob_moved = async {
+                while saved_progress(&bob_config).map_or(true, |p| p.moves_made == 0) {
+                    sleep(Duration::from_millis(10)).await;
+                }
+            };
+            // Dropping bob's future kills it.
+            tokio::select! {
+                result = &mut alice => panic!("alice stopped early: {result:?}"),
+                result = run_peer(test_config(2, &peer_addr, &chain_addr, &dir)) => {
+                    panic!("bob stopped early: {result:?}")
+                }
+                () = bob_moved => {}
+            }
+            tokio::join!(alice, run_peer(bob_config))
+        })
+        .await;
+        chain.abort();
+
+        let (alice_result, bob_result) = finished.expect("the game should finish");
+        alice_result.expect("alice should finish");
+        bob_result.expect("bob should finish after restarting");
+        // Alice makes three moves and bob two.
+        for (player, moves) in [(1, 3), (2, 2)] {
+            let progress = saved_progress(&test_config(player, &peer_addr, &chain_addr, &dir))
+                .expect("should have saved");
+            assert!(progress.finished);
+            assert_eq!(progress.moves_made, moves);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_frames_round_trip() {
+        let (mut ours, mut theirs) = tokio::io::duplex(1024);
+        for msg in [b"".to_vec(), b"potato".to_vec(), vec![7; 300]] {
+            write_frame(&mut ours, &msg).await.expect("should write");
+            assert_eq!(read_frame(&mut theirs).await.expect("should read"), msg);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frames_are_refused() {
+        let (mut ours, mut theirs) = tokio::io::duplex(1024);
+        let too_big = vec![0; MAX_FRAME as usize + 1];
+        assert!(write_frame(&mut ours, &too_big).await.is_err());
+
+        // Nothing was written, and a length past the limit isn't read.
+        ours.write_u32(MAX_FRAME + 1).await.expect("should write");
+        match read_frame(&mut theirs).await {
+            Err(Error::StrErr(e)) => assert!(e.contains("byte frame"), "{e}"),
+            other => panic!("expected the frame to be refused, got {other:?}"),
+        }
+    }
+}
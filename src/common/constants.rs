@@ -9,6 +9,39 @@ lazy_static! {
 pub const CREATE_COIN: u32 = 51;
 pub const REM: u32 = 1;
 
+pub const AGG_SIG_PARENT: u32 = 43;
+pub const AGG_SIG_PUZZLE: u32 = 44;
+pub const AGG_SIG_AMOUNT: u32 = 45;
+pub const AGG_SIG_PUZZLE_AMOUNT: u32 = 46;
+pub const AGG_SIG_PARENT_AMOUNT: u32 = 47;
+pub const AGG_SIG_PARENT_PUZZLE: u32 = 48;
+pub const AGG_SIG_UNSAFE: u32 = 49;
+pub const AGG_SIG_ME: u32 = 50;
+pub const RESERVE_FEE: u32 = 52;
+pub const CREATE_COIN_ANNOUNCEMENT: u32 = 60;
+pub const ASSERT_COIN_ANNOUNCEMENT: u32 = 61;
+pub const CREATE_PUZZLE_ANNOUNCEMENT: u32 = 62;
+pub const ASSERT_PUZZLE_ANNOUNCEMENT: u32 = 63;
+pub const ASSERT_CONCURRENT_SPEND: u32 = 64;
+pub const ASSERT_CONCURRENT_PUZZLE: u32 = 65;
+pub const SEND_MESSAGE: u32 = 66;
+pub const RECEIVE_MESSAGE: u32 = 67;
+pub const ASSERT_MY_COIN_ID: u32 = 70;
+pub const ASSERT_MY_PARENT_ID: u32 = 71;
+pub const ASSERT_MY_PUZZLEHASH: u32 = 72;
+pub const ASSERT_MY_AMOUNT: u32 = 73;
+pub const ASSERT_MY_BIRTH_SECONDS: u32 = 74;
+pub const ASSERT_MY_BIRTH_HEIGHT: u32 = 75;
+pub const ASSERT_EPHEMERAL: u32 = 76;
+pub const ASSERT_SECONDS_RELATIVE: u32 = 80;
+pub const ASSERT_SECONDS_ABSOLUTE: u32 = 81;
+pub const ASSERT_HEIGHT_RELATIVE: u32 = 82;
+pub const ASSERT_HEIGHT_ABSOLUTE: u32 = 83;
+pub const ASSERT_BEFORE_SECONDS_RELATIVE: u32 = 84;
+pub const ASSERT_BEFORE_SECONDS_ABSOLUTE: u32 = 85;
+pub const ASSERT_BEFORE_HEIGHT_RELATIVE: u32 = 86;
+pub const ASSERT_BEFORE_HEIGHT_ABSOLUTE: u32 = 87;
+
 pub const AGG_SIG_UNSAFE_ATOM: [u8; 1] = [49];
 pub const AGG_SIG_ME_ATOM: [u8; 1] = [50];
 pub const CREATE_COIN_ATOM: [u8; 1] = [51];
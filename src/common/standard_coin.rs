@@ -14,6 +14,8 @@ use clvmr::NodePtr;
 
 use clvm_tools_rs::util::{number_from_u8, u8_from_number};
 
+use serde::{Deserialize, Serialize};
+
 use clvm_tools_rs::classic::clvm::__type_compatibility__::{
     Bytes, Stream, UnvalidatedBytesFromType,
 };
@@ -471,7 +473,7 @@ pub fn standard_solution_partial(
     let conds = CoinCondition::from_nodeptr(allocator, conditions);
     for cond in conds.iter() {
         match cond {
-            CoinCondition::CreateCoin(_, _) => {
+            CoinCondition::CreateCoin(..) => {
                 debug!("adding signature based on create coin: {aggregate_public_key:?} {coin_agg_sig_me_message:?}");
                 add_signature(
                     &mut aggregated_signature,
@@ -529,7 +531,7 @@ pub fn standard_solution_partial(
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ChiaIdentity {
     pub private_key: PrivateKey,
     pub synthetic_public_key: PublicKey,
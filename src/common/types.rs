@@ -14,16 +14,29 @@ use sha2::{Digest, Sha256};
 
 use clvmr::allocator::{Allocator, NodePtr, SExp};
 use clvmr::reduction::EvalErr;
+use clvmr::run_program;
 use clvmr::serde::{node_from_bytes, node_to_bytes};
 
 use clvm_tools_rs::classic::clvm::sexp::proper_list;
 use clvm_tools_rs::classic::clvm::syntax_error::SyntaxErr;
 use clvm_tools_rs::classic::clvm_tools::sha256tree::sha256tree;
 
-use crate::common::constants::{AGG_SIG_ME_ATOM, AGG_SIG_UNSAFE_ATOM, CREATE_COIN_ATOM, REM_ATOM};
+use crate::channel_handler::game_handler::chia_dialect;
+use crate::common::constants::{
+    AGG_SIG_AMOUNT, AGG_SIG_ME, AGG_SIG_ME_ADDITIONAL_DATA, AGG_SIG_PARENT, AGG_SIG_PARENT_AMOUNT,
+    AGG_SIG_PARENT_PUZZLE, AGG_SIG_PUZZLE, AGG_SIG_PUZZLE_AMOUNT, AGG_SIG_UNSAFE,
+    ASSERT_BEFORE_HEIGHT_ABSOLUTE, ASSERT_BEFORE_HEIGHT_RELATIVE, ASSERT_BEFORE_SECONDS_ABSOLUTE,
+    ASSERT_BEFORE_SECONDS_RELATIVE, ASSERT_COIN_ANNOUNCEMENT, ASSERT_CONCURRENT_PUZZLE,
+    ASSERT_CONCURRENT_SPEND, ASSERT_EPHEMERAL, ASSERT_HEIGHT_ABSOLUTE, ASSERT_HEIGHT_RELATIVE,
+    ASSERT_MY_AMOUNT, ASSERT_MY_BIRTH_HEIGHT, ASSERT_MY_BIRTH_SECONDS, ASSERT_MY_COIN_ID,
+    ASSERT_MY_PARENT_ID, ASSERT_MY_PUZZLEHASH, ASSERT_PUZZLE_ANNOUNCEMENT, ASSERT_SECONDS_ABSOLUTE,
+    ASSERT_SECONDS_RELATIVE, CREATE_COIN, CREATE_COIN_ANNOUNCEMENT, CREATE_PUZZLE_ANNOUNCEMENT,
+    RECEIVE_MESSAGE, REM, RESERVE_FEE, SEND_MESSAGE,
+};
+use crate::common::standard_coin::agg_sig_me_message;
 
 use chia_bls;
-use chia_bls::signature::{sign, verify};
+use chia_bls::signature::{aggregate_verify, sign, verify};
 use clvm_traits::{ClvmEncoder, ToClvm, ToClvmError};
 
 #[cfg(test)]
@@ -147,6 +160,37 @@ impl PrivateKey {
     }
 }
 
+impl Serialize for PrivateKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let bytes = self.bytes();
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for PrivateKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let b = SerdeByteConsumer;
+        let bytes = deserializer.deserialize_bytes(b);
+        let mut fixed_bytes: [u8; 32] = [0; 32];
+        for v in bytes.into_iter().take(1) {
+            if v.len() > fixed_bytes.len() {
+                return Err(serde::de::Error::custom("private key too long"));
+            }
+            for (i, b) in v.into_iter().enumerate() {
+                fixed_bytes[i] = b;
+            }
+        }
+        PrivateKey::from_bytes(&fixed_bytes)
+            .map_err(|e| serde::de::Error::custom(format!("couldn't make private key: {e:?}")))
+    }
+}
+
 impl Distribution<Hash> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Hash {
         let mut pk = [0; 32];
@@ -199,6 +243,9 @@ impl<'de> Deserialize<'de> for PublicKey {
         let bytes = deserializer.deserialize_bytes(b);
         let mut fixed_bytes: [u8; 48] = [0; 48];
         for v in bytes.into_iter().take(1) {
+            if v.len() > fixed_bytes.len() {
+                return Err(serde::de::Error::custom("public key too long"));
+            }
             for (i, b) in v.into_iter().enumerate() {
                 fixed_bytes[i] = b;
             }
@@ -275,6 +322,9 @@ impl<'de> Deserialize<'de> for Aggsig {
         let bytes = deserializer.deserialize_bytes(b);
         let mut fixed_bytes: [u8; 96] = [0; 96];
         for v in bytes.into_iter().take(1) {
+            if v.len() > fixed_bytes.len() {
+                return Err(serde::de::Error::custom("aggsig too long"));
+            }
             for (i, b) in v.into_iter().enumerate() {
                 fixed_bytes[i] = b;
             }
@@ -316,6 +366,11 @@ impl Aggsig {
         verify(&self.0, &public_key.to_bls(), msg)
     }
 
+    /// Check a signature aggregated over several public key and message pairs.
+    pub fn aggregate_verify(&self, pairs: &[(PublicKey, Vec<u8>)]) -> bool {
+        aggregate_verify(&self.0, pairs.iter().map(|(pk, msg)| (pk.to_bls(), msg)))
+    }
+
     pub fn aggregate(&self, other: &Aggsig) -> Aggsig {
         let mut result = self.0.clone();
         result.aggregate(&other.0);
@@ -579,6 +634,29 @@ pub enum Error {
     JsonErr(serde_json::Error),
     HexErr(hex::FromHexError),
     Channel(String),
+    Handshake(HandshakeError),
+    Protocol(ProtocolError),
+}
+
+/// Reasons the peer's handshake was refused.  These are raised before any
+/// channel state is created so that incompatible builds fail immediately
+/// rather than when a signature or puzzle hash fails to match later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    ProtocolVersion { ours: u32, theirs: u32 },
+    PuzzleMismatch(String),
+    NoCommonGames,
+}
+
+/// Reasons a message from the peer was refused after the handshake began.
+/// Anything the peer sends us must end up here (or in another error) rather
+/// than in a panic, since the peer controls its content and its timing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    UnexpectedMessage { state: String, message_kind: String },
+    BadSignature(String),
+    StateMismatch(String),
+    Malformed(String),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -609,7 +687,12 @@ impl Default for Node {
 }
 
 impl Node {
-    #[cfg(any(test, feature = "sim-tests", feature = "simulator"))]
+    #[cfg(any(
+        test,
+        feature = "sim-tests",
+        feature = "simulator",
+        feature = "native-sim"
+    ))]
     pub fn to_hex(&self, allocator: &mut AllocEncoder) -> Result<String, Error> {
         let bytes = node_to_bytes(allocator.allocator(), self.0).into_gen()?;
         Ok(hex::encode(bytes))
@@ -880,6 +963,12 @@ impl ErrToError for serde_json::Error {
     }
 }
 
+impl ErrToError for Error {
+    fn into_gen(self) -> Error {
+        self
+    }
+}
+
 impl ErrToError for hex::FromHexError {
     fn into_gen(self) -> Error {
         Error::HexErr(self)
@@ -899,78 +988,196 @@ where
     }
 }
 
-#[derive(Debug, Clone)]
+/// A condition output by a coin's puzzle, covering the full chia condition
+/// set.  Conditions with unknown opcodes or malformed arguments aren't
+/// represented.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoinCondition {
-    AggSigMe(PublicKey, Vec<u8>),
+    AggSigParent(PublicKey, Vec<u8>),
+    AggSigPuzzle(PublicKey, Vec<u8>),
+    AggSigAmount(PublicKey, Vec<u8>),
+    AggSigPuzzleAmount(PublicKey, Vec<u8>),
+    AggSigParentAmount(PublicKey, Vec<u8>),
+    AggSigParentPuzzle(PublicKey, Vec<u8>),
     AggSigUnsafe(PublicKey, Vec<u8>),
-    #[allow(dead_code)]
-    CreateCoin(PuzzleHash, Amount),
+    AggSigMe(PublicKey, Vec<u8>),
+    /// Puzzle hash, amount and memos, which are empty if none were given.
+    CreateCoin(PuzzleHash, Amount, Vec<Vec<u8>>),
+    ReserveFee(Amount),
+    CreateCoinAnnouncement(Vec<u8>),
+    AssertCoinAnnouncement(Hash),
+    CreatePuzzleAnnouncement(Vec<u8>),
+    AssertPuzzleAnnouncement(Hash),
+    AssertConcurrentSpend(CoinID),
+    AssertConcurrentPuzzle(PuzzleHash),
+    /// Mode, message and the arguments naming the other side.
+    SendMessage(u8, Vec<u8>, Vec<Vec<u8>>),
+    ReceiveMessage(u8, Vec<u8>, Vec<Vec<u8>>),
+    AssertMyCoinId(CoinID),
+    AssertMyParentId(CoinID),
+    AssertMyPuzzleHash(PuzzleHash),
+    AssertMyAmount(Amount),
+    AssertMyBirthSeconds(u64),
+    AssertMyBirthHeight(u64),
+    AssertEphemeral,
+    AssertSecondsRelative(u64),
+    AssertSecondsAbsolute(u64),
+    AssertHeightRelative(u64),
+    AssertHeightAbsolute(u64),
+    AssertBeforeSecondsRelative(u64),
+    AssertBeforeSecondsAbsolute(u64),
+    AssertBeforeHeightRelative(u64),
+    AssertBeforeHeightAbsolute(u64),
     Rem(Vec<Vec<u8>>),
 }
 
+fn condition_atom(allocator: &mut AllocEncoder, args: &[NodePtr], i: usize) -> Option<Vec<u8>> {
+    let arg = args.get(i)?;
+    atom_from_clvm(allocator, *arg).map(|a| a.to_vec())
+}
+
+fn condition_atoms(allocator: &mut AllocEncoder, args: &[NodePtr]) -> Option<Vec<Vec<u8>>> {
+    args.iter()
+        .map(|a| atom_from_clvm(allocator, *a).map(|a| a.to_vec()))
+        .collect()
+}
+
+fn condition_hash(allocator: &mut AllocEncoder, args: &[NodePtr], i: usize) -> Option<Hash> {
+    condition_atom(allocator, args, i)
+        .filter(|h| h.len() == 32)
+        .map(|h| Hash::from_slice(&h))
+}
+
+// Condition numbers are signed, so a negative one isn't read as a huge u64.
+fn condition_u64(allocator: &mut AllocEncoder, args: &[NodePtr], i: usize) -> Option<u64> {
+    condition_atom(allocator, args, i)
+        .filter(|a| a.first().map(|b| b & 0x80 == 0).unwrap_or(true))
+        .and_then(|a| u64_from_atom(&a))
+}
+
+fn condition_agg_sig(
+    allocator: &mut AllocEncoder,
+    args: &[NodePtr],
+) -> Option<(PublicKey, Vec<u8>)> {
+    let public_key = condition_atom(allocator, args, 1)
+        .and_then(|pk| <[u8; 48]>::try_from(pk.as_slice()).ok())
+        .and_then(|pk| PublicKey::from_bytes(pk).ok())?;
+    let message = condition_atom(allocator, args, 2)?;
+    Some((public_key, message))
+}
+
+fn condition_message(
+    allocator: &mut AllocEncoder,
+    args: &[NodePtr],
+) -> Option<(u8, Vec<u8>, Vec<Vec<u8>>)> {
+    let mode = condition_u64(allocator, args, 1).and_then(|m| u8::try_from(m).ok())?;
+    let message = condition_atom(allocator, args, 2)?;
+    let rest = condition_atoms(allocator, args.get(3..).unwrap_or_default())?;
+    Some((mode, message, rest))
+}
+
 fn parse_condition(allocator: &mut AllocEncoder, condition: NodePtr) -> Option<CoinCondition> {
-    let exploded = proper_list(allocator.allocator(), condition, true)?;
-    let public_key_from_bytes = |b: &[u8]| -> Result<PublicKey, Error> {
-        let mut fixed: [u8; 48] = [0; 48];
-        for (i, b) in b.iter().enumerate() {
-            fixed[i % 48] = *b;
-        }
-        PublicKey::from_bytes(fixed)
+    let args = proper_list(allocator.allocator(), condition, true)?;
+    let opcode = condition_atom(allocator, &args, 0)?;
+    if opcode.len() != 1 {
+        return None;
+    }
+
+    let coin_id =
+        |allocator: &mut AllocEncoder, i| condition_hash(allocator, &args, i).map(CoinID::new);
+    let puzzle_hash = |allocator: &mut AllocEncoder, i| {
+        condition_hash(allocator, &args, i).map(PuzzleHash::from_hash)
     };
-    if exploded.len() > 2
-        && matches!(
-            (
-                allocator.allocator().sexp(exploded[0]),
-                allocator.allocator().sexp(exploded[1]),
-                allocator.allocator().sexp(exploded[2])
-            ),
-            (SExp::Atom, SExp::Atom, SExp::Atom)
-        )
-    {
-        let atoms: Vec<Vec<u8>> = exploded
-            .iter()
-            .take(3)
-            .map(|a| allocator.allocator().atom(*a).to_vec())
-            .collect();
-        if *atoms[0] == AGG_SIG_UNSAFE_ATOM {
-            if let Ok(pk) = public_key_from_bytes(&atoms[1]) {
-                return Some(CoinCondition::AggSigUnsafe(pk, atoms[2].to_vec()));
-            }
-        } else if *atoms[0] == AGG_SIG_ME_ATOM {
-            if let Ok(pk) = public_key_from_bytes(&atoms[1]) {
-                return Some(CoinCondition::AggSigMe(pk, atoms[2].to_vec()));
-            }
-        } else if *atoms[0] == CREATE_COIN_ATOM {
-            if let Some(amt) = u64_from_atom(&atoms[2]) {
-                return Some(CoinCondition::CreateCoin(
-                    PuzzleHash::from_hash(Hash::from_slice(&atoms[1])),
-                    Amount::new(amt),
-                ));
-            }
-        } else {
-            return None;
+    let amount =
+        |allocator: &mut AllocEncoder, i| condition_u64(allocator, &args, i).map(Amount::new);
+    match opcode[0] as u32 {
+        AGG_SIG_PARENT => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigParent(pk, msg)),
+        AGG_SIG_PUZZLE => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigPuzzle(pk, msg)),
+        AGG_SIG_AMOUNT => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigAmount(pk, msg)),
+        AGG_SIG_PUZZLE_AMOUNT => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigPuzzleAmount(pk, msg)),
+        AGG_SIG_PARENT_AMOUNT => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigParentAmount(pk, msg)),
+        AGG_SIG_PARENT_PUZZLE => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigParentPuzzle(pk, msg)),
+        AGG_SIG_UNSAFE => condition_agg_sig(allocator, &args)
+            .map(|(pk, msg)| CoinCondition::AggSigUnsafe(pk, msg)),
+        AGG_SIG_ME => {
+            condition_agg_sig(allocator, &args).map(|(pk, msg)| CoinCondition::AggSigMe(pk, msg))
         }
-    } else if exploded.len() > 1
-        && matches!(
-            (
-                allocator.allocator().sexp(exploded[0]),
-                allocator.allocator().sexp(exploded[1])
-            ),
-            (SExp::Atom, SExp::Atom)
-        )
-    {
-        let atoms: Vec<Vec<u8>> = exploded
-            .iter()
-            .map(|a| allocator.allocator().atom(*a).to_vec())
-            .collect();
-        if *atoms[0] == REM_ATOM {
-            return Some(CoinCondition::Rem(
-                atoms.iter().skip(1).map(|a| a.to_vec()).collect(),
-            ));
+        CREATE_COIN => {
+            let ph = puzzle_hash(allocator, 1)?;
+            let amt = amount(allocator, 2)?;
+            // Memos that aren't a list of atoms are ignored, as on chain.
+            let memos = args
+                .get(3)
+                .and_then(|memos| proper_list(allocator.allocator(), *memos, true))
+                .and_then(|memos| condition_atoms(allocator, &memos))
+                .unwrap_or_default();
+            Some(CoinCondition::CreateCoin(ph, amt, memos))
+        }
+        RESERVE_FEE => amount(allocator, 1).map(CoinCondition::ReserveFee),
+        CREATE_COIN_ANNOUNCEMENT => {
+            condition_atom(allocator, &args, 1).map(CoinCondition::CreateCoinAnnouncement)
+        }
+        ASSERT_COIN_ANNOUNCEMENT => {
+            condition_hash(allocator, &args, 1).map(CoinCondition::AssertCoinAnnouncement)
+        }
+        CREATE_PUZZLE_ANNOUNCEMENT => {
+            condition_atom(allocator, &args, 1).map(CoinCondition::CreatePuzzleAnnouncement)
+        }
+        ASSERT_PUZZLE_ANNOUNCEMENT => {
+            condition_hash(allocator, &args, 1).map(CoinCondition::AssertPuzzleAnnouncement)
+        }
+        ASSERT_CONCURRENT_SPEND => coin_id(allocator, 1).map(CoinCondition::AssertConcurrentSpend),
+        ASSERT_CONCURRENT_PUZZLE => {
+            puzzle_hash(allocator, 1).map(CoinCondition::AssertConcurrentPuzzle)
+        }
+        SEND_MESSAGE => condition_message(allocator, &args)
+            .map(|(mode, msg, rest)| CoinCondition::SendMessage(mode, msg, rest)),
+        RECEIVE_MESSAGE => condition_message(allocator, &args)
+            .map(|(mode, msg, rest)| CoinCondition::ReceiveMessage(mode, msg, rest)),
+        ASSERT_MY_COIN_ID => coin_id(allocator, 1).map(CoinCondition::AssertMyCoinId),
+        ASSERT_MY_PARENT_ID => coin_id(allocator, 1).map(CoinCondition::AssertMyParentId),
+        ASSERT_MY_PUZZLEHASH => puzzle_hash(allocator, 1).map(CoinCondition::AssertMyPuzzleHash),
+        ASSERT_MY_AMOUNT => amount(allocator, 1).map(CoinCondition::AssertMyAmount),
+        ASSERT_MY_BIRTH_SECONDS => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertMyBirthSeconds)
         }
+        ASSERT_MY_BIRTH_HEIGHT => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertMyBirthHeight)
+        }
+        ASSERT_EPHEMERAL => Some(CoinCondition::AssertEphemeral),
+        ASSERT_SECONDS_RELATIVE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertSecondsRelative)
+        }
+        ASSERT_SECONDS_ABSOLUTE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertSecondsAbsolute)
+        }
+        ASSERT_HEIGHT_RELATIVE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertHeightRelative)
+        }
+        ASSERT_HEIGHT_ABSOLUTE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertHeightAbsolute)
+        }
+        ASSERT_BEFORE_SECONDS_RELATIVE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertBeforeSecondsRelative)
+        }
+        ASSERT_BEFORE_SECONDS_ABSOLUTE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertBeforeSecondsAbsolute)
+        }
+        ASSERT_BEFORE_HEIGHT_RELATIVE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertBeforeHeightRelative)
+        }
+        ASSERT_BEFORE_HEIGHT_ABSOLUTE => {
+            condition_u64(allocator, &args, 1).map(CoinCondition::AssertBeforeHeightAbsolute)
+        }
+        REM => condition_atoms(allocator, &args[1..]).map(CoinCondition::Rem),
+        _ => None,
     }
-
-    None
 }
 
 impl CoinCondition {
@@ -985,6 +1192,220 @@ impl CoinCondition {
             Vec::new()
         }
     }
+
+    /// The public key and full message an AGG_SIG condition in a spend of
+    /// coin has to be signed with, or None for any other condition.
+    pub fn signed_message(&self, coin: &CoinString) -> Option<(PublicKey, Vec<u8>)> {
+        let (parent_id, puzzle_hash, amount) = coin.to_parts()?;
+        let (opcode, pk, msg, suffix) = match self {
+            CoinCondition::AggSigUnsafe(pk, msg) => return Some((pk.clone(), msg.clone())),
+            CoinCondition::AggSigMe(pk, msg) => {
+                return Some((
+                    pk.clone(),
+                    agg_sig_me_message(
+                        msg,
+                        &coin.to_coin_id(),
+                        &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+                    ),
+                ));
+            }
+            CoinCondition::AggSigParent(pk, msg) => {
+                (AGG_SIG_PARENT, pk, msg, parent_id.bytes().to_vec())
+            }
+            CoinCondition::AggSigPuzzle(pk, msg) => {
+                (AGG_SIG_PUZZLE, pk, msg, puzzle_hash.bytes().to_vec())
+            }
+            CoinCondition::AggSigAmount(pk, msg) => {
+                (AGG_SIG_AMOUNT, pk, msg, amount_bytes(&amount))
+            }
+            CoinCondition::AggSigPuzzleAmount(pk, msg) => (
+                AGG_SIG_PUZZLE_AMOUNT,
+                pk,
+                msg,
+                [puzzle_hash.bytes(), &amount_bytes(&amount)[..]].concat(),
+            ),
+            CoinCondition::AggSigParentAmount(pk, msg) => (
+                AGG_SIG_PARENT_AMOUNT,
+                pk,
+                msg,
+                [parent_id.bytes(), &amount_bytes(&amount)[..]].concat(),
+            ),
+            CoinCondition::AggSigParentPuzzle(pk, msg) => (
+                AGG_SIG_PARENT_PUZZLE,
+                pk,
+                msg,
+                [parent_id.bytes(), puzzle_hash.bytes()].concat(),
+            ),
+            _ => return None,
+        };
+        // Each of the other AGG_SIG variants commits to its own additional data.
+        let additional_data =
+            Hash::new(&[&AGG_SIG_ME_ADDITIONAL_DATA[..], &[opcode as u8][..]].concat());
+        Some((
+            pk.clone(),
+            [&msg[..], &suffix[..], additional_data.bytes()].concat(),
+        ))
+    }
+}
+
+// Canonical clvm encoding of an amount, as signed in AGG_SIG_*AMOUNT.
+fn amount_bytes(amount: &Amount) -> Vec<u8> {
+    let bytes = amount.to_u64().to_be_bytes();
+    let first = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    let mut result = Vec::new();
+    if first < bytes.len() && bytes[first] & 0x80 != 0 {
+        result.push(0);
+    }
+    result.extend(&bytes[first..]);
+    result
+}
+
+fn encode_atom_list(
+    encoder: &mut impl ClvmEncoder<Node = NodePtr>,
+    atoms: &[Vec<u8>],
+) -> Result<NodePtr, ToClvmError> {
+    let mut result = encoder.encode_atom(&[])?;
+    for atom in atoms.iter().rev() {
+        let atom = encoder.encode_atom(atom)?;
+        result = encoder.encode_pair(atom, result)?;
+    }
+    Ok(result)
+}
+
+impl ToClvm<NodePtr> for CoinCondition {
+    fn to_clvm(
+        &self,
+        encoder: &mut impl ClvmEncoder<Node = NodePtr>,
+    ) -> Result<NodePtr, ToClvmError> {
+        let (opcode, args) = match self {
+            CoinCondition::AggSigParent(pk, msg) => (
+                AGG_SIG_PARENT,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigPuzzle(pk, msg) => (
+                AGG_SIG_PUZZLE,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigAmount(pk, msg) => (
+                AGG_SIG_AMOUNT,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigPuzzleAmount(pk, msg) => (
+                AGG_SIG_PUZZLE_AMOUNT,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigParentAmount(pk, msg) => (
+                AGG_SIG_PARENT_AMOUNT,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigParentPuzzle(pk, msg) => (
+                AGG_SIG_PARENT_PUZZLE,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigUnsafe(pk, msg) => (
+                AGG_SIG_UNSAFE,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::AggSigMe(pk, msg) => (
+                AGG_SIG_ME,
+                vec![pk.to_clvm(encoder)?, encoder.encode_atom(msg)?],
+            ),
+            CoinCondition::CreateCoin(ph, amt, memos) => {
+                let mut args = vec![ph.to_clvm(encoder)?, amt.to_clvm(encoder)?];
+                if !memos.is_empty() {
+                    args.push(encode_atom_list(encoder, memos)?);
+                }
+                (CREATE_COIN, args)
+            }
+            CoinCondition::ReserveFee(amt) => (RESERVE_FEE, vec![amt.to_clvm(encoder)?]),
+            CoinCondition::CreateCoinAnnouncement(msg) => {
+                (CREATE_COIN_ANNOUNCEMENT, vec![encoder.encode_atom(msg)?])
+            }
+            CoinCondition::AssertCoinAnnouncement(h) => {
+                (ASSERT_COIN_ANNOUNCEMENT, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::CreatePuzzleAnnouncement(msg) => {
+                (CREATE_PUZZLE_ANNOUNCEMENT, vec![encoder.encode_atom(msg)?])
+            }
+            CoinCondition::AssertPuzzleAnnouncement(h) => {
+                (ASSERT_PUZZLE_ANNOUNCEMENT, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertConcurrentSpend(coin_id) => {
+                (ASSERT_CONCURRENT_SPEND, vec![coin_id.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertConcurrentPuzzle(ph) => {
+                (ASSERT_CONCURRENT_PUZZLE, vec![ph.to_clvm(encoder)?])
+            }
+            CoinCondition::SendMessage(mode, msg, rest)
+            | CoinCondition::ReceiveMessage(mode, msg, rest) => {
+                let opcode = if matches!(self, CoinCondition::SendMessage(..)) {
+                    SEND_MESSAGE
+                } else {
+                    RECEIVE_MESSAGE
+                };
+                let mut args = vec![(*mode as u64).to_clvm(encoder)?, encoder.encode_atom(msg)?];
+                for atom in rest.iter() {
+                    args.push(encoder.encode_atom(atom)?);
+                }
+                (opcode, args)
+            }
+            CoinCondition::AssertMyCoinId(coin_id) => {
+                (ASSERT_MY_COIN_ID, vec![coin_id.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertMyParentId(coin_id) => {
+                (ASSERT_MY_PARENT_ID, vec![coin_id.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertMyPuzzleHash(ph) => {
+                (ASSERT_MY_PUZZLEHASH, vec![ph.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertMyAmount(amt) => (ASSERT_MY_AMOUNT, vec![amt.to_clvm(encoder)?]),
+            CoinCondition::AssertMyBirthSeconds(t) => {
+                (ASSERT_MY_BIRTH_SECONDS, vec![t.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertMyBirthHeight(h) => {
+                (ASSERT_MY_BIRTH_HEIGHT, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertEphemeral => (ASSERT_EPHEMERAL, vec![]),
+            CoinCondition::AssertSecondsRelative(t) => {
+                (ASSERT_SECONDS_RELATIVE, vec![t.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertSecondsAbsolute(t) => {
+                (ASSERT_SECONDS_ABSOLUTE, vec![t.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertHeightRelative(h) => {
+                (ASSERT_HEIGHT_RELATIVE, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertHeightAbsolute(h) => {
+                (ASSERT_HEIGHT_ABSOLUTE, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertBeforeSecondsRelative(t) => {
+                (ASSERT_BEFORE_SECONDS_RELATIVE, vec![t.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertBeforeSecondsAbsolute(t) => {
+                (ASSERT_BEFORE_SECONDS_ABSOLUTE, vec![t.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertBeforeHeightRelative(h) => {
+                (ASSERT_BEFORE_HEIGHT_RELATIVE, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::AssertBeforeHeightAbsolute(h) => {
+                (ASSERT_BEFORE_HEIGHT_ABSOLUTE, vec![h.to_clvm(encoder)?])
+            }
+            CoinCondition::Rem(atoms) => {
+                let mut args = Vec::new();
+                for atom in atoms.iter() {
+                    args.push(encoder.encode_atom(atom)?);
+                }
+                (REM, args)
+            }
+        };
+
+        let mut result = encoder.encode_atom(&[])?;
+        for arg in args.into_iter().rev() {
+            result = encoder.encode_pair(arg, result)?;
+        }
+        let opcode = opcode.to_clvm(encoder)?;
+        encoder.encode_pair(opcode, result)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -1000,6 +1421,48 @@ pub struct CoinSpend {
     pub bundle: Spend,
 }
 
+impl CoinSpend {
+    /// Run the puzzle on the solution after checking that it hashes to the
+    /// coin's puzzle hash.  Returns the clvm cost and the conditions output.
+    pub fn conditions(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<(u64, Vec<CoinCondition>), Error> {
+        let (_, puzzle_hash, _) = self
+            .coin
+            .to_parts()
+            .ok_or_else(|| Error::StrErr(format!("bad coin string in spend {:?}", self.coin)))?;
+        if self.bundle.puzzle.sha256tree(allocator) != puzzle_hash {
+            return Err(Error::StrErr(format!(
+                "puzzle doesn't hash to the puzzle hash of {:?}",
+                self.coin
+            )));
+        }
+
+        let puzzle = self.bundle.puzzle.to_program().to_nodeptr(allocator)?;
+        let solution = self.bundle.solution.to_nodeptr(allocator)?;
+        let result = run_program(allocator.allocator(), &chia_dialect(), puzzle, solution, 0)
+            .map_err(|e| Error::StrErr(format!("spend of {:?} failed: {e:?}", self.coin)))?;
+        Ok((result.0, CoinCondition::from_nodeptr(allocator, result.1)))
+    }
+
+    /// The coins this spend creates.
+    pub fn additions(&self, allocator: &mut AllocEncoder) -> Result<Vec<CoinString>, Error> {
+        let parent = self.coin.to_coin_id();
+        let (_, conditions) = self.conditions(allocator)?;
+        Ok(conditions
+            .iter()
+            .filter_map(|c| {
+                if let CoinCondition::CreateCoin(ph, amt, _) = c {
+                    Some(CoinString::from_parts(&parent, ph, amt))
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
 impl Default for Spend {
     fn default() -> Self {
         Spend {
@@ -1020,6 +1483,47 @@ pub struct SpendBundle {
     pub spends: Vec<CoinSpend>,
 }
 
+impl SpendBundle {
+    /// Check the bundle as the mempool would before it's broadcast: each
+    /// puzzle must hash to its coin's puzzle hash and run on its solution, and
+    /// the aggregate of the signatures must cover every AGG_SIG condition,
+    /// each with the message CoinCondition::signed_message builds for it.
+    /// Returns the total clvm cost of the spends.
+    pub fn validate(&self, allocator: &mut AllocEncoder) -> Result<u64, Error> {
+        if self.spends.is_empty() {
+            return Err(Error::StrErr("spend bundle has no spends".to_string()));
+        }
+
+        let mut cost = 0;
+        let mut signature = Aggsig::default();
+        let mut signed = Vec::new();
+        for spend in self.spends.iter() {
+            let (spend_cost, conditions) = spend.conditions(allocator)?;
+            cost += spend_cost;
+            signed.extend(
+                conditions
+                    .iter()
+                    .filter_map(|condition| condition.signed_message(&spend.coin)),
+            );
+            signature += spend.bundle.signature.clone();
+        }
+
+        let signature_ok = if signed.is_empty() {
+            signature == Aggsig::default()
+        } else {
+            signature.aggregate_verify(&signed)
+        };
+        if !signature_ok {
+            return Err(Error::StrErr(format!(
+                "aggregate signature doesn't verify for {} spends",
+                self.spends.len()
+            )));
+        }
+
+        Ok(cost)
+    }
+}
+
 pub fn usize_from_atom(a: &[u8]) -> Option<usize> {
     let bi = BigInt::from_bytes_be(Sign::Plus, a);
     bi.to_usize()
@@ -1,6 +1,7 @@
 use std::cmp::Ordering;
 
 use crate::channel_handler::types::ReadableMove;
+use clvm_traits::{ClvmEncoder, ToClvm};
 use clvmr::NodePtr;
 
 use clvm_tools_rs::classic::clvm::sexp::proper_list;
@@ -13,8 +14,8 @@ use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
 
 use crate::common::types::{
-    atom_from_clvm, divmod, i64_from_atom, usize_from_atom, AllocEncoder, Amount, Error,
-    Sha256Input,
+    atom_from_clvm, divmod, i64_from_atom, usize_from_atom, AllocEncoder, Amount, ErrToError,
+    Error, Program, Sha256Input,
 };
 
 pub type Card = (usize, usize);
@@ -230,7 +231,8 @@ pub fn make_cards(alice_hash: &[u8], bob_hash: &[u8], amount: Amount) -> (Vec<Ca
     if amount_bytes == [0] {
         amount_bytes = vec![];
     }
-    if amount_bytes[0] & 0x80 != 0 {
+    // A zero amount hashes as the empty atom, which needs no sign byte.
+    if amount_bytes.first().is_some_and(|b| b & 0x80 != 0) {
         amount_bytes.insert(0, 0);
     }
     let rand_input = Sha256Input::Array(vec![
@@ -332,6 +334,45 @@ pub fn decode_hand_result(
     Ok(RawCalpokerHandValue::SimpleList(result_list))
 }
 
+/// The readable move committing to our entropy.  Alice's first move and bob's
+/// reply are each a single atom.
+pub fn encode_calpoker_commit(
+    allocator: &mut AllocEncoder,
+    entropy: &[u8],
+) -> Result<Program, Error> {
+    let node = allocator.encode_atom(entropy).map_err(|e| e.into_gen())?;
+    Program::from_nodeptr(allocator, node)
+}
+
+/// The readable move choosing the four cards to keep, given as indices into
+/// the eight cards make_cards dealt us.
+pub fn encode_calpoker_picks(
+    allocator: &mut AllocEncoder,
+    picks: &[usize],
+) -> Result<Program, Error> {
+    let mut flags = vec![false; 8];
+    for p in picks.iter() {
+        if *p >= flags.len() || flags[*p] {
+            return Err(Error::StrErr(format!("bad calpoker picks {picks:?}")));
+        }
+        flags[*p] = true;
+    }
+    if picks.len() != 4 {
+        return Err(Error::StrErr(format!(
+            "calpoker needs 4 picks, got {}",
+            picks.len()
+        )));
+    }
+
+    let node = flags.to_clvm(allocator).map_err(|e| e.into_gen())?;
+    Program::from_nodeptr(allocator, node)
+}
+
+/// The card indices set in one of the bitmasks of a CalpokerResult.
+pub fn picks_from_bitmask(mask: usize) -> Vec<usize> {
+    (0..8).filter(|i| mask & (1 << i) != 0).collect()
+}
+
 /// Given a readable move, decode it as a calpoker outcome.
 pub fn decode_calpoker_readable(
     allocator: &mut AllocEncoder,
@@ -436,3 +477,17 @@ fn test_decode_calpoker_readable() {
         }
     );
 }
+
+#[test]
+fn test_encode_calpoker_picks() {
+    let mut allocator = AllocEncoder::new();
+    let picks = encode_calpoker_picks(&mut allocator, &[1, 3, 5, 7]).expect("should encode");
+    let expected_node = [0, 1, 0, 1, 0, 1, 0, 1]
+        .to_clvm(&mut allocator)
+        .expect("should work");
+    let expected = Program::from_nodeptr(&mut allocator, expected_node).expect("should work");
+    assert_eq!(picks.bytes(), expected.bytes());
+    assert_eq!(picks_from_bitmask(170), vec![1, 3, 5, 7]);
+    assert!(encode_calpoker_picks(&mut allocator, &[1, 1, 5, 7]).is_err());
+    assert!(encode_calpoker_picks(&mut allocator, &[1, 3, 5]).is_err());
+}
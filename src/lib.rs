@@ -1,4 +1,9 @@
+/// Runs a game cradle in its own task, driven by commands and peer messages.
+#[cfg(feature = "server")]
+pub mod async_cradle;
 pub mod channel_handler;
+/// Runs the channels to many peers over one block feed.
+pub mod channel_manager;
 pub mod common;
 pub mod games;
 pub mod log;
@@ -7,7 +12,7 @@ pub mod log;
 pub mod peer_container;
 pub mod potato_handler;
 mod referee;
-#[cfg(any(feature = "sim-tests", feature = "simulator"))]
+#[cfg(any(feature = "sim-tests", feature = "simulator", feature = "native-sim"))]
 pub mod simulator;
 
 #[cfg(test)]
@@ -4,6 +4,7 @@ use std::mem::swap;
 use clvm_traits::ToClvm;
 use log::debug;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::channel_handler::runner::channel_handler_env;
 use crate::channel_handler::types::{
@@ -14,13 +15,16 @@ use crate::common::standard_coin::{
     sign_agg_sig_me, solution_for_conditions, standard_solution_partial, ChiaIdentity,
 };
 use crate::common::types::{
-    AllocEncoder, Amount, CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Program, PuzzleHash,
-    Sha256tree, Spend, SpendBundle, Timeout, ToQuotedProgram,
+    AllocEncoder, Amount, CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Program,
+    ProtocolError, PuzzleHash, Sha256tree, Spend, SpendBundle, Timeout, ToQuotedProgram,
 };
+#[cfg(test)]
+use crate::potato_handler::HandshakeStepWithSpend;
 use crate::potato_handler::{
-    BootstrapTowardGame, BootstrapTowardWallet, FromLocalUI, GameStart, GameType, PacketSender,
-    PeerEnv, PeerMessage, PotatoHandler, PotatoHandlerInit, SpendWalletReceiver, ToLocalUI,
-    WalletSpendInterface,
+    BootstrapTowardGame, BootstrapTowardWallet, FromLocalUI, GameStart, GameType,
+    GoingOnChainReason, LivenessPolicy, PacketSender, PeerEnv, PeerMessage, PotatoHandler,
+    PotatoHandlerInit, PotatoHandlerSnapshot, SpendWalletReceiver, ToLocalUI, WalletSpendInterface,
+    WireGameStart, WATCH_CHANNEL, WATCH_NAMES,
 };
 
 #[derive(Default)]
@@ -38,18 +42,76 @@ pub trait MessagePeerQueue {
     fn get_unfunded_offer(&self) -> Option<SpendBundle>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WatchEntry {
     pub timeout_blocks: Timeout,
     pub timeout_at: Option<u64>,
     pub name: Option<&'static str>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+struct FlatWatchEntry {
+    timeout_blocks: Timeout,
+    timeout_at: Option<u64>,
+    name: Option<String>,
+}
+
+impl WatchEntry {
+    fn to_serializable(&self) -> FlatWatchEntry {
+        FlatWatchEntry {
+            timeout_blocks: self.timeout_blocks.clone(),
+            timeout_at: self.timeout_at,
+            name: self.name.map(|n| n.to_string()),
+        }
+    }
+
+    fn from_serializable(serializable: &FlatWatchEntry) -> WatchEntry {
+        WatchEntry {
+            timeout_blocks: serializable.timeout_blocks.clone(),
+            timeout_at: serializable.timeout_at,
+            name: serializable
+                .name
+                .as_ref()
+                .and_then(|n| WATCH_NAMES.iter().find(|w| *w == n).copied()),
+        }
+    }
+}
+
+fn watch_entries_to_serializable<'a>(
+    entries: impl Iterator<Item = (&'a CoinString, &'a WatchEntry)>,
+) -> Vec<(CoinString, FlatWatchEntry)> {
+    entries
+        .map(|(c, w)| (c.clone(), w.to_serializable()))
+        .collect()
+}
+
+fn watch_entries_from_serializable<C: FromIterator<(CoinString, WatchEntry)>>(
+    entries: &[(CoinString, FlatWatchEntry)],
+) -> C {
+    entries
+        .iter()
+        .map(|(c, w)| (c.clone(), WatchEntry::from_serializable(w)))
+        .collect()
+}
+
+/// How many blocks back a reorg can reach and still be undone.
+pub const MAX_REORG_DEPTH: usize = 32;
+
+/// Names a block and the block it builds on, so that a report whose parent
+/// isn't the last block seen can be recognized as a reorg.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockId {
+    pub header_hash: Hash,
+    pub parent_hash: Hash,
+}
+
 #[derive(Debug, Clone)]
 pub struct WatchReport {
     pub created_watched: HashSet<CoinString>,
     pub deleted_watched: HashSet<CoinString>,
     pub timed_out: HashSet<CoinString>,
+    /// Without this, blocks are assumed to only ever extend the chain.
+    pub block: Option<BlockId>,
 }
 
 pub enum WalletBootstrapState {
@@ -64,6 +126,9 @@ pub enum WalletBootstrapState {
 pub struct FullCoinSetAdapter {
     pub current_height: u64,
     pub current_coins: HashSet<CoinString>,
+    // The coin set after each recent block, for diffing against the fork
+    // point after a reorg.
+    recent_blocks: VecDeque<(Hash, HashSet<CoinString>)>,
 }
 
 impl FullCoinSetAdapter {
@@ -91,8 +156,48 @@ impl FullCoinSetAdapter {
             created_watched: created_coins,
             deleted_watched: deleted_coins,
             timed_out: HashSet::default(),
+            block: None,
         })
     }
+
+    /// Like make_report_from_coin_set_update, but for a known block.  If the
+    /// block doesn't build on the last one, the report is made against the
+    /// coin set at its parent so that it describes the new chain from the
+    /// fork point.
+    pub fn make_report_for_block(
+        &mut self,
+        current_height: u64,
+        block: &BlockId,
+        current_coins: &[CoinString],
+    ) -> Result<WatchReport, Error> {
+        let is_reorg = self
+            .recent_blocks
+            .back()
+            .map(|(header_hash, _)| *header_hash != block.parent_hash)
+            .unwrap_or(false);
+        if is_reorg {
+            while let Some((header_hash, _)) = self.recent_blocks.back() {
+                if *header_hash == block.parent_hash {
+                    break;
+                }
+                self.recent_blocks.pop_back();
+            }
+            let (_, coins) = self.recent_blocks.back().ok_or_else(|| {
+                Error::StrErr(format!("reorg to unknown parent {:?}", block.parent_hash))
+            })?;
+            debug!("reorg at height {current_height}");
+            self.current_coins = coins.clone();
+        }
+
+        let mut report = self.make_report_from_coin_set_update(current_height, current_coins)?;
+        report.block = Some(block.clone());
+        self.recent_blocks
+            .push_back((block.header_hash.clone(), self.current_coins.clone()));
+        if self.recent_blocks.len() > MAX_REORG_DEPTH {
+            self.recent_blocks.pop_front();
+        }
+        Ok(report)
+    }
 }
 
 // potato handler tests with simulator.
@@ -148,17 +253,384 @@ impl<'a> Iterator for RegisteredCoinsIterator<'a> {
     }
 }
 
+/// The result of one call to GameCradle::idle.  Most callers want
+/// SynchronousGameCradle::idle_events, which drains everything at once.
 #[derive(Default)]
 pub struct IdleResult {
     pub continue_on: bool,
     pub outbound_transactions: VecDeque<SpendBundle>,
     pub coin_solution_requests: VecDeque<CoinString>,
+    /// Coins registered for watching since the last idle.
+    pub coins_to_watch: VecDeque<CoinString>,
     pub outbound_messages: VecDeque<Vec<u8>>,
     pub opponent_move: Option<(GameID, ReadableMove)>,
     pub game_finished: Option<(GameID, Amount)>,
     pub receive_error: Option<Error>,
 }
 
+/// Everything SynchronousGameCradle::idle_events reports.  Moves are given as
+/// Program so that events can be kept or sent elsewhere without the
+/// allocator.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CradleEvent {
+    HandshakeFinished,
+    /// The channel coin was created on chain.
+    ChannelCreated(CoinString),
+    /// Send this to the peer.
+    OutboundMessage(Vec<u8>),
+    SelfMove(GameID, Vec<u8>),
+    OpponentMoved(GameID, Program),
+    RawGameMessage(GameID, Vec<u8>),
+    GameMessage(GameID, Program),
+    GameFinished(GameID, Amount),
+    GameCancelled(GameID),
+    OpponentSlashed(GameID, Amount),
+    GoingOnChain(GoingOnChainReason),
+    ShutdownComplete(CoinString),
+    GameProposed(WireGameStart),
+    GameProposalAccepted(GameID),
+    GameProposalDeclined(GameID),
+    GameProposalCancelled(GameID),
+    ChannelToppedUp(Amount, Amount),
+    ChannelWithdrawn(Amount, Amount),
+    SettlementOffered(GameID, Amount),
+    SettlementDeclined(GameID),
+    /// Submit this to the blockchain.
+    Transaction(SpendBundle),
+    /// Report this coin's creation, spend and timeout to new_block.
+    WatchCoin(CoinString),
+    /// Answer with report_puzzle_and_solution.
+    PuzzleSolutionRequest(CoinString),
+    /// Either a refused peer message, after which the channel goes on chain,
+    /// or a failure in idle itself.  These are never queued in a cradle, so
+    /// they can't be serialized.
+    #[serde(skip)]
+    Error(Error),
+}
+
+// Pass an event the UI hears about to local_ui.  The rest are handed out by
+// idle some other way.
+fn report_to_local_ui(
+    allocator: &mut AllocEncoder,
+    local_ui: &mut dyn ToLocalUI,
+    event: CradleEvent,
+) -> Result<(), Error> {
+    match event {
+        CradleEvent::SelfMove(id, readable) => local_ui.self_move(&id, &readable),
+        CradleEvent::OpponentMoved(id, readable) => {
+            let readable = ReadableMove::from_nodeptr(readable.to_nodeptr(allocator)?);
+            local_ui.opponent_moved(allocator, &id, readable)
+        }
+        CradleEvent::RawGameMessage(id, readable) => local_ui.raw_game_message(&id, &readable),
+        CradleEvent::GameMessage(id, readable) => {
+            let readable = ReadableMove::from_nodeptr(readable.to_nodeptr(allocator)?);
+            local_ui.game_message(allocator, &id, readable)
+        }
+        CradleEvent::GameFinished(id, my_share) => local_ui.game_finished(&id, my_share),
+        CradleEvent::GameCancelled(id) => local_ui.game_cancelled(&id),
+        CradleEvent::OpponentSlashed(id, my_reward) => local_ui.opponent_slashed(&id, my_reward),
+        CradleEvent::GoingOnChain(reason) => local_ui.going_on_chain(&reason),
+        CradleEvent::ShutdownComplete(reward) => local_ui.shutdown_complete(&reward),
+        CradleEvent::GameProposed(proposal) => local_ui.game_proposed(&proposal),
+        CradleEvent::GameProposalAccepted(id) => local_ui.game_proposal_accepted(&id),
+        CradleEvent::GameProposalDeclined(id) => local_ui.game_proposal_declined(&id),
+        CradleEvent::GameProposalCancelled(id) => local_ui.game_proposal_cancelled(&id),
+        CradleEvent::ChannelToppedUp(my_balance, their_balance) => {
+            local_ui.channel_topped_up(&my_balance, &their_balance)
+        }
+        CradleEvent::ChannelWithdrawn(my_balance, their_balance) => {
+            local_ui.channel_withdrawn(&my_balance, &their_balance)
+        }
+        CradleEvent::SettlementOffered(id, my_share) => local_ui.settlement_offered(&id, &my_share),
+        CradleEvent::SettlementDeclined(id) => local_ui.settlement_declined(&id),
+        CradleEvent::HandshakeFinished
+        | CradleEvent::ChannelCreated(_)
+        | CradleEvent::OutboundMessage(_)
+        | CradleEvent::Transaction(_)
+        | CradleEvent::WatchCoin(_)
+        | CradleEvent::PuzzleSolutionRequest(_)
+        | CradleEvent::Error(_) => Ok(()),
+    }
+}
+
+/// A ToLocalUI that records each callback as a CradleEvent.
+#[derive(Default)]
+pub struct CradleEventCollector {
+    events: VecDeque<CradleEvent>,
+}
+
+impl CradleEventCollector {
+    pub fn take_events(&mut self) -> VecDeque<CradleEvent> {
+        let mut events = VecDeque::default();
+        swap(&mut events, &mut self.events);
+        events
+    }
+}
+
+impl ToLocalUI for CradleEventCollector {
+    fn self_move(&mut self, id: &GameID, readable: &[u8]) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::SelfMove(id.clone(), readable.to_vec()));
+        Ok(())
+    }
+    fn opponent_moved(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        id: &GameID,
+        readable: ReadableMove,
+    ) -> Result<(), Error> {
+        let readable = Program::from_nodeptr(allocator, readable.to_nodeptr())?;
+        self.events
+            .push_back(CradleEvent::OpponentMoved(id.clone(), readable));
+        Ok(())
+    }
+    fn raw_game_message(&mut self, id: &GameID, readable: &[u8]) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::RawGameMessage(id.clone(), readable.to_vec()));
+        Ok(())
+    }
+    fn game_message(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        id: &GameID,
+        readable: ReadableMove,
+    ) -> Result<(), Error> {
+        let readable = Program::from_nodeptr(allocator, readable.to_nodeptr())?;
+        self.events
+            .push_back(CradleEvent::GameMessage(id.clone(), readable));
+        Ok(())
+    }
+    fn game_finished(&mut self, id: &GameID, my_share: Amount) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameFinished(id.clone(), my_share));
+        Ok(())
+    }
+    fn game_cancelled(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameCancelled(id.clone()));
+        Ok(())
+    }
+    fn opponent_slashed(&mut self, id: &GameID, my_reward: Amount) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::OpponentSlashed(id.clone(), my_reward));
+        Ok(())
+    }
+    fn shutdown_complete(&mut self, reward_coin_string: &CoinString) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::ShutdownComplete(reward_coin_string.clone()));
+        Ok(())
+    }
+    fn going_on_chain(&mut self, reason: &GoingOnChainReason) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GoingOnChain(reason.clone()));
+        Ok(())
+    }
+    fn game_proposed(&mut self, proposal: &WireGameStart) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposed(proposal.clone()));
+        Ok(())
+    }
+    fn game_proposal_accepted(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposalAccepted(id.clone()));
+        Ok(())
+    }
+    fn game_proposal_declined(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposalDeclined(id.clone()));
+        Ok(())
+    }
+    fn game_proposal_cancelled(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposalCancelled(id.clone()));
+        Ok(())
+    }
+    fn channel_topped_up(
+        &mut self,
+        my_balance: &Amount,
+        their_balance: &Amount,
+    ) -> Result<(), Error> {
+        self.events.push_back(CradleEvent::ChannelToppedUp(
+            my_balance.clone(),
+            their_balance.clone(),
+        ));
+        Ok(())
+    }
+    fn channel_withdrawn(
+        &mut self,
+        my_balance: &Amount,
+        their_balance: &Amount,
+    ) -> Result<(), Error> {
+        self.events.push_back(CradleEvent::ChannelWithdrawn(
+            my_balance.clone(),
+            their_balance.clone(),
+        ));
+        Ok(())
+    }
+    fn settlement_offered(&mut self, id: &GameID, my_share: &Amount) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::SettlementOffered(id.clone(), my_share.clone()));
+        Ok(())
+    }
+    fn settlement_declined(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::SettlementDeclined(id.clone()));
+        Ok(())
+    }
+}
+
+/// Wire wrapper around an encoded PeerMessage.  Messages are numbered so the
+/// receiver can acknowledge them and drop duplicates.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PeerEnvelope {
+    Message {
+        seq: u64,
+        ack: u64,
+        msg: Vec<u8>,
+    },
+    Ack(u64),
+    /// Sent by both sides after the transport reconnects, giving the last
+    /// sequence number received and the sender's view of the channel.
+    Resume {
+        ack: u64,
+        state_number: usize,
+        have_potato: bool,
+    },
+}
+
+/// What an inbound envelope asks of the cradle.
+pub enum SequencedInbound {
+    Message(Vec<u8>),
+    Resume {
+        state_number: usize,
+        have_potato: bool,
+    },
+    Nothing,
+}
+
+/// Numbering, acknowledgement and retransmission of messages exchanged with
+/// the peer.  Every message sent is kept until the peer acknowledges it so
+/// that it can be sent again after a reconnect.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct MessageSequencer {
+    // Sequence number of the last message we sent.
+    last_sent: u64,
+    // Highest sequence number received in order from the peer.
+    last_received: u64,
+    // Messages the peer hasn't acknowledged yet, oldest first.
+    unacked: VecDeque<(u64, Vec<u8>)>,
+    // We've received something we haven't acknowledged yet.
+    ack_pending: bool,
+}
+
+impl MessageSequencer {
+    fn encode(envelope: &PeerEnvelope) -> Result<Vec<u8>, Error> {
+        let bson_doc = bson::to_bson(envelope).map_err(|e| Error::StrErr(format!("{e:?}")))?;
+        bson::to_vec(&bson_doc).map_err(|e| Error::StrErr(format!("{e:?}")))
+    }
+
+    fn acknowledged(&mut self, ack: u64) {
+        while let Some((seq, _)) = self.unacked.front() {
+            if *seq > ack {
+                break;
+            }
+            self.unacked.pop_front();
+        }
+    }
+
+    pub fn has_unacked(&self) -> bool {
+        !self.unacked.is_empty()
+    }
+
+    /// Number an outbound message, keeping it until it's acknowledged.
+    pub fn wrap(&mut self, msg: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.last_sent += 1;
+        self.unacked.push_back((self.last_sent, msg.clone()));
+        self.ack_pending = false;
+        MessageSequencer::encode(&PeerEnvelope::Message {
+            seq: self.last_sent,
+            ack: self.last_received,
+            msg,
+        })
+    }
+
+    /// A standalone ack if we've received messages that no outbound message
+    /// acknowledged.
+    pub fn take_ack(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if !self.ack_pending {
+            return Ok(None);
+        }
+
+        self.ack_pending = false;
+        Ok(Some(MessageSequencer::encode(&PeerEnvelope::Ack(
+            self.last_received,
+        ))?))
+    }
+
+    pub fn resume(&self, state_number: usize, have_potato: bool) -> Result<Vec<u8>, Error> {
+        MessageSequencer::encode(&PeerEnvelope::Resume {
+            ack: self.last_received,
+            state_number,
+            have_potato,
+        })
+    }
+
+    /// Envelopes for every message the peer hasn't acknowledged, in order.
+    pub fn retransmit(&mut self) -> Result<Vec<Vec<u8>>, Error> {
+        self.ack_pending = false;
+        self.unacked
+            .iter()
+            .map(|(seq, msg)| {
+                MessageSequencer::encode(&PeerEnvelope::Message {
+                    seq: *seq,
+                    ack: self.last_received,
+                    msg: msg.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn unwrap(&mut self, data: &[u8]) -> Result<SequencedInbound, Error> {
+        let malformed = |e: String| Error::Protocol(ProtocolError::Malformed(e));
+        let doc =
+            bson::Document::from_reader(&mut &data[..]).map_err(|e| malformed(format!("{e:?}")))?;
+        let envelope: PeerEnvelope =
+            bson::from_bson(bson::Bson::Document(doc)).map_err(|e| malformed(format!("{e:?}")))?;
+        match envelope {
+            PeerEnvelope::Message { seq, ack, msg } => {
+                self.acknowledged(ack);
+                self.ack_pending = true;
+                if seq != self.last_received + 1 {
+                    // Either a duplicate or a message after a gap.  The peer
+                    // resends everything we haven't acknowledged on resume.
+                    debug!(
+                        "dropping message {seq}, last received {}",
+                        self.last_received
+                    );
+                    return Ok(SequencedInbound::Nothing);
+                }
+                self.last_received = seq;
+                Ok(SequencedInbound::Message(msg))
+            }
+            PeerEnvelope::Ack(ack) => {
+                self.acknowledged(ack);
+                Ok(SequencedInbound::Nothing)
+            }
+            PeerEnvelope::Resume {
+                ack,
+                state_number,
+                have_potato,
+            } => {
+                self.acknowledged(ack);
+                Ok(SequencedInbound::Resume {
+                    state_number,
+                    have_potato,
+                })
+            }
+        }
+    }
+}
+
 pub trait GameCradle {
     /// Tell this cradle to use this coin for funding.
     fn opening_coin<R: Rng>(
@@ -199,9 +671,93 @@ pub trait GameCradle {
         id: &GameID,
     ) -> Result<(), Error>;
 
-    /// Signal shutdown.  Forwards to FromLocalUI::shut_down.
-    /// Perhaps we should consider reporting the reward coins.
-    fn shut_down(&mut self) -> Result<(), Error>;
+    /// Offer a game to the peer.  Forwards to FromLocalUI::propose_game.
+    fn propose_game<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        game: &GameStart,
+    ) -> Result<Vec<GameID>, Error>;
+
+    /// Agree to a game the peer proposed.  Forwards to
+    /// FromLocalUI::accept_proposal.
+    fn accept_proposal<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error>;
+
+    /// Refuse a game the peer proposed.  Forwards to
+    /// FromLocalUI::decline_proposal.
+    fn decline_proposal<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error>;
+
+    /// Withdraw one of our proposals.  Forwards to
+    /// FromLocalUI::cancel_proposal.
+    fn cancel_proposal<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error>;
+
+    /// Add this coin's value to our side of the channel.  Forwards to
+    /// FromLocalUI::top_up; the coin is spent once the peer countersigns and
+    /// channel_topped_up reports the new balances.
+    fn top_up<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        coin: CoinString,
+    ) -> Result<(), Error>;
+
+    /// Take amount out of the channel to our reward puzzle hash.  Forwards to
+    /// FromLocalUI::withdraw; channel_withdrawn reports the new balances.
+    fn withdraw<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        amount: &Amount,
+    ) -> Result<(), Error>;
+
+    /// Offer to end a game with us receiving my_share.  Forwards to
+    /// FromLocalUI::offer_settlement.
+    fn offer_settlement<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+        my_share: &Amount,
+    ) -> Result<(), Error>;
+
+    /// Agree to the peer's settlement offer.  Forwards to
+    /// FromLocalUI::accept_settlement.
+    fn accept_settlement<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error>;
+
+    /// Refuse the peer's settlement offer.  Forwards to
+    /// FromLocalUI::decline_settlement.
+    fn decline_settlement<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error>;
+
+    /// Signal shutdown.  Forwards to FromLocalUI::shut_down with conditions
+    /// paying each side its balance.  Live games are accepted first and
+    /// shutdown_complete reports our reward coin.
+    fn shut_down<R: Rng>(&mut self, allocator: &mut AllocEncoder, rng: &mut R)
+        -> Result<(), Error>;
 
     /// Tell the game cradle that a new block arrived, giving a watch report.
     fn new_block<R: Rng>(
@@ -215,6 +771,10 @@ pub trait GameCradle {
     /// Deliver a message from the peer.
     fn deliver_message(&mut self, inbound_message: &[u8]) -> Result<(), Error>;
 
+    /// Resynchronize with the peer after the transport reconnects.  Both sides
+    /// call this; each then resends whatever the other hasn't acknowledged.
+    fn resume(&mut self) -> Result<(), Error>;
+
     /// Allow the game to carry out tasks it needs to perform, yielding peer messages that
     /// should be forwarded.  Returns false when no more work is needed.
     fn idle<R: Rng>(
@@ -245,24 +805,76 @@ pub trait GameCradle {
     ) -> Result<(), Error>;
 }
 
+// What's needed to undo one block after a reorg.
+struct BlockRecord {
+    header_hash: Hash,
+    // Watch entries as they were before the block touched them.
+    watch_undo: Vec<(CoinString, WatchEntry)>,
+    // Kept when the block sent the channel toward or along on chain play:
+    // the handler and every watched coin from before the block.
+    handler: Option<(PotatoHandlerSnapshot, HashMap<CoinString, WatchEntry>)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct FlatBlockRecord {
+    header_hash: Hash,
+    watch_undo: Vec<(CoinString, FlatWatchEntry)>,
+    handler: Option<(PotatoHandlerSnapshot, Vec<(CoinString, FlatWatchEntry)>)>,
+}
+
+impl BlockRecord {
+    fn to_serializable(&self) -> FlatBlockRecord {
+        FlatBlockRecord {
+            header_hash: self.header_hash.clone(),
+            watch_undo: watch_entries_to_serializable(self.watch_undo.iter().map(|(c, w)| (c, w))),
+            handler: self
+                .handler
+                .as_ref()
+                .map(|(h, watching)| (h.clone(), watch_entries_to_serializable(watching.iter()))),
+        }
+    }
+
+    fn from_serializable(serializable: &FlatBlockRecord) -> BlockRecord {
+        BlockRecord {
+            header_hash: serializable.header_hash.clone(),
+            watch_undo: watch_entries_from_serializable(&serializable.watch_undo),
+            handler: serializable
+                .handler
+                .as_ref()
+                .map(|(h, watching)| (h.clone(), watch_entries_from_serializable(watching))),
+        }
+    }
+}
+
 struct SynchronousGameCradleState {
     current_height: u64,
     watching_coins: HashMap<CoinString, WatchEntry>,
+    // The last MAX_REORG_DEPTH blocks, newest last.
+    blocks: VecDeque<BlockRecord>,
+    // Transactions sent in the last MAX_REORG_DEPTH blocks with the height
+    // they were sent at.  They're sent again after a reorg.
+    recent_transactions: VecDeque<(u64, SpendBundle)>,
 
     is_initiator: bool,
+    validate_outbound: bool,
     channel_puzzle_hash: Option<PuzzleHash>,
     funding_coin: Option<CoinString>,
     unfunded_offer: Option<SpendBundle>,
     inbound_messages: VecDeque<Vec<u8>>,
-    outbound_messages: VecDeque<Vec<u8>>,
-    outbound_transactions: VecDeque<SpendBundle>,
-    coin_solution_requests: VecDeque<CoinString>,
-    our_moves: VecDeque<(GameID, Vec<u8>)>,
-    opponent_moves: VecDeque<(GameID, ReadableMove)>,
-    raw_game_messages: VecDeque<(GameID, Vec<u8>)>,
-    game_messages: VecDeque<(GameID, ReadableMove)>,
-    game_finished: VecDeque<(GameID, Amount)>,
-    shutdown: Option<CoinString>,
+    // Everything idle has yet to hand out, in the order it happened.
+    // Outbound messages are sequenced as they leave.
+    events: VecDeque<CradleEvent>,
+    sequencer: MessageSequencer,
+    // We owe the peer a resume message.
+    resume_requested: bool,
+    // The peer resumed and wants our unacknowledged messages again.
+    retransmit_requested: bool,
+    channel_created_seen: bool,
+    handshake_finished_reported: bool,
+    // The coin we're adding to the channel and, once the peer countersigns,
+    // the channel coin spend it goes with.
+    top_up_coin: Option<CoinString>,
+    top_up_offer: Option<SpendBundle>,
     identity: ChiaIdentity,
     #[allow(dead_code)]
     on_chain_game_coins: Vec<OnChainGameCoin>,
@@ -272,7 +884,8 @@ impl PacketSender for SynchronousGameCradleState {
     fn send_message(&mut self, msg: &PeerMessage) -> Result<(), Error> {
         let bson_doc = bson::to_bson(&msg).map_err(|e| Error::StrErr(format!("{e:?}")))?;
         let msg_data = bson::to_vec(&bson_doc).map_err(|e| Error::StrErr(format!("{e:?}")))?;
-        self.outbound_messages.push_back(msg_data);
+        self.events
+            .push_back(CradleEvent::OutboundMessage(msg_data));
         Ok(())
     }
 }
@@ -280,7 +893,14 @@ impl PacketSender for SynchronousGameCradleState {
 impl WalletSpendInterface for SynchronousGameCradleState {
     /// Enqueue an outbound transaction.
     fn spend_transaction_and_add_fee(&mut self, bundle: &SpendBundle) -> Result<(), Error> {
-        self.outbound_transactions.push_back(bundle.clone());
+        if self.validate_outbound {
+            let cost = bundle.validate(&mut AllocEncoder::new())?;
+            debug!("outbound transaction validated with cost {cost}");
+        }
+        self.events
+            .push_back(CradleEvent::Transaction(bundle.clone()));
+        self.recent_transactions
+            .push_back((self.current_height, bundle.clone()));
         Ok(())
     }
     /// Coin should report its lifecycle until it gets spent, then should be
@@ -292,6 +912,8 @@ impl WalletSpendInterface for SynchronousGameCradleState {
         name: Option<&'static str>,
     ) -> Result<(), Error> {
         debug!("register coin {coin_id:?} as {name:?}");
+        self.events
+            .push_back(CradleEvent::WatchCoin(coin_id.clone()));
         self.watching_coins.insert(
             coin_id.clone(),
             WatchEntry {
@@ -306,13 +928,14 @@ impl WalletSpendInterface for SynchronousGameCradleState {
     /// Request the puzzle and solution from a coin spend.
     fn request_puzzle_and_solution(&mut self, coin_id: &CoinString) -> Result<(), Error> {
         debug!("request puzzle and solution for {coin_id:?}");
-        self.coin_solution_requests.push_back(coin_id.clone());
+        self.events
+            .push_back(CradleEvent::PuzzleSolutionRequest(coin_id.clone()));
         Ok(())
     }
 }
 
-/// A game cradle that operates synchronously.  It can be composed with a game cradle that
-/// operates message pipes to become asynchronous.
+/// A game cradle that operates synchronously.  async_cradle::AsyncGameCradle runs one in
+/// its own task to make it asynchronous.
 pub struct SynchronousGameCradle {
     state: SynchronousGameCradleState,
     peer: PotatoHandler,
@@ -327,6 +950,10 @@ pub struct SynchronousGameCradleConfig<'a> {
     pub channel_timeout: Timeout,
     pub unroll_timeout: Timeout,
     pub reward_puzzle_hash: PuzzleHash,
+    pub liveness: LivenessPolicy,
+    /// Check each outbound transaction with SpendBundle::validate before
+    /// handing it out, failing the action that produced it if it's bad.
+    pub validate_outbound: bool,
 }
 
 impl SynchronousGameCradle {
@@ -335,22 +962,24 @@ impl SynchronousGameCradle {
         SynchronousGameCradle {
             state: SynchronousGameCradleState {
                 is_initiator: config.have_potato,
+                validate_outbound: config.validate_outbound,
                 current_height: 0,
                 watching_coins: HashMap::default(),
+                blocks: VecDeque::default(),
+                recent_transactions: VecDeque::default(),
                 identity: config.identity.clone(),
                 inbound_messages: VecDeque::default(),
-                outbound_transactions: VecDeque::default(),
-                outbound_messages: VecDeque::default(),
-                coin_solution_requests: VecDeque::default(),
-                our_moves: VecDeque::default(),
-                opponent_moves: VecDeque::default(),
-                game_messages: VecDeque::default(),
-                raw_game_messages: VecDeque::default(),
-                game_finished: VecDeque::default(),
+                events: VecDeque::default(),
+                sequencer: MessageSequencer::default(),
+                resume_requested: false,
+                retransmit_requested: false,
+                channel_created_seen: false,
+                handshake_finished_reported: false,
+                top_up_coin: None,
+                top_up_offer: None,
                 channel_puzzle_hash: None,
                 funding_coin: None,
                 unfunded_offer: None,
-                shutdown: None,
                 on_chain_game_coins: Vec::default(),
             },
             peer: PotatoHandler::new(PotatoHandlerInit {
@@ -362,11 +991,156 @@ impl SynchronousGameCradle {
                 channel_timeout: config.channel_timeout,
                 unroll_timeout: config.unroll_timeout,
                 reward_puzzle_hash: config.reward_puzzle_hash,
+                liveness: config.liveness,
             }),
         }
     }
 }
 
+/// Version of the game cradle snapshot format.  Bump this when the contents
+/// of SynchronousGameCradleSnapshot change incompatibly.
+pub const SYNCHRONOUS_GAME_CRADLE_SNAPSHOT_VERSION: u32 = 2;
+
+/// A complete, serializable copy of a SynchronousGameCradle: our identity,
+/// the watched coins, the queues that idle hasn't handed out yet and a
+/// snapshot of the potato handler.
+#[derive(Serialize, Deserialize)]
+pub struct SynchronousGameCradleSnapshot {
+    version: u32,
+
+    current_height: u64,
+    watching_coins: Vec<(CoinString, FlatWatchEntry)>,
+    blocks: Vec<FlatBlockRecord>,
+    recent_transactions: Vec<(u64, SpendBundle)>,
+
+    is_initiator: bool,
+    validate_outbound: bool,
+    channel_puzzle_hash: Option<PuzzleHash>,
+    funding_coin: Option<CoinString>,
+    unfunded_offer: Option<SpendBundle>,
+    inbound_messages: Vec<Vec<u8>>,
+    // Each queued CradleEvent as bson, since events don't clone.
+    events: Vec<bson::Bson>,
+    sequencer: MessageSequencer,
+    resume_requested: bool,
+    retransmit_requested: bool,
+    channel_created_seen: bool,
+    handshake_finished_reported: bool,
+    top_up_coin: Option<CoinString>,
+    top_up_offer: Option<SpendBundle>,
+    identity: ChiaIdentity,
+    on_chain_game_coins: Vec<OnChainGameCoin>,
+
+    peer: PotatoHandlerSnapshot,
+}
+
+impl SynchronousGameCradle {
+    /// Capture the full state of this cradle so that it can be stored and
+    /// later restored with from_snapshot, for example across a restart.
+    pub fn to_snapshot(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<SynchronousGameCradleSnapshot, Error> {
+        let state = &self.state;
+        Ok(SynchronousGameCradleSnapshot {
+            version: SYNCHRONOUS_GAME_CRADLE_SNAPSHOT_VERSION,
+            current_height: state.current_height,
+            watching_coins: watch_entries_to_serializable(state.watching_coins.iter()),
+            blocks: state.blocks.iter().map(|b| b.to_serializable()).collect(),
+            recent_transactions: state.recent_transactions.iter().cloned().collect(),
+            is_initiator: state.is_initiator,
+            validate_outbound: state.validate_outbound,
+            channel_puzzle_hash: state.channel_puzzle_hash.clone(),
+            funding_coin: state.funding_coin.clone(),
+            unfunded_offer: state.unfunded_offer.clone(),
+            inbound_messages: state.inbound_messages.iter().cloned().collect(),
+            events: state
+                .events
+                .iter()
+                .map(bson::to_bson)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::StrErr(format!("{e:?}")))?,
+            sequencer: state.sequencer.clone(),
+            resume_requested: state.resume_requested,
+            retransmit_requested: state.retransmit_requested,
+            channel_created_seen: state.channel_created_seen,
+            handshake_finished_reported: state.handshake_finished_reported,
+            top_up_coin: state.top_up_coin.clone(),
+            top_up_offer: state.top_up_offer.clone(),
+            identity: state.identity.clone(),
+            on_chain_game_coins: state.on_chain_game_coins.clone(),
+            peer: self.peer.to_snapshot(allocator)?,
+        })
+    }
+
+    /// Rebuild a cradle from a snapshot, placing its clvm data in the given
+    /// allocator.
+    pub fn from_snapshot(
+        allocator: &mut AllocEncoder,
+        snapshot: &SynchronousGameCradleSnapshot,
+    ) -> Result<SynchronousGameCradle, Error> {
+        if snapshot.version != SYNCHRONOUS_GAME_CRADLE_SNAPSHOT_VERSION {
+            return Err(Error::StrErr(format!(
+                "unsupported game cradle snapshot version {} (expected {})",
+                snapshot.version, SYNCHRONOUS_GAME_CRADLE_SNAPSHOT_VERSION
+            )));
+        }
+
+        Ok(SynchronousGameCradle {
+            state: SynchronousGameCradleState {
+                current_height: snapshot.current_height,
+                watching_coins: watch_entries_from_serializable(&snapshot.watching_coins),
+                blocks: snapshot
+                    .blocks
+                    .iter()
+                    .map(BlockRecord::from_serializable)
+                    .collect(),
+                recent_transactions: snapshot.recent_transactions.iter().cloned().collect(),
+                is_initiator: snapshot.is_initiator,
+                validate_outbound: snapshot.validate_outbound,
+                channel_puzzle_hash: snapshot.channel_puzzle_hash.clone(),
+                funding_coin: snapshot.funding_coin.clone(),
+                unfunded_offer: snapshot.unfunded_offer.clone(),
+                inbound_messages: snapshot.inbound_messages.iter().cloned().collect(),
+                events: snapshot
+                    .events
+                    .iter()
+                    .map(|event| bson::from_bson(event.clone()))
+                    .collect::<Result<VecDeque<_>, _>>()
+                    .into_gen()?,
+                sequencer: snapshot.sequencer.clone(),
+                resume_requested: snapshot.resume_requested,
+                retransmit_requested: snapshot.retransmit_requested,
+                channel_created_seen: snapshot.channel_created_seen,
+                handshake_finished_reported: snapshot.handshake_finished_reported,
+                top_up_coin: snapshot.top_up_coin.clone(),
+                top_up_offer: snapshot.top_up_offer.clone(),
+                identity: snapshot.identity.clone(),
+                on_chain_game_coins: snapshot.on_chain_game_coins.clone(),
+            },
+            peer: PotatoHandler::from_snapshot(allocator, &snapshot.peer)?,
+        })
+    }
+
+    /// Encode a snapshot of this cradle as bson bytes.
+    pub fn to_snapshot_bytes(&self, allocator: &mut AllocEncoder) -> Result<Vec<u8>, Error> {
+        let snapshot = self.to_snapshot(allocator)?;
+        let bson_doc = bson::to_bson(&snapshot).map_err(|e| Error::StrErr(format!("{e:?}")))?;
+        bson::to_vec(&bson_doc).map_err(|e| Error::StrErr(format!("{e:?}")))
+    }
+
+    /// Decode bytes from to_snapshot_bytes and rebuild the cradle.
+    pub fn from_snapshot_bytes(
+        allocator: &mut AllocEncoder,
+        data: &[u8],
+    ) -> Result<SynchronousGameCradle, Error> {
+        let doc = bson::Document::from_reader(&mut &data[..]).into_gen()?;
+        let snapshot: SynchronousGameCradleSnapshot =
+            bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
+        SynchronousGameCradle::from_snapshot(allocator, &snapshot)
+    }
+}
+
 impl BootstrapTowardWallet for SynchronousGameCradleState {
     fn channel_puzzle_hash(&mut self, puzzle_hash: &PuzzleHash) -> Result<(), Error> {
         self.channel_puzzle_hash = Some(puzzle_hash.clone());
@@ -385,63 +1159,136 @@ impl BootstrapTowardWallet for SynchronousGameCradleState {
     ) -> Result<(), Error> {
         todo!();
     }
+
+    fn channel_top_up(&mut self, bundle: &SpendBundle) -> Result<(), Error> {
+        self.top_up_offer = Some(bundle.clone());
+        Ok(())
+    }
 }
 
 impl ToLocalUI for SynchronousGameCradleState {
     fn self_move(&mut self, id: &GameID, readable: &[u8]) -> Result<(), Error> {
-        self.our_moves.push_back((id.clone(), readable.to_vec()));
+        self.events
+            .push_back(CradleEvent::SelfMove(id.clone(), readable.to_vec()));
         Ok(())
     }
 
     fn opponent_moved(
         &mut self,
-        _allocator: &mut AllocEncoder,
+        allocator: &mut AllocEncoder,
         id: &GameID,
         readable: ReadableMove,
     ) -> Result<(), Error> {
-        self.opponent_moves.push_back((id.clone(), readable));
+        let readable = Program::from_nodeptr(allocator, readable.to_nodeptr())?;
+        self.events
+            .push_back(CradleEvent::OpponentMoved(id.clone(), readable));
         Ok(())
     }
     fn raw_game_message(&mut self, id: &GameID, readable: &[u8]) -> Result<(), Error> {
-        self.raw_game_messages
-            .push_back((id.clone(), readable.to_vec()));
+        self.events
+            .push_back(CradleEvent::RawGameMessage(id.clone(), readable.to_vec()));
         Ok(())
     }
     fn game_message(
         &mut self,
-        _allocator: &mut AllocEncoder,
+        allocator: &mut AllocEncoder,
         id: &GameID,
         readable: ReadableMove,
     ) -> Result<(), Error> {
-        self.game_messages.push_back((id.clone(), readable.clone()));
+        let readable = Program::from_nodeptr(allocator, readable.to_nodeptr())?;
+        self.events
+            .push_back(CradleEvent::GameMessage(id.clone(), readable));
         Ok(())
     }
     fn game_finished(&mut self, id: &GameID, my_share: Amount) -> Result<(), Error> {
-        self.game_finished.push_back((id.clone(), my_share));
+        self.events
+            .push_back(CradleEvent::GameFinished(id.clone(), my_share));
         Ok(())
     }
-    fn game_cancelled(&mut self, _id: &GameID) -> Result<(), Error> {
-        todo!();
+    fn game_cancelled(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameCancelled(id.clone()));
+        Ok(())
+    }
+    fn opponent_slashed(&mut self, id: &GameID, my_reward: Amount) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::OpponentSlashed(id.clone(), my_reward));
+        Ok(())
     }
     fn shutdown_complete(&mut self, reward_coin_string: &CoinString) -> Result<(), Error> {
-        self.shutdown = Some(reward_coin_string.clone());
+        self.events
+            .push_back(CradleEvent::ShutdownComplete(reward_coin_string.clone()));
         Ok(())
     }
-    fn going_on_chain(&mut self) -> Result<(), Error> {
-        todo!();
+    fn going_on_chain(&mut self, reason: &GoingOnChainReason) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GoingOnChain(reason.clone()));
+        Ok(())
     }
-}
-
-struct SynchronousGamePeerEnv<'a, R: Rng> {
-    env: &'a mut ChannelHandlerEnv<'a, R>,
-    system_interface: &'a mut SynchronousGameCradleState,
-}
-
-impl<'a, R: Rng> PeerEnv<'a, SynchronousGameCradleState, R> for SynchronousGamePeerEnv<'a, R> {
-    fn env(
-        &mut self,
-    ) -> (
-        &mut ChannelHandlerEnv<'a, R>,
+    fn game_proposed(&mut self, proposal: &WireGameStart) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposed(proposal.clone()));
+        Ok(())
+    }
+    fn game_proposal_accepted(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposalAccepted(id.clone()));
+        Ok(())
+    }
+    fn game_proposal_declined(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposalDeclined(id.clone()));
+        Ok(())
+    }
+    fn game_proposal_cancelled(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::GameProposalCancelled(id.clone()));
+        Ok(())
+    }
+    fn channel_topped_up(
+        &mut self,
+        my_balance: &Amount,
+        their_balance: &Amount,
+    ) -> Result<(), Error> {
+        self.events.push_back(CradleEvent::ChannelToppedUp(
+            my_balance.clone(),
+            their_balance.clone(),
+        ));
+        Ok(())
+    }
+    fn channel_withdrawn(
+        &mut self,
+        my_balance: &Amount,
+        their_balance: &Amount,
+    ) -> Result<(), Error> {
+        self.events.push_back(CradleEvent::ChannelWithdrawn(
+            my_balance.clone(),
+            their_balance.clone(),
+        ));
+        Ok(())
+    }
+    fn settlement_offered(&mut self, id: &GameID, my_share: &Amount) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::SettlementOffered(id.clone(), my_share.clone()));
+        Ok(())
+    }
+    fn settlement_declined(&mut self, id: &GameID) -> Result<(), Error> {
+        self.events
+            .push_back(CradleEvent::SettlementDeclined(id.clone()));
+        Ok(())
+    }
+}
+
+struct SynchronousGamePeerEnv<'a, R: Rng> {
+    env: &'a mut ChannelHandlerEnv<'a, R>,
+    system_interface: &'a mut SynchronousGameCradleState,
+}
+
+impl<'a, R: Rng> PeerEnv<'a, SynchronousGameCradleState, R> for SynchronousGamePeerEnv<'a, R> {
+    fn env(
+        &mut self,
+    ) -> (
+        &mut ChannelHandlerEnv<'a, R>,
         &mut SynchronousGameCradleState,
     ) {
         (self.env, self.system_interface)
@@ -485,6 +1332,103 @@ impl SynchronousGameCradle {
         self.peer.amount()
     }
 
+    /// Call idle until it has nothing more to do, returning everything it
+    /// produced in the order it happened.  Nothing is reported through
+    /// ToLocalUI.  HandshakeFinished follows the events of the pass that
+    /// finished the handshake.  If idle fails the error is the last event.
+    pub fn idle_events<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+    ) -> VecDeque<CradleEvent> {
+        let mut events = VecDeque::default();
+        loop {
+            let step = self.step(allocator, rng);
+            match self.take_events() {
+                Ok(mut taken) => events.append(&mut taken),
+                Err(e) => {
+                    events.push_back(CradleEvent::Error(e));
+                    break;
+                }
+            }
+            let result = match step {
+                Ok(result) => result,
+                Err(e) => {
+                    events.push_back(CradleEvent::Error(e));
+                    break;
+                }
+            };
+
+            if !self.state.handshake_finished_reported && self.handshake_finished() {
+                self.state.handshake_finished_reported = true;
+                events.push_back(CradleEvent::HandshakeFinished);
+            }
+            if let Some(e) = result.receive_error {
+                events.push_back(CradleEvent::Error(e));
+            }
+
+            if !result.continue_on {
+                break;
+            }
+        }
+        events
+    }
+
+    /// Undo the blocks a reorg orphaned, back to the one named parent_hash,
+    /// and send our recent transactions again in case they were in them.
+    fn undo_orphaned_blocks(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        parent_hash: &Hash,
+    ) -> Result<(), Error> {
+        let mut handler = None;
+        loop {
+            let record = match self.state.blocks.pop_back() {
+                Some(record) if record.header_hash == *parent_hash => {
+                    self.state.blocks.push_back(record);
+                    break;
+                }
+                Some(record) => record,
+                None => {
+                    return Err(Error::StrErr(format!(
+                        "reorg deeper than {MAX_REORG_DEPTH} blocks"
+                    )));
+                }
+            };
+            debug!("undoing orphaned block {:?}", record.header_hash);
+            for (coin, entry) in record.watch_undo.into_iter().rev() {
+                self.state.watching_coins.insert(coin, entry);
+            }
+            if record.handler.is_some() {
+                handler = record.handler;
+            }
+        }
+
+        // Only roll the handler back if it's still acting on the orphaned
+        // spends.  A splice, for one, lands back in the channel and confirms
+        // again once its transaction is resent.
+        if let Some((snapshot, watching_coins)) = handler {
+            if self.peer.is_going_on_chain() || self.peer.is_on_chain() {
+                debug!("reorg undid the channel going on chain");
+                self.peer = PotatoHandler::from_snapshot(allocator, &snapshot)?;
+                self.state.watching_coins = watching_coins;
+            }
+        }
+
+        // Any already in the new chain are simply refused.
+        for (_, tx) in self.state.recent_transactions.iter() {
+            self.state
+                .events
+                .push_back(CradleEvent::Transaction(tx.clone()));
+        }
+        Ok(())
+    }
+
+    /// Coins registered with register_coin that haven't been spent.
+    pub fn watched_coins(&self) -> impl Iterator<Item = &CoinString> {
+        self.state.watching_coins.keys()
+    }
+
     fn create_partial_spend_for_channel_coin<R: Rng>(
         &mut self,
         allocator: &mut AllocEncoder,
@@ -553,6 +1497,133 @@ impl SynchronousGameCradle {
         Ok(true)
     }
 
+    /// Do one piece of work, queueing whatever it produces.  The result only
+    /// says whether to continue and gives any error receiving a message.
+    fn step<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+    ) -> Result<IdleResult, Error> {
+        let mut result = IdleResult::default();
+
+        // If there's a message to deliver, deliver it and signal to continue.
+        if let Some(envelope) = self.state.inbound_messages.pop_front() {
+            let msg = match self.state.sequencer.unwrap(&envelope) {
+                Ok(SequencedInbound::Message(msg)) => msg,
+                Ok(SequencedInbound::Resume {
+                    state_number,
+                    have_potato,
+                }) => {
+                    let ours_in_flight = self.state.sequencer.has_unacked();
+                    if let Err(e) =
+                        self.peer
+                            .check_resume(state_number, have_potato, ours_in_flight)
+                    {
+                        self.state
+                            .going_on_chain(&GoingOnChainReason::ReceiveError(format!("{e:?}")))?;
+                        result.receive_error = Some(e);
+                        return Ok(result);
+                    }
+                    self.state.retransmit_requested = true;
+                    result.continue_on = true;
+                    return Ok(result);
+                }
+                Ok(SequencedInbound::Nothing) => {
+                    result.continue_on = true;
+                    return Ok(result);
+                }
+                Err(e) => {
+                    self.state
+                        .going_on_chain(&GoingOnChainReason::ReceiveError(format!("{e:?}")))?;
+                    result.receive_error = Some(e);
+                    return Ok(result);
+                }
+            };
+            let mut env = channel_handler_env(allocator, rng);
+            let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+                env: &mut env,
+                system_interface: &mut self.state,
+            };
+            // Try to receive a message.  If we get failure back, then a cheat was probably
+            // attempted so we need to go on chain.
+            match self.peer.received_message(&mut penv, msg) {
+                Ok(_) => {
+                    result.continue_on = true;
+                    return Ok(result);
+                }
+                Err(e) => {
+                    // Go on chain.
+                    self.state
+                        .going_on_chain(&GoingOnChainReason::ReceiveError(format!("{e:?}")))?;
+                    result.receive_error = Some(e);
+                    return Ok(result);
+                }
+            }
+        }
+
+        if let Some(ph) = self.state.channel_puzzle_hash.clone() {
+            result.continue_on = self.create_partial_spend_for_channel_coin(allocator, rng, ph)?;
+            return Ok(result);
+        }
+
+        if let (false, Some(uo)) = (self.state.is_initiator, self.state.unfunded_offer.clone()) {
+            result.continue_on = self.respond_to_unfunded_offer(allocator, rng, uo)?;
+            return Ok(result);
+        }
+
+        if let Some(offer) = self.state.top_up_offer.clone() {
+            result.continue_on = self.submit_top_up(allocator, rng, offer)?;
+            return Ok(result);
+        }
+
+        Ok(result)
+    }
+
+    /// Take the queued events in the order they happened, sequencing peer
+    /// messages into envelopes.  Anything a reconnect asked for goes first.
+    fn take_events(&mut self) -> Result<VecDeque<CradleEvent>, Error> {
+        let mut events = VecDeque::default();
+        if self.state.resume_requested {
+            self.state.resume_requested = false;
+            let state_number = self
+                .peer
+                .channel_handler()
+                .map(|ch| ch.get_state_number())
+                .unwrap_or(0);
+            let resume = self
+                .state
+                .sequencer
+                .resume(state_number, self.peer.has_potato())?;
+            events.push_back(CradleEvent::OutboundMessage(resume));
+        }
+
+        if self.state.retransmit_requested {
+            self.state.retransmit_requested = false;
+            events.extend(
+                self.state
+                    .sequencer
+                    .retransmit()?
+                    .into_iter()
+                    .map(CradleEvent::OutboundMessage),
+            );
+        }
+
+        while let Some(event) = self.state.events.pop_front() {
+            if let CradleEvent::OutboundMessage(msg) = event {
+                let envelope = self.state.sequencer.wrap(msg)?;
+                events.push_back(CradleEvent::OutboundMessage(envelope));
+            } else {
+                events.push_back(event);
+            }
+        }
+
+        if let Some(ack) = self.state.sequencer.take_ack()? {
+            events.push_back(CradleEvent::OutboundMessage(ack));
+        }
+
+        Ok(events)
+    }
+
     fn respond_to_unfunded_offer<R: Rng>(
         &mut self,
         allocator: &mut AllocEncoder,
@@ -568,54 +1639,136 @@ impl SynchronousGameCradle {
         self.state.unfunded_offer = None;
 
         let mut env = channel_handler_env(allocator, rng);
+        let mut spends = unfunded_offer.clone();
+        // Create no coins.  The target is already created in the partially funded
+        // transaction.
+        spends
+            .spends
+            .push(self.spend_adding_coin(&mut env, &parent_coin)?);
+
+        self.state
+            .events
+            .push_back(CradleEvent::Transaction(spends));
+
+        {
+            let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+                env: &mut env,
+                system_interface: &mut self.state,
+            };
+            self.peer
+                .channel_transaction_completion(&mut penv, &unfunded_offer)?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl SynchronousGameCradle {
+    // Spend one of our coins creating nothing, so its whole value goes to
+    // whatever else the bundle creates.
+    fn spend_adding_coin<R: Rng>(
+        &self,
+        env: &mut ChannelHandlerEnv<R>,
+        coin: &CoinString,
+    ) -> Result<CoinSpend, Error> {
         let empty_conditions = ().to_clvm(env.allocator).into_gen()?;
         let quoted_empty_conditions = empty_conditions.to_quoted_program(env.allocator)?;
         let solution = solution_for_conditions(env.allocator, empty_conditions)?;
         let quoted_empty_hash = quoted_empty_conditions.sha256tree(env.allocator);
-
-        let mut spends = unfunded_offer.clone();
-        // Create no coins.  The target is already created in the partially funded
-        // transaction.
-        //
-        // XXX break this code out
         let signature = sign_agg_sig_me(
             &self.state.identity.synthetic_private_key,
             quoted_empty_hash.bytes(),
-            &parent_coin.to_coin_id(),
+            &coin.to_coin_id(),
             &env.agg_sig_me_additional_data,
         );
-        spends.spends.push(CoinSpend {
-            coin: parent_coin.clone(),
+        Ok(CoinSpend {
+            coin: coin.clone(),
             bundle: Spend {
                 puzzle: self.state.identity.puzzle.clone(),
                 solution: Program::from_nodeptr(env.allocator, solution)?,
                 signature,
             },
-        });
+        })
+    }
 
-        self.state.outbound_transactions.push_back(spends);
+    // The peer countersigned our top-up.  Spend the coin we're adding along
+    // with the channel coin.
+    fn submit_top_up<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        top_up_offer: SpendBundle,
+    ) -> Result<bool, Error> {
+        self.state.top_up_offer = None;
+        let coin = if let Some(coin) = self.state.top_up_coin.take() {
+            coin
+        } else {
+            return Err(Error::StrErr(
+                "top-up countersigned without a coin".to_string(),
+            ));
+        };
 
-        {
-            let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
-                env: &mut env,
-                system_interface: &mut self.state,
-            };
-            self.peer
-                .channel_transaction_completion(&mut penv, &unfunded_offer)?;
-        }
+        let mut env = channel_handler_env(allocator, rng);
+        let mut spends = top_up_offer;
+        spends.spends.push(self.spend_adding_coin(&mut env, &coin)?);
+        self.state
+            .events
+            .push_back(CradleEvent::Transaction(spends));
 
         Ok(true)
     }
 }
 
 impl SynchronousGameCradle {
+    #[cfg(test)]
+    pub fn cheat_on_next_move(
+        &mut self,
+        game_id: &GameID,
+        mover_share: Amount,
+    ) -> Result<(), Error> {
+        self.peer.cheat_on_next_move(game_id, mover_share)
+    }
+
+    #[cfg(test)]
+    pub fn channel_spend(&self) -> Option<HandshakeStepWithSpend> {
+        self.peer.channel_spend()
+    }
+
+    #[cfg(test)]
+    pub fn go_on_chain_with_stale_state<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        spend: HandshakeStepWithSpend,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.go_on_chain_with_stale_state(&mut penv, spend)
+    }
+
+    /// What the potato handler reports to, so a test can queue UI events.
+    #[cfg(test)]
+    pub fn queued_local_ui(&mut self) -> &mut dyn ToLocalUI {
+        &mut self.state
+    }
+
     #[cfg(test)]
     pub fn replace_last_message<F>(&mut self, f: F) -> Result<(), Error>
     where
         F: FnOnce(&PeerMessage) -> Result<PeerMessage, Error>,
     {
         // Grab and decode the message.
-        let msg = if let Some(msg) = self.state.outbound_messages.pop_back() {
+        let last = self
+            .state
+            .events
+            .iter()
+            .rposition(|e| matches!(e, CradleEvent::OutboundMessage(_)));
+        let msg = if let Some(CradleEvent::OutboundMessage(msg)) =
+            last.and_then(|i| self.state.events.remove(i))
+        {
             msg
         } else {
             todo!();
@@ -628,7 +1781,14 @@ impl SynchronousGameCradle {
         self.state.send_message(&fake_move)
     }
 
-    fn filter_coin_report(&mut self, block: u64, watch_report: &WatchReport) -> WatchReport {
+    /// Pass on the watched coins from a report, recording the entries it
+    /// changes in undo.
+    fn filter_coin_report(
+        &mut self,
+        block: u64,
+        watch_report: &WatchReport,
+        undo: &mut Vec<(CoinString, WatchEntry)>,
+    ) -> WatchReport {
         // Pass on creates and deletes that are being watched.
         let deleted_watched: HashSet<CoinString> = watch_report
             .deleted_watched
@@ -638,7 +1798,9 @@ impl SynchronousGameCradle {
             .collect();
         for d in deleted_watched.iter() {
             debug!("filter: spent coin {:?}", self.state.watching_coins.get(d));
-            self.state.watching_coins.remove(d);
+            if let Some(w) = self.state.watching_coins.remove(d) {
+                undo.push((d.clone(), w));
+            }
         }
         let created_watched: HashSet<CoinString> = watch_report
             .created_watched
@@ -649,6 +1811,7 @@ impl SynchronousGameCradle {
         for c in created_watched.iter() {
             if let Some(w) = self.state.watching_coins.get_mut(c) {
                 debug!("filter: created coin {w:?}");
+                undo.push((c.clone(), w.clone()));
                 w.timeout_at = Some(w.timeout_blocks.to_u64() + block);
             }
         }
@@ -659,6 +1822,7 @@ impl SynchronousGameCradle {
             if let Some(t) = w.timeout_at {
                 if t <= block {
                     debug!("filter: timeout on coin: {w:?}");
+                    undo.push((k.clone(), w.clone()));
                     w.timeout_at = None;
                     timed_out.insert(k.clone());
                 }
@@ -669,6 +1833,7 @@ impl SynchronousGameCradle {
             created_watched,
             deleted_watched,
             timed_out,
+            block: watch_report.block.clone(),
         }
     }
 }
@@ -755,10 +1920,162 @@ impl GameCradle for SynchronousGameCradle {
         self.peer.accept(&mut penv, id)
     }
 
-    /// Signal shutdown.  Forwards to FromLocalUI::shut_down.
-    /// Perhaps we should consider reporting the reward coins.
-    fn shut_down(&mut self) -> Result<(), Error> {
-        todo!();
+    fn propose_game<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        game: &GameStart,
+    ) -> Result<Vec<GameID>, Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.propose_game(&mut penv, game)
+    }
+
+    fn accept_proposal<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.accept_proposal(&mut penv, id)
+    }
+
+    fn decline_proposal<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.decline_proposal(&mut penv, id)
+    }
+
+    fn cancel_proposal<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.cancel_proposal(&mut penv, id)
+    }
+
+    fn top_up<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        coin: CoinString,
+    ) -> Result<(), Error> {
+        if self.state.top_up_coin.is_some() {
+            return Err(Error::StrErr("already topping up".to_string()));
+        }
+        let amount = if let Some((_, _, amount)) = coin.to_parts() {
+            amount
+        } else {
+            return Err(Error::StrErr("bad top-up coin".to_string()));
+        };
+
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.top_up(&mut penv, &amount)?;
+        self.state.top_up_coin = Some(coin);
+        Ok(())
+    }
+
+    fn withdraw<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        amount: &Amount,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.withdraw(&mut penv, amount)
+    }
+
+    fn offer_settlement<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+        my_share: &Amount,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.offer_settlement(&mut penv, id, my_share)
+    }
+
+    fn accept_settlement<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.accept_settlement(&mut penv, id)
+    }
+
+    fn decline_settlement<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+        id: &GameID,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.decline_settlement(&mut penv, id)
+    }
+
+    /// Signal shutdown.  Forwards to FromLocalUI::shut_down with conditions
+    /// paying each side its balance.  Live games are accepted first and
+    /// shutdown_complete reports our reward coin.
+    fn shut_down<R: Rng>(
+        &mut self,
+        allocator: &mut AllocEncoder,
+        rng: &mut R,
+    ) -> Result<(), Error> {
+        let mut env = channel_handler_env(allocator, rng);
+        let conditions = self
+            .peer
+            .channel_handler()?
+            .clean_shutdown_conditions(&mut env)?;
+        let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
+            env: &mut env,
+            system_interface: &mut self.state,
+        };
+        self.peer.shut_down(&mut penv, conditions)
     }
 
     /// Tell the game cradle that a new block arrived, giving a watch report.
@@ -769,14 +2086,70 @@ impl GameCradle for SynchronousGameCradle {
         height: usize,
         report: &WatchReport,
     ) -> Result<(), Error> {
+        let watching_before = if let Some(block) = &report.block {
+            let is_reorg = self
+                .state
+                .blocks
+                .back()
+                .map(|b| b.header_hash != block.parent_hash)
+                .unwrap_or(false);
+            if is_reorg {
+                self.undo_orphaned_blocks(allocator, &block.parent_hash)?;
+            }
+            Some(self.state.watching_coins.clone())
+        } else {
+            None
+        };
+
         self.state.current_height = height as u64;
-        let filtered_report = self.filter_coin_report(self.state.current_height, report);
+        self.state
+            .recent_transactions
+            .retain(|(h, _)| h + MAX_REORG_DEPTH as u64 >= height as u64);
+        let mut watch_undo = Vec::new();
+        let filtered_report =
+            self.filter_coin_report(self.state.current_height, report, &mut watch_undo);
+        let coins_changed = !(filtered_report.created_watched.is_empty()
+            && filtered_report.deleted_watched.is_empty()
+            && filtered_report.timed_out.is_empty());
+        let handler_before = match watching_before {
+            Some(watching) if coins_changed => Some((self.peer.to_snapshot(allocator)?, watching)),
+            _ => None,
+        };
+        if !self.state.channel_created_seen {
+            let channel_coin = filtered_report.created_watched.iter().find(|c| {
+                self.state
+                    .watching_coins
+                    .get(c)
+                    .map(|w| w.name == Some(WATCH_CHANNEL))
+                    .unwrap_or(false)
+            });
+            if let Some(coin) = channel_coin {
+                self.state.channel_created_seen = true;
+                self.state
+                    .events
+                    .push_back(CradleEvent::ChannelCreated(coin.clone()));
+            }
+        }
         let mut env = channel_handler_env(allocator, rng);
         let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
             env: &mut env,
             system_interface: &mut self.state,
         };
         report_coin_changes_to_peer(&mut penv, &mut self.peer, &filtered_report)?;
+        self.peer
+            .check_peer_liveness(&mut penv, self.state.current_height)?;
+
+        if let Some(block) = &report.block {
+            let left_channel = self.peer.is_going_on_chain() || self.peer.is_on_chain();
+            self.state.blocks.push_back(BlockRecord {
+                header_hash: block.header_hash.clone(),
+                watch_undo,
+                handler: handler_before.filter(|_| left_channel),
+            });
+            if self.state.blocks.len() > MAX_REORG_DEPTH {
+                self.state.blocks.pop_front();
+            }
+        }
         Ok(())
     }
 
@@ -788,6 +2161,11 @@ impl GameCradle for SynchronousGameCradle {
         Ok(())
     }
 
+    fn resume(&mut self) -> Result<(), Error> {
+        self.state.resume_requested = true;
+        Ok(())
+    }
+
     /// Allow the game to carry out tasks it needs to perform, yielding peer messages that
     /// should be forwarded.  Returns false when no more work is needed.
     fn idle<R: Rng>(
@@ -796,81 +2174,18 @@ impl GameCradle for SynchronousGameCradle {
         rng: &mut R,
         local_ui: &mut dyn ToLocalUI,
     ) -> Result<IdleResult, Error> {
-        let mut result = IdleResult::default();
-
-        swap(
-            &mut result.outbound_transactions,
-            &mut self.state.outbound_transactions,
-        );
-        self.state.outbound_transactions.clear();
-
-        swap(
-            &mut result.outbound_messages,
-            &mut self.state.outbound_messages,
-        );
-        self.state.outbound_messages.clear();
-
-        swap(
-            &mut result.coin_solution_requests,
-            &mut self.state.coin_solution_requests,
-        );
-        self.state.coin_solution_requests.clear();
-
-        if let Some((id, msg)) = self.state.our_moves.pop_front() {
-            local_ui.self_move(&id, &msg)?;
-            return Ok(result);
-        }
-
-        if let Some((id, msg)) = self.state.game_messages.pop_front() {
-            local_ui.game_message(allocator, &id, msg)?;
-            return Ok(result);
-        }
-
-        if let Some((id, readable)) = self.state.opponent_moves.pop_front() {
-            local_ui.opponent_moved(allocator, &id, readable)?;
-            result.continue_on = true;
-            return Ok(result);
-        }
-
-        if let Some((id, amount)) = self.state.game_finished.pop_front() {
-            local_ui.game_finished(&id, amount.clone())?;
-            result.continue_on = true;
-            return Ok(result);
-        }
-
-        // If there's a message to deliver, deliver it and signal to continue.
-        if let Some(msg) = self.state.inbound_messages.pop_front() {
-            let mut env = channel_handler_env(allocator, rng);
-            let mut penv: SynchronousGamePeerEnv<R> = SynchronousGamePeerEnv {
-                env: &mut env,
-                system_interface: &mut self.state,
-            };
-            // Try to receive a message.  If we get failure back, then a cheat was probably
-            // attempted so we need to go on chain.
-            match self.peer.received_message(&mut penv, msg) {
-                Ok(_) => {
-                    result.continue_on = true;
-                    return Ok(result);
-                }
-                Err(e) => {
-                    result.receive_error = Some(e);
-                    // Go on chain.
-                    local_ui.going_on_chain()?;
-                    return Ok(result);
+        let mut result = self.step(allocator, rng)?;
+        for event in self.take_events()? {
+            match event {
+                CradleEvent::OutboundMessage(msg) => result.outbound_messages.push_back(msg),
+                CradleEvent::Transaction(tx) => result.outbound_transactions.push_back(tx),
+                CradleEvent::WatchCoin(coin) => result.coins_to_watch.push_back(coin),
+                CradleEvent::PuzzleSolutionRequest(coin) => {
+                    result.coin_solution_requests.push_back(coin)
                 }
+                event => report_to_local_ui(allocator, local_ui, event)?,
             }
         }
-
-        if let Some(ph) = self.state.channel_puzzle_hash.clone() {
-            result.continue_on = self.create_partial_spend_for_channel_coin(allocator, rng, ph)?;
-            return Ok(result);
-        }
-
-        if let (false, Some(uo)) = (self.state.is_initiator, self.state.unfunded_offer.clone()) {
-            result.continue_on = self.respond_to_unfunded_offer(allocator, rng, uo)?;
-            return Ok(result);
-        }
-
         Ok(result)
     }
 
@@ -1,12 +1,10 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::mem::swap;
 
 use clvm_traits::ToClvm;
 use clvmr::serde::node_from_bytes;
 use clvmr::{run_program, Allocator, NodePtr};
 
-use clvm_tools_rs::classic::clvm_tools::binutils::disassemble;
-
 use log::debug;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -14,18 +12,19 @@ use serde::{Deserialize, Serialize};
 use crate::channel_handler::game_handler::chia_dialect;
 use crate::channel_handler::types::{
     ChannelCoinSpendInfo, ChannelHandlerEnv, ChannelHandlerInitiationData,
-    ChannelHandlerPrivateKeys, FlatGameStartInfo, GameStartInfo, MoveResult, PotatoSignatures,
-    PrintableGameStartInfo, ReadableMove,
+    ChannelHandlerPrivateKeys, CoinSpentDisposition, FlatGameStartInfo, GameStartInfo, MoveResult,
+    PotatoSignatures, PrintableGameStartInfo, ReadableMove,
 };
-use crate::channel_handler::ChannelHandler;
+use crate::channel_handler::{ChannelHandler, FlatChannelHandler};
 use crate::common::standard_coin::{
     private_to_public_key, puzzle_for_synthetic_public_key, puzzle_hash_for_pk,
 };
 use crate::common::types::{
     Aggsig, AllocEncoder, Amount, CoinCondition, CoinID, CoinSpend, CoinString, Error, GameID,
-    Hash, IntoErr, Node, Program, PublicKey, Puzzle, PuzzleHash, Sha256Input, Sha256tree, Spend,
-    SpendBundle, Timeout,
+    HandshakeError, Hash, IntoErr, Node, Program, ProtocolError, PublicKey, PuzzleHash,
+    Sha256Input, Sha256tree, Spend, SpendBundle, Timeout,
 };
+use crate::referee::{SlashOutcome, TheirTurnCoinSpentResult};
 use clvm_tools_rs::classic::clvm::sexp::proper_list;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -38,14 +37,57 @@ pub struct GameStart {
     pub parameters: Vec<u8>,
 }
 
+/// A game start offered to the peer.  The game ids are allocated by the
+/// proposer and the first one names the proposal.  The start is given from
+/// the point of view of whoever holds it, so my_contribution and my_turn are
+/// flipped when the peer receives it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WireGameStart {
     pub game_ids: Vec<GameID>,
     pub start: GameStart,
 }
 
-#[derive(Debug, Clone)]
-pub struct GameStartQueueEntry;
+impl WireGameStart {
+    pub fn proposal_id(&self) -> Result<&GameID, Error> {
+        self.game_ids
+            .first()
+            .ok_or_else(|| Error::StrErr("game proposal has no games".to_string()))
+    }
+
+    // The same proposal as seen by the peer.
+    fn for_peer(&self) -> Result<WireGameStart, Error> {
+        if self.start.my_contribution > self.start.amount {
+            return Err(Error::Protocol(ProtocolError::Malformed(
+                "proposer contribution is more than the game amount".to_string(),
+            )));
+        }
+
+        Ok(WireGameStart {
+            game_ids: self.game_ids.clone(),
+            start: GameStart {
+                my_contribution: self.start.amount.clone() - self.start.my_contribution.clone(),
+                my_turn: !self.start.my_turn,
+                ..self.start.clone()
+            },
+        })
+    }
+}
+
+// A proposal from the peer and whether we've accepted it.  Accepted proposals
+// are kept until their games start so that a cancel crossing our accept can
+// withdraw the start we queued.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TheirGameProposal {
+    proposal: WireGameStart,
+    accepted: bool,
+}
+
+// A game start we're waiting for from the peer.  Starts for a proposal we
+// accepted carry the proposal's id so only its games can fill them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameStartQueueEntry {
+    proposal: Option<GameID>,
+}
 
 #[derive(Debug, Clone)]
 pub struct MyGameStartQueueEntry {
@@ -181,6 +223,11 @@ pub trait BootstrapTowardWallet {
         &mut self,
         bundle: &SpendBundle,
     ) -> Result<(), Error>;
+
+    /// The peer countersigned our top-up.  The bundle spends the old channel
+    /// coin into the larger new one; the wallet must add a spend of the coin
+    /// being added, which makes up the difference, and submit both.
+    fn channel_top_up(&mut self, bundle: &SpendBundle) -> Result<(), Error>;
 }
 
 /// Spend wallet receiver
@@ -224,6 +271,22 @@ pub trait SpendWalletReceiver<
         R: 'a;
 }
 
+// The names coins are registered under, by what they hold.
+pub const WATCH_CHANNEL: &str = "channel";
+pub const WATCH_GAME: &str = "game";
+pub const WATCH_PARENT: &str = "parent";
+pub const WATCH_REWARD: &str = "reward";
+pub const WATCH_UNROLL: &str = "unroll";
+
+/// Every name register_coin is given.
+pub const WATCH_NAMES: &[&str] = &[
+    WATCH_CHANNEL,
+    WATCH_GAME,
+    WATCH_PARENT,
+    WATCH_REWARD,
+    WATCH_UNROLL,
+];
+
 /// Unroll time wallet interface.
 pub trait WalletSpendInterface {
     /// Enqueue an outbound transaction.
@@ -245,6 +308,40 @@ pub trait WalletSpendInterface {
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, PartialOrd, Ord)]
 pub struct GameType(pub Vec<u8>);
 
+/// Something we're waiting on the peer to send.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum PeerWait {
+    /// We asked for the potato.
+    Potato,
+    /// The peer holds the potato while games are live, so it owes a move.
+    Move,
+    /// A top-up or withdrawal is waiting on the peer's countersignature or
+    /// for its spend to confirm.
+    Splice,
+}
+
+/// How many blocks the peer may leave us waiting before we take the channel
+/// on chain.  None waits forever.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LivenessPolicy {
+    /// Also bounds the wait on the peer during a splice, which is otherwise
+    /// the channel timeout.
+    pub potato_timeout: Option<Timeout>,
+    pub move_timeout: Option<Timeout>,
+}
+
+/// Why the channel is going on chain.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum GoingOnChainReason {
+    /// A message from the peer was rejected.
+    ReceiveError(String),
+    /// The peer didn't finish a clean shutdown before our reward coin timed
+    /// out.
+    ShutdownTimeout,
+    /// The peer left us waiting for the given number of blocks.
+    PeerInactive(PeerWait, u64),
+}
+
 pub trait ToLocalUI {
     fn self_move(&mut self, _id: &GameID, _readable: &[u8]) -> Result<(), Error> {
         Ok(())
@@ -266,9 +363,62 @@ pub trait ToLocalUI {
     ) -> Result<(), Error>;
     fn game_finished(&mut self, id: &GameID, my_share: Amount) -> Result<(), Error>;
     fn game_cancelled(&mut self, id: &GameID) -> Result<(), Error>;
+    /// The opponent made an invalid move on chain and we slashed the game
+    /// coin, taking my_reward.
+    fn opponent_slashed(&mut self, _id: &GameID, _my_reward: Amount) -> Result<(), Error> {
+        Ok(())
+    }
 
     fn shutdown_complete(&mut self, reward_coin_string: &CoinString) -> Result<(), Error>;
-    fn going_on_chain(&mut self) -> Result<(), Error>;
+    fn going_on_chain(&mut self, reason: &GoingOnChainReason) -> Result<(), Error>;
+
+    /// The peer proposed a game.  The start is given from our side and is
+    /// answered with accept_proposal or decline_proposal.
+    fn game_proposed(&mut self, _proposal: &WireGameStart) -> Result<(), Error> {
+        Ok(())
+    }
+    /// The peer accepted our proposal and its games will start.
+    fn game_proposal_accepted(&mut self, _id: &GameID) -> Result<(), Error> {
+        Ok(())
+    }
+    fn game_proposal_declined(&mut self, _id: &GameID) -> Result<(), Error> {
+        Ok(())
+    }
+    /// The peer withdrew a proposal, including one we had accepted whose
+    /// games hadn't started yet.
+    fn game_proposal_cancelled(&mut self, _id: &GameID) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// A top-up confirmed on chain and the channel now holds these balances.
+    fn channel_topped_up(
+        &mut self,
+        _my_balance: &Amount,
+        _their_balance: &Amount,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+    /// A withdrawal confirmed on chain, paying out of the channel, which now
+    /// holds these balances.
+    fn channel_withdrawn(
+        &mut self,
+        _my_balance: &Amount,
+        _their_balance: &Amount,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The peer offered to end a game with us receiving my_share.  Answered
+    /// with accept_settlement or decline_settlement; if accepted the game
+    /// ends with game_finished like an accepted game.
+    fn settlement_offered(&mut self, _id: &GameID, _my_share: &Amount) -> Result<(), Error> {
+        Ok(())
+    }
+    /// The peer declined our settlement offer, or a settlement we accepted
+    /// was dropped because the channel went on chain first.
+    fn settlement_declined(&mut self, _id: &GameID) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub trait FromLocalUI<
@@ -320,6 +470,191 @@ pub trait FromLocalUI<
     where
         G: 'a,
         R: 'a;
+
+    /// Offer a game to the peer instead of both sides calling start_games.
+    /// Returns the game ids, the first of which names the proposal.  The
+    /// games start once the peer accepts.
+    fn propose_game<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        game: &GameStart,
+    ) -> Result<Vec<GameID>, Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    fn accept_proposal<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    fn decline_proposal<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    /// Withdraw one of our proposals the peer hasn't accepted yet.
+    fn cancel_proposal<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    /// Add amount to our side of the channel without closing it.  Once the
+    /// peer countersigns, the wallet is asked via channel_top_up to spend a
+    /// coin of exactly this amount alongside the channel coin.  The channel
+    /// is paused until that spend confirms.
+    fn top_up<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        amount: &Amount,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    /// Take amount of our balance not in games out of the channel to our
+    /// reward puzzle hash without closing it.  The channel is paused until
+    /// the spend confirms; if the peer stalls, the liveness policy takes the
+    /// channel on chain instead.
+    fn withdraw<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        amount: &Amount,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    /// Offer to end a live game with us receiving my_share of what's at
+    /// stake.  A newer offer for the same game replaces the old one.
+    fn offer_settlement<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+        my_share: &Amount,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    /// Agree to the peer's settlement offer.  The game ends once we have the
+    /// potato.
+    fn accept_settlement<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+
+    fn decline_settlement<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a;
+}
+
+/// Version of the peer protocol spoken by this build.  A peer advertising a
+/// different version is refused during the handshake.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol features this build understands.  Only features both
+/// peers advertise are enabled for the channel.
+pub const PROTOCOL_FEATURES: &[&str] = &[];
+
+/// What each peer can do, exchanged in the handshake so that incompatible
+/// builds are detected before any channel state exists.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HandshakeCapabilities {
+    protocol_version: u32,
+    referee_puzzle_hash: PuzzleHash,
+    unroll_puzzle_hash: PuzzleHash,
+    unroll_metapuzzle_hash: PuzzleHash,
+    standard_puzzle_hash: PuzzleHash,
+    // Each supported game type with the tree hash of its factory.
+    games: Vec<(GameType, PuzzleHash)>,
+    features: Vec<String>,
+}
+
+impl HandshakeCapabilities {
+    /// Check the peer's capabilities against ours, giving the game types and
+    /// features both sides support.
+    fn negotiate(
+        &self,
+        theirs: &HandshakeCapabilities,
+    ) -> Result<(BTreeSet<GameType>, Vec<String>), Error> {
+        if self.protocol_version != theirs.protocol_version {
+            return Err(Error::Handshake(HandshakeError::ProtocolVersion {
+                ours: self.protocol_version,
+                theirs: theirs.protocol_version,
+            }));
+        }
+
+        for (name, my_hash, their_hash) in [
+            (
+                "referee",
+                &self.referee_puzzle_hash,
+                &theirs.referee_puzzle_hash,
+            ),
+            (
+                "unroll",
+                &self.unroll_puzzle_hash,
+                &theirs.unroll_puzzle_hash,
+            ),
+            (
+                "unroll metapuzzle",
+                &self.unroll_metapuzzle_hash,
+                &theirs.unroll_metapuzzle_hash,
+            ),
+            (
+                "standard",
+                &self.standard_puzzle_hash,
+                &theirs.standard_puzzle_hash,
+            ),
+        ] {
+            if my_hash != their_hash {
+                return Err(Error::Handshake(HandshakeError::PuzzleMismatch(
+                    name.to_string(),
+                )));
+            }
+        }
+
+        let their_games: BTreeMap<GameType, PuzzleHash> = theirs.games.iter().cloned().collect();
+        let common_games: BTreeSet<GameType> = self
+            .games
+            .iter()
+            .filter(|(game_type, hash)| their_games.get(game_type) == Some(hash))
+            .map(|(game_type, _)| game_type.clone())
+            .collect();
+        if common_games.is_empty() {
+            return Err(Error::Handshake(HandshakeError::NoCommonGames));
+        }
+
+        let features = self
+            .features
+            .iter()
+            .filter(|f| theirs.features.contains(f))
+            .cloned()
+            .collect();
+
+        Ok((common_games, features))
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -328,6 +663,7 @@ pub struct HandshakeB {
     unroll_public_key: PublicKey,
     reward_puzzle_hash: PuzzleHash,
     referee_puzzle_hash: PuzzleHash,
+    capabilities: HandshakeCapabilities,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -357,9 +693,70 @@ pub enum PeerMessage {
     Shutdown(Aggsig, Program),
     RequestPotato(()),
     StartGames(PotatoSignatures, Vec<FlatGameStartInfo>),
+
+    // Game negotiation.  None of these need the potato.
+    ProposeGame(WireGameStart),
+    AcceptProposal(GameID),
+    DeclineProposal(GameID),
+    CancelProposal(GameID),
+
+    /// Adds amount from the sender's side, with the sender's signatures for
+    /// the first unroll of the new channel coin.
+    TopUp(Amount, PotatoSignatures),
+    /// Takes amount from the sender's side to the given puzzle hash, with the
+    /// sender's signatures for the first unroll of the new channel coin.
+    Withdraw(Amount, PuzzleHash, PotatoSignatures),
+    /// The countersignatures for a top-up or withdrawal plus a half signature
+    /// of the spend of the old channel coin into the new one.
+    SpliceAccept(PotatoSignatures, Aggsig),
+
+    /// Offers to end a game with the sender receiving amount.  Doesn't need
+    /// the potato.
+    OfferSettlement(GameID, Amount),
+    DeclineSettlement(GameID),
+    /// Ends a game at the split the receiver offered.
+    Settle(GameID, PotatoSignatures),
 }
 
-#[derive(Debug, Clone)]
+impl PeerMessage {
+    /// Short name of the message used when reporting protocol errors.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PeerMessage::HandshakeA(_) => "HandshakeA",
+            PeerMessage::HandshakeB(_) => "HandshakeB",
+            PeerMessage::HandshakeE { .. } => "HandshakeE",
+            PeerMessage::HandshakeF { .. } => "HandshakeF",
+            PeerMessage::Nil(_) => "Nil",
+            PeerMessage::Move(_, _) => "Move",
+            PeerMessage::Message(_, _) => "Message",
+            PeerMessage::Accept(_, _, _) => "Accept",
+            PeerMessage::Shutdown(_, _) => "Shutdown",
+            PeerMessage::RequestPotato(_) => "RequestPotato",
+            PeerMessage::StartGames(_, _) => "StartGames",
+            PeerMessage::ProposeGame(_) => "ProposeGame",
+            PeerMessage::AcceptProposal(_) => "AcceptProposal",
+            PeerMessage::DeclineProposal(_) => "DeclineProposal",
+            PeerMessage::CancelProposal(_) => "CancelProposal",
+            PeerMessage::TopUp(_, _) => "TopUp",
+            PeerMessage::Withdraw(_, _, _) => "Withdraw",
+            PeerMessage::SpliceAccept(_, _) => "SpliceAccept",
+            PeerMessage::OfferSettlement(_, _) => "OfferSettlement",
+            PeerMessage::DeclineSettlement(_) => "DeclineSettlement",
+            PeerMessage::Settle(_, _) => "Settle",
+        }
+    }
+
+    /// Decode a message received from the peer.  Bytes that don't decode are
+    /// a protocol error rather than a local one.
+    pub fn from_bytes(msg: &[u8]) -> Result<PeerMessage, Error> {
+        let malformed = |e: String| Error::Protocol(ProtocolError::Malformed(e));
+        let doc =
+            bson::Document::from_reader(&mut &msg[..]).map_err(|e| malformed(format!("{e:?}")))?;
+        bson::from_bson(bson::Bson::Document(doc)).map_err(|e| malformed(format!("{e:?}")))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HandshakeStepInfo {
     #[allow(dead_code)]
     pub first_player_hs_info: HandshakeA,
@@ -367,7 +764,7 @@ pub struct HandshakeStepInfo {
     pub second_player_hs_info: HandshakeB,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HandshakeStepWithSpend {
     #[allow(dead_code)]
     pub info: HandshakeStepInfo,
@@ -375,10 +772,45 @@ pub struct HandshakeStepWithSpend {
     pub spend: SpendBundle,
 }
 
-#[derive(Debug)]
-struct OnChainGameState {}
+// A game coin we're watching after the channel has unrolled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OnChainGameState {
+    game_id: GameID,
+    // The referee coin is waiting on our move.
+    our_turn: bool,
+    // The UI accepted the game as it stands, so we make no further moves in it.
+    accepted: bool,
+}
 
-#[derive(Debug)]
+// Maps keyed by coin string are stored as lists of pairs, since the formats we
+// serialize to require string keys.
+mod coin_map {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::common::types::CoinString;
+
+    pub fn serialize<S, V>(map: &HashMap<CoinString, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        let pairs: Vec<(&CoinString, &V)> = map.iter().collect();
+        pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<HashMap<CoinString, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        let pairs: Vec<(CoinString, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum HandshakeState {
     StepA,
     StepB,
@@ -397,12 +829,52 @@ enum HandshakeState {
     // Converge here to on chain state.
     OnChainWaitingForUnrollSpend(CoinString),
     OnChainWaitingForUnrollConditions(CoinString),
-    #[allow(dead_code)]
-    OnChain(HashMap<CoinString, OnChainGameState>),
-    WaitingForShutdown(CoinString, CoinString),
+    OnChain(#[serde(with = "coin_map")] HashMap<CoinString, OnChainGameState>),
+    // Reward coin, channel coin and the spend to unroll with if the shutdown
+    // never lands.
+    WaitingForShutdown(CoinString, CoinString, Box<HandshakeStepWithSpend>),
     Completed,
 }
 
+impl HandshakeState {
+    fn name(&self) -> &'static str {
+        match self {
+            HandshakeState::StepA => "StepA",
+            HandshakeState::StepB => "StepB",
+            HandshakeState::StepC(_, _) => "StepC",
+            HandshakeState::StepD(_) => "StepD",
+            HandshakeState::StepE(_) => "StepE",
+            HandshakeState::PostStepE(_) => "PostStepE",
+            HandshakeState::StepF(_) => "StepF",
+            HandshakeState::PostStepF(_) => "PostStepF",
+            HandshakeState::Finished(_) => "Finished",
+            HandshakeState::OnChainTransition(_, _) => "OnChainTransition",
+            HandshakeState::OnChainWaitingForUnrollTimeoutOrSpend(_, _) => {
+                "OnChainWaitingForUnrollTimeoutOrSpend"
+            }
+            HandshakeState::OnChainWaitForConditions(_, _) => "OnChainWaitForConditions",
+            HandshakeState::OnChainWaitingForUnrollSpend(_) => "OnChainWaitingForUnrollSpend",
+            HandshakeState::OnChainWaitingForUnrollConditions(_) => {
+                "OnChainWaitingForUnrollConditions"
+            }
+            HandshakeState::OnChain(_) => "OnChain",
+            HandshakeState::WaitingForShutdown(_, _, _) => "WaitingForShutdown",
+            HandshakeState::Completed => "Completed",
+        }
+    }
+
+    fn unexpected(&self, msg: &PeerMessage) -> Error {
+        unexpected_message(self.name(), msg)
+    }
+}
+
+fn unexpected_message(state: &str, msg: &PeerMessage) -> Error {
+    Error::Protocol(ProtocolError::UnexpectedMessage {
+        state: state.to_string(),
+        message_kind: msg.kind().to_string(),
+    })
+}
+
 pub trait PacketSender {
     fn send_message(&mut self, msg: &PeerMessage) -> Result<(), Error>;
 }
@@ -415,6 +887,7 @@ where
     fn env(&mut self) -> (&mut ChannelHandlerEnv<'inputs, R>, &mut G);
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 enum PotatoState {
     Absent,
     Requested,
@@ -425,6 +898,166 @@ enum GameAction {
     Move(GameID, ReadableMove, Hash),
     Accept(GameID),
     Shutdown(NodePtr),
+    TopUp(Amount),
+    Withdraw(Amount),
+    Settle(GameID, Amount),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum FlatGameAction {
+    Move(GameID, Program, Hash),
+    Accept(GameID),
+    Shutdown(Program),
+    TopUp(Amount),
+    Withdraw(Amount),
+    Settle(GameID, Amount),
+}
+
+impl GameAction {
+    fn to_serializable(&self, allocator: &mut AllocEncoder) -> Result<FlatGameAction, Error> {
+        match self {
+            GameAction::Move(game_id, readable, entropy) => Ok(FlatGameAction::Move(
+                game_id.clone(),
+                Program::from_nodeptr(allocator, readable.to_nodeptr())?,
+                entropy.clone(),
+            )),
+            GameAction::Accept(game_id) => Ok(FlatGameAction::Accept(game_id.clone())),
+            GameAction::Shutdown(conditions) => Ok(FlatGameAction::Shutdown(
+                Program::from_nodeptr(allocator, *conditions)?,
+            )),
+            GameAction::TopUp(amount) => Ok(FlatGameAction::TopUp(amount.clone())),
+            GameAction::Withdraw(amount) => Ok(FlatGameAction::Withdraw(amount.clone())),
+            GameAction::Settle(game_id, my_share) => {
+                Ok(FlatGameAction::Settle(game_id.clone(), my_share.clone()))
+            }
+        }
+    }
+
+    fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatGameAction,
+    ) -> Result<GameAction, Error> {
+        match serializable {
+            FlatGameAction::Move(game_id, readable, entropy) => Ok(GameAction::Move(
+                game_id.clone(),
+                ReadableMove::from_nodeptr(readable.to_nodeptr(allocator)?),
+                entropy.clone(),
+            )),
+            FlatGameAction::Accept(game_id) => Ok(GameAction::Accept(game_id.clone())),
+            FlatGameAction::Shutdown(conditions) => {
+                Ok(GameAction::Shutdown(conditions.to_nodeptr(allocator)?))
+            }
+            FlatGameAction::TopUp(amount) => Ok(GameAction::TopUp(amount.clone())),
+            FlatGameAction::Withdraw(amount) => Ok(GameAction::Withdraw(amount.clone())),
+            FlatGameAction::Settle(game_id, my_share) => {
+                Ok(GameAction::Settle(game_id.clone(), my_share.clone()))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct FlatMyGameStartQueueEntry {
+    my_games: Vec<FlatGameStartInfo>,
+    their_games: Vec<FlatGameStartInfo>,
+}
+
+impl MyGameStartQueueEntry {
+    fn to_serializable(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<FlatMyGameStartQueueEntry, Error> {
+        let mut my_games = Vec::new();
+        for g in self.my_games.iter() {
+            my_games.push(g.to_serializable(allocator)?);
+        }
+        let mut their_games = Vec::new();
+        for g in self.their_games.iter() {
+            their_games.push(g.to_serializable(allocator)?);
+        }
+        Ok(FlatMyGameStartQueueEntry {
+            my_games,
+            their_games,
+        })
+    }
+
+    fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatMyGameStartQueueEntry,
+    ) -> Result<MyGameStartQueueEntry, Error> {
+        let mut my_games = Vec::new();
+        for g in serializable.my_games.iter() {
+            my_games.push(GameStartInfo::from_serializable(allocator, g)?);
+        }
+        let mut their_games = Vec::new();
+        for g in serializable.their_games.iter() {
+            their_games.push(GameStartInfo::from_serializable(allocator, g)?);
+        }
+        Ok(MyGameStartQueueEntry {
+            my_games,
+            their_games,
+        })
+    }
+}
+
+/// Version of the potato handler snapshot format.  Bump this when the contents
+/// of PotatoHandlerSnapshot change incompatibly.
+pub const POTATO_HANDLER_SNAPSHOT_VERSION: u32 = 1;
+
+/// A complete, serializable copy of a PotatoHandler and everything it owns.
+///
+/// A snapshot is taken against the allocator that holds the handler's clvm
+/// data and can be restored into any allocator, including a fresh one in a
+/// new process.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PotatoHandlerSnapshot {
+    version: u32,
+
+    initiator: bool,
+    have_potato: PotatoState,
+
+    handshake_state: HandshakeState,
+
+    their_start_queue: Vec<GameStartQueueEntry>,
+    my_start_queue: Vec<FlatMyGameStartQueueEntry>,
+
+    #[serde(default)]
+    my_proposals: Vec<(GameID, FlatMyGameStartQueueEntry)>,
+    #[serde(default)]
+    their_proposals: Vec<TheirGameProposal>,
+
+    #[serde(default)]
+    my_settlement_offers: Vec<(GameID, Amount)>,
+    #[serde(default)]
+    their_settlement_offers: Vec<(GameID, Amount)>,
+
+    game_action_queue: Vec<FlatGameAction>,
+
+    next_game_id: Vec<u8>,
+
+    channel_handler: Option<FlatChannelHandler>,
+    channel_initiation_transaction: Option<SpendBundle>,
+    channel_finished_transaction: Option<SpendBundle>,
+
+    game_types: Vec<(GameType, Program)>,
+    #[serde(default)]
+    features: Vec<String>,
+
+    private_keys: ChannelHandlerPrivateKeys,
+
+    my_contribution: Amount,
+    their_contribution: Amount,
+
+    reward_puzzle_hash: PuzzleHash,
+
+    waiting_to_start: bool,
+    channel_timeout: Timeout,
+    unroll_timeout: Timeout,
+
+    #[serde(default)]
+    liveness: LivenessPolicy,
+    #[serde(default)]
+    waiting_on_peer: Option<(PeerWait, u64)>,
 }
 
 pub struct PotatoHandlerInit {
@@ -436,6 +1069,7 @@ pub struct PotatoHandlerInit {
     pub channel_timeout: Timeout,
     pub unroll_timeout: Timeout,
     pub reward_puzzle_hash: PuzzleHash,
+    pub liveness: LivenessPolicy,
 }
 
 /// Handle potato in flight when I request potato:
@@ -476,6 +1110,16 @@ pub struct PotatoHandler {
     // Our outgoing game starts.
     my_start_queue: VecDeque<MyGameStartQueueEntry>,
 
+    // Our proposals waiting on the peer, which join my_start_queue when
+    // accepted.
+    my_proposals: Vec<(GameID, MyGameStartQueueEntry)>,
+    // The peer's proposals, pending or accepted but not yet started.
+    their_proposals: Vec<TheirGameProposal>,
+
+    // Settlement offers by game, each with the share we'd receive.
+    my_settlement_offers: Vec<(GameID, Amount)>,
+    their_settlement_offers: Vec<(GameID, Amount)>,
+
     game_action_queue: VecDeque<GameAction>,
 
     next_game_id: Vec<u8>,
@@ -485,6 +1129,9 @@ pub struct PotatoHandler {
     channel_finished_transaction: Option<SpendBundle>,
 
     game_types: BTreeMap<GameType, Program>,
+    // Optional protocol features enabled for this channel.  Until the
+    // handshake completes these are the ones we advertise.
+    features: Vec<String>,
 
     private_keys: ChannelHandlerPrivateKeys,
 
@@ -500,6 +1147,11 @@ pub struct PotatoHandler {
     channel_timeout: Timeout,
     // Unroll timeout
     unroll_timeout: Timeout,
+
+    liveness: LivenessPolicy,
+    // What we're waiting for from the peer and the block height we started
+    // waiting at.
+    waiting_on_peer: Option<(PeerWait, u64)>,
 }
 
 fn init_game_id(private_keys: &ChannelHandlerPrivateKeys) -> Vec<u8> {
@@ -550,9 +1202,14 @@ impl PotatoHandler {
 
             next_game_id: Vec::new(),
             game_types: phi.game_types,
+            features: PROTOCOL_FEATURES.iter().map(|f| f.to_string()).collect(),
 
             their_start_queue: VecDeque::default(),
             my_start_queue: VecDeque::default(),
+            my_proposals: Vec::default(),
+            their_proposals: Vec::default(),
+            my_settlement_offers: Vec::default(),
+            their_settlement_offers: Vec::default(),
             game_action_queue: VecDeque::default(),
 
             channel_handler: None,
@@ -566,6 +1223,8 @@ impl PotatoHandler {
             their_contribution: phi.their_contribution,
             channel_timeout: phi.channel_timeout,
             unroll_timeout: phi.unroll_timeout,
+            liveness: phi.liveness,
+            waiting_on_peer: None,
             reward_puzzle_hash: phi.reward_puzzle_hash,
         }
     }
@@ -574,21 +1233,197 @@ impl PotatoHandler {
         self.my_contribution.clone() + self.their_contribution.clone()
     }
 
-    pub fn is_on_chain(&self) -> bool {
-        matches!(self.handshake_state, HandshakeState::OnChain(_))
-    }
+    /// Capture the full state of this handler so that it can be stored and
+    /// later restored with from_snapshot.
+    pub fn to_snapshot(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<PotatoHandlerSnapshot, Error> {
+        let mut my_start_queue = Vec::new();
+        for entry in self.my_start_queue.iter() {
+            my_start_queue.push(entry.to_serializable(allocator)?);
+        }
+        let mut my_proposals = Vec::new();
+        for (id, entry) in self.my_proposals.iter() {
+            my_proposals.push((id.clone(), entry.to_serializable(allocator)?));
+        }
+        let mut game_action_queue = Vec::new();
+        for action in self.game_action_queue.iter() {
+            game_action_queue.push(action.to_serializable(allocator)?);
+        }
+        let channel_handler = if let Some(ch) = &self.channel_handler {
+            Some(ch.to_serializable(allocator)?)
+        } else {
+            None
+        };
 
-    pub fn is_initiator(&self) -> bool {
-        self.initiator
+        Ok(PotatoHandlerSnapshot {
+            version: POTATO_HANDLER_SNAPSHOT_VERSION,
+            initiator: self.initiator,
+            have_potato: self.have_potato.clone(),
+            handshake_state: self.handshake_state.clone(),
+            their_start_queue: self.their_start_queue.iter().cloned().collect(),
+            my_start_queue,
+            my_proposals,
+            their_proposals: self.their_proposals.clone(),
+            my_settlement_offers: self.my_settlement_offers.clone(),
+            their_settlement_offers: self.their_settlement_offers.clone(),
+            game_action_queue,
+            next_game_id: self.next_game_id.clone(),
+            channel_handler,
+            channel_initiation_transaction: self.channel_initiation_transaction.clone(),
+            channel_finished_transaction: self.channel_finished_transaction.clone(),
+            game_types: self
+                .game_types
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            features: self.features.clone(),
+            private_keys: self.private_keys.clone(),
+            my_contribution: self.my_contribution.clone(),
+            their_contribution: self.their_contribution.clone(),
+            reward_puzzle_hash: self.reward_puzzle_hash.clone(),
+            waiting_to_start: self.waiting_to_start,
+            channel_timeout: self.channel_timeout.clone(),
+            unroll_timeout: self.unroll_timeout.clone(),
+            liveness: self.liveness.clone(),
+            waiting_on_peer: self.waiting_on_peer,
+        })
     }
 
-    pub fn channel_handler(&self) -> Result<&ChannelHandler, Error> {
-        if let Some(ch) = &self.channel_handler {
-            Ok(ch)
-        } else {
-            Err(Error::StrErr("no channel handler".to_string()))
-        }
-    }
+    /// Rebuild a handler from a snapshot, placing its clvm data in the given
+    /// allocator.
+    pub fn from_snapshot(
+        allocator: &mut AllocEncoder,
+        snapshot: &PotatoHandlerSnapshot,
+    ) -> Result<PotatoHandler, Error> {
+        if snapshot.version != POTATO_HANDLER_SNAPSHOT_VERSION {
+            return Err(Error::StrErr(format!(
+                "unsupported potato handler snapshot version {} (expected {})",
+                snapshot.version, POTATO_HANDLER_SNAPSHOT_VERSION
+            )));
+        }
+
+        let mut my_start_queue = VecDeque::new();
+        for entry in snapshot.my_start_queue.iter() {
+            my_start_queue.push_back(MyGameStartQueueEntry::from_serializable(allocator, entry)?);
+        }
+        let mut my_proposals = Vec::new();
+        for (id, entry) in snapshot.my_proposals.iter() {
+            my_proposals.push((
+                id.clone(),
+                MyGameStartQueueEntry::from_serializable(allocator, entry)?,
+            ));
+        }
+        let mut game_action_queue = VecDeque::new();
+        for action in snapshot.game_action_queue.iter() {
+            game_action_queue.push_back(GameAction::from_serializable(allocator, action)?);
+        }
+        let channel_handler = if let Some(ch) = &snapshot.channel_handler {
+            Some(ChannelHandler::from_serializable(allocator, ch)?)
+        } else {
+            None
+        };
+
+        Ok(PotatoHandler {
+            initiator: snapshot.initiator,
+            have_potato: snapshot.have_potato.clone(),
+            handshake_state: snapshot.handshake_state.clone(),
+            their_start_queue: snapshot.their_start_queue.iter().cloned().collect(),
+            my_start_queue,
+            my_proposals,
+            their_proposals: snapshot.their_proposals.clone(),
+            my_settlement_offers: snapshot.my_settlement_offers.clone(),
+            their_settlement_offers: snapshot.their_settlement_offers.clone(),
+            game_action_queue,
+            next_game_id: snapshot.next_game_id.clone(),
+            channel_handler,
+            channel_initiation_transaction: snapshot.channel_initiation_transaction.clone(),
+            channel_finished_transaction: snapshot.channel_finished_transaction.clone(),
+            game_types: snapshot.game_types.iter().cloned().collect(),
+            features: snapshot.features.clone(),
+            private_keys: snapshot.private_keys.clone(),
+            my_contribution: snapshot.my_contribution.clone(),
+            their_contribution: snapshot.their_contribution.clone(),
+            reward_puzzle_hash: snapshot.reward_puzzle_hash.clone(),
+            waiting_to_start: snapshot.waiting_to_start,
+            channel_timeout: snapshot.channel_timeout.clone(),
+            unroll_timeout: snapshot.unroll_timeout.clone(),
+            liveness: snapshot.liveness.clone(),
+            waiting_on_peer: snapshot.waiting_on_peer,
+        })
+    }
+
+    /// Encode a snapshot of this handler as bson bytes.
+    pub fn to_snapshot_bytes(&self, allocator: &mut AllocEncoder) -> Result<Vec<u8>, Error> {
+        let snapshot = self.to_snapshot(allocator)?;
+        let bson_doc = bson::to_bson(&snapshot).map_err(|e| Error::StrErr(format!("{e:?}")))?;
+        bson::to_vec(&bson_doc).map_err(|e| Error::StrErr(format!("{e:?}")))
+    }
+
+    /// Restore a handler from bytes produced by to_snapshot_bytes.
+    pub fn from_snapshot_bytes(
+        allocator: &mut AllocEncoder,
+        data: &[u8],
+    ) -> Result<PotatoHandler, Error> {
+        let doc = bson::Document::from_reader(&mut &data[..]).into_gen()?;
+        let snapshot: PotatoHandlerSnapshot =
+            bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
+        PotatoHandler::from_snapshot(allocator, &snapshot)
+    }
+
+    pub fn is_on_chain(&self) -> bool {
+        matches!(self.handshake_state, HandshakeState::OnChain(_))
+    }
+
+    #[cfg(test)]
+    pub fn cheat_on_next_move(
+        &mut self,
+        game_id: &GameID,
+        mover_share: Amount,
+    ) -> Result<(), Error> {
+        self.channel_handler_mut()?
+            .cheat_on_next_move(game_id, mover_share)
+    }
+
+    /// The fully signed channel coin spend we'd currently use to go on chain.
+    #[cfg(test)]
+    pub fn channel_spend(&self) -> Option<HandshakeStepWithSpend> {
+        if let HandshakeState::Finished(hs) = &self.handshake_state {
+            Some(*hs.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Go on chain with a channel coin spend saved from an earlier state.
+    #[cfg(test)]
+    pub fn go_on_chain_with_stale_state<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        spend: HandshakeStepWithSpend,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return Err(Error::StrErr("not running off chain".to_string()));
+        }
+
+        self.do_channel_spend_to_unroll(penv, Box::new(spend))
+    }
+
+    pub fn is_initiator(&self) -> bool {
+        self.initiator
+    }
+
+    pub fn channel_handler(&self) -> Result<&ChannelHandler, Error> {
+        if let Some(ch) = &self.channel_handler {
+            Ok(ch)
+        } else {
+            Err(Error::StrErr("no channel handler".to_string()))
+        }
+    }
 
     fn channel_handler_mut(&mut self) -> Result<&mut ChannelHandler, Error> {
         if let Some(ch) = &mut self.channel_handler {
@@ -598,6 +1433,37 @@ impl PotatoHandler {
         }
     }
 
+    /// Optional protocol features enabled for this channel.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    fn my_capabilities<R: Rng>(&self, env: &mut ChannelHandlerEnv<R>) -> HandshakeCapabilities {
+        let games = self
+            .game_types
+            .iter()
+            .map(|(game_type, factory)| (game_type.clone(), factory.sha256tree(env.allocator)))
+            .collect();
+        HandshakeCapabilities {
+            protocol_version: PROTOCOL_VERSION,
+            referee_puzzle_hash: env.referee_coin_puzzle_hash.clone(),
+            unroll_puzzle_hash: env.unroll_puzzle.sha256tree(env.allocator),
+            unroll_metapuzzle_hash: env.unroll_metapuzzle.sha256tree(env.allocator),
+            standard_puzzle_hash: env.standard_puzzle.sha256tree(env.allocator),
+            games,
+            features: self.features.clone(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn handshake_state_name(&self) -> &'static str {
+        self.handshake_state.name()
+    }
+
     pub fn handshake_finished(&self) -> bool {
         matches!(self.handshake_state, HandshakeState::Finished(_))
     }
@@ -608,6 +1474,38 @@ impl PotatoHandler {
         matches!(self.have_potato, PotatoState::Present)
     }
 
+    /// Check the channel view a peer reported when resuming after a reconnect.
+    /// ours_in_flight tells whether any of our messages are still
+    /// unacknowledged by the peer; if none are, everything we sent has been
+    /// seen and the peer can't be behind us.
+    pub fn check_resume(
+        &self,
+        their_state_number: usize,
+        they_have_potato: bool,
+        ours_in_flight: bool,
+    ) -> Result<(), Error> {
+        if !self.handshake_finished() {
+            return Ok(());
+        }
+
+        let my_state_number = self.channel_handler()?.get_state_number();
+        debug!("resume: our state {my_state_number} theirs {their_state_number}");
+
+        if self.has_potato() && they_have_potato {
+            return Err(Error::Channel(
+                "both peers hold the potato after resume".to_string(),
+            ));
+        }
+
+        if !ours_in_flight && their_state_number < my_state_number {
+            return Err(Error::Channel(format!(
+                "peer resumed at state {their_state_number} behind our state {my_state_number} with nothing in flight"
+            )));
+        }
+
+        Ok(())
+    }
+
     pub fn start<'a, G, R: Rng + 'a>(
         &mut self,
         penv: &'a mut dyn PeerEnv<'a, G, R>,
@@ -623,6 +1521,7 @@ impl PotatoHandler {
             private_to_public_key(&self.private_keys.my_unroll_coin_private_key);
         let referee_public_key = private_to_public_key(&self.private_keys.my_referee_private_key);
         let referee_puzzle_hash = puzzle_hash_for_pk(env.allocator, &referee_public_key)?;
+        let capabilities = self.my_capabilities(env);
 
         debug!("Start: our channel public key {:?}", channel_public_key);
 
@@ -634,6 +1533,7 @@ impl PotatoHandler {
                 unroll_public_key,
                 reward_puzzle_hash: self.reward_puzzle_hash.clone(),
                 referee_puzzle_hash,
+                capabilities,
             },
         };
         self.handshake_state =
@@ -653,14 +1553,6 @@ impl PotatoHandler {
     {
         self.have_potato = PotatoState::Present;
 
-        if self.have_potato_start_game(penv)? {
-            return Ok(());
-        }
-
-        if self.have_potato_move(penv)? {
-            return Ok(());
-        }
-
         let (channel_coin, channel_public_key) = {
             let ch = self.channel_handler()?;
             let cc = ch.state_channel_coin().coin_string().clone();
@@ -683,7 +1575,19 @@ impl PotatoHandler {
                     puzzle: channel_coin_puzzle,
                 },
             }];
-            debug!("updated spend to {:?}", hs.spend.spends[0]);
+            debug!("updated spend to {:?}", hs.spend.spends);
+        }
+
+        if self.have_potato_start_game(penv)? {
+            return Ok(());
+        }
+
+        if self.have_potato_move(penv)? {
+            // Get the potato back for anything still queued.
+            if !self.game_action_queue.is_empty() {
+                self.request_potato(penv)?;
+            }
+            return Ok(());
         }
 
         Ok(())
@@ -692,17 +1596,15 @@ impl PotatoHandler {
     fn pass_on_channel_handler_message<'a, G, R: Rng + 'a>(
         &mut self,
         penv: &mut dyn PeerEnv<'a, G, R>,
-        msg: Vec<u8>,
+        msg_envelope: PeerMessage,
     ) -> Result<Option<HandshakeState>, Error>
     where
         G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
     {
         let timeout = self.channel_timeout.clone();
+        let state_name = self.handshake_state.name();
         let ch = self.channel_handler_mut()?;
 
-        let doc = bson::Document::from_reader(&mut msg.as_slice()).into_gen()?;
-        let msg_envelope: PeerMessage = bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
-
         debug!("msg {msg_envelope:?}");
         match msg_envelope {
             PeerMessage::Nil(n) => {
@@ -749,10 +1651,11 @@ impl PotatoHandler {
                     system_interface.game_finished(&game_id, amount)?;
                     Ok(result)
                 }?;
+                self.forget_settlement_offers(&game_id);
                 self.update_channel_coin_after_receive(penv, &spend_info)?;
             }
             PeerMessage::Shutdown(sig, conditions) => {
-                let coin = ch.state_channel_coin().coin_string();
+                let coin = ch.state_channel_coin().coin_string().clone();
                 let (env, system_interface) = penv.env();
                 let clvm_conditions = conditions.to_nodeptr(env.allocator)?;
                 // conditions must have a reward coin targeted at our referee_public_key.
@@ -762,7 +1665,7 @@ impl PotatoHandler {
                 let want_amount = ch.clean_shutdown_amount();
                 let condition_list = CoinCondition::from_nodeptr(env.allocator, clvm_conditions);
                 let found_conditions = condition_list.iter().any(|cond| {
-                    if let CoinCondition::CreateCoin(ph, amt) = cond {
+                    if let CoinCondition::CreateCoin(ph, amt, _) = cond {
                         *ph == want_puzzle_hash && *amt >= want_amount
                     } else {
                         false
@@ -770,17 +1673,17 @@ impl PotatoHandler {
                 });
 
                 if !found_conditions {
-                    return Err(Error::StrErr(
+                    return Err(Error::Protocol(ProtocolError::StateMismatch(
                         "given conditions don't pay our referee puzzle hash what's expected"
                             .to_string(),
-                    ));
+                    )));
                 }
 
                 let my_reward =
                     CoinString::from_parts(&coin.to_coin_id(), &want_puzzle_hash, &want_amount);
-                system_interface.register_coin(&my_reward, &timeout, Some("reward"))?;
+                system_interface.register_coin(&my_reward, &timeout, Some(WATCH_REWARD))?;
 
-                system_interface.register_coin(coin, &timeout, Some("parent"))?;
+                system_interface.register_coin(&coin, &timeout, Some(WATCH_PARENT))?;
                 let full_spend = ch.received_potato_clean_shutdown(env, &sig, clvm_conditions)?;
 
                 let solution = Program::from_nodeptr(env.allocator, full_spend.solution)?;
@@ -801,15 +1704,22 @@ impl PotatoHandler {
                     }],
                 })?;
 
+                let spend = if let HandshakeState::Finished(spend) = &self.handshake_state {
+                    spend.clone()
+                } else {
+                    return Err(Error::StrErr(
+                        "shutdown received before handshake finished".to_string(),
+                    ));
+                };
+
                 // Expected reward coin is shutdown amount + puzzle hash of referee
                 // coin and parent is the reported reward coin.
                 return Ok(Some(HandshakeState::WaitingForShutdown(
-                    my_reward,
-                    coin.clone(),
+                    my_reward, coin, spend,
                 )));
             }
             _ => {
-                todo!("unhandled passthrough message {msg_envelope:?}");
+                return Err(unexpected_message(state_name, &msg_envelope));
             }
         }
 
@@ -972,6 +1882,7 @@ impl PotatoHandler {
                     let (env, _) = penv.env();
                     ch.send_potato_accept(env, &game_id)?
                 };
+                self.forget_settlement_offers(&game_id);
 
                 let (_, system_interface) = penv.env();
                 system_interface.send_message(&PeerMessage::Accept(
@@ -1010,10 +1921,30 @@ impl PotatoHandler {
                     &want_amount,
                 );
 
+                let spend = if let HandshakeState::Finished(spend) = &self.handshake_state {
+                    spend.clone()
+                } else {
+                    return Err(Error::StrErr(
+                        "shut down before handshake finished".to_string(),
+                    ));
+                };
+
+                // If this times out before the reward coin appears, the peer didn't
+                // finish the shutdown and we unroll instead.  The channel coin's
+                // timer restarts from here too, so a peer that never spends it
+                // is caught even though the reward coin never exists.
                 let (env, system_interface) = penv.env();
-                system_interface.register_coin(&my_reward, &timeout, Some("reward"))?;
-                self.handshake_state =
-                    HandshakeState::WaitingForShutdown(my_reward, state_channel_coin.clone());
+                system_interface.register_coin(&my_reward, &timeout, Some(WATCH_REWARD))?;
+                system_interface.register_coin(
+                    &state_channel_coin,
+                    &timeout,
+                    Some(WATCH_CHANNEL),
+                )?;
+                self.handshake_state = HandshakeState::WaitingForShutdown(
+                    my_reward,
+                    state_channel_coin.clone(),
+                    spend,
+                );
 
                 // If the state channel coin is spent, then we signal full shutdown.
                 let shutdown_condition_program = Program::from_nodeptr(env.allocator, conditions)?;
@@ -1024,6 +1955,51 @@ impl PotatoHandler {
 
                 Ok(true)
             }
+            Some(GameAction::TopUp(amount)) => {
+                let sigs = {
+                    let ch = self.channel_handler_mut()?;
+                    let (env, _) = penv.env();
+                    ch.send_potato_top_up(env, &amount)?
+                };
+
+                let (_, system_interface) = penv.env();
+                system_interface.send_message(&PeerMessage::TopUp(amount, sigs))?;
+                self.have_potato = PotatoState::Absent;
+
+                Ok(true)
+            }
+            Some(GameAction::Withdraw(amount)) => {
+                let sigs = {
+                    let ch = self.channel_handler_mut()?;
+                    let (env, _) = penv.env();
+                    ch.send_potato_withdraw(env, &amount, &self.reward_puzzle_hash)?
+                };
+
+                let (_, system_interface) = penv.env();
+                system_interface.send_message(&PeerMessage::Withdraw(
+                    amount,
+                    self.reward_puzzle_hash.clone(),
+                    sigs,
+                ))?;
+                self.have_potato = PotatoState::Absent;
+
+                Ok(true)
+            }
+            Some(GameAction::Settle(game_id, my_share)) => {
+                let sigs = {
+                    let ch = self.channel_handler_mut()?;
+                    let (env, _) = penv.env();
+                    ch.send_potato_settle(env, &game_id, &my_share)?
+                };
+                self.forget_settlement_offers(&game_id);
+
+                let (_, system_interface) = penv.env();
+                system_interface.send_message(&PeerMessage::Settle(game_id.clone(), sigs))?;
+                self.have_potato = PotatoState::Absent;
+                system_interface.game_finished(&game_id, my_share)?;
+
+                Ok(true)
+            }
             None => Ok(false),
         }
     }
@@ -1033,6 +2009,20 @@ impl PotatoHandler {
         penv: &mut dyn PeerEnv<'a, G, R>,
         game_start: &GameStart,
     ) -> Result<(Vec<GameStartInfo>, Vec<GameStartInfo>), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        self.run_game_factory(penv, game_start, None)
+    }
+
+    // Run the factory for game_start.  New game ids are allocated unless the
+    // caller already knows them, as when rebuilding a peer's proposal.
+    fn run_game_factory<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        game_start: &GameStart,
+        known_game_ids: Option<&[GameID]>,
+    ) -> Result<(Vec<GameStartInfo>, Vec<GameStartInfo>), Error>
     where
         G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
     {
@@ -1104,10 +2094,22 @@ impl PotatoHandler {
             ));
         }
 
-        let mut game_ids = Vec::new();
-        for _ in my_info_list.iter() {
-            game_ids.push(self.next_game_id()?);
-        }
+        let game_ids = if let Some(known) = known_game_ids {
+            if known.len() != my_info_list.len() {
+                return Err(Error::StrErr(format!(
+                    "factory made {} games but {} ids were given",
+                    my_info_list.len(),
+                    known.len()
+                )));
+            }
+            known.to_vec()
+        } else {
+            let mut game_ids = Vec::new();
+            for _ in my_info_list.iter() {
+                game_ids.push(self.next_game_id()?);
+            }
+            game_ids
+        };
 
         let convert_info_list = |allocator: &mut AllocEncoder,
                                  my_turn: bool,
@@ -1143,6 +2145,13 @@ impl PotatoHandler {
             return Ok(());
         }
 
+        if self.splice_in_progress() {
+            // Whoever didn't start the splice gets the potato when it
+            // confirms.
+            debug!("potato request waits for the splice to confirm");
+            return Ok(());
+        }
+
         debug!("requesting potato");
 
         let (_, system_interface) = penv.env();
@@ -1151,6 +2160,161 @@ impl PotatoHandler {
         Ok(())
     }
 
+    fn splice_in_progress(&self) -> bool {
+        self.channel_handler
+            .as_ref()
+            .map(|ch| ch.splice_in_progress())
+            .unwrap_or(false)
+    }
+
+    fn received_top_up<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        amount: &Amount,
+        sigs: &PotatoSignatures,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let (our_sigs, half_signature) = {
+            let ch = self.channel_handler_mut()?;
+            let (env, _) = penv.env();
+            ch.received_potato_top_up(env, amount, sigs)?
+        };
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::SpliceAccept(our_sigs, half_signature))
+    }
+
+    fn received_withdraw<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        amount: &Amount,
+        payout_puzzle_hash: &PuzzleHash,
+        sigs: &PotatoSignatures,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let (our_sigs, half_signature) = {
+            let ch = self.channel_handler_mut()?;
+            let (env, _) = penv.env();
+            ch.received_potato_withdraw(env, amount, payout_puzzle_hash, sigs)?
+        };
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::SpliceAccept(our_sigs, half_signature))
+    }
+
+    fn received_splice_accept<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        sigs: &PotatoSignatures,
+        half_signature: &Aggsig,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let (channel_coin, spend, top_up) = {
+            let ch = self.channel_handler_mut()?;
+            let (env, _) = penv.env();
+            let spend = ch.received_potato_splice_accept(env, sigs, half_signature)?;
+            let top_up = ch
+                .pending_splice()
+                .map(|p| p.my_addition > Amount::default())
+                .unwrap_or(false);
+            (ch.state_channel_coin().coin_string().clone(), spend, top_up)
+        };
+
+        let bundle = SpendBundle {
+            spends: vec![CoinSpend {
+                coin: channel_coin,
+                bundle: spend,
+            }],
+        };
+        let (_, system_interface) = penv.env();
+        if top_up {
+            system_interface.channel_top_up(&bundle)
+        } else {
+            // A withdrawal is paid for by the channel coin alone.
+            system_interface.spend_transaction_and_add_fee(&bundle)
+        }
+    }
+
+    // The splice confirmed, so the channel carries on with the new coin.
+    fn finish_splice<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        channel_coin: CoinString,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        if !matches!(
+            self.handshake_state,
+            HandshakeState::OnChainWaitForConditions(_, _)
+        ) {
+            return Err(Error::StrErr(format!(
+                "splice confirmed in state {:?}",
+                self.handshake_state
+            )));
+        }
+
+        let (have_potato, spend, top_up, my_balance, their_balance) = {
+            let ch = self.channel_handler_mut()?;
+            let top_up = ch
+                .pending_splice()
+                .map(|p| p.my_addition.clone() + p.their_addition.clone() > Amount::default())
+                .unwrap_or(false);
+            let (have_potato, spend) = ch.splice_confirmed()?;
+            let (my_balance, their_balance) = ch.out_of_game_balances();
+            (have_potato, spend, top_up, my_balance, their_balance)
+        };
+
+        let mut hs = HandshakeState::StepA;
+        swap(&mut hs, &mut self.handshake_state);
+        if let HandshakeState::OnChainWaitForConditions(_, mut hs) = hs {
+            hs.spend.spends = vec![CoinSpend {
+                coin: channel_coin.clone(),
+                bundle: spend,
+            }];
+            self.handshake_state = HandshakeState::Finished(hs);
+        }
+
+        {
+            let (_, system_interface) = penv.env();
+            system_interface.register_coin(
+                &channel_coin,
+                &self.channel_timeout,
+                Some(WATCH_CHANNEL),
+            )?;
+            if top_up {
+                system_interface.channel_topped_up(&my_balance, &their_balance)?;
+            } else {
+                system_interface.channel_withdrawn(&my_balance, &their_balance)?;
+            }
+        }
+
+        if !have_potato {
+            self.have_potato = PotatoState::Absent;
+            if !self.game_action_queue.is_empty() || !self.my_start_queue.is_empty() {
+                self.request_potato(penv)?;
+            }
+            return Ok(());
+        }
+
+        self.have_potato = PotatoState::Present;
+        if self.have_potato_start_game(penv)? {
+            return Ok(());
+        }
+
+        if self.have_potato_move(penv)? && !self.game_action_queue.is_empty() {
+            self.request_potato(penv)?;
+        }
+
+        Ok(())
+    }
+
     fn next_game_id(&mut self) -> Result<GameID, Error> {
         if self.next_game_id.is_empty() {
             return Err(Error::StrErr("no game id set".to_string()));
@@ -1179,9 +2343,8 @@ impl PotatoHandler {
     {
         // We must have received a peer layer message indicating that we're waiting for this
         // game start.
-        if self.their_start_queue.pop_front().is_none() {
-            return Err(Error::StrErr("no waiting games to start".to_string()));
-        };
+        let queue_index = self.start_queue_index_for(games)?;
+        self.check_games_match_proposal(penv, queue_index, games)?;
 
         let ch = self.channel_handler_mut()?;
         let spend_info = {
@@ -1190,7 +2353,16 @@ impl PotatoHandler {
             for game in games.iter() {
                 let new_rehydrated_game = GameStartInfo::from_serializable(env.allocator, game)?;
                 let re_dehydrated = new_rehydrated_game.to_serializable(env.allocator)?;
-                assert_eq!(&re_dehydrated, game);
+                if &re_dehydrated != game {
+                    return Err(Error::Protocol(ProtocolError::Malformed(
+                        "game start does not round trip".to_string(),
+                    )));
+                }
+                if new_rehydrated_game.initial_mover_share > new_rehydrated_game.amount {
+                    return Err(Error::Protocol(ProtocolError::Malformed(
+                        "game start mover share is more than the game amount".to_string(),
+                    )));
+                }
                 debug!(
                     "their game {:?}",
                     PrintableGameStartInfo {
@@ -1202,13 +2374,337 @@ impl PotatoHandler {
             }
             ch.received_potato_start_game(env, sigs, &rehydrated_games)?
         };
+        self.their_start_queue.remove(queue_index);
+
+        // Accepted proposals whose games are now running are done.
+        let started: BTreeSet<&GameID> = games.iter().map(|g| &g.game_id).collect();
+        self.their_proposals
+            .retain(|p| !(p.accepted && p.proposal.game_ids.iter().all(|id| started.contains(id))));
 
         self.update_channel_coin_after_receive(penv, &spend_info)?;
 
         Ok(())
     }
 
-    pub fn received_message<'a, G, R: Rng + 'a>(
+    // Pick the queued start these games fill.  Games for a proposal we
+    // accepted fill its entry; any others fill the oldest entry that isn't
+    // waiting on a proposal, so fresh game ids can't stand in for a proposal's
+    // games and skip checking against it.
+    fn start_queue_index_for(&self, games: &[FlatGameStartInfo]) -> Result<usize, Error> {
+        if self.their_start_queue.is_empty() {
+            return Err(Error::Protocol(ProtocolError::StateMismatch(
+                "no waiting games to start".to_string(),
+            )));
+        }
+
+        let first_id = games.first().map(|g| &g.game_id);
+        if let Some(index) = self
+            .their_start_queue
+            .iter()
+            .position(|entry| entry.proposal.is_some() && entry.proposal.as_ref() == first_id)
+        {
+            return Ok(index);
+        }
+
+        self.their_start_queue
+            .iter()
+            .position(|entry| entry.proposal.is_none())
+            .ok_or_else(|| {
+                Error::Protocol(ProtocolError::StateMismatch(
+                    "game start doesn't match the accepted proposal".to_string(),
+                ))
+            })
+    }
+
+    // Games started for a proposal we accepted must be the ones it described.
+    // The peer's factory run is repeated from its side so the games it would
+    // have sent us can be compared whole.
+    fn check_games_match_proposal<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        queue_index: usize,
+        games: &[FlatGameStartInfo],
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let proposal_id = if let Some(id) = &self.their_start_queue[queue_index].proposal {
+            id.clone()
+        } else {
+            return Ok(());
+        };
+        let proposal = if let Some(p) = self
+            .their_proposals
+            .iter()
+            .find(|p| p.accepted && p.proposal.game_ids.first() == Some(&proposal_id))
+        {
+            p.proposal.clone()
+        } else {
+            return Err(Error::StrErr(format!(
+                "no accepted proposal {proposal_id:?} for a queued start"
+            )));
+        };
+
+        let mismatch = |what: &str| {
+            Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+                "game start for proposal {:?} has the wrong {what}",
+                proposal.game_ids.first()
+            ))))
+        };
+
+        let incoming_ids: Vec<GameID> = games.iter().map(|g| g.game_id.clone()).collect();
+        if incoming_ids != proposal.game_ids {
+            return mismatch("game ids");
+        }
+
+        let their_start = proposal.for_peer()?;
+        let (_, expected) =
+            self.run_game_factory(penv, &their_start.start, Some(&proposal.game_ids))?;
+        let (env, _) = penv.env();
+        for (game, expected) in games.iter().zip(expected.iter()) {
+            let expected = expected.to_serializable(env.allocator)?;
+            if game.amount != expected.amount {
+                return mismatch("amount");
+            }
+            if game.my_contribution_this_game != expected.my_contribution_this_game {
+                return mismatch("contribution from us");
+            }
+            if game.their_contribution_this_game != expected.their_contribution_this_game {
+                return mismatch("contribution from the peer");
+            }
+            if game.timeout != expected.timeout {
+                return mismatch("timeout");
+            }
+            if game.game_handler.my_turn != expected.game_handler.my_turn {
+                return mismatch("turn");
+            }
+            if *game != expected {
+                return mismatch("game details");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn received_game_proposal<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        proposal: &WireGameStart,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        if proposal.game_ids.is_empty() {
+            return Err(Error::Protocol(ProtocolError::Malformed(
+                "game proposal has no games".to_string(),
+            )));
+        }
+        if !self.game_types.contains_key(&proposal.start.game_type) {
+            return Err(Error::Protocol(ProtocolError::Malformed(format!(
+                "proposed game {:?} was not negotiated",
+                proposal.start.game_type
+            ))));
+        }
+        let known = |id: &GameID| {
+            self.their_proposals
+                .iter()
+                .any(|p| p.proposal.game_ids.contains(id))
+        };
+        if proposal.game_ids.iter().any(known) {
+            return Err(Error::Protocol(ProtocolError::Malformed(
+                "game proposal reuses a game id".to_string(),
+            )));
+        }
+
+        let proposal = proposal.for_peer()?;
+        self.their_proposals.push(TheirGameProposal {
+            proposal: proposal.clone(),
+            accepted: false,
+        });
+
+        let (_, system_interface) = penv.env();
+        system_interface.game_proposed(&proposal)
+    }
+
+    // Answers to our proposals may cross a cancel we sent, so unknown ids are
+    // only logged.
+    fn received_proposal_accept<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let index = if let Some(index) = self.my_proposals.iter().position(|(p, _)| p == id) {
+            index
+        } else {
+            debug!("accept for unknown proposal {id:?}");
+            return Ok(());
+        };
+
+        let (_, entry) = self.my_proposals.remove(index);
+        self.my_start_queue.push_back(entry);
+
+        {
+            let (_, system_interface) = penv.env();
+            system_interface.game_proposal_accepted(id)?;
+        }
+
+        if matches!(self.have_potato, PotatoState::Present) {
+            self.have_potato_start_game(penv)?;
+        } else {
+            self.request_potato(penv)?;
+        }
+
+        Ok(())
+    }
+
+    fn received_proposal_decline<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let before = self.my_proposals.len();
+        self.my_proposals.retain(|(p, _)| p != id);
+        if self.my_proposals.len() == before {
+            debug!("decline for unknown proposal {id:?}");
+            return Ok(());
+        }
+
+        let (_, system_interface) = penv.env();
+        system_interface.game_proposal_declined(id)
+    }
+
+    fn received_proposal_cancel<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let index = if let Some(index) = self
+            .their_proposals
+            .iter()
+            .position(|p| p.proposal.game_ids.first() == Some(id))
+        {
+            index
+        } else {
+            debug!("cancel for unknown proposal {id:?}");
+            return Ok(());
+        };
+
+        // If our accept crossed the cancel, the peer won't send the games so
+        // the start we were waiting for goes too.
+        let removed = self.their_proposals.remove(index);
+        if removed.accepted {
+            self.their_start_queue
+                .retain(|entry| entry.proposal.as_ref() != Some(id));
+        }
+
+        let (_, system_interface) = penv.env();
+        system_interface.game_proposal_cancelled(id)
+    }
+
+    // Once a game ends, offers to settle it are moot, including a settlement
+    // we accepted that hasn't gone out yet.
+    fn forget_settlement_offers(&mut self, id: &GameID) {
+        self.my_settlement_offers.retain(|(g, _)| g != id);
+        self.their_settlement_offers.retain(|(g, _)| g != id);
+        self.game_action_queue
+            .retain(|a| !matches!(a, GameAction::Settle(g, _) if g == id));
+    }
+
+    // An offer can cross the game ending, so offers for games we don't have
+    // are only logged.
+    fn received_settlement_offer<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+        their_share: &Amount,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let ch = self.channel_handler()?;
+        if !ch.live_game_ids().contains(id) {
+            debug!("settlement offer for unknown game {id:?}");
+            return Ok(());
+        }
+
+        let at_stake = ch.game_amount(id)?;
+        if *their_share > at_stake {
+            return Err(Error::Protocol(ProtocolError::Malformed(format!(
+                "settlement share {their_share:?} is more than the {at_stake:?} at stake"
+            ))));
+        }
+
+        let my_share = at_stake - their_share.clone();
+        self.their_settlement_offers.retain(|(g, _)| g != id);
+        self.their_settlement_offers
+            .push((id.clone(), my_share.clone()));
+
+        let (_, system_interface) = penv.env();
+        system_interface.settlement_offered(id, &my_share)
+    }
+
+    fn received_settlement_decline<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let before = self.my_settlement_offers.len();
+        self.my_settlement_offers.retain(|(g, _)| g != id);
+        if self.my_settlement_offers.len() == before {
+            debug!("decline for unknown settlement offer {id:?}");
+            return Ok(());
+        }
+
+        let (_, system_interface) = penv.env();
+        system_interface.settlement_declined(id)
+    }
+
+    fn received_settle<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+        sigs: &PotatoSignatures,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let my_share =
+            if let Some((_, my_share)) = self.my_settlement_offers.iter().find(|(g, _)| g == id) {
+                my_share.clone()
+            } else {
+                return Err(Error::Protocol(ProtocolError::StateMismatch(format!(
+                    "settlement of {id:?} which we didn't offer"
+                ))));
+            };
+
+        let spend_info = {
+            let ch = self.channel_handler_mut()?;
+            let (env, _) = penv.env();
+            ch.received_potato_settle(env, sigs, id, &my_share)?
+        };
+        self.forget_settlement_offers(id);
+
+        {
+            let (_, system_interface) = penv.env();
+            system_interface.game_finished(id, my_share)?;
+        }
+
+        self.update_channel_coin_after_receive(penv, &spend_info)
+    }
+
+    pub fn received_message<'a, G, R: Rng + 'a>(
         &mut self,
         penv: &mut dyn PeerEnv<'a, G, R>,
         msg: Vec<u8>,
@@ -1216,7 +2712,7 @@ impl PotatoHandler {
     where
         G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
     {
-        let doc = bson::Document::from_reader(&mut msg.as_slice()).into_gen()?;
+        let msg_envelope = PeerMessage::from_bytes(&msg)?;
 
         let make_channel_handler_initiation =
             |parent: CoinID, start_potato, msg: &HandshakeB| ChannelHandlerInitiationData {
@@ -1227,20 +2723,16 @@ impl PotatoHandler {
                 their_referee_puzzle_hash: msg.referee_puzzle_hash.clone(),
                 my_contribution: self.my_contribution.clone(),
                 their_contribution: self.their_contribution.clone(),
-                unroll_advance_timeout: self.channel_timeout.clone(),
+                unroll_advance_timeout: self.unroll_timeout.clone(),
             };
 
         match &self.handshake_state {
             // non potato progression
             HandshakeState::StepA => {
-                let msg_envelope: PeerMessage =
-                    bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
                 let msg = if let PeerMessage::HandshakeA(msg) = msg_envelope {
                     msg
                 } else {
-                    return Err(Error::StrErr(format!(
-                        "Expected handshake a message, got {msg_envelope:?}"
-                    )));
+                    return Err(self.handshake_state.unexpected(&msg_envelope));
                 };
 
                 debug!(
@@ -1250,16 +2742,21 @@ impl PotatoHandler {
             }
 
             HandshakeState::StepC(parent_coin, handshake_a) => {
-                let msg_envelope: PeerMessage =
-                    bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
                 let msg = if let PeerMessage::HandshakeB(msg) = msg_envelope {
                     msg
                 } else {
-                    return Err(Error::StrErr(format!(
-                        "Expected handshake a message, got {msg_envelope:?}"
-                    )));
+                    return Err(self.handshake_state.unexpected(&msg_envelope));
                 };
 
+                let (common_games, features) = {
+                    let (env, _system_interface) = penv.env();
+                    self.my_capabilities(env).negotiate(&msg.capabilities)?
+                };
+                debug!("negotiated games {common_games:?} features {features:?}");
+                self.game_types
+                    .retain(|game_type, _| common_games.contains(game_type));
+                self.features = features;
+
                 // XXX Call the UX saying the channel coin has been created
                 // and play can happen.
                 // Register the channel coin in the bootstrap provider.
@@ -1293,7 +2790,7 @@ impl PotatoHandler {
                     system_interface.register_coin(
                         channel_coin.coin_string(),
                         &self.channel_timeout,
-                        Some("channel"),
+                        Some(WATCH_CHANNEL),
                     )?;
                 };
 
@@ -1303,9 +2800,12 @@ impl PotatoHandler {
                     private_to_public_key(&self.private_keys.my_unroll_coin_private_key);
                 let referee_public_key =
                     private_to_public_key(&self.private_keys.my_referee_private_key);
-                let referee_puzzle_hash = {
+                let (referee_puzzle_hash, capabilities) = {
                     let (env, _system_interface) = penv.env();
-                    puzzle_hash_for_pk(env.allocator, &referee_public_key)?
+                    (
+                        puzzle_hash_for_pk(env.allocator, &referee_public_key)?,
+                        self.my_capabilities(env),
+                    )
                 };
 
                 let our_handshake_data = HandshakeB {
@@ -1313,6 +2813,7 @@ impl PotatoHandler {
                     unroll_public_key,
                     reward_puzzle_hash: self.reward_puzzle_hash.clone(),
                     referee_puzzle_hash,
+                    capabilities,
                 };
 
                 {
@@ -1331,30 +2832,44 @@ impl PotatoHandler {
             }
 
             HandshakeState::StepE(info) => {
+                if !matches!(msg_envelope, PeerMessage::Nil(_)) {
+                    return Err(self.handshake_state.unexpected(&msg_envelope));
+                }
+
+                let info = info.clone();
                 let first_player_hs = info.first_player_hs_info.clone();
                 let second_player_hs = info.second_player_hs_info.clone();
 
                 self.handshake_state = HandshakeState::PostStepE(info.clone());
 
-                self.pass_on_channel_handler_message(penv, msg)?;
+                if let Err(e) = self.pass_on_channel_handler_message(penv, msg_envelope) {
+                    self.handshake_state = HandshakeState::StepE(info);
+                    return Err(e);
+                }
 
                 self.try_complete_step_e(penv, first_player_hs, second_player_hs)?;
             }
 
             // potato progression
             HandshakeState::StepB => {
-                let msg_envelope: PeerMessage =
-                    bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
                 let msg = if let PeerMessage::HandshakeA(msg) = msg_envelope {
                     msg
                 } else {
-                    return Err(Error::StrErr(format!(
-                        "Expected handshake a message, got {msg_envelope:?}"
-                    )));
+                    return Err(self.handshake_state.unexpected(&msg_envelope));
                 };
 
                 let init_data =
                     make_channel_handler_initiation(msg.parent.to_coin_id(), true, &msg.simple);
+                let (common_games, features) = {
+                    let (env, _system_interface) = penv.env();
+                    self.my_capabilities(env)
+                        .negotiate(&msg.simple.capabilities)?
+                };
+                debug!("negotiated games {common_games:?} features {features:?}");
+                self.game_types
+                    .retain(|game_type, _| common_games.contains(game_type));
+                self.features = features;
+
                 let (channel_handler, _init_result) = {
                     let (env, _system_interface) = penv.env();
                     ChannelHandler::new(env, self.private_keys.clone(), &init_data)?
@@ -1366,9 +2881,12 @@ impl PotatoHandler {
                     private_to_public_key(&channel_handler.unroll_private_key());
                 let referee_public_key =
                     private_to_public_key(&self.private_keys.my_referee_private_key);
-                let referee_puzzle_hash = {
+                let (referee_puzzle_hash, capabilities) = {
                     let (env, _system_interface) = penv.env();
-                    puzzle_hash_for_pk(env.allocator, &referee_public_key)?
+                    (
+                        puzzle_hash_for_pk(env.allocator, &referee_public_key)?,
+                        self.my_capabilities(env),
+                    )
                 };
 
                 let my_hs_info = HandshakeB {
@@ -1376,6 +2894,7 @@ impl PotatoHandler {
                     unroll_public_key,
                     reward_puzzle_hash: self.reward_puzzle_hash.clone(),
                     referee_puzzle_hash,
+                    capabilities,
                 };
 
                 self.next_game_id = init_game_id(&self.private_keys);
@@ -1392,9 +2911,17 @@ impl PotatoHandler {
             }
 
             HandshakeState::StepD(info) => {
+                if !matches!(msg_envelope, PeerMessage::Nil(_)) {
+                    return Err(self.handshake_state.unexpected(&msg_envelope));
+                }
+
+                let info = info.clone();
                 self.handshake_state = HandshakeState::StepF(info.clone());
 
-                self.pass_on_channel_handler_message(penv, msg)?;
+                if let Err(e) = self.pass_on_channel_handler_message(penv, msg_envelope) {
+                    self.handshake_state = HandshakeState::StepD(info);
+                    return Err(e);
+                }
 
                 let ch = self.channel_handler_mut()?;
                 {
@@ -1405,14 +2932,10 @@ impl PotatoHandler {
             }
 
             HandshakeState::StepF(info) => {
-                let msg_envelope: PeerMessage =
-                    bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
                 let bundle = if let PeerMessage::HandshakeE { bundle } = msg_envelope {
                     bundle
                 } else {
-                    return Err(Error::StrErr(format!(
-                        "Expected handshake e message, got {msg_envelope:?}"
-                    )));
+                    return Err(self.handshake_state.unexpected(&msg_envelope));
                 };
 
                 let channel_coin = {
@@ -1425,16 +2948,16 @@ impl PotatoHandler {
                 {
                     let (_env, system_interface) = penv.env();
                     if bundle.spends.is_empty() {
-                        return Err(Error::StrErr(
+                        return Err(Error::Protocol(ProtocolError::Malformed(
                             "No spends to draw the channel coin from".to_string(),
-                        ));
+                        )));
                     }
 
                     // Ensure we're watching for this coin.
                     system_interface.register_coin(
                         channel_coin.coin_string(),
                         &self.channel_timeout,
-                        Some("channel"),
+                        Some(WATCH_CHANNEL),
                     )?;
 
                     system_interface.received_channel_offer(&bundle)?;
@@ -1450,9 +2973,6 @@ impl PotatoHandler {
             }
 
             HandshakeState::Finished(_) => {
-                let msg_envelope: PeerMessage =
-                    bson::from_bson(bson::Bson::Document(doc)).into_gen()?;
-
                 debug!("running: got message {:?}", msg_envelope);
 
                 match msg_envelope {
@@ -1462,6 +2982,16 @@ impl PotatoHandler {
                         system_interface.received_channel_offer(&bundle)?;
                     }
                     PeerMessage::RequestPotato(_) => {
+                        if self.splice_in_progress() {
+                            // The splice passes the potato when it confirms.
+                            debug!("ignoring potato request during splice");
+                            return Ok(());
+                        }
+                        if !self.channel_handler()?.has_potato() {
+                            // The request crossed the potato on its way over.
+                            debug!("ignoring potato request without the potato");
+                            return Ok(());
+                        }
                         {
                             let (env, system_interface) = penv.env();
                             let ch = self.channel_handler_mut()?;
@@ -1473,8 +3003,42 @@ impl PotatoHandler {
                     PeerMessage::StartGames(sigs, g) => {
                         self.received_game_start(penv, &sigs, &g)?;
                     }
+                    PeerMessage::ProposeGame(proposal) => {
+                        self.received_game_proposal(penv, &proposal)?;
+                    }
+                    PeerMessage::AcceptProposal(id) => {
+                        self.received_proposal_accept(penv, &id)?;
+                    }
+                    PeerMessage::DeclineProposal(id) => {
+                        self.received_proposal_decline(penv, &id)?;
+                    }
+                    PeerMessage::CancelProposal(id) => {
+                        self.received_proposal_cancel(penv, &id)?;
+                    }
+                    PeerMessage::TopUp(amount, sigs) => {
+                        self.received_top_up(penv, &amount, &sigs)?;
+                    }
+                    PeerMessage::Withdraw(amount, payout_puzzle_hash, sigs) => {
+                        self.received_withdraw(penv, &amount, &payout_puzzle_hash, &sigs)?;
+                    }
+                    PeerMessage::SpliceAccept(sigs, half_signature) => {
+                        self.received_splice_accept(penv, &sigs, &half_signature)?;
+                    }
+                    PeerMessage::OfferSettlement(id, their_share) => {
+                        self.received_settlement_offer(penv, &id, &their_share)?;
+                    }
+                    PeerMessage::DeclineSettlement(id) => {
+                        self.received_settlement_decline(penv, &id)?;
+                    }
+                    PeerMessage::Settle(id, sigs) => {
+                        self.received_settle(penv, &id, &sigs)?;
+                    }
                     _ => {
-                        self.pass_on_channel_handler_message(penv, msg)?;
+                        if let Some(new_state) =
+                            self.pass_on_channel_handler_message(penv, msg_envelope)?
+                        {
+                            self.handshake_state = new_state;
+                        }
                     }
                 }
 
@@ -1482,10 +3046,7 @@ impl PotatoHandler {
             }
 
             _ => {
-                return Err(Error::StrErr(format!(
-                    "should not receive message in state {:?}",
-                    self.handshake_state
-                )));
+                return Err(self.handshake_state.unexpected(&msg_envelope));
             }
         }
 
@@ -1523,7 +3084,7 @@ impl PotatoHandler {
                         system_interface.register_coin(
                             &unroll_coin,
                             &self.unroll_timeout,
-                            Some("unroll"),
+                            Some(WATCH_UNROLL),
                         )?;
                         assert!(!matches!(self.handshake_state, HandshakeState::StepA));
                         return Ok(true);
@@ -1632,15 +3193,19 @@ impl PotatoHandler {
 
         debug!("{} CHANNEL: AGGREGATE UNROLL hash {unroll_puzzle_solution_hash:?} {aggregate_unroll_signature:?}", player_ch.is_initial_potato());
 
-        let run_puzzle = spend.spend.spends[0]
+        let channel_spend = if let Some(channel_spend) = spend.spend.spends.first() {
+            channel_spend
+        } else {
+            return Err(Error::StrErr(
+                "no channel coin spend to unroll with".to_string(),
+            ));
+        };
+        let run_puzzle = channel_spend
             .bundle
             .puzzle
             .to_program()
             .to_nodeptr(env.allocator)?;
-        let run_args = spend.spend.spends[0]
-            .bundle
-            .solution
-            .to_nodeptr(env.allocator)?;
+        let run_args = channel_spend.bundle.solution.to_nodeptr(env.allocator)?;
         let puzzle_result = run_program(
             env.allocator.allocator(),
             &chia_dialect(),
@@ -1653,7 +3218,7 @@ impl PotatoHandler {
         let unroll_result = if let Some(unroll_coin) = condition_list
             .iter()
             .filter_map(|cond| {
-                if let CoinCondition::CreateCoin(ph, amt) = cond {
+                if let CoinCondition::CreateCoin(ph, amt, _) = cond {
                     if *amt > Amount::default() {
                         let coin_id = CoinString::from_parts(
                             &player_ch.state_channel_coin().to_coin_id(),
@@ -1696,49 +3261,22 @@ impl PotatoHandler {
         debug!("spend from unroll coin {unroll_coin:?}");
         let (env, system_interface) = penv.env();
         let player_ch = self.channel_handler()?;
-        // Channel coin
+        // Our unroll coin timed out without being superceded so spend it with
+        // its default conditions.
         let finished_unroll_coin = player_ch.get_finished_unroll_coin();
-        let curried_unroll_puzzle = finished_unroll_coin
-            .coin
-            .make_curried_unroll_puzzle(env, &player_ch.get_aggregate_unroll_public_key())?;
-        let curried_unroll_program = Puzzle::from_nodeptr(env.allocator, curried_unroll_puzzle)?;
-        let unroll_solution = finished_unroll_coin
-            .coin
-            .make_unroll_puzzle_solution(env, &player_ch.get_aggregate_unroll_public_key())?;
-        let unroll_solution_program = Program::from_nodeptr(env.allocator, unroll_solution)?;
-
-        // For debugging: get internal idea of what's signed.
-        let unroll_puzzle_solution = finished_unroll_coin
-            .coin
-            .get_internal_conditions_for_unroll_coin_spend()?;
-        let unroll_puzzle_solution_hash = Node(unroll_puzzle_solution).sha256tree(env.allocator);
-        let aggregate_unroll_signature = finished_unroll_coin
-            .signatures
-            .my_unroll_half_signature_peer
-            .clone()
-            + finished_unroll_coin.coin.get_unroll_coin_signature()?;
-        assert!(aggregate_unroll_signature.verify(
-            &player_ch.get_aggregate_unroll_public_key(),
-            unroll_puzzle_solution_hash.bytes()
-        ));
+        let unroll_spend =
+            player_ch.get_create_unroll_coin_transaction(env, finished_unroll_coin)?;
 
-        debug!("{} SPEND: AGGREGATE UNROLL hash {unroll_puzzle_solution_hash:?} {aggregate_unroll_signature:?}", player_ch.is_initial_potato());
-        debug!(
-            "Internal solution {}",
-            disassemble(env.allocator.allocator(), unroll_puzzle_solution, None)
-        );
         debug!(
-            "Given solution {}",
-            disassemble(env.allocator.allocator(), unroll_solution, None)
+            "{} SPEND: unroll state {} solution {:?}",
+            player_ch.is_initial_potato(),
+            finished_unroll_coin.coin.state_number,
+            unroll_spend.transaction.solution.to_hex()
         );
 
         system_interface.spend_transaction_and_add_fee(&SpendBundle {
             spends: vec![CoinSpend {
-                bundle: Spend {
-                    puzzle: curried_unroll_program,
-                    solution: unroll_solution_program,
-                    signature: aggregate_unroll_signature,
-                },
+                bundle: unroll_spend.transaction,
                 coin: unroll_coin.clone(),
             }],
         })?;
@@ -1770,6 +3308,10 @@ impl PotatoHandler {
             }
             x => {
                 self.handshake_state = x;
+                if self.is_going_on_chain() || self.is_on_chain() {
+                    return Ok(());
+                }
+
                 Err(Error::StrErr(
                     "go on chain before handshake finished".to_string(),
                 ))
@@ -1777,21 +3319,121 @@ impl PotatoHandler {
         }
     }
 
-    fn do_game_action<'a, G, R: Rng + 'a>(
+    // What the peer owes us while the channel is running off chain.
+    fn waiting_for_peer(&self) -> Option<PeerWait> {
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return None;
+        }
+
+        // The peer owes a countersignature for our splice or the spend of its
+        // own.  If neither comes, we fall back to the unroll.  Once we've
+        // submitted our own splice, we're only waiting on the chain.
+        if let Some(splice) = self
+            .channel_handler
+            .as_ref()
+            .and_then(|ch| ch.pending_splice())
+        {
+            if splice.is_ours() && splice.countersigned {
+                return None;
+            }
+            return Some(PeerWait::Splice);
+        }
+
+        match self.have_potato {
+            PotatoState::Requested => Some(PeerWait::Potato),
+            PotatoState::Absent => {
+                let games_live = self
+                    .channel_handler
+                    .as_ref()
+                    .map(|ch| !ch.live_game_ids().is_empty())
+                    .unwrap_or(false);
+                if games_live {
+                    Some(PeerWait::Move)
+                } else {
+                    None
+                }
+            }
+            PotatoState::Present => None,
+        }
+    }
+
+    /// Called with each new block height.  If the peer has left us waiting
+    /// longer than the liveness policy allows, take the channel on chain.
+    pub fn check_peer_liveness<'a, G, R: Rng + 'a>(
         &mut self,
         penv: &mut dyn PeerEnv<'a, G, R>,
-        action: GameAction,
+        height: u64,
     ) -> Result<(), Error>
     where
         G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
     {
-        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
-            return Err(Error::StrErr(
-                "move without finishing handshake".to_string(),
-            ));
-        }
+        let wait = if let Some(wait) = self.waiting_for_peer() {
+            wait
+        } else {
+            self.waiting_on_peer = None;
+            return Ok(());
+        };
 
-        self.game_action_queue.push_back(action);
+        let since = match &self.waiting_on_peer {
+            Some((waiting, since)) if *waiting == wait => *since,
+            _ => {
+                self.waiting_on_peer = Some((wait, height));
+                return Ok(());
+            }
+        };
+
+        // A stalled splice always falls back to the unroll, since the funds
+        // it moves are stuck until it does.
+        let limit = match wait {
+            PeerWait::Potato => self.liveness.potato_timeout.clone(),
+            PeerWait::Splice => Some(
+                self.liveness
+                    .potato_timeout
+                    .clone()
+                    .unwrap_or_else(|| self.channel_timeout.clone()),
+            ),
+            PeerWait::Move => self.liveness.move_timeout.clone(),
+        };
+        let waited = height.saturating_sub(since);
+        if let Some(limit) = limit {
+            if waited >= limit.to_u64() {
+                debug!("peer inactive for {waited} blocks waiting for {wait:?}");
+                self.waiting_on_peer = None;
+                self.go_on_chain(penv)?;
+                let (_, system_interface) = penv.env();
+                system_interface.going_on_chain(&GoingOnChainReason::PeerInactive(wait, waited))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn do_game_action<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        action: GameAction,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        if matches!(self.handshake_state, HandshakeState::OnChain(_)) {
+            self.game_action_queue.push_back(action);
+            return self.do_on_chain_game_actions(penv);
+        }
+
+        if self.is_going_on_chain() {
+            // Played out once the game coins exist.
+            self.game_action_queue.push_back(action);
+            return Ok(());
+        }
+
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return Err(Error::StrErr(
+                "move without finishing handshake".to_string(),
+            ));
+        }
+
+        self.game_action_queue.push_back(action);
 
         if !matches!(self.have_potato, PotatoState::Present) {
             self.request_potato(penv)?;
@@ -1842,13 +3484,11 @@ impl PotatoHandler {
         )
         .into_gen()?;
 
-        // XXX If I wasn't the one who initiated the on chain transition, determine whether
-        // to bump the unroll coin.
         let channel_conditions = CoinCondition::from_nodeptr(env.allocator, conditions.1);
         let unroll_coin = if let Some(coin_id) = channel_conditions
             .iter()
             .filter_map(|c| {
-                if let CoinCondition::CreateCoin(ph, amt) = c {
+                if let CoinCondition::CreateCoin(ph, amt, _) = c {
                     let created = CoinString::from_parts(&coin_id.to_coin_id(), ph, amt);
                     debug!("created unroll coin {created:?}");
                     return Some(created);
@@ -1865,8 +3505,31 @@ impl PotatoHandler {
             ));
         };
 
+        // A splice spends the channel coin into a new one rather than unrolling.
+        if self.channel_handler()?.pending_splice_coin() == Some(&unroll_coin) {
+            return self.finish_splice(penv, unroll_coin);
+        }
+
+        // If our peer unrolled an older state than the newest one we hold signatures
+        // for, supercede it now rather than waiting for them to spend it at the
+        // timeout.
+        let player_ch = self.channel_handler()?;
+        if player_ch.is_stale_unroll(env, conditions.1)? {
+            let spent_result = player_ch.channel_coin_spent(env, false, conditions.1)?;
+            debug!(
+                "{} superceding stale unroll {unroll_coin:?}",
+                player_ch.is_initial_potato()
+            );
+            system_interface.spend_transaction_and_add_fee(&SpendBundle {
+                spends: vec![CoinSpend {
+                    bundle: spent_result.transaction,
+                    coin: unroll_coin.clone(),
+                }],
+            })?;
+        }
+
         self.handshake_state = HandshakeState::OnChainWaitingForUnrollSpend(unroll_coin.clone());
-        system_interface.register_coin(&unroll_coin, &self.unroll_timeout, Some("unroll"))?;
+        system_interface.register_coin(&unroll_coin, &self.unroll_timeout, Some(WATCH_UNROLL))?;
 
         Ok(())
     }
@@ -1876,22 +3539,382 @@ impl PotatoHandler {
     // matches the state system given so on chain play can proceed.
     fn finish_on_chain_transition<'a, G, R: Rng + 'a>(
         &mut self,
-        _penv: &mut dyn PeerEnv<'a, G, R>,
-        _coin_id: &CoinString,
-        _puzzle_and_solution: Option<(&Program, &Program)>,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        coin_id: &CoinString,
+        puzzle_and_solution: Option<(&Program, &Program)>,
     ) -> Result<(), Error>
     where
         G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
     {
+        let (puzzle, solution) = if let Some((puzzle, solution)) = puzzle_and_solution {
+            (puzzle, solution)
+        } else {
+            return Err(Error::StrErr(
+                "Retrieve of puzzle and solution failed for unroll coin".to_string(),
+            ));
+        };
+
+        let (env, system_interface) = penv.env();
         let player_ch = self.channel_handler()?;
         debug!(
             "{} FINISH ON CHAIN TRANSITION",
             player_ch.is_initial_potato()
         );
-        // XXX ensure map.
-        self.handshake_state = HandshakeState::OnChain(HashMap::new());
+
+        let run_puzzle = puzzle.to_nodeptr(env.allocator)?;
+        let run_args = solution.to_nodeptr(env.allocator)?;
+        let conditions = run_program(
+            env.allocator.allocator(),
+            &chia_dialect(),
+            run_puzzle,
+            run_args,
+            0,
+        )
+        .into_gen()?;
+
+        let spent_result = player_ch.unroll_coin_spent(env, coin_id, conditions.1)?;
+        debug!("unroll coin spent {spent_result:?}");
+
+        let mut game_map = HashMap::new();
+        for game_coin in spent_result.new_game_coins_on_chain.iter() {
+            if let Some(coin) = game_coin.coin_string_up.as_ref() {
+                let timeout = player_ch.get_game_timeout(&game_coin.game_id_up)?;
+                system_interface.register_coin(coin, &timeout, Some(WATCH_GAME))?;
+                game_map.insert(
+                    coin.clone(),
+                    OnChainGameState {
+                        game_id: game_coin.game_id_up.clone(),
+                        our_turn: player_ch.game_is_my_turn_on_chain(&game_coin.game_id_up)?,
+                        accepted: false,
+                    },
+                );
+            }
+        }
+
+        match spent_result.disposition {
+            Some(CoinSpentDisposition::Move(replay)) => {
+                // Our last move wasn't acknowledged before the unroll, so the
+                // game coin is from before it.  Make the move on chain.
+                let timeout = player_ch.get_game_timeout(&replay.game_id)?;
+                system_interface.spend_transaction_and_add_fee(&SpendBundle {
+                    spends: vec![replay.spend_before_game_coin.clone()],
+                })?;
+                system_interface.register_coin(
+                    &replay.after_update_game_coin,
+                    &timeout,
+                    Some(WATCH_GAME),
+                )?;
+                game_map.retain(|_, g| g.game_id != replay.game_id);
+                game_map.insert(
+                    replay.after_update_game_coin.clone(),
+                    OnChainGameState {
+                        game_id: replay.game_id.clone(),
+                        our_turn: false,
+                        accepted: false,
+                    },
+                );
+            }
+            Some(CoinSpentDisposition::Accept(accepted)) => {
+                // The UI was told the game finished when we accepted it.  A
+                // settlement the peer never countersigned is claimed here at
+                // the game's current share rather than the settled one.
+                debug!("unrolled over accepted game {:?}", accepted.game_id);
+            }
+            Some(CoinSpentDisposition::CancelledUX(ids)) => {
+                for id in ids.iter() {
+                    system_interface.game_cancelled(id)?;
+                }
+            }
+            None => {}
+        }
+
+        self.handshake_state = HandshakeState::OnChain(game_map);
+
+        // Carry out anything the UI asked for while we were unrolling.
+        self.do_on_chain_game_actions(penv)
+    }
+
+    /// The channel coin was spent, or we spent it, and the games haven't
+    /// reached the chain yet.
+    pub fn is_going_on_chain(&self) -> bool {
+        matches!(
+            self.handshake_state,
+            HandshakeState::OnChainTransition(_, _)
+                | HandshakeState::OnChainWaitingForUnrollTimeoutOrSpend(_, _)
+                | HandshakeState::OnChainWaitForConditions(_, _)
+                | HandshakeState::OnChainWaitingForUnrollSpend(_)
+                | HandshakeState::OnChainWaitingForUnrollConditions(_)
+        )
+    }
+
+    fn on_chain_game_coin(
+        &self,
+        game_id: &GameID,
+    ) -> Result<(CoinString, OnChainGameState), Error> {
+        if let HandshakeState::OnChain(games) = &self.handshake_state {
+            if let Some((coin, game)) = games.iter().find(|(_, g)| &g.game_id == game_id) {
+                return Ok((coin.clone(), game.clone()));
+            }
+        }
+
+        Err(Error::StrErr(format!(
+            "no game coin on chain for {game_id:?}"
+        )))
+    }
+
+    fn update_on_chain_game_coin(
+        &mut self,
+        old_coin: &CoinString,
+        new_coin: Option<(CoinString, OnChainGameState)>,
+    ) {
+        if let HandshakeState::OnChain(games) = &mut self.handshake_state {
+            games.remove(old_coin);
+            if let Some((coin, game)) = new_coin {
+                games.insert(coin, game);
+            }
+        }
+    }
+
+    // Perform queued game actions against the game coins, stopping at a move
+    // that has to wait for the opponent's move to reach the chain.
+    fn do_on_chain_game_actions<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        while let Some(action) = self.game_action_queue.pop_front() {
+            match action {
+                GameAction::Move(game_id, readable_move, new_entropy) => {
+                    let (coin, game) = self.on_chain_game_coin(&game_id)?;
+                    if game.accepted {
+                        return Err(Error::StrErr(
+                            "move in a game that was already accepted".to_string(),
+                        ));
+                    }
+
+                    if !game.our_turn {
+                        self.game_action_queue.push_front(GameAction::Move(
+                            game_id,
+                            readable_move,
+                            new_entropy,
+                        ));
+                        return Ok(());
+                    }
+
+                    self.do_on_chain_move(penv, &coin, game, &readable_move, new_entropy)?;
+                }
+                GameAction::Accept(game_id) => {
+                    // We make no more moves; the game ends when its coin times out.
+                    let (coin, game) = self.on_chain_game_coin(&game_id)?;
+                    self.update_on_chain_game_coin(
+                        &coin,
+                        Some((
+                            coin.clone(),
+                            OnChainGameState {
+                                accepted: true,
+                                ..game
+                            },
+                        )),
+                    );
+                }
+                GameAction::Shutdown(_) => {
+                    return Err(Error::StrErr(
+                        "can't shut down cleanly once on chain".to_string(),
+                    ));
+                }
+                GameAction::TopUp(_) => {
+                    return Err(Error::StrErr("can't top up once on chain".to_string()));
+                }
+                GameAction::Withdraw(_) => {
+                    return Err(Error::StrErr("can't withdraw once on chain".to_string()));
+                }
+                GameAction::Settle(game_id, _) => {
+                    // A settlement we accepted that never went out; the game
+                    // is played out on chain instead.
+                    debug!("dropping settlement of {game_id:?} on chain");
+                    let (_, system_interface) = penv.env();
+                    system_interface.settlement_declined(&game_id)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn do_on_chain_move<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        coin: &CoinString,
+        game: OnChainGameState,
+        readable_move: &ReadableMove,
+        new_entropy: Hash,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let (move_result, timeout) = {
+            let ch = self.channel_handler_mut()?;
+            let (env, _) = penv.env();
+            let move_result =
+                ch.on_chain_our_move(env, &game.game_id, readable_move, new_entropy, coin)?;
+            (move_result, ch.get_game_timeout(&game.game_id)?)
+        };
+
+        let (_, system_interface) = penv.env();
+        system_interface.spend_transaction_and_add_fee(&SpendBundle {
+            spends: vec![move_result.spend],
+        })?;
+        system_interface.register_coin(&move_result.new_game_coin, &timeout, Some(WATCH_GAME))?;
+        system_interface.self_move(&game.game_id, &move_result.game_move.basic.move_made)?;
+
+        self.update_on_chain_game_coin(
+            coin,
+            Some((
+                move_result.new_game_coin,
+                OnChainGameState {
+                    our_turn: false,
+                    ..game
+                },
+            )),
+        );
+
         Ok(())
     }
+
+    // A game coin we're watching was spent, either by their move or by a
+    // timeout or slash that ends the game.
+    fn handle_game_coin_spent<'a, G, R: Rng + 'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        coin_id: &CoinString,
+        game: OnChainGameState,
+        puzzle_and_solution: Option<(&Program, &Program)>,
+    ) -> Result<(), Error>
+    where
+        G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender + 'a,
+    {
+        let (puzzle, solution) = if let Some((puzzle, solution)) = puzzle_and_solution {
+            (puzzle, solution)
+        } else {
+            return Err(Error::StrErr(
+                "Retrieve of puzzle and solution failed for game coin".to_string(),
+            ));
+        };
+
+        let (env, system_interface) = penv.env();
+        let run_puzzle = puzzle.to_nodeptr(env.allocator)?;
+        let run_args = solution.to_nodeptr(env.allocator)?;
+        let conditions = run_program(
+            env.allocator.allocator(),
+            &chia_dialect(),
+            run_puzzle,
+            run_args,
+            0,
+        )
+        .into_gen()?;
+
+        let is_move =
+            self.channel_handler()?
+                .game_coin_spend_is_move(env, &game.game_id, conditions.1)?;
+
+        if !is_move {
+            let ch = self.channel_handler_mut()?;
+            let reward_coin = ch.get_game_reward_coin(env, &game.game_id, coin_id, conditions.1)?;
+            ch.game_finished_on_chain(&game.game_id)?;
+            let my_share = reward_coin
+                .and_then(|c| c.to_parts())
+                .map(|(_, _, amt)| amt)
+                .unwrap_or_default();
+            debug!(
+                "game {:?} finished on chain with {my_share:?}",
+                game.game_id
+            );
+            self.update_on_chain_game_coin(coin_id, None);
+            return system_interface.game_finished(&game.game_id, my_share);
+        }
+
+        if game.our_turn {
+            return Err(Error::StrErr(
+                "game coin waiting on our move was moved by someone else".to_string(),
+            ));
+        }
+
+        let (spent_result, timeout) = {
+            let ch = self.channel_handler_mut()?;
+            let spent_result = ch.game_coin_spent(env, &game.game_id, coin_id, conditions.1)?;
+            (spent_result, ch.get_game_timeout(&game.game_id)?)
+        };
+
+        match spent_result {
+            TheirTurnCoinSpentResult::Moved {
+                new_coin_string,
+                readable,
+            } => {
+                system_interface.register_coin(&new_coin_string, &timeout, Some(WATCH_GAME))?;
+                system_interface.opponent_moved(
+                    env.allocator,
+                    &game.game_id,
+                    ReadableMove::from_nodeptr(readable),
+                )?;
+                self.update_on_chain_game_coin(
+                    coin_id,
+                    Some((
+                        new_coin_string,
+                        OnChainGameState {
+                            our_turn: true,
+                            ..game
+                        },
+                    )),
+                );
+            }
+            TheirTurnCoinSpentResult::Timedout {
+                my_reward_coin_string,
+            } => {
+                let ch = self.channel_handler_mut()?;
+                ch.game_finished_on_chain(&game.game_id)?;
+                let my_share = my_reward_coin_string
+                    .and_then(|c| c.to_parts())
+                    .map(|(_, _, amt)| amt)
+                    .unwrap_or_default();
+                self.update_on_chain_game_coin(coin_id, None);
+                system_interface.game_finished(&game.game_id, my_share)?;
+            }
+            TheirTurnCoinSpentResult::Slash(outcome) => {
+                debug!("opponent made a slashable move in {:?}", game.game_id);
+                let ch = self.channel_handler_mut()?;
+                ch.game_finished_on_chain(&game.game_id)?;
+                self.update_on_chain_game_coin(coin_id, None);
+                match *outcome {
+                    SlashOutcome::NoReward => {
+                        system_interface.opponent_slashed(&game.game_id, Amount::default())?;
+                    }
+                    SlashOutcome::Reward {
+                        transaction,
+                        my_reward_coin_string,
+                    } => {
+                        system_interface.spend_transaction_and_add_fee(&SpendBundle {
+                            spends: vec![*transaction],
+                        })?;
+                        system_interface.register_coin(
+                            &my_reward_coin_string,
+                            &timeout,
+                            Some(WATCH_REWARD),
+                        )?;
+                        let my_reward = my_reward_coin_string
+                            .to_parts()
+                            .map(|(_, _, amt)| amt)
+                            .unwrap_or_default();
+                        system_interface.opponent_slashed(&game.game_id, my_reward)?;
+                    }
+                }
+            }
+        }
+
+        // A move that was waiting on theirs may proceed now.
+        self.do_on_chain_game_actions(penv)
+    }
 }
 
 impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender, R: Rng>
@@ -1935,7 +3958,8 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
             self.have_potato_start_game(penv)?;
         } else {
             // All checking needed is done by channel handler.
-            self.their_start_queue.push_back(GameStartQueueEntry);
+            self.their_start_queue
+                .push_back(GameStartQueueEntry { proposal: None });
         }
 
         Ok(game_id_list)
@@ -1966,6 +3990,240 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
         self.do_game_action(penv, GameAction::Accept(id.clone()))
     }
 
+    fn propose_game<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        game: &GameStart,
+    ) -> Result<Vec<GameID>, Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return Err(Error::StrErr(format!(
+                "propose game without finishing handshake: {:?}",
+                self.handshake_state
+            )));
+        }
+
+        if game.my_contribution > game.amount {
+            return Err(Error::StrErr(
+                "contribution is more than the game amount".to_string(),
+            ));
+        }
+
+        let (my_games, their_games) = self.get_games_by_start_type(penv, game)?;
+        let game_ids: Vec<GameID> = my_games.iter().map(|g| g.game_id.clone()).collect();
+        let proposal_id = if let Some(id) = game_ids.first() {
+            id.clone()
+        } else {
+            return Err(Error::StrErr(format!(
+                "game {:?} has no games to start",
+                game.game_type
+            )));
+        };
+
+        self.my_proposals.push((
+            proposal_id,
+            MyGameStartQueueEntry {
+                my_games,
+                their_games,
+            },
+        ));
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::ProposeGame(WireGameStart {
+            game_ids: game_ids.clone(),
+            start: game.clone(),
+        }))?;
+
+        Ok(game_ids)
+    }
+
+    fn accept_proposal<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        let proposal = if let Some(proposal) = self
+            .their_proposals
+            .iter_mut()
+            .find(|p| !p.accepted && p.proposal.game_ids.first() == Some(id))
+        {
+            proposal
+        } else {
+            return Err(Error::StrErr(format!("no pending proposal {id:?}")));
+        };
+
+        // Like start_games(false), the peer sends the games once it has the
+        // potato.
+        proposal.accepted = true;
+        self.their_start_queue.push_back(GameStartQueueEntry {
+            proposal: Some(id.clone()),
+        });
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::AcceptProposal(id.clone()))
+    }
+
+    fn decline_proposal<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        let before = self.their_proposals.len();
+        self.their_proposals
+            .retain(|p| p.accepted || p.proposal.game_ids.first() != Some(id));
+        if self.their_proposals.len() == before {
+            return Err(Error::StrErr(format!("no pending proposal {id:?}")));
+        }
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::DeclineProposal(id.clone()))
+    }
+
+    fn cancel_proposal<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        let before = self.my_proposals.len();
+        self.my_proposals.retain(|(p, _)| p != id);
+        if self.my_proposals.len() == before {
+            return Err(Error::StrErr(format!("no pending proposal {id:?}")));
+        }
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::CancelProposal(id.clone()))
+    }
+
+    fn top_up<'a>(&mut self, penv: &mut dyn PeerEnv<'a, G, R>, amount: &Amount) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return Err(Error::StrErr(format!(
+                "top up without finishing handshake: {:?}",
+                self.handshake_state
+            )));
+        }
+
+        self.do_game_action(penv, GameAction::TopUp(amount.clone()))
+    }
+
+    fn withdraw<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        amount: &Amount,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return Err(Error::StrErr(format!(
+                "withdraw without finishing handshake: {:?}",
+                self.handshake_state
+            )));
+        }
+
+        self.do_game_action(penv, GameAction::Withdraw(amount.clone()))
+    }
+
+    fn offer_settlement<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+        my_share: &Amount,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        if !matches!(self.handshake_state, HandshakeState::Finished(_)) {
+            return Err(Error::StrErr(format!(
+                "offer settlement without finishing handshake: {:?}",
+                self.handshake_state
+            )));
+        }
+
+        let at_stake = self.channel_handler()?.game_amount(id)?;
+        if *my_share > at_stake {
+            return Err(Error::StrErr(format!(
+                "settlement share {my_share:?} is more than the {at_stake:?} at stake"
+            )));
+        }
+
+        // The peer's settle is signed for the split it was offered, so a game
+        // has at most one offer of ours outstanding.
+        if self.my_settlement_offers.iter().any(|(g, _)| g == id) {
+            return Err(Error::StrErr(format!(
+                "settlement offer for {id:?} is already waiting on the peer"
+            )));
+        }
+
+        self.my_settlement_offers
+            .push((id.clone(), my_share.clone()));
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::OfferSettlement(id.clone(), my_share.clone()))
+    }
+
+    fn accept_settlement<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        let index = if let Some(index) = self
+            .their_settlement_offers
+            .iter()
+            .position(|(g, _)| g == id)
+        {
+            index
+        } else {
+            return Err(Error::StrErr(format!("no settlement offer for {id:?}")));
+        };
+
+        let (_, my_share) = self.their_settlement_offers.remove(index);
+        self.do_game_action(penv, GameAction::Settle(id.clone(), my_share))
+    }
+
+    fn decline_settlement<'a>(
+        &mut self,
+        penv: &mut dyn PeerEnv<'a, G, R>,
+        id: &GameID,
+    ) -> Result<(), Error>
+    where
+        G: 'a,
+        R: 'a,
+    {
+        let before = self.their_settlement_offers.len();
+        self.their_settlement_offers.retain(|(g, _)| g != id);
+        if self.their_settlement_offers.len() == before {
+            return Err(Error::StrErr(format!("no settlement offer for {id:?}")));
+        }
+
+        let (_, system_interface) = penv.env();
+        system_interface.send_message(&PeerMessage::DeclineSettlement(id.clone()))
+    }
+
     fn shut_down<'a>(
         &mut self,
         penv: &mut dyn PeerEnv<'a, G, R>,
@@ -1981,6 +4239,24 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
             ));
         }
 
+        // Settle any games still running before the channel closes.
+        let queued_accepts: Vec<GameID> = self
+            .game_action_queue
+            .iter()
+            .filter_map(|a| {
+                if let GameAction::Accept(game_id) = a {
+                    Some(game_id.clone())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for game_id in self.channel_handler()?.live_game_ids() {
+            if !queued_accepts.contains(&game_id) {
+                self.do_game_action(penv, GameAction::Accept(game_id))?;
+            }
+        }
+
         self.do_game_action(penv, GameAction::Shutdown(conditions))
     }
 }
@@ -2066,7 +4342,7 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
         }
 
         if let Some(reward) =
-            if let HandshakeState::WaitingForShutdown(reward, _state) = &self.handshake_state {
+            if let HandshakeState::WaitingForShutdown(reward, _state, _) = &self.handshake_state {
                 Some(reward.clone())
             } else {
                 None
@@ -2093,7 +4369,7 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
         R: 'a,
     {
         if let Some((reward, state_coin)) =
-            if let HandshakeState::WaitingForShutdown(reward, coin) = &self.handshake_state {
+            if let HandshakeState::WaitingForShutdown(reward, coin, _) = &self.handshake_state {
                 Some((reward.clone(), coin.clone()))
             } else {
                 None
@@ -2114,6 +4390,14 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
             }
         }
 
+        if let HandshakeState::OnChain(games) = &self.handshake_state {
+            if games.contains_key(coin_id) {
+                // Find out whether this was a move or the end of the game.
+                let (_, system_interface) = penv.env();
+                return system_interface.request_puzzle_and_solution(coin_id);
+            }
+        }
+
         self.check_channel_spent(penv, coin_id)?;
 
         self.check_unroll_spent(penv, coin_id)?;
@@ -2148,6 +4432,44 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
             return self.do_unroll_spend_to_games(penv, coin_id);
         }
 
+        // Our reward never showed up, so the peer didn't finish the clean
+        // shutdown.  Unroll the channel instead.
+        let shutdown_timed_out =
+            if let HandshakeState::WaitingForShutdown(reward, channel, _) = &self.handshake_state {
+                coin_id == reward || coin_id == channel
+            } else {
+                false
+            };
+
+        if shutdown_timed_out {
+            let mut hs = HandshakeState::StepA;
+            swap(&mut hs, &mut self.handshake_state);
+            if let HandshakeState::WaitingForShutdown(_, _, spend) = hs {
+                debug!("clean shutdown timed out, unrolling");
+                self.do_channel_spend_to_unroll(penv, spend)?;
+                let (_, system_interface) = penv.env();
+                system_interface.going_on_chain(&GoingOnChainReason::ShutdownTimeout)?;
+            }
+            return Ok(());
+        }
+
+        // A game coin timing out ends the game.  One of us claims it.
+        let timed_out_game = if let HandshakeState::OnChain(games) = &self.handshake_state {
+            games.get(coin_id).map(|g| g.game_id.clone())
+        } else {
+            None
+        };
+
+        if let Some(game_id) = timed_out_game {
+            let ch = self.channel_handler()?;
+            let (env, system_interface) = penv.env();
+            if let Some(spend) = ch.get_game_timeout_transaction(env, &game_id, coin_id)? {
+                system_interface.spend_transaction_and_add_fee(&SpendBundle {
+                    spends: vec![spend],
+                })?;
+            }
+        }
+
         Ok(())
     }
 
@@ -2167,6 +4489,17 @@ impl<G: ToLocalUI + BootstrapTowardWallet + WalletSpendInterface + PacketSender,
             player_ch.is_initial_potato(),
             self.handshake_state
         );
+
+        let spent_game = if let HandshakeState::OnChain(games) = &self.handshake_state {
+            games.get(coin_id).cloned()
+        } else {
+            None
+        };
+
+        if let Some(game) = spent_game {
+            return self.handle_game_coin_spent(penv, coin_id, game, puzzle_and_solution);
+        }
+
         let state_coin_id = match &self.handshake_state {
             HandshakeState::OnChainWaitForConditions(state_coin_id, _data) => {
                 Some(ConditionWaitKind::Channel(state_coin_id.clone()))
@@ -13,8 +13,8 @@ use log::debug;
 use serde::{Deserialize, Serialize};
 
 use crate::channel_handler::game_handler::{
-    chia_dialect, GameHandler, MessageHandler, MessageInputs, MyTurnInputs, TheirTurnInputs,
-    TheirTurnResult,
+    chia_dialect, FlatGameHandler, GameHandler, MessageHandler, MessageInputs, MyTurnInputs,
+    TheirTurnInputs, TheirTurnResult,
 };
 use crate::channel_handler::types::{
     Evidence, GameStartInfo, PrintableGameStartInfo, ReadableMove, ValidationInfo,
@@ -22,13 +22,12 @@ use crate::channel_handler::types::{
 };
 use crate::common::constants::CREATE_COIN;
 use crate::common::standard_coin::{
-    calculate_hash_of_quoted_mod_hash, curry_and_treehash, puzzle_for_pk,
-    standard_solution_partial, standard_solution_unsafe, ChiaIdentity,
+    calculate_hash_of_quoted_mod_hash, curry_and_treehash, standard_solution_partial, ChiaIdentity,
 };
 use crate::common::types::{
-    u64_from_atom, usize_from_atom, Aggsig, AllocEncoder, Amount, CoinCondition, CoinSpend,
-    CoinString, Error, GameID, Hash, IntoErr, Node, Program, Puzzle, PuzzleHash, Sha256tree, Spend,
-    Timeout,
+    u64_from_atom, usize_from_atom, Aggsig, AllocEncoder, Amount, BrokenOutCoinSpendInfo,
+    CoinCondition, CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Node, Program,
+    ProtocolError, Puzzle, PuzzleHash, Sha256tree, Spend, Timeout,
 };
 
 pub const REM_CONDITION_FIELDS: usize = 4;
@@ -174,7 +173,11 @@ impl RefereePuzzleArgs {
                 .to_clvm(allocator)
                 .into_gen()?,
             if let Some(p) = self.previous_validation_info_hash.as_ref() {
-                assert_ne!(p, &Hash::default());
+                if *p == Hash::default() {
+                    return Err(Error::StrErr(
+                        "previous validation info hash is empty".to_string(),
+                    ));
+                }
                 p.to_clvm(allocator).into_gen()?
             } else {
                 ().to_clvm(allocator).into_gen()?
@@ -186,6 +189,104 @@ impl RefereePuzzleArgs {
     }
 }
 
+// The validation info hash of the referee coin for a game that has just
+// started, which the coin made by the first move names as its previous one.
+fn initial_validation_info_hash(
+    allocator: &mut AllocEncoder,
+    initial_validation_program: &ValidationProgram,
+    initial_state: NodePtr,
+) -> Hash {
+    ValidationInfo::new(allocator, initial_validation_program.clone(), initial_state)
+        .hash()
+        .clone()
+}
+
+// The referee coin for a game that has just started.
+fn initial_referee_puzzle_args(
+    my_puzzle_hash: &PuzzleHash,
+    their_puzzle_hash: &PuzzleHash,
+    timeout: &Timeout,
+    amount: &Amount,
+    nonce: usize,
+    initial_move: &GameMoveStateInfo,
+    validation_info_hash: &Hash,
+    my_turn: bool,
+) -> RefereePuzzleArgs {
+    RefereePuzzleArgs {
+        mover_puzzle_hash: if my_turn {
+            my_puzzle_hash.clone()
+        } else {
+            their_puzzle_hash.clone()
+        },
+        waiter_puzzle_hash: if my_turn {
+            their_puzzle_hash.clone()
+        } else {
+            my_puzzle_hash.clone()
+        },
+        timeout: timeout.clone(),
+        amount: amount.clone(),
+        nonce,
+        game_move: GameMoveDetails {
+            basic: GameMoveStateInfo {
+                mover_share: if my_turn {
+                    amount.clone() - initial_move.mover_share.clone()
+                } else {
+                    initial_move.mover_share.clone()
+                },
+                ..initial_move.clone()
+            },
+            validation_info_hash: validation_info_hash.clone(),
+        },
+        previous_validation_info_hash: None,
+    }
+}
+
+// The referee coin made by a move on chain.  It hands the game to the waiter
+// and names the spent coin's validation info hash as the previous one.
+fn referee_args_after_move(
+    spent_coin_args: &RefereePuzzleArgs,
+    game_move: &GameMoveDetails,
+) -> RefereePuzzleArgs {
+    RefereePuzzleArgs {
+        mover_puzzle_hash: spent_coin_args.waiter_puzzle_hash.clone(),
+        waiter_puzzle_hash: spent_coin_args.mover_puzzle_hash.clone(),
+        timeout: spent_coin_args.timeout.clone(),
+        amount: spent_coin_args.amount.clone(),
+        nonce: spent_coin_args.nonce,
+        game_move: game_move.clone(),
+        previous_validation_info_hash: Some(spent_coin_args.game_move.validation_info_hash.clone()),
+    }
+}
+
+// Read the move a referee coin's remark describes.
+fn game_move_from_rem_condition(rem_condition: &[Vec<u8>]) -> Result<GameMoveDetails, Error> {
+    if rem_condition.len() != REM_CONDITION_FIELDS {
+        return Err(Error::StrErr(
+            "rem condition should have the right number of fields".to_string(),
+        ));
+    }
+
+    let (mover_share, max_move_size) = if let (Some(share), Some(max_size)) = (
+        u64_from_atom(&rem_condition[2]),
+        usize_from_atom(&rem_condition[3]),
+    ) {
+        (Amount::new(share), max_size)
+    } else {
+        return Err(Error::StrErr(
+            "mover share wasn't a properly sized atom".to_string(),
+        ));
+    };
+
+    Ok(GameMoveDetails {
+        basic: GameMoveStateInfo {
+            move_made: rem_condition[0].clone(),
+            max_move_size,
+            mover_share,
+        },
+        validation_info_hash: Hash::from_slice(&rem_condition[1]),
+    })
+}
+
 fn curry_referee_puzzle_hash(
     allocator: &mut AllocEncoder,
     referee_coin_puzzle_hash: &PuzzleHash,
@@ -306,6 +407,8 @@ enum RefereeMakerGameState {
     Initial {
         initial_state: NodePtr,
         initial_validation_program: ValidationProgram,
+        // Derived from the two above.
+        initial_validation_info_hash: Hash,
         initial_move: GameMoveStateInfo,
         game_handler: GameHandler,
     },
@@ -346,6 +449,186 @@ impl RefereeMakerGameState {
     }
 }
 
+// Serializable form of RefereeMakerGameState.  Each NodePtr is flattened to a
+// Program so that it can be rebuilt in a different allocator.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum FlatRefereeMakerGameState {
+    Initial {
+        initial_state: Program,
+        initial_validation_program: Program,
+        initial_move: GameMoveStateInfo,
+        game_handler: FlatGameHandler,
+    },
+    AfterOurTurn {
+        game_handler: FlatGameHandler,
+        their_turn_game_handler: FlatGameHandler,
+        their_previous_validation_info_hash: Option<Hash>,
+        validation_program: Program,
+        state: Program,
+        most_recent_their_move: GameMoveStateInfo,
+        most_recent_our_move: GameMoveDetails,
+    },
+    AfterTheirTurn {
+        game_handler: FlatGameHandler,
+        our_turn_game_handler: FlatGameHandler,
+        our_previous_validation_info_hash: Option<Hash>,
+        most_recent_our_validation_program: Program,
+        most_recent_our_state_result: Program,
+        most_recent_our_move: GameMoveStateInfo,
+        most_recent_their_move: GameMoveDetails,
+    },
+}
+
+impl RefereeMakerGameState {
+    fn to_serializable(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<FlatRefereeMakerGameState, Error> {
+        match self {
+            RefereeMakerGameState::Initial {
+                initial_state,
+                initial_validation_program,
+                initial_move,
+                game_handler,
+                ..
+            } => Ok(FlatRefereeMakerGameState::Initial {
+                initial_state: Program::from_nodeptr(allocator, *initial_state)?,
+                initial_validation_program: Program::from_nodeptr(
+                    allocator,
+                    initial_validation_program.to_nodeptr(),
+                )?,
+                initial_move: initial_move.clone(),
+                game_handler: game_handler.to_serializable(allocator)?,
+            }),
+            RefereeMakerGameState::AfterOurTurn {
+                game_handler,
+                their_turn_game_handler,
+                their_previous_validation_info_hash,
+                validation_program,
+                state,
+                most_recent_their_move,
+                most_recent_our_move,
+            } => Ok(FlatRefereeMakerGameState::AfterOurTurn {
+                game_handler: game_handler.to_serializable(allocator)?,
+                their_turn_game_handler: their_turn_game_handler.to_serializable(allocator)?,
+                their_previous_validation_info_hash: their_previous_validation_info_hash.clone(),
+                validation_program: Program::from_nodeptr(
+                    allocator,
+                    validation_program.to_nodeptr(),
+                )?,
+                state: Program::from_nodeptr(allocator, *state)?,
+                most_recent_their_move: most_recent_their_move.clone(),
+                most_recent_our_move: most_recent_our_move.clone(),
+            }),
+            RefereeMakerGameState::AfterTheirTurn {
+                game_handler,
+                our_turn_game_handler,
+                our_previous_validation_info_hash,
+                most_recent_our_validation_program,
+                most_recent_our_state_result,
+                most_recent_our_move,
+                most_recent_their_move,
+            } => Ok(FlatRefereeMakerGameState::AfterTheirTurn {
+                game_handler: game_handler.to_serializable(allocator)?,
+                our_turn_game_handler: our_turn_game_handler.to_serializable(allocator)?,
+                our_previous_validation_info_hash: our_previous_validation_info_hash.clone(),
+                most_recent_our_validation_program: Program::from_nodeptr(
+                    allocator,
+                    most_recent_our_validation_program.to_nodeptr(),
+                )?,
+                most_recent_our_state_result: Program::from_nodeptr(
+                    allocator,
+                    *most_recent_our_state_result,
+                )?,
+                most_recent_our_move: most_recent_our_move.clone(),
+                most_recent_their_move: most_recent_their_move.clone(),
+            }),
+        }
+    }
+
+    fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatRefereeMakerGameState,
+    ) -> Result<RefereeMakerGameState, Error> {
+        match serializable {
+            FlatRefereeMakerGameState::Initial {
+                initial_state,
+                initial_validation_program,
+                initial_move,
+                game_handler,
+            } => {
+                let validation_program_node = initial_validation_program.to_nodeptr(allocator)?;
+                let initial_validation_program =
+                    ValidationProgram::new(allocator, validation_program_node);
+                let initial_state = initial_state.to_nodeptr(allocator)?;
+                Ok(RefereeMakerGameState::Initial {
+                    initial_validation_info_hash: initial_validation_info_hash(
+                        allocator,
+                        &initial_validation_program,
+                        initial_state,
+                    ),
+                    initial_state,
+                    initial_validation_program,
+                    initial_move: initial_move.clone(),
+                    game_handler: GameHandler::from_serializable(allocator, game_handler)?,
+                })
+            }
+            FlatRefereeMakerGameState::AfterOurTurn {
+                game_handler,
+                their_turn_game_handler,
+                their_previous_validation_info_hash,
+                validation_program,
+                state,
+                most_recent_their_move,
+                most_recent_our_move,
+            } => {
+                let validation_program_node = validation_program.to_nodeptr(allocator)?;
+                Ok(RefereeMakerGameState::AfterOurTurn {
+                    game_handler: GameHandler::from_serializable(allocator, game_handler)?,
+                    their_turn_game_handler: GameHandler::from_serializable(
+                        allocator,
+                        their_turn_game_handler,
+                    )?,
+                    their_previous_validation_info_hash: their_previous_validation_info_hash
+                        .clone(),
+                    validation_program: ValidationProgram::new(allocator, validation_program_node),
+                    state: state.to_nodeptr(allocator)?,
+                    most_recent_their_move: most_recent_their_move.clone(),
+                    most_recent_our_move: most_recent_our_move.clone(),
+                })
+            }
+            FlatRefereeMakerGameState::AfterTheirTurn {
+                game_handler,
+                our_turn_game_handler,
+                our_previous_validation_info_hash,
+                most_recent_our_validation_program,
+                most_recent_our_state_result,
+                most_recent_our_move,
+                most_recent_their_move,
+            } => {
+                let validation_program_node =
+                    most_recent_our_validation_program.to_nodeptr(allocator)?;
+                Ok(RefereeMakerGameState::AfterTheirTurn {
+                    game_handler: GameHandler::from_serializable(allocator, game_handler)?,
+                    our_turn_game_handler: GameHandler::from_serializable(
+                        allocator,
+                        our_turn_game_handler,
+                    )?,
+                    our_previous_validation_info_hash: our_previous_validation_info_hash.clone(),
+                    most_recent_our_validation_program: ValidationProgram::new(
+                        allocator,
+                        validation_program_node,
+                    ),
+                    most_recent_our_state_result: most_recent_our_state_result
+                        .to_nodeptr(allocator)?,
+                    most_recent_our_move: most_recent_our_move.clone(),
+                    most_recent_their_move: most_recent_their_move.clone(),
+                })
+            }
+        }
+    }
+}
+
 /// A puzzle for a coin that will be run inside the referee to generate
 /// conditions that are acted on to spend the referee coin.
 /// The referee knows the mover puzzle hash, so we've already decided what
@@ -387,9 +670,10 @@ pub struct OnChainRefereeSlash {
     pub previous_game_state: NodePtr,
 
     /// Since notionally we optimistically accept game updates at the referee
-    /// layer, "previous" here is the current state at the time the move arrived,
-    /// previous to the update that caused this slash.
-    pub previous_validation_info: ValidationInfo,
+    /// layer, "previous" here is the validation program in effect when the move
+    /// arrived, previous to the update that caused this slash.  The referee
+    /// checks it against PREVIOUS_VALIDATION_INFO_HASH and runs it.
+    pub previous_validation_program: ValidationProgram,
 
     /// Coin puzzle and solution that are used to generate conditions for the
     /// next generation of the on chain refere coin.
@@ -410,7 +694,6 @@ pub struct OnChainRefereeSlash {
 pub enum OnChainRefereeSolution {
     Timeout,
     Move(OnChainRefereeMove),
-    #[allow(dead_code)]
     Slash(OnChainRefereeSlash),
 }
 
@@ -460,7 +743,7 @@ impl ToClvm<NodePtr> for OnChainRefereeSolution {
             OnChainRefereeSolution::Slash(refslash) => (
                 Node(refslash.previous_game_state),
                 (
-                    refslash.previous_validation_info.hash(),
+                    Node(refslash.previous_validation_program.to_nodeptr()),
                     (
                         refslash.mover_coin.mover_coin_puzzle.clone(),
                         (
@@ -500,6 +783,25 @@ pub struct RefereeMaker {
 
     #[cfg(test)]
     pub run_debug: bool,
+    #[cfg(test)]
+    cheat_next_move_share: Option<Amount>,
+}
+
+/// Serializable form of a RefereeMaker, including the previous state that
+/// allows the referee to rewind.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FlatRefereeMaker {
+    referee_coin_puzzle: Puzzle,
+    referee_coin_puzzle_hash: PuzzleHash,
+    my_identity: ChiaIdentity,
+    their_referee_puzzle_hash: PuzzleHash,
+    timeout: Timeout,
+    amount: Amount,
+    nonce: usize,
+    finished: bool,
+    state: FlatRefereeMakerGameState,
+    old_state: Option<FlatRefereeMakerGameState>,
+    message_handler: Option<Program>,
 }
 
 impl RefereeMaker {
@@ -524,9 +826,15 @@ impl RefereeMaker {
             move_made: game_start_info.initial_move.clone(),
             max_move_size: game_start_info.initial_max_move_size,
         };
+        let validation_info_hash = initial_validation_info_hash(
+            allocator,
+            &game_start_info.initial_validation_program,
+            game_start_info.initial_state,
+        );
         let state = Rc::new(RefereeMakerGameState::Initial {
             initial_state: game_start_info.initial_state,
             initial_validation_program: game_start_info.initial_validation_program.clone(),
+            initial_validation_info_hash: validation_info_hash.clone(),
             initial_move: initial_move.clone(),
             game_handler: game_start_info.game_handler.clone(),
         });
@@ -536,33 +844,16 @@ impl RefereeMaker {
         let puzzle_hash = curry_referee_puzzle_hash(
             allocator,
             &referee_coin_puzzle_hash,
-            &RefereePuzzleArgs {
-                mover_puzzle_hash: if my_turn {
-                    my_identity.puzzle_hash.clone()
-                } else {
-                    their_puzzle_hash.clone()
-                },
-                waiter_puzzle_hash: if my_turn {
-                    their_puzzle_hash.clone()
-                } else {
-                    my_identity.puzzle_hash.clone()
-                },
-                timeout: game_start_info.timeout.clone(),
-                amount: game_start_info.amount.clone(),
+            &initial_referee_puzzle_args(
+                &my_identity.puzzle_hash,
+                their_puzzle_hash,
+                &game_start_info.timeout,
+                &game_start_info.amount,
                 nonce,
-                game_move: GameMoveDetails {
-                    basic: GameMoveStateInfo {
-                        mover_share: if my_turn {
-                            game_start_info.amount.clone() - initial_move.mover_share.clone()
-                        } else {
-                            initial_move.mover_share.clone()
-                        },
-                        ..initial_move.clone()
-                    },
-                    validation_info_hash: Hash::default(),
-                },
-                previous_validation_info_hash: None,
-            },
+                &initial_move,
+                &validation_info_hash,
+                my_turn,
+            ),
         )?;
 
         Ok((
@@ -582,11 +873,83 @@ impl RefereeMaker {
                 message_handler: None,
                 #[cfg(test)]
                 run_debug: false,
+                #[cfg(test)]
+                cheat_next_move_share: None,
             },
             puzzle_hash,
         ))
     }
 
+    pub fn to_serializable(&self, allocator: &mut AllocEncoder) -> Result<FlatRefereeMaker, Error> {
+        let old_state = if let Some(old_state) = &self.old_state {
+            Some(old_state.to_serializable(allocator)?)
+        } else {
+            None
+        };
+        let message_handler = if let Some(message_handler) = &self.message_handler {
+            Some(Program::from_nodeptr(allocator, message_handler.0)?)
+        } else {
+            None
+        };
+
+        Ok(FlatRefereeMaker {
+            referee_coin_puzzle: self.referee_coin_puzzle.clone(),
+            referee_coin_puzzle_hash: self.referee_coin_puzzle_hash.clone(),
+            my_identity: self.my_identity.clone(),
+            their_referee_puzzle_hash: self.their_referee_puzzle_hash.clone(),
+            timeout: self.timeout.clone(),
+            amount: self.amount.clone(),
+            nonce: self.nonce,
+            finished: self.finished,
+            state: self.state.to_serializable(allocator)?,
+            old_state,
+            message_handler,
+        })
+    }
+
+    /// Rebuild a referee from its serialized form, placing all clvm data in
+    /// the given allocator.
+    pub fn from_serializable(
+        allocator: &mut AllocEncoder,
+        serializable: &FlatRefereeMaker,
+    ) -> Result<RefereeMaker, Error> {
+        let old_state = if let Some(old_state) = &serializable.old_state {
+            Some(Rc::new(RefereeMakerGameState::from_serializable(
+                allocator, old_state,
+            )?))
+        } else {
+            None
+        };
+        let message_handler = if let Some(message_handler) = &serializable.message_handler {
+            Some(MessageHandler::from_nodeptr(
+                message_handler.to_nodeptr(allocator)?,
+            ))
+        } else {
+            None
+        };
+
+        Ok(RefereeMaker {
+            referee_coin_puzzle: serializable.referee_coin_puzzle.clone(),
+            referee_coin_puzzle_hash: serializable.referee_coin_puzzle_hash.clone(),
+            my_identity: serializable.my_identity.clone(),
+            their_referee_puzzle_hash: serializable.their_referee_puzzle_hash.clone(),
+            timeout: serializable.timeout.clone(),
+            amount: serializable.amount.clone(),
+            nonce: serializable.nonce,
+            finished: serializable.finished,
+            state: Rc::new(RefereeMakerGameState::from_serializable(
+                allocator,
+                &serializable.state,
+            )?),
+            old_state,
+            message_handler,
+            #[cfg(test)]
+            run_debug: false,
+            #[cfg(test)]
+            cheat_next_move_share: None,
+        })
+    }
+
     pub fn rewind(&mut self) -> Result<bool, Error> {
         if let Some(old_state) = &self.old_state {
             self.state = old_state.clone();
@@ -629,7 +992,9 @@ impl RefereeMaker {
                 ..
             } => Ok(most_recent_our_move.clone()),
             RefereeMakerGameState::AfterTheirTurn { .. } => {
-                todo!();
+                Err(Error::Protocol(ProtocolError::StateMismatch(
+                    "their move is the most recent one in this game".to_string(),
+                )))
             }
         }
     }
@@ -648,12 +1013,18 @@ impl RefereeMaker {
         &self,
     ) -> Result<(NodePtr, ValidationProgram), Error> {
         match self.state.borrow() {
-            RefereeMakerGameState::Initial { .. } => {
-                Err(Error::StrErr("no moves have been made yet".to_string()))
-            }
-            RefereeMakerGameState::AfterOurTurn { .. } => Err(Error::StrErr(
-                "we accepted an our move so their move has sunsetted".to_string(),
-            )),
+            // While their move is awaited, it's checked against the program
+            // our side of the game left behind.
+            RefereeMakerGameState::Initial {
+                initial_state,
+                initial_validation_program,
+                ..
+            } => Ok((*initial_state, initial_validation_program.clone())),
+            RefereeMakerGameState::AfterOurTurn {
+                state,
+                validation_program,
+                ..
+            } => Ok((*state, validation_program.clone())),
             RefereeMakerGameState::AfterTheirTurn {
                 most_recent_our_validation_program,
                 most_recent_our_state_result,
@@ -691,6 +1062,13 @@ impl RefereeMaker {
         self.run_debug = ena;
     }
 
+    /// Make our next move claim this mover share instead of the one the game
+    /// gives, so tests can play a peer that cheats.
+    #[cfg(test)]
+    pub fn cheat_on_next_move(&mut self, mover_share: Amount) {
+        self.cheat_next_move_share = Some(mover_share);
+    }
+
     pub fn get_validation_program_clvm(&self) -> Result<NodePtr, Error> {
         match self.state.borrow() {
             RefereeMakerGameState::Initial {
@@ -742,17 +1120,19 @@ impl RefereeMaker {
     ) -> Result<(), Error> {
         debug!("accept move {details:?}");
         let new_state = match self.state.borrow() {
-            RefereeMakerGameState::Initial { initial_move, .. } => {
-                RefereeMakerGameState::AfterOurTurn {
-                    game_handler: game_handler.clone(),
-                    their_turn_game_handler: game_handler.clone(),
-                    validation_program: validation_program.clone(),
-                    their_previous_validation_info_hash: None,
-                    state,
-                    most_recent_their_move: initial_move.clone(),
-                    most_recent_our_move: details.clone(),
-                }
-            }
+            RefereeMakerGameState::Initial {
+                initial_move,
+                initial_validation_info_hash,
+                ..
+            } => RefereeMakerGameState::AfterOurTurn {
+                game_handler: game_handler.clone(),
+                their_turn_game_handler: game_handler.clone(),
+                validation_program: validation_program.clone(),
+                their_previous_validation_info_hash: Some(initial_validation_info_hash.clone()),
+                state,
+                most_recent_their_move: initial_move.clone(),
+                most_recent_our_move: details.clone(),
+            },
             RefereeMakerGameState::AfterOurTurn { .. } => {
                 return Err(Error::StrErr(
                     "accept our move when it's already past our turn".to_string(),
@@ -800,6 +1180,7 @@ impl RefereeMaker {
                 initial_validation_program,
                 initial_state,
                 initial_move,
+                initial_validation_info_hash,
                 ..
             } => RefereeMakerGameState::AfterTheirTurn {
                 game_handler: raw_game_handler.clone(),
@@ -808,7 +1189,7 @@ impl RefereeMaker {
                 most_recent_our_validation_program: initial_validation_program.clone(),
                 most_recent_our_move: initial_move.clone(),
                 most_recent_their_move: details.clone(),
-                our_previous_validation_info_hash: None,
+                our_previous_validation_info_hash: Some(initial_validation_info_hash.clone()),
             },
             RefereeMakerGameState::AfterOurTurn {
                 most_recent_our_move,
@@ -854,11 +1235,15 @@ impl RefereeMaker {
         let game_handler = self.get_game_handler();
         let (move_data, mover_share, max_move_size, previous_validation_info_hash) =
             match self.state.borrow() {
-                RefereeMakerGameState::Initial { initial_move, .. } => (
+                RefereeMakerGameState::Initial {
+                    initial_move,
+                    initial_validation_info_hash,
+                    ..
+                } => (
                     initial_move.move_made.clone(),
                     initial_move.mover_share.clone(),
                     initial_move.max_move_size,
-                    None,
+                    Some(initial_validation_info_hash.clone()),
                 ),
                 RefereeMakerGameState::AfterOurTurn { .. } => {
                     return Err(Error::StrErr(
@@ -892,6 +1277,15 @@ impl RefereeMaker {
             },
         )?;
 
+        #[cfg(test)]
+        let result = {
+            let mut result = result;
+            if let Some(mover_share) = self.cheat_next_move_share.take() {
+                result.game_move.basic.mover_share = mover_share;
+            }
+            result
+        };
+
         debug!("my turn result {result:?}");
         debug!(
             "new state {}",
@@ -1071,47 +1465,107 @@ impl RefereeMaker {
         )
     }
 
-    // Ensure this returns
+    // The curried arguments of the referee coin as it sits on chain in the
+    // given state.  Each move hands the coin to the waiter, so the mover named
+    // here is the one whose move is awaited.
+    fn on_chain_referee_args_for_state(&self, state: &RefereeMakerGameState) -> RefereePuzzleArgs {
+        match state {
+            RefereeMakerGameState::Initial {
+                initial_move,
+                initial_validation_info_hash,
+                game_handler,
+                ..
+            } => initial_referee_puzzle_args(
+                &self.my_identity.puzzle_hash,
+                &self.their_referee_puzzle_hash,
+                &self.timeout,
+                &self.amount,
+                self.nonce,
+                initial_move,
+                initial_validation_info_hash,
+                game_handler.is_my_turn(),
+            ),
+            RefereeMakerGameState::AfterOurTurn {
+                most_recent_our_move,
+                their_previous_validation_info_hash,
+                ..
+            } => RefereePuzzleArgs {
+                mover_puzzle_hash: self.their_referee_puzzle_hash.clone(),
+                waiter_puzzle_hash: self.my_identity.puzzle_hash.clone(),
+                timeout: self.timeout.clone(),
+                amount: self.amount.clone(),
+                nonce: self.nonce,
+                game_move: most_recent_our_move.clone(),
+                previous_validation_info_hash: their_previous_validation_info_hash.clone(),
+            },
+            RefereeMakerGameState::AfterTheirTurn {
+                most_recent_their_move,
+                our_previous_validation_info_hash,
+                ..
+            } => RefereePuzzleArgs {
+                mover_puzzle_hash: self.my_identity.puzzle_hash.clone(),
+                waiter_puzzle_hash: self.their_referee_puzzle_hash.clone(),
+                timeout: self.timeout.clone(),
+                amount: self.amount.clone(),
+                nonce: self.nonce,
+                game_move: most_recent_their_move.clone(),
+                previous_validation_info_hash: our_previous_validation_info_hash.clone(),
+            },
+        }
+    }
+
+    /// Puzzle hash of the referee coin that holds this game on chain.
+    pub fn on_chain_referee_puzzle_hash(
+        &self,
+        allocator: &mut AllocEncoder,
+    ) -> Result<PuzzleHash, Error> {
+        let args = self.on_chain_referee_args_for_state(&self.state);
+        curry_referee_puzzle_hash(allocator, &self.referee_coin_puzzle_hash, &args)
+    }
+
+    /// True if the referee coin holding this game on chain is waiting for our
+    /// move.
+    pub fn is_my_turn_on_chain(&self) -> bool {
+        let args = self.on_chain_referee_args_for_state(&self.state);
+        args.mover_puzzle_hash == self.my_identity.puzzle_hash
+    }
+
+    // What the referee's timeout pays us for a coin with these arguments.
+    fn on_chain_timeout_share(&self, args: &RefereePuzzleArgs) -> Amount {
+        if args.mover_puzzle_hash == self.my_identity.puzzle_hash {
+            args.game_move.basic.mover_share.clone()
+        } else {
+            self.amount.clone() - args.game_move.basic.mover_share.clone()
+        }
+    }
+
     fn get_transaction(
         &self,
         allocator: &mut AllocEncoder,
-        coin_string: &CoinString,
         puzzle: &Puzzle,
-        always_produce_transaction: bool,
         args: &OnChainRefereeSolution,
-    ) -> Result<Option<RefereeOnChainTransaction>, Error> {
-        let my_mover_share = self.get_our_current_share();
-
-        if always_produce_transaction || my_mover_share != Amount::default() {
-            let signature = args.get_signature().unwrap_or_default();
-
-            // The transaction solution is not the same as the solution for the
-            // inner puzzle as we take additional move or slash data.
-            //
-            // OnChainRefereeSolution encodes this properly.
-            let transaction_solution = args.to_clvm(allocator).into_gen()?;
-            debug!(
-                "transaction_solution {}",
-                disassemble(allocator.allocator(), transaction_solution, None)
-            );
-            let transaction_bundle = Spend {
-                puzzle: puzzle.clone(),
-                solution: Program::from_nodeptr(allocator, transaction_solution)?,
-                signature,
-            };
-            let output_coin_string = CoinString::from_parts(
-                &coin_string.to_coin_id(),
-                &puzzle.sha256tree(allocator),
-                &my_mover_share,
-            );
-            return Ok(Some(RefereeOnChainTransaction {
-                bundle: transaction_bundle,
-                reward_coin: output_coin_string,
-            }));
-        }
+        output_coin_string: CoinString,
+    ) -> Result<RefereeOnChainTransaction, Error> {
+        let signature = args.get_signature().unwrap_or_default();
 
-        // Zero mover share case.
-        Ok(None)
+        // The transaction solution is not the same as the solution for the
+        // inner puzzle as we take additional move or slash data.
+        //
+        // OnChainRefereeSolution encodes this properly.
+        let transaction_solution = args.to_clvm(allocator).into_gen()?;
+        debug!(
+            "transaction_solution {}",
+            disassemble(allocator.allocator(), transaction_solution, None)
+        );
+        let transaction_bundle = Spend {
+            puzzle: puzzle.clone(),
+            solution: Program::from_nodeptr(allocator, transaction_solution)?,
+            signature,
+        };
+        Ok(RefereeOnChainTransaction {
+            bundle: transaction_bundle,
+            reward_coin: output_coin_string,
+        })
     }
 
     /// Output coin_string:
@@ -1122,14 +1576,162 @@ impl RefereeMaker {
         allocator: &mut AllocEncoder,
         coin_string: &CoinString,
     ) -> Result<Option<RefereeOnChainTransaction>, Error> {
+        let my_mover_share = self.get_our_current_share();
+        if my_mover_share == Amount::default() {
+            return Ok(None);
+        }
+
         let spend_puzzle = self.curried_referee_puzzle_for_validator(allocator)?;
+        let output_coin_string = CoinString::from_parts(
+            &coin_string.to_coin_id(),
+            &self.my_identity.puzzle_hash,
+            &my_mover_share,
+        );
+        self.get_transaction(
+            allocator,
+            &spend_puzzle,
+            &OnChainRefereeSolution::Timeout,
+            output_coin_string,
+        )
+        .map(Some)
+    }
+
+    /// Time out the referee coin that holds this game on chain.
+    ///
+    /// The timeout pays the mover share to whoever was expected to move and the
+    /// rest to the other player.  Returns None if we'd receive nothing.
+    pub fn get_transaction_for_on_chain_timeout(
+        &self,
+        allocator: &mut AllocEncoder,
+        coin_string: &CoinString,
+    ) -> Result<Option<RefereeOnChainTransaction>, Error> {
+        let args = self.on_chain_referee_args_for_state(&self.state);
+        let my_share = self.on_chain_timeout_share(&args);
+        if my_share == Amount::default() {
+            return Ok(None);
+        }
+
+        let spend_puzzle = curry_referee_puzzle(
+            allocator,
+            &self.referee_coin_puzzle,
+            &self.referee_coin_puzzle_hash,
+            &args,
+        )?;
+        let output_coin_string = CoinString::from_parts(
+            &coin_string.to_coin_id(),
+            &self.my_identity.puzzle_hash,
+            &my_share,
+        );
         self.get_transaction(
             allocator,
-            coin_string,
             &spend_puzzle,
-            false,
             &OnChainRefereeSolution::Timeout,
+            output_coin_string,
         )
+        .map(Some)
+    }
+
+    /// Both players can time out a referee coin, but only one spend can win.
+    /// The waiter claims it unless it would receive nothing, in which case the
+    /// mover does.
+    pub fn should_claim_on_chain_timeout(&self) -> bool {
+        let args = self.on_chain_referee_args_for_state(&self.state);
+        let mover_share = args.game_move.basic.mover_share.clone();
+        let waiter_share = self.amount.clone() - mover_share.clone();
+        if args.mover_puzzle_hash == self.my_identity.puzzle_hash {
+            waiter_share == Amount::default() && mover_share != Amount::default()
+        } else {
+            waiter_share != Amount::default()
+        }
+    }
+
+    /// Whether a spend of our referee coin was a move, which makes the
+    /// referee coin described by its remark, rather than a timeout or slash
+    /// that ends the game.
+    pub fn is_move_spend(
+        &self,
+        allocator: &mut AllocEncoder,
+        conditions: NodePtr,
+    ) -> Result<bool, Error> {
+        let conditions = CoinCondition::from_nodeptr(allocator, conditions);
+        let game_move = if let Some(Ok(game_move)) = conditions.iter().find_map(|c| {
+            if let CoinCondition::Rem(rem) = c {
+                Some(game_move_from_rem_condition(rem))
+            } else {
+                None
+            }
+        }) {
+            game_move
+        } else {
+            return Ok(false);
+        };
+
+        let spent_coin_args = self.on_chain_referee_args_for_state(&self.state);
+        let new_puzzle_hash = curry_referee_puzzle_hash(
+            allocator,
+            &self.referee_coin_puzzle_hash,
+            &referee_args_after_move(&spent_coin_args, &game_move),
+        )?;
+        Ok(conditions.iter().any(|c| {
+            matches!(
+                c,
+                CoinCondition::CreateCoin(ph, amt, _) if *ph == new_puzzle_hash && *amt == self.amount
+            )
+        }))
+    }
+
+    /// Find the coin paying us among the conditions produced by spending the
+    /// referee coin.
+    pub fn get_my_reward_coin_from_conditions(
+        &self,
+        allocator: &mut AllocEncoder,
+        coin_string: &CoinString,
+        conditions: NodePtr,
+    ) -> Option<CoinString> {
+        CoinCondition::from_nodeptr(allocator, conditions)
+            .iter()
+            .find_map(|c| {
+                if let CoinCondition::CreateCoin(ph, amt, _) = c {
+                    if *ph == self.my_identity.puzzle_hash && *amt > Amount::default() {
+                        return Some(CoinString::from_parts(&coin_string.to_coin_id(), ph, amt));
+                    }
+                }
+
+                None
+            })
+    }
+
+    // The arguments of the referee coin our move spends and of the coin it
+    // makes.  Usually the spent coin is the one from before our move, which
+    // is recorded in the state we had before consuming it.  A coin curried
+    // as the validator sees our move is spent by restating the move onto
+    // the coin that waits for theirs.
+    fn referee_args_for_move_spend(
+        &self,
+        allocator: &mut AllocEncoder,
+        coin_string: &CoinString,
+    ) -> Result<(RefereePuzzleArgs, RefereePuzzleArgs), Error> {
+        let validator_args = self.curried_referee_args_for_validator()?;
+        let validator_puzzle_hash =
+            curry_referee_puzzle_hash(allocator, &self.referee_coin_puzzle_hash, &validator_args)?;
+        if coin_string.to_parts().map(|(_, ph, _)| ph) == Some(validator_puzzle_hash) {
+            debug!("get_transaction_for_move: spending the validator's coin");
+            let target_args = referee_args_after_move(&validator_args, &validator_args.game_move);
+            return Ok((validator_args, target_args));
+        }
+
+        debug!("get_transaction_for_move: previous curry");
+        let previous_state = if let Some(previous_state) = self.old_state.as_ref() {
+            previous_state.clone()
+        } else {
+            return Err(Error::StrErr(
+                "no state from before our move to spend on chain".to_string(),
+            ));
+        };
+        Ok((
+            self.on_chain_referee_args_for_state(&previous_state),
+            self.on_chain_referee_args_for_state(&self.state),
+        ))
     }
 
     /// The move transaction works like this:
@@ -1158,6 +1760,9 @@ impl RefereeMaker {
     /// In all cases, we're spending a referee coin that already exists.  The use
     /// of the mover coin here is purely to take advantage of its puzzle to provide
     /// a signature requirement.
+    ///
+    /// The returned reward coin is the next referee coin, which is waiting on
+    /// their move.
     pub fn get_transaction_for_move(
         &self,
         allocator: &mut AllocEncoder,
@@ -1165,31 +1770,14 @@ impl RefereeMaker {
         agg_sig_me_additional_data: &Hash,
     ) -> Result<RefereeOnChainTransaction, Error> {
         // We can only do a move to replicate our turn.
-        assert!(self.is_my_turn());
-
-        // Get the puzzle hash for the next referee state.
-        // This reflects a "their turn" state with the updated state from the
-        // game handler returned by consuming our move.  This is assumed to
-        // have been done by consuming the move in a different method call.
-
-        // Get the current state of the referee on chain.  This reflects the
-        // current state at the time the move was made.
-        // The current referee uses the previous state since we have already
-        // taken the move.
-        //
-        debug!("get_transaction_for_move: previous curry");
-        let (their_most_recent_game_move, previous_validation_info_hash) =
-            self.get_their_move_and_validation_info_for_onchain_move()?;
+        if !self.is_my_turn() {
+            return Err(Error::Protocol(ProtocolError::StateMismatch(
+                "no move of ours to replicate on chain".to_string(),
+            )));
+        }
 
-        let existing_curry_args = RefereePuzzleArgs {
-            mover_puzzle_hash: self.my_identity.puzzle_hash.clone(),
-            waiter_puzzle_hash: self.their_referee_puzzle_hash.clone(),
-            timeout: self.timeout.clone(),
-            amount: self.amount.clone(),
-            game_move: their_most_recent_game_move.clone(),
-            nonce: self.nonce,
-            previous_validation_info_hash,
-        };
+        let (existing_curry_args, target_args) =
+            self.referee_args_for_move_spend(allocator, coin_string)?;
         let current_referee_puzzle_hash = curry_referee_puzzle_hash(
             allocator,
             &self.referee_coin_puzzle_hash,
@@ -1213,34 +1801,15 @@ impl RefereeMaker {
             current_referee_puzzle_hash
         );
 
+        // The referee checks that we create the next coin with the previous
+        // validation info hash taken from the coin we're spending.
         debug!("get_transaction_for_move: target curry");
-        let game_move = self.get_our_most_recent_game_move()?;
-        let target_args = RefereePuzzleArgs {
-            mover_puzzle_hash: self.their_referee_puzzle_hash.clone(),
-            waiter_puzzle_hash: self.my_identity.puzzle_hash.clone(),
-            timeout: self.timeout.clone(),
-            amount: self.amount.clone(),
-            game_move,
-            nonce: self.nonce,
-            previous_validation_info_hash: self.get_our_most_recent_validation_info_hash(),
-        };
-        let target_referee_puzzle_hash =
-            curry_referee_puzzle_hash(allocator, &self.referee_coin_puzzle_hash, &target_args)?;
-        let target_referee_puzzle = curry_referee_puzzle(
-            allocator,
-            &self.referee_coin_puzzle,
-            &self.referee_coin_puzzle_hash,
-            &target_args,
-        )?;
-        let target_referee_puzzle_nodeptr = target_referee_puzzle.to_clvm(allocator).into_gen()?;
-        debug!(
-            "target_referee_puzzle {}",
-            disassemble(allocator.allocator(), target_referee_puzzle_nodeptr, None)
-        );
         assert_eq!(
-            target_referee_puzzle.sha256tree(allocator),
-            target_referee_puzzle_hash
+            target_args.previous_validation_info_hash.as_ref(),
+            Some(&existing_curry_args.game_move.validation_info_hash)
         );
+        let target_referee_puzzle_hash =
+            curry_referee_puzzle_hash(allocator, &self.referee_coin_puzzle_hash, &target_args)?;
 
         let inner_conditions = [(
             CREATE_COIN,
@@ -1273,16 +1842,12 @@ impl RefereeMaker {
             },
         });
 
-        if let Some(transaction) =
-            self.get_transaction(allocator, coin_string, &spend_puzzle, true, &args_list)?
-        {
-            Ok(transaction)
-        } else {
-            // Return err
-            Err(Error::StrErr(
-                "no transaction returned when doing on chain move".to_string(),
-            ))
-        }
+        let output_coin_string = CoinString::from_parts(
+            &coin_string.to_coin_id(),
+            &target_referee_puzzle_hash,
+            &self.amount,
+        );
+        self.get_transaction(allocator, &spend_puzzle, &args_list, output_coin_string)
     }
 
     pub fn run_validator_for_their_move(
@@ -1312,14 +1877,25 @@ impl RefereeMaker {
     ) -> Result<TheirTurnMoveResult, Error> {
         debug!("do their turn {details:?}");
 
+        if details.basic.mover_share > self.amount {
+            return Err(Error::Protocol(ProtocolError::Malformed(
+                "their mover share is more than the game amount".to_string(),
+            )));
+        }
+
         let handler = self.get_game_handler();
 
         let (last_state, last_move, previous_validation_info_hash) = match self.state.borrow() {
             RefereeMakerGameState::Initial {
                 initial_state,
                 initial_move,
+                initial_validation_info_hash,
                 ..
-            } => (initial_state, initial_move.clone(), None),
+            } => (
+                initial_state,
+                initial_move.clone(),
+                Some(initial_validation_info_hash.clone()),
+            ),
             RefereeMakerGameState::AfterOurTurn {
                 most_recent_our_move,
                 state,
@@ -1330,9 +1906,9 @@ impl RefereeMaker {
                 Some(most_recent_our_move.validation_info_hash.clone()),
             ),
             RefereeMakerGameState::AfterTheirTurn { .. } => {
-                return Err(Error::StrErr(
+                return Err(Error::Protocol(ProtocolError::StateMismatch(
                     "Can't take their move when we're after their move".to_string(),
-                ));
+                )));
             }
         };
 
@@ -1422,77 +1998,51 @@ impl RefereeMaker {
         self.my_identity.puzzle_hash.clone()
     }
 
-    fn slashing_coin_solution(
-        &self,
-        allocator: &mut AllocEncoder,
-        state: NodePtr,
-        validation_program_clvm: NodePtr,
-        slash_solution: NodePtr,
-        evidence: Evidence,
-    ) -> Result<NodePtr, Error> {
-        (
-            Node(state),
-            (
-                Node(validation_program_clvm),
-                (
-                    self.target_puzzle_hash_for_slash(),
-                    (Node(slash_solution), (Node(evidence.to_nodeptr()), ())),
-                ),
-            ),
-        )
-            .to_clvm(allocator)
-            .into_gen()
-    }
-
-    #[allow(clippy::too_many_arguments)]
+    /// Their move made a coin whose validation, run with this evidence,
+    /// produces conditions rather than failing, so the coin is ours to take.
+    /// The slash spends it, paying the whole amount to our puzzle hash.
     fn make_slash_for_their_turn(
         &self,
         allocator: &mut AllocEncoder,
-        coin_string: &CoinString,
+        slashed_coin: &CoinString,
         new_puzzle: &Puzzle,
-        new_puzzle_hash: &PuzzleHash,
-        slash_solution: NodePtr,
+        slash_spend: &BrokenOutCoinSpendInfo,
         evidence: Evidence,
         sig: &Aggsig,
     ) -> Result<TheirTurnCoinSpentResult, Error> {
-        // Probably readable_info overlaps solution.
-        // Moving driver in that context is the signature.
-        // My reward coin string is the coin that we'll make
-        // after the transaction below has been spent so its
-        // parent is the coin id of that coin.
-        let current_mover_share = self.get_our_current_share();
-
-        let (state, validation_program) = self.get_validation_program_for_their_move()?;
-        let reward_amount = self.amount.clone() - current_mover_share;
-        if reward_amount == Amount::default() {
+        if self.amount == Amount::default() {
             return Ok(TheirTurnCoinSpentResult::Slash(Box::new(
                 SlashOutcome::NoReward,
             )));
         }
 
-        let slashing_coin_solution = self.slashing_coin_solution(
-            allocator,
-            state,
-            validation_program.to_nodeptr(),
-            slash_solution,
-            evidence,
-        )?;
+        let (state, validation_program) = self.get_validation_program_for_their_move()?;
+        let my_reward_coin_string = CoinString::from_parts(
+            &slashed_coin.to_coin_id(),
+            &self.target_puzzle_hash_for_slash(),
+            &self.amount,
+        );
 
-        let coin_string_of_output_coin =
-            CoinString::from_parts(&coin_string.to_coin_id(), new_puzzle_hash, &reward_amount);
+        let args = OnChainRefereeSolution::Slash(OnChainRefereeSlash {
+            previous_game_state: state,
+            previous_validation_program: validation_program.clone(),
+            mover_coin: IdentityCoinAndSolution {
+                mover_coin_puzzle: self.my_identity.puzzle.clone(),
+                mover_coin_spend_solution: slash_spend.solution,
+                mover_coin_spend_signature: slash_spend.signature.clone() + sig.clone(),
+            },
+            slash_evidence: evidence,
+        });
+        let transaction =
+            self.get_transaction(allocator, new_puzzle, &args, my_reward_coin_string.clone())?;
 
         Ok(TheirTurnCoinSpentResult::Slash(Box::new(
             SlashOutcome::Reward {
                 transaction: Box::new(CoinSpend {
-                    // Ultimate parent of these coins.
-                    coin: coin_string.clone(),
-                    bundle: Spend {
-                        puzzle: new_puzzle.clone(),
-                        solution: Program::from_nodeptr(allocator, slashing_coin_solution)?,
-                        signature: sig.clone(),
-                    },
+                    coin: slashed_coin.clone(),
+                    bundle: transaction.bundle,
                 }),
-                my_reward_coin_string: coin_string_of_output_coin,
+                my_reward_coin_string,
             },
         )))
     }
@@ -1500,6 +2050,7 @@ impl RefereeMaker {
     pub fn their_turn_coin_spent(
         &mut self,
         allocator: &mut AllocEncoder,
+        agg_sig_me_additional_data: &Hash,
         coin_string: &CoinString,
         conditions: &NodePtr,
     ) -> Result<TheirTurnCoinSpentResult, Error> {
@@ -1515,65 +2066,31 @@ impl RefereeMaker {
             Vec::default()
         };
 
-        let mover_share = self.get_our_current_share();
-
         // Check properties of conditions
         if rem_condition.is_empty() {
             // Timeout case
             // Return enum timeout and we give the coin string of our reward
             // coin if any.
-            // Something went wrong if i think it's my turn
-            debug_assert!(!self.is_my_turn());
-
-            let my_reward_coin_string = CoinString::from_parts(
-                &coin_string.to_coin_id(),
-                &self.my_identity.puzzle_hash,
-                &mover_share,
-            );
-
             return Ok(TheirTurnCoinSpentResult::Timedout {
-                my_reward_coin_string: Some(my_reward_coin_string),
+                my_reward_coin_string: self.get_my_reward_coin_from_conditions(
+                    allocator,
+                    coin_string,
+                    *conditions,
+                ),
             });
         }
 
-        if rem_condition.len() != REM_CONDITION_FIELDS {
-            return Err(Error::StrErr(
-                "rem condition should have the right number of fields".to_string(),
-            ));
+        let their_move = game_move_from_rem_condition(&rem_condition)?;
+        if their_move.basic.mover_share > self.amount {
+            return Err(Error::Protocol(ProtocolError::Malformed(
+                "their mover share is more than the game amount".to_string(),
+            )));
         }
 
-        let my_inner_puzzle = puzzle_for_pk(allocator, &self.my_identity.public_key)?;
-
-        let new_move = &rem_condition[0];
-        let new_validation_info_hash = Hash::from_slice(&rem_condition[1]);
-        let (new_mover_share, new_max_move_size) = if let (Some(share), Some(max_size)) = (
-            u64_from_atom(&rem_condition[2]),
-            usize_from_atom(&rem_condition[3]),
-        ) {
-            (Amount::new(share), max_size)
-        } else {
-            return Err(Error::StrErr(
-                "mover share wasn't a properly sized atom".to_string(),
-            ));
-        };
-
-        let our_previous_move = self.get_our_most_recent_game_move()?;
-        let ref_puzzle_args = RefereePuzzleArgs {
-            mover_puzzle_hash: self.their_referee_puzzle_hash.clone(),
-            waiter_puzzle_hash: self.my_identity.puzzle_hash.clone(),
-            timeout: self.timeout.clone(),
-            amount: self.amount.clone(),
-            nonce: self.nonce,
-            game_move: GameMoveDetails {
-                basic: GameMoveStateInfo {
-                    move_made: new_move.clone(),
-                    max_move_size: new_max_move_size,
-                    mover_share: new_mover_share.clone(),
-                },
-                validation_info_hash: new_validation_info_hash.clone(),
-            },
-            previous_validation_info_hash: Some(our_previous_move.validation_info_hash.clone()),
-        };
+        // The coin they spent was waiting on their move, so the new coin
+        // waits on ours.
+        let spent_coin_args = self.on_chain_referee_args_for_state(&self.state);
+        let ref_puzzle_args = referee_args_after_move(&spent_coin_args, &their_move);
         let new_puzzle = curry_referee_puzzle(
             allocator,
             &self.referee_coin_puzzle,
@@ -1583,144 +2100,72 @@ impl RefereeMaker {
         let new_puzzle_hash =
             curry_referee_puzzle_hash(allocator, &self.referee_coin_puzzle_hash, &ref_puzzle_args)?;
 
+        let new_coin_string =
+            CoinString::from_parts(&coin_string.to_coin_id(), &new_puzzle_hash, &self.amount);
+
         let game_handler = self.get_game_handler();
 
-        // my_inner_solution maker is just in charge of making aggsigs from
-        // conditions.
-        let slash_conditions = (
+        // If their move turns out to be slashable, the slash spends the coin
+        // it made, so our mover puzzle's conditions are signed against that
+        // coin.
+        let slash_conditions = [(
             CREATE_COIN,
             (
                 self.target_puzzle_hash_for_slash(),
                 (self.amount.clone(), ()),
             ),
-        )
-            .to_clvm(allocator)
-            .into_gen()?;
-
-        let slash_spend =
-            standard_solution_unsafe(allocator, &self.my_identity.private_key, slash_conditions)?;
-
-        let (state, validation_program) = self.get_validation_program_for_their_move()?;
-        let full_slash_program = CurriedProgram {
-            program: Node(validation_program.to_nodeptr()),
-            args: clvm_curried_args!(
-                Node(state),
-                Node(validation_program.to_nodeptr()),
-                my_inner_puzzle,
-                Node(slash_spend.solution),
-                0
-            ),
-        }
+        )]
         .to_clvm(allocator)
         .into_gen()?;
+        let slash_spend = standard_solution_partial(
+            allocator,
+            &self.my_identity.synthetic_private_key,
+            &new_coin_string.to_coin_id(),
+            slash_conditions,
+            &self.my_identity.synthetic_public_key,
+            agg_sig_me_additional_data,
+            false,
+        )?;
 
-        let nil = allocator.allocator().null();
-        let full_slash_result = run_program(
-            allocator.allocator(),
-            &chia_dialect(),
-            full_slash_program,
-            nil,
-            0,
-        );
+        let (state, _) = self.get_validation_program_for_their_move()?;
+        let (readable_move, game_handler) = match game_handler.call_their_turn_driver(
+            allocator,
+            &TheirTurnInputs {
+                amount: self.amount.clone(),
+                last_state: state,
+                last_move: &spent_coin_args.game_move.basic.move_made,
+                last_mover_share: self.get_our_current_share(),
 
-        let full_slash_solution = (
-            Node(state),
-            (
-                Node(validation_program.to_nodeptr()),
-                // No evidence here.
-                (new_puzzle_hash.clone(), ((), (0, ()))),
-            ),
-        )
-            .to_clvm(allocator)
-            .into_gen()?;
-
-        match full_slash_result {
-            Ok(_) => {
-                // Ultimately each of these cases returns some kind of
-                // TheirTurnCoinSpentResult.
-                let nil_evidence = Evidence::nil(allocator);
-
-                // result is NodePtr containing solution and aggsig.
-                // The aggsig for the nil slash is the same as the slash
-                // below, having been created for the reward coin by using
-                // the standard solution signer.
-                self.make_slash_for_their_turn(
+                new_move: their_move.clone(),
+
+                #[cfg(test)]
+                run_debug: self.run_debug,
+            },
+        )? {
+            TheirTurnResult::Slash(evidence, sig) => {
+                // The game's driver found their move invalid and gave us the
+                // evidence the validation program needs to prove it.
+                return self.make_slash_for_their_turn(
                     allocator,
-                    coin_string,
+                    &new_coin_string,
                     &new_puzzle,
-                    &new_puzzle_hash,
-                    full_slash_solution,
-                    nil_evidence,
-                    &slash_spend.signature,
-                )
+                    &slash_spend,
+                    evidence,
+                    &sig,
+                );
             }
-            Err(_) => {
-                // Slash wasn't allowed.  Run the move handler.
-                let (readable_move, game_handler) = match game_handler.call_their_turn_driver(
-                    allocator,
-                    &TheirTurnInputs {
-                        amount: self.amount.clone(),
-                        last_state: state,
-                        last_move: &self
-                            .get_our_most_recent_game_move()?
-                            .basic
-                            .move_made
-                            .clone(),
-                        last_mover_share: self.get_our_current_share(),
-
-                        new_move: GameMoveDetails {
-                            basic: GameMoveStateInfo {
-                                move_made: new_move.clone(),
-                                max_move_size: new_max_move_size,
-                                mover_share: new_mover_share.clone(),
-                            },
-                            validation_info_hash: new_validation_info_hash.clone(),
-                        },
-
-                        #[cfg(test)]
-                        run_debug: self.run_debug,
-                    },
-                )? {
-                    TheirTurnResult::Slash(evidence, sig) => {
-                        return self.make_slash_for_their_turn(
-                            allocator,
-                            coin_string,
-                            &new_puzzle,
-                            &new_puzzle_hash,
-                            full_slash_solution,
-                            evidence,
-                            &(slash_spend.signature + *sig),
-                        );
-                    }
-                    TheirTurnResult::FinalMove(readable_move) => (readable_move, None),
-                    TheirTurnResult::MakeMove(readable_move, game_handler, _message) => {
-                        (readable_move, Some(game_handler))
-                    }
-                };
-
-                // Otherwise accept move by updating our state
-                self.accept_their_move(
-                    allocator,
-                    game_handler,
-                    &GameMoveDetails {
-                        basic: GameMoveStateInfo {
-                            move_made: new_move.clone(),
-                            max_move_size: new_max_move_size,
-                            mover_share: new_mover_share.clone(),
-                        },
-                        validation_info_hash: new_validation_info_hash.clone(),
-                    },
-                )?;
-
-                Ok(TheirTurnCoinSpentResult::Moved {
-                    new_coin_string: CoinString::from_parts(
-                        &coin_string.to_coin_id(),
-                        &new_puzzle_hash,
-                        &self.amount,
-                    ),
-                    readable: readable_move,
-                })
+            TheirTurnResult::FinalMove(readable_move) => (readable_move, None),
+            TheirTurnResult::MakeMove(readable_move, game_handler, _message) => {
+                (readable_move, Some(game_handler))
             }
-        }
+        };
+
+        // Otherwise accept move by updating our state
+        self.accept_their_move(allocator, game_handler, &their_move)?;
+
+        Ok(TheirTurnCoinSpentResult::Moved {
+            new_coin_string,
+            readable: readable_move,
+        })
     }
 }
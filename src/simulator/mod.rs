@@ -0,0 +1,230 @@
+use clvm_traits::{ClvmEncoder, ToClvm};
+
+use clvm_tools_rs::compiler::comptypes::map_m;
+
+use log::debug;
+
+use crate::common::constants::{AGG_SIG_ME_ADDITIONAL_DATA, CREATE_COIN};
+use crate::common::standard_coin::{
+    agg_sig_me_message, sign_agg_sig_me, solution_for_conditions, standard_solution_partial,
+    ChiaIdentity,
+};
+use crate::common::types::{
+    AllocEncoder, Amount, CoinSpend, CoinString, Error, Hash, IntoErr, Node, Program, Puzzle,
+    PuzzleHash, Sha256tree, Spend, ToQuotedProgram,
+};
+
+/// Validates spends in process, without python.
+#[cfg(feature = "native-sim")]
+pub mod native;
+/// Drives chia's spend_sim through pyo3.
+#[cfg(not(feature = "native-sim"))]
+pub mod spend_sim;
+
+#[cfg(feature = "native-sim")]
+pub use native::{IncludeTransactionResult, Simulator};
+#[cfg(not(feature = "native-sim"))]
+pub use spend_sim::{IncludeTransactionResult, Simulator};
+
+// Wallet style helpers that work the same whichever simulator is in use.
+impl Simulator {
+    /// Given a coin in our inventory, spend the coin to the target puzzle hash.
+    pub fn spend_coin_to_puzzle_hash(
+        &self,
+        allocator: &mut AllocEncoder,
+        identity: &ChiaIdentity,
+        puzzle: &Puzzle,
+        coin: &CoinString,
+        target_coins: &[(PuzzleHash, Amount)],
+    ) -> Result<Vec<CoinString>, Error> {
+        let agg_sig_me_additional_data = Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA);
+        let (_first_coin_parent, first_coin_ph, _first_coin_amt) = coin.to_parts().unwrap();
+        assert_eq!(puzzle.sha256tree(allocator), first_coin_ph);
+
+        let conditions_vec = map_m(
+            |(ph, amt): &(PuzzleHash, Amount)| -> Result<Node, Error> {
+                Ok(Node(
+                    (CREATE_COIN, (ph.clone(), (amt.clone(), ())))
+                        .to_clvm(allocator)
+                        .into_gen()?,
+                ))
+            },
+            target_coins,
+        )?;
+        let conditions = conditions_vec.to_clvm(allocator).into_gen()?;
+
+        let coin_spend_info = standard_solution_partial(
+            allocator,
+            &identity.synthetic_private_key,
+            &coin.to_coin_id(),
+            conditions,
+            &identity.synthetic_public_key,
+            &agg_sig_me_additional_data,
+            false,
+        )
+        .expect("should build");
+
+        let quoted_conds = conditions
+            .to_quoted_program(allocator)
+            .expect("should work");
+        let hashed_conds = quoted_conds.sha256tree(allocator);
+        let agg_sig_me_message = agg_sig_me_message(
+            hashed_conds.bytes(),
+            &coin.to_coin_id(),
+            &agg_sig_me_additional_data,
+        );
+        debug!("our message {agg_sig_me_message:?}");
+        let signature2 = identity.synthetic_private_key.sign(&agg_sig_me_message);
+        assert_eq!(coin_spend_info.signature, signature2);
+
+        let specific = CoinSpend {
+            coin: coin.clone(),
+            bundle: Spend {
+                puzzle: identity.puzzle.clone(),
+                solution: Program::from_nodeptr(allocator, coin_spend_info.solution)?,
+                signature: coin_spend_info.signature,
+            },
+        };
+
+        let status = self.push_tx(allocator, &[specific]).expect("should spend");
+        if status.code == 3 {
+            return Err(Error::StrErr("failed to spend coin".to_string()));
+        }
+
+        Ok(target_coins
+            .iter()
+            .map(|(ph, amt)| CoinString::from_parts(&coin.to_coin_id(), ph, amt))
+            .collect())
+    }
+
+    /// Create a coin belonging to identity_target which currently belongs
+    /// to identity_source.  Return change to identity_source.
+    pub fn transfer_coin_amount(
+        &self,
+        allocator: &mut AllocEncoder,
+        identity_target: &ChiaIdentity,
+        identity_source: &ChiaIdentity,
+        source_coin: &CoinString,
+        target_amt: Amount,
+    ) -> Result<(CoinString, CoinString), Error> {
+        let (_parent, _, amt) = if let Some(p) = source_coin.to_parts() {
+            p
+        } else {
+            return Err(Error::StrErr("failed to parse coin string".to_string()));
+        };
+
+        let change_amt = amt.clone() - target_amt.clone();
+        let first_coin = CoinString::from_parts(
+            &source_coin.to_coin_id(),
+            &identity_target.puzzle_hash,
+            &target_amt,
+        );
+        let second_coin = CoinString::from_parts(
+            &source_coin.to_coin_id(),
+            &identity_source.puzzle_hash,
+            &change_amt,
+        );
+
+        let conditions = (
+            (
+                CREATE_COIN,
+                (
+                    identity_target.puzzle_hash.clone(),
+                    (target_amt.clone(), ()),
+                ),
+            ),
+            (
+                (
+                    CREATE_COIN,
+                    (identity_source.puzzle_hash.clone(), (change_amt, ())),
+                ),
+                (),
+            ),
+        )
+            .to_clvm(allocator)
+            .into_gen()?;
+        let quoted_conditions = conditions.to_quoted_program(allocator)?;
+        let quoted_conditions_hash = quoted_conditions.sha256tree(allocator);
+        let standard_solution = solution_for_conditions(allocator, conditions)?;
+        let signature = sign_agg_sig_me(
+            &identity_source.synthetic_private_key,
+            quoted_conditions_hash.bytes(),
+            &source_coin.to_coin_id(),
+            &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+        );
+        let tx = CoinSpend {
+            bundle: Spend {
+                puzzle: identity_source.puzzle.clone(),
+                solution: Program::from_nodeptr(allocator, standard_solution)?,
+                signature,
+            },
+            coin: source_coin.clone(),
+        };
+        let included = self.push_tx(allocator, &[tx]).into_gen()?;
+        if included.code != 1 {
+            return Err(Error::StrErr(format!("failed to spend: {included:?}")));
+        }
+        Ok((first_coin, second_coin))
+    }
+
+    /// Combine coins, spending to a specific puzzle hash
+    pub fn combine_coins(
+        &self,
+        allocator: &mut AllocEncoder,
+        owner: &ChiaIdentity,
+        target_ph: &PuzzleHash,
+        coins: &[CoinString],
+    ) -> Result<CoinString, Error> {
+        let mut amount = Amount::default();
+        let mut spends = Vec::new();
+        let nil = allocator.encode_atom(&[]).into_gen()?;
+
+        if coins.is_empty() {
+            return Err(Error::StrErr("no coins".to_string()));
+        }
+
+        for (i, c) in coins.iter().enumerate() {
+            let (_, _, amt) = if let Some(p) = c.to_parts() {
+                p
+            } else {
+                return Err(Error::StrErr("improper coin string".to_string()));
+            };
+            amount += amt.clone();
+            let conditions = if i == coins.len() - 1 {
+                ((CREATE_COIN, (target_ph.clone(), (amount.clone(), ()))), ())
+                    .to_clvm(allocator)
+                    .into_gen()?
+            } else {
+                nil
+            };
+            let solution = solution_for_conditions(allocator, conditions)?;
+            let quoted_conditions = conditions.to_quoted_program(allocator)?;
+            let quoted_conditions_hash = quoted_conditions.sha256tree(allocator);
+            let signature = sign_agg_sig_me(
+                &owner.synthetic_private_key,
+                quoted_conditions_hash.bytes(),
+                &c.to_coin_id(),
+                &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+            );
+            spends.push(CoinSpend {
+                bundle: Spend {
+                    puzzle: owner.puzzle.clone(),
+                    solution: Program::from_nodeptr(allocator, solution)?,
+                    signature,
+                },
+                coin: c.clone(),
+            });
+        }
+
+        let included = self.push_tx(allocator, &spends).into_gen()?;
+        if included.code != 1 {
+            return Err(Error::StrErr(format!("failed to spend: {included:?}")));
+        }
+
+        Ok(CoinString::from_parts(
+            &coins[coins.len() - 1].to_coin_id(),
+            target_ph,
+            &amount,
+        ))
+    }
+}
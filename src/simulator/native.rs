@@ -0,0 +1,576 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem::take;
+
+use clvmr::allocator::NodePtr;
+use clvmr::run_program;
+
+use log::debug;
+
+use crate::channel_handler::game_handler::chia_dialect;
+use crate::common::types::{
+    Aggsig, AllocEncoder, Amount, CoinCondition, CoinID, CoinSpend, CoinString, Error, Hash,
+    IntoErr, Program, PublicKey, PuzzleHash, Sha256tree,
+};
+use crate::peer_container::BlockId;
+
+// Mempool inclusion status, numbered as chia does.
+const SUCCESS: u32 = 1;
+const PENDING: u32 = 2;
+const FAILED: u32 = 3;
+
+// Error codes from chia's Err enum.
+const UNKNOWN: u32 = 1;
+const INVALID_COIN_SOLUTION: u32 = 3;
+const DUPLICATE_OUTPUT: u32 = 4;
+const DOUBLE_SPEND: u32 = 5;
+const UNKNOWN_UNSPENT: u32 = 6;
+const BAD_AGGREGATE_SIGNATURE: u32 = 7;
+const WRONG_PUZZLE_HASH: u32 = 8;
+const ASSERT_HEIGHT_RELATIVE_FAILED: u32 = 13;
+const ASSERT_HEIGHT_ABSOLUTE_FAILED: u32 = 14;
+const ASSERT_SECONDS_ABSOLUTE_FAILED: u32 = 15;
+const MEMPOOL_CONFLICT: u32 = 19;
+const MINTING_COIN: u32 = 20;
+const ASSERT_SECONDS_RELATIVE_FAILED: u32 = 105;
+
+const POOL_REWARD: u64 = 1_750_000_000_000;
+const FARMER_REWARD: u64 = 250_000_000_000;
+
+const GENESIS_TIMESTAMP: u64 = 10_000_000;
+const SECONDS_PER_BLOCK: u64 = 20;
+
+#[derive(Debug, Clone)]
+pub struct IncludeTransactionResult {
+    pub code: u32,
+    #[allow(dead_code)]
+    pub e: Option<u32>,
+    #[allow(dead_code)]
+    pub diagnostic: String,
+}
+
+impl IncludeTransactionResult {
+    fn success() -> Self {
+        IncludeTransactionResult {
+            code: SUCCESS,
+            e: None,
+            diagnostic: "None".to_string(),
+        }
+    }
+
+    fn rejected(code: u32, e: u32, diagnostic: String) -> Self {
+        IncludeTransactionResult {
+            code,
+            e: Some(e),
+            diagnostic,
+        }
+    }
+}
+
+struct CoinRecord {
+    coin: CoinString,
+    confirmed_height: usize,
+    spent_height: Option<usize>,
+    reward: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Timelock {
+    SecondsRelative(i64),
+    SecondsAbsolute(i64),
+    HeightRelative(i64),
+    HeightAbsolute(i64),
+}
+
+struct MempoolItem {
+    name: Hash,
+    spends: Vec<CoinSpend>,
+    removals: Vec<CoinID>,
+    additions: Vec<CoinString>,
+}
+
+#[derive(Default)]
+struct SimulatorState {
+    height: usize,
+    // Header hash at each height, made up as in the pyo3 simulator.
+    headers: Vec<Hash>,
+    rewinds: u64,
+    // In the order they were confirmed, so a rewind truncates.
+    coins: Vec<CoinRecord>,
+    coin_index: HashMap<CoinID, usize>,
+    // Puzzle and solution of each spent coin, with the height it was spent.
+    spends: HashMap<CoinID, (usize, Program, Program)>,
+    mempool: Vec<MempoolItem>,
+    // Bundles waiting for a timelock to pass.
+    pending: Vec<(Hash, Vec<CoinSpend>)>,
+}
+
+fn block_timestamp(height: usize) -> u64 {
+    GENESIS_TIMESTAMP + height as u64 * SECONDS_PER_BLOCK
+}
+
+fn bundle_name(spends: &[CoinSpend]) -> Hash {
+    let mut bytes = Vec::new();
+    for spend in spends.iter() {
+        bytes.extend(spend.coin.to_bytes());
+        bytes.extend(spend.bundle.puzzle.to_program().bytes());
+        bytes.extend(spend.bundle.solution.bytes());
+        bytes.extend(spend.bundle.signature.bytes());
+    }
+    Hash::new(&bytes)
+}
+
+fn invalid(e: u32, diagnostic: String) -> IncludeTransactionResult {
+    IncludeTransactionResult::rejected(FAILED, e, diagnostic)
+}
+
+fn timelock_value(t: u64) -> i64 {
+    i64::try_from(t).unwrap_or(i64::MAX)
+}
+
+struct SpendConditions {
+    additions: Vec<CoinString>,
+    signed: Vec<(PublicKey, Vec<u8>)>,
+    timelocks: Vec<Timelock>,
+}
+
+fn spend_conditions(
+    allocator: &mut AllocEncoder,
+    spend: &CoinSpend,
+) -> Result<SpendConditions, IncludeTransactionResult> {
+    let (_, puzzle_hash, _) = spend
+        .coin
+        .to_parts()
+        .ok_or_else(|| invalid(INVALID_COIN_SOLUTION, "bad coin string".to_string()))?;
+    if spend.bundle.puzzle.sha256tree(allocator) != puzzle_hash {
+        return Err(invalid(
+            WRONG_PUZZLE_HASH,
+            format!("puzzle doesn't match {:?}", spend.coin),
+        ));
+    }
+
+    let run = |allocator: &mut AllocEncoder| -> Result<NodePtr, Error> {
+        let puzzle = spend.bundle.puzzle.to_program().to_nodeptr(allocator)?;
+        let solution = spend.bundle.solution.to_nodeptr(allocator)?;
+        let result =
+            run_program(allocator.allocator(), &chia_dialect(), puzzle, solution, 0).into_gen()?;
+        Ok(result.1)
+    };
+    let conditions = run(allocator)
+        .map_err(|e| invalid(INVALID_COIN_SOLUTION, format!("{:?}: {e:?}", spend.coin)))?;
+
+    let coin_id = spend.coin.to_coin_id();
+    let mut result = SpendConditions {
+        additions: Vec::new(),
+        signed: Vec::new(),
+        timelocks: Vec::new(),
+    };
+    // Unknown and malformed conditions are ignored.
+    for condition in CoinCondition::from_nodeptr(allocator, conditions).into_iter() {
+        match condition {
+            CoinCondition::CreateCoin(ph, amt, _) => {
+                result
+                    .additions
+                    .push(CoinString::from_parts(&coin_id, &ph, &amt));
+            }
+            CoinCondition::AssertSecondsRelative(t) => {
+                result
+                    .timelocks
+                    .push(Timelock::SecondsRelative(timelock_value(t)));
+            }
+            CoinCondition::AssertSecondsAbsolute(t) => {
+                result
+                    .timelocks
+                    .push(Timelock::SecondsAbsolute(timelock_value(t)));
+            }
+            CoinCondition::AssertHeightRelative(h) => {
+                result
+                    .timelocks
+                    .push(Timelock::HeightRelative(timelock_value(h)));
+            }
+            CoinCondition::AssertHeightAbsolute(h) => {
+                result
+                    .timelocks
+                    .push(Timelock::HeightAbsolute(timelock_value(h)));
+            }
+            condition => {
+                result.signed.extend(condition.signed_message(&spend.coin));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+impl SimulatorState {
+    fn unspent(&self, coin_id: &CoinID) -> Option<&CoinRecord> {
+        self.coin_index
+            .get(coin_id)
+            .map(|i| &self.coins[*i])
+            .filter(|record| record.spent_height.is_none())
+    }
+
+    // Check a bundle against the current peak.  Timelocks that haven't
+    // passed yet give a PENDING result rather than FAILED.
+    fn validate(&self, spends: &[CoinSpend]) -> Result<MempoolItem, IncludeTransactionResult> {
+        if spends.is_empty() {
+            return Err(invalid(UNKNOWN, "empty spend bundle".to_string()));
+        }
+
+        let mut allocator = AllocEncoder::new();
+        let mut removals = Vec::new();
+        let mut removal_set = HashSet::new();
+        let mut additions = Vec::new();
+        let mut addition_set = HashSet::new();
+        let mut signed = Vec::new();
+        let mut timelocks = Vec::new();
+        for spend in spends.iter() {
+            let coin_id = spend.coin.to_coin_id();
+            if !removal_set.insert(coin_id.clone()) {
+                return Err(invalid(
+                    DOUBLE_SPEND,
+                    format!("{:?} spent twice", spend.coin),
+                ));
+            }
+            removals.push(coin_id);
+
+            let conditions = spend_conditions(&mut allocator, spend)?;
+            for coin in conditions.additions.into_iter() {
+                if !addition_set.insert(coin.to_coin_id()) {
+                    return Err(invalid(DUPLICATE_OUTPUT, format!("{coin:?} created twice")));
+                }
+                additions.push(coin);
+            }
+            signed.extend(conditions.signed);
+            timelocks.extend(
+                conditions
+                    .timelocks
+                    .into_iter()
+                    .map(|t| (spend.coin.clone(), t)),
+            );
+        }
+
+        let name = bundle_name(spends);
+        let mut input_amount: u128 = 0;
+        for spend in spends.iter() {
+            let coin_id = spend.coin.to_coin_id();
+            if let Some((_, _, amount)) = spend.coin.to_parts() {
+                input_amount += amount.to_u64() as u128;
+            }
+            if addition_set.contains(&coin_id) {
+                // Ephemeral: created and spent by this bundle.
+                continue;
+            }
+            if self.unspent(&coin_id).is_none() {
+                return Err(if self.coin_index.contains_key(&coin_id) {
+                    invalid(DOUBLE_SPEND, format!("{:?} already spent", spend.coin))
+                } else {
+                    invalid(UNKNOWN_UNSPENT, format!("{:?} doesn't exist", spend.coin))
+                });
+            }
+            if self
+                .mempool
+                .iter()
+                .any(|item| item.name != name && item.removals.contains(&coin_id))
+            {
+                return Err(invalid(
+                    MEMPOOL_CONFLICT,
+                    format!("{:?} is spent by another mempool item", spend.coin),
+                ));
+            }
+        }
+
+        let output_amount: u128 = additions
+            .iter()
+            .filter_map(|coin| coin.to_parts())
+            .map(|(_, _, amount)| amount.to_u64() as u128)
+            .sum();
+        if output_amount > input_amount {
+            return Err(invalid(
+                MINTING_COIN,
+                format!("outputs {output_amount} exceed inputs {input_amount}"),
+            ));
+        }
+
+        let mut signature = Aggsig::default();
+        for spend in spends.iter() {
+            signature += spend.bundle.signature.clone();
+        }
+        let signature_ok = if signed.is_empty() {
+            signature == Aggsig::default()
+        } else {
+            signature.aggregate_verify(&signed)
+        };
+        if !signature_ok {
+            return Err(invalid(
+                BAD_AGGREGATE_SIGNATURE,
+                format!("bad signature for {} spends", spends.len()),
+            ));
+        }
+
+        let peak_timestamp = block_timestamp(self.height) as i64;
+        for (coin, timelock) in timelocks.iter() {
+            let confirmed_height = self
+                .unspent(&coin.to_coin_id())
+                .map(|record| record.confirmed_height)
+                .unwrap_or(self.height);
+            let confirmed_timestamp = block_timestamp(confirmed_height) as i64;
+            let failed = match timelock {
+                Timelock::SecondsRelative(t) => (confirmed_timestamp + t > peak_timestamp)
+                    .then_some(ASSERT_SECONDS_RELATIVE_FAILED),
+                Timelock::SecondsAbsolute(t) => {
+                    (*t > peak_timestamp).then_some(ASSERT_SECONDS_ABSOLUTE_FAILED)
+                }
+                Timelock::HeightRelative(h) => (confirmed_height as i64 + h > self.height as i64)
+                    .then_some(ASSERT_HEIGHT_RELATIVE_FAILED),
+                Timelock::HeightAbsolute(h) => {
+                    (*h > self.height as i64).then_some(ASSERT_HEIGHT_ABSOLUTE_FAILED)
+                }
+            };
+            if let Some(e) = failed {
+                return Err(IncludeTransactionResult::rejected(
+                    PENDING,
+                    e,
+                    format!("{timelock:?} on {coin:?} hasn't passed"),
+                ));
+            }
+        }
+
+        Ok(MempoolItem {
+            name,
+            spends: spends.to_vec(),
+            removals,
+            additions,
+        })
+    }
+
+    fn admit(&mut self, spends: &[CoinSpend]) -> IncludeTransactionResult {
+        let name = bundle_name(spends);
+        if self.mempool.iter().any(|item| item.name == name) {
+            return IncludeTransactionResult::success();
+        }
+
+        match self.validate(spends) {
+            Ok(item) => {
+                self.pending
+                    .retain(|(pending_name, _)| *pending_name != name);
+                self.mempool.push(item);
+                IncludeTransactionResult::success()
+            }
+            Err(result) => {
+                if result.code == PENDING
+                    && !self
+                        .pending
+                        .iter()
+                        .any(|(pending_name, _)| *pending_name == name)
+                {
+                    self.pending.push((name, spends.to_vec()));
+                }
+                result
+            }
+        }
+    }
+
+    fn add_coin(&mut self, coin: CoinString, height: usize, reward: bool) {
+        self.coin_index.insert(coin.to_coin_id(), self.coins.len());
+        self.coins.push(CoinRecord {
+            coin,
+            confirmed_height: height,
+            spent_height: None,
+            reward,
+        });
+    }
+
+    fn include(&mut self, height: usize, item: MempoolItem) {
+        for coin in item.additions.into_iter() {
+            self.add_coin(coin, height, false);
+        }
+        for (coin_id, spend) in item.removals.iter().zip(item.spends.iter()) {
+            if let Some(i) = self.coin_index.get(coin_id) {
+                self.coins[*i].spent_height = Some(height);
+            }
+            self.spends.insert(
+                coin_id.clone(),
+                (
+                    height,
+                    spend.bundle.puzzle.to_program(),
+                    spend.bundle.solution.clone(),
+                ),
+            );
+        }
+    }
+
+    // Give everything waiting another chance against the new peak.
+    fn readmit(&mut self, bundles: Vec<Vec<CoinSpend>>) {
+        for spends in bundles.iter() {
+            let result = self.admit(spends);
+            if result.code == FAILED {
+                debug!("dropping bundle {result:?}");
+            }
+        }
+    }
+
+    fn waiting_bundles(&mut self) -> Vec<Vec<CoinSpend>> {
+        let mempool = take(&mut self.mempool);
+        let pending = take(&mut self.pending);
+        mempool
+            .into_iter()
+            .map(|item| item.spends)
+            .chain(pending.into_iter().map(|(_, spends)| spends))
+            .collect()
+    }
+}
+
+/// A blockchain simulator with the same interface as the pyo3 one.  It keeps
+/// the coin set itself and checks each bundle's puzzles, signatures, amounts
+/// and timelocks before taking it into the mempool.  Every mempool item goes
+/// into the next farmed block.
+pub struct Simulator {
+    state: RefCell<SimulatorState>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Simulator {
+            state: RefCell::new(SimulatorState {
+                headers: vec![Hash::default()],
+                ..SimulatorState::default()
+            }),
+        }
+    }
+}
+
+impl Simulator {
+    pub fn farm_block(&self, puzzle_hash: &PuzzleHash) {
+        let state = &mut *self.state.borrow_mut();
+        let height = state.height + 1;
+        for item in take(&mut state.mempool).into_iter() {
+            state.include(height, item);
+        }
+        for (kind, amount) in [("pool", POOL_REWARD), ("farmer", FARMER_REWARD)] {
+            let parent = Hash::new(&[kind.as_bytes(), &(height as u64).to_be_bytes()[..]].concat());
+            let coin =
+                CoinString::from_parts(&CoinID::new(parent), puzzle_hash, &Amount::new(amount));
+            state.add_coin(coin, height, true);
+        }
+
+        let parent = state.headers[height - 1].clone();
+        let header = Hash::new(
+            &[
+                &parent.bytes()[..],
+                &(height as u64).to_be_bytes(),
+                &state.rewinds.to_be_bytes(),
+            ]
+            .concat(),
+        );
+        state.headers.push(header);
+        state.height = height;
+
+        let pending = take(&mut state.pending)
+            .into_iter()
+            .map(|(_, spends)| spends)
+            .collect();
+        state.readmit(pending);
+    }
+
+    pub fn get_current_height(&self) -> usize {
+        self.state.borrow().height
+    }
+
+    /// The current block and its parent.
+    pub fn get_current_block(&self) -> BlockId {
+        let state = self.state.borrow();
+        BlockId {
+            header_hash: state.headers[state.height].clone(),
+            parent_hash: state.headers[state.height.max(1) - 1].clone(),
+        }
+    }
+
+    /// Drop every block above height, as a reorg would.  Blocks farmed
+    /// afterward form a fork with new header hashes.  Transactions in the
+    /// dropped blocks are gone; the mempool is checked again.
+    pub fn rewind(&self, height: usize) {
+        let state = &mut *self.state.borrow_mut();
+        if height >= state.height {
+            return;
+        }
+
+        let keep = state
+            .coins
+            .iter()
+            .position(|record| record.confirmed_height > height)
+            .unwrap_or(state.coins.len());
+        for record in state.coins.drain(keep..) {
+            debug!("rewind drops {:?}", record.coin);
+        }
+        for record in state.coins.iter_mut() {
+            if record.spent_height.is_some_and(|h| h > height) {
+                record.spent_height = None;
+            }
+        }
+        state.coin_index = state
+            .coins
+            .iter()
+            .enumerate()
+            .map(|(i, record)| (record.coin.to_coin_id(), i))
+            .collect();
+        state.spends.retain(|_, (h, _, _)| *h <= height);
+        state.height = height;
+        state.headers.truncate(height + 1);
+        state.rewinds += 1;
+
+        let bundles = state.waiting_bundles();
+        state.readmit(bundles);
+    }
+
+    /// Unspent coins that didn't come from farming.
+    pub fn get_all_coins(&self) -> Result<Vec<CoinString>, Error> {
+        Ok(self
+            .state
+            .borrow()
+            .coins
+            .iter()
+            .filter(|record| !record.reward && record.spent_height.is_none())
+            .map(|record| record.coin.clone())
+            .collect())
+    }
+
+    pub fn get_my_coins(&self, puzzle_hash: &PuzzleHash) -> Result<Vec<CoinString>, Error> {
+        Ok(self
+            .state
+            .borrow()
+            .coins
+            .iter()
+            .filter(|record| record.spent_height.is_none())
+            .filter(|record| {
+                record
+                    .coin
+                    .to_parts()
+                    .is_some_and(|(_, ph, _)| ph == *puzzle_hash)
+            })
+            .map(|record| record.coin.clone())
+            .collect())
+    }
+
+    pub fn get_puzzle_and_solution(
+        &self,
+        coin_string: &CoinString,
+    ) -> Result<Option<(Program, Program)>, Error> {
+        Ok(self
+            .state
+            .borrow()
+            .spends
+            .get(&coin_string.to_coin_id())
+            .map(|(_, puzzle, solution)| (puzzle.clone(), solution.clone())))
+    }
+
+    pub fn push_tx(
+        &self,
+        _allocator: &mut AllocEncoder,
+        txs: &[CoinSpend],
+    ) -> Result<IncludeTransactionResult, Error> {
+        let result = self.state.borrow_mut().admit(txs);
+        debug!("push_tx {} spends: {result:?}", txs.len());
+        Ok(result)
+    }
+}
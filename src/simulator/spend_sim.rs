@@ -1,10 +1,8 @@
 use std::cell::RefCell;
 
-use clvm_traits::{ClvmEncoder, ToClvm};
+use clvm_traits::ToClvm;
 use clvmr::allocator::NodePtr;
 
-use clvm_tools_rs::compiler::comptypes::map_m;
-
 use indoc::indoc;
 use log::debug;
 
@@ -12,15 +10,11 @@ use pyo3::exceptions::PyIndexError;
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyNone, PyTuple};
 
-use crate::common::constants::{AGG_SIG_ME_ADDITIONAL_DATA, CREATE_COIN};
-use crate::common::standard_coin::{
-    agg_sig_me_message, sign_agg_sig_me, solution_for_conditions, standard_solution_partial,
-    ChiaIdentity,
-};
 use crate::common::types::{
-    Aggsig, AllocEncoder, Amount, CoinID, CoinSpend, CoinString, ErrToError, Error, Hash, IntoErr,
-    Node, Program, Puzzle, PuzzleHash, Sha256tree, Spend, ToQuotedProgram,
+    Aggsig, AllocEncoder, Amount, CoinID, CoinSpend, CoinString, ErrToError, Error, Hash, Node,
+    Program, Puzzle, PuzzleHash,
 };
+use crate::peer_container::BlockId;
 
 #[derive(Debug, Clone)]
 pub struct IncludeTransactionResult {
@@ -44,6 +38,10 @@ pub struct Simulator {
     g2_element: PyObject,
     coin_as_list: PyObject,
     height: RefCell<usize>,
+    // Header hash at each height.  The simulator has no real headers, so
+    // these are made up, differently on each side of a rewind.
+    headers: RefCell<Vec<Hash>>,
+    rewinds: RefCell<u64>,
 }
 
 impl ErrToError for PyErr {
@@ -167,6 +165,8 @@ impl Default for Simulator {
                 g2_element: evloop.get_item(8)?.extract()?,
                 coin_as_list: evloop.get_item(9)?.extract()?,
                 height: RefCell::new(0),
+                headers: RefCell::new(vec![Hash::default()]),
+                rewinds: RefCell::new(0),
             })
         })
         .expect("should work")
@@ -174,75 +174,6 @@ impl Default for Simulator {
 }
 
 impl Simulator {
-    /// Given a coin in our inventory, spend the coin to the target puzzle hash.
-    pub fn spend_coin_to_puzzle_hash(
-        &self,
-        allocator: &mut AllocEncoder,
-        identity: &ChiaIdentity,
-        puzzle: &Puzzle,
-        coin: &CoinString,
-        target_coins: &[(PuzzleHash, Amount)],
-    ) -> Result<Vec<CoinString>, Error> {
-        let agg_sig_me_additional_data = Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA);
-        let (_first_coin_parent, first_coin_ph, _first_coin_amt) = coin.to_parts().unwrap();
-        assert_eq!(puzzle.sha256tree(allocator), first_coin_ph);
-
-        let conditions_vec = map_m(
-            |(ph, amt): &(PuzzleHash, Amount)| -> Result<Node, Error> {
-                Ok(Node(
-                    (CREATE_COIN, (ph.clone(), (amt.clone(), ())))
-                        .to_clvm(allocator)
-                        .into_gen()?,
-                ))
-            },
-            target_coins,
-        )?;
-        let conditions = conditions_vec.to_clvm(allocator).into_gen()?;
-
-        let coin_spend_info = standard_solution_partial(
-            allocator,
-            &identity.synthetic_private_key,
-            &coin.to_coin_id(),
-            conditions,
-            &identity.synthetic_public_key,
-            &agg_sig_me_additional_data,
-            false,
-        )
-        .expect("should build");
-
-        let quoted_conds = conditions
-            .to_quoted_program(allocator)
-            .expect("should work");
-        let hashed_conds = quoted_conds.sha256tree(allocator);
-        let agg_sig_me_message = agg_sig_me_message(
-            hashed_conds.bytes(),
-            &coin.to_coin_id(),
-            &agg_sig_me_additional_data,
-        );
-        debug!("our message {agg_sig_me_message:?}");
-        let signature2 = identity.synthetic_private_key.sign(&agg_sig_me_message);
-        assert_eq!(coin_spend_info.signature, signature2);
-
-        let specific = CoinSpend {
-            coin: coin.clone(),
-            bundle: Spend {
-                puzzle: identity.puzzle.clone(),
-                solution: Program::from_nodeptr(allocator, coin_spend_info.solution)?,
-                signature: coin_spend_info.signature,
-            },
-        };
-
-        let status = self.push_tx(allocator, &[specific]).expect("should spend");
-        if status.code == 3 {
-            return Err(Error::StrErr("failed to spend coin".to_string()));
-        }
-
-        Ok(target_coins
-            .iter()
-            .map(|(ph, amt)| CoinString::from_parts(&coin.to_coin_id(), ph, amt))
-            .collect())
-    }
-
     fn async_call<ArgT>(&self, py: Python<'_>, name: &str, args: ArgT) -> PyResult<PyObject>
     where
         ArgT: IntoPy<Py<PyTuple>>,
@@ -274,6 +205,16 @@ impl Simulator {
             self.async_call(py, "farm_block", (puzzle_hash_bytes,))?;
             let old_height = *self.height.borrow();
             self.height.replace(old_height + 1);
+            let parent = self.headers.borrow()[old_height].clone();
+            let header = Hash::new(
+                &[
+                    &parent.bytes()[..],
+                    &(old_height as u64 + 1).to_be_bytes(),
+                    &self.rewinds.borrow().to_be_bytes(),
+                ]
+                .concat(),
+            );
+            self.headers.borrow_mut().push(header);
             Ok(())
         })
         .expect("should farm")
@@ -283,6 +224,30 @@ impl Simulator {
         *self.height.borrow()
     }
 
+    /// The current block and its parent.
+    pub fn get_current_block(&self) -> BlockId {
+        let headers = self.headers.borrow();
+        let height = *self.height.borrow();
+        BlockId {
+            header_hash: headers[height].clone(),
+            parent_hash: headers[height.max(1) - 1].clone(),
+        }
+    }
+
+    /// Drop every block above height, as a reorg would.  Blocks farmed
+    /// afterward form a fork with new header hashes.
+    pub fn rewind(&self, height: usize) {
+        Python::with_gil(|py| -> PyResult<()> {
+            self.async_call(py, "rewind", (height,))?;
+            Ok(())
+        })
+        .expect("should rewind");
+        self.height.replace(height);
+        self.headers.borrow_mut().truncate(height + 1);
+        let rewinds = *self.rewinds.borrow();
+        self.rewinds.replace(rewinds + 1);
+    }
+
     fn convert_coin_list_to_coin_strings(
         &self,
         _py: Python<'_>,
@@ -473,135 +438,4 @@ impl Simulator {
             to_spend_result(py, spend_res)
         })
     }
-
-    /// Create a coin belonging to identity_target which currently belongs
-    /// to identity_source.  Return change to identity_source.
-    pub fn transfer_coin_amount(
-        &self,
-        allocator: &mut AllocEncoder,
-        identity_target: &ChiaIdentity,
-        identity_source: &ChiaIdentity,
-        source_coin: &CoinString,
-        target_amt: Amount,
-    ) -> Result<(CoinString, CoinString), Error> {
-        let (_parent, _, amt) = if let Some(p) = source_coin.to_parts() {
-            p
-        } else {
-            return Err(Error::StrErr("failed to parse coin string".to_string()));
-        };
-
-        let change_amt = amt.clone() - target_amt.clone();
-        let first_coin = CoinString::from_parts(
-            &source_coin.to_coin_id(),
-            &identity_target.puzzle_hash,
-            &target_amt,
-        );
-        let second_coin = CoinString::from_parts(
-            &source_coin.to_coin_id(),
-            &identity_source.puzzle_hash,
-            &change_amt,
-        );
-
-        let conditions = (
-            (
-                CREATE_COIN,
-                (
-                    identity_target.puzzle_hash.clone(),
-                    (target_amt.clone(), ()),
-                ),
-            ),
-            (
-                (
-                    CREATE_COIN,
-                    (identity_source.puzzle_hash.clone(), (change_amt, ())),
-                ),
-                (),
-            ),
-        )
-            .to_clvm(allocator)
-            .into_gen()?;
-        let quoted_conditions = conditions.to_quoted_program(allocator)?;
-        let quoted_conditions_hash = quoted_conditions.sha256tree(allocator);
-        let standard_solution = solution_for_conditions(allocator, conditions)?;
-        let signature = sign_agg_sig_me(
-            &identity_source.synthetic_private_key,
-            quoted_conditions_hash.bytes(),
-            &source_coin.to_coin_id(),
-            &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
-        );
-        let tx = CoinSpend {
-            bundle: Spend {
-                puzzle: identity_source.puzzle.clone(),
-                solution: Program::from_nodeptr(allocator, standard_solution)?,
-                signature,
-            },
-            coin: source_coin.clone(),
-        };
-        let included = self.push_tx(allocator, &[tx]).into_gen()?;
-        if included.code != 1 {
-            return Err(Error::StrErr(format!("failed to spend: {included:?}")));
-        }
-        Ok((first_coin, second_coin))
-    }
-
-    /// Combine coins, spending to a specific puzzle hash
-    pub fn combine_coins(
-        &self,
-        allocator: &mut AllocEncoder,
-        owner: &ChiaIdentity,
-        target_ph: &PuzzleHash,
-        coins: &[CoinString],
-    ) -> Result<CoinString, Error> {
-        let mut amount = Amount::default();
-        let mut spends = Vec::new();
-        let nil = allocator.encode_atom(&[]).into_gen()?;
-
-        if coins.is_empty() {
-            return Err(Error::StrErr("no coins".to_string()));
-        }
-
-        for (i, c) in coins.iter().enumerate() {
-            let (_, _, amt) = if let Some(p) = c.to_parts() {
-                p
-            } else {
-                return Err(Error::StrErr("improper coin string".to_string()));
-            };
-            amount += amt.clone();
-            let conditions = if i == coins.len() - 1 {
-                ((CREATE_COIN, (target_ph.clone(), (amount.clone(), ()))), ())
-                    .to_clvm(allocator)
-                    .into_gen()?
-            } else {
-                nil
-            };
-            let solution = solution_for_conditions(allocator, conditions)?;
-            let quoted_conditions = conditions.to_quoted_program(allocator)?;
-            let quoted_conditions_hash = quoted_conditions.sha256tree(allocator);
-            let signature = sign_agg_sig_me(
-                &owner.synthetic_private_key,
-                quoted_conditions_hash.bytes(),
-                &c.to_coin_id(),
-                &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
-            );
-            spends.push(CoinSpend {
-                bundle: Spend {
-                    puzzle: owner.puzzle.clone(),
-                    solution: Program::from_nodeptr(allocator, solution)?,
-                    signature,
-                },
-                coin: c.clone(),
-            });
-        }
-
-        let included = self.push_tx(allocator, &spends).into_gen()?;
-        if included.code != 1 {
-            return Err(Error::StrErr(format!("failed to spend: {included:?}")));
-        }
-
-        Ok(CoinString::from_parts(
-            &coins[coins.len() - 1].to_coin_id(),
-            target_ph,
-            &amount,
-        ))
-    }
 }
@@ -0,0 +1,369 @@
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use std::future::{ready, Future};
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use tokio::task::unconstrained;
+
+use crate::async_cradle::{AsyncGameCradle, AsyncGameCradleConfig};
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::async_cradle::{CradleEvents, PeerTransport};
+use crate::common::standard_coin::ChiaIdentity;
+use crate::common::types::{AllocEncoder, Amount, Error, PrivateKey, Timeout};
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::common::types::{Program, PuzzleHash};
+use crate::games::poker_collection;
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::peer_container::{CradleEvent, FullCoinSetAdapter};
+use crate::potato_handler::LivenessPolicy;
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::potato_handler::{GameStart, GameType};
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::simulator::Simulator;
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::tests::calpoker::test_moves_1;
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+use crate::tests::game::GameAction;
+
+fn cradle_config(
+    allocator: &mut AllocEncoder,
+    identity: &ChiaIdentity,
+    have_potato: bool,
+    seed: u8,
+) -> AsyncGameCradleConfig {
+    AsyncGameCradleConfig {
+        game_types: poker_collection(allocator),
+        have_potato,
+        identity: identity.clone(),
+        my_contribution: Amount::new(100),
+        their_contribution: Amount::new(100),
+        channel_timeout: Timeout::new(100),
+        unroll_timeout: Timeout::new(5),
+        reward_puzzle_hash: identity.puzzle_hash.clone(),
+        liveness: LivenessPolicy::default(),
+        validate_outbound: true,
+        rng: ChaCha8Rng::from_seed([seed; 32]),
+    }
+}
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+struct Side {
+    cradle: AsyncGameCradle,
+    events: CradleEvents,
+    transport: PeerTransport,
+    seen: Vec<CradleEvent>,
+}
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+impl Side {
+    fn has_seen(&self, f: impl Fn(&CradleEvent) -> bool) -> bool {
+        self.seen.iter().any(f)
+    }
+}
+
+// Whatever is already waiting, without waiting for more.
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+async fn waiting<T>(next: impl Future<Output = Option<T>>) -> Option<T> {
+    tokio::select! {
+        biased;
+        item = unconstrained(next) => item,
+        () = ready(()) => None,
+    }
+}
+
+// Stand in for the network between the two cradles and act on their events
+// until neither has anything left.  Asking a cradle anything returns only
+// once it has dealt with every message delivered before, so a round with
+// nothing waiting after that means both are done.
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+async fn settle(allocator: &mut AllocEncoder, simulator: &Simulator, sides: &mut [Side; 2]) {
+    loop {
+        for side in sides.iter() {
+            side.cradle.handshake_finished().await.expect("should work");
+        }
+
+        let mut busy = false;
+        for i in 0..2 {
+            while let Some(msg) = waiting(sides[i].transport.next_outbound()).await {
+                sides[1 - i]
+                    .transport
+                    .sink
+                    .deliver(msg)
+                    .await
+                    .expect("should deliver");
+                busy = true;
+            }
+            while let Some(event) = waiting(sides[i].events.next()).await {
+                handle_event(allocator, simulator, &mut sides[i], event).await;
+                busy = true;
+            }
+        }
+
+        if !busy {
+            break;
+        }
+    }
+}
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+async fn handle_event(
+    allocator: &mut AllocEncoder,
+    simulator: &Simulator,
+    side: &mut Side,
+    event: CradleEvent,
+) {
+    match &event {
+        CradleEvent::Transaction(tx) => {
+            let included_result = simulator
+                .push_tx(allocator, &tx.spends)
+                .expect("should work");
+            assert_eq!(included_result.code, 1);
+        }
+        CradleEvent::PuzzleSolutionRequest(coin) => {
+            let puzzle_and_solution = simulator
+                .get_puzzle_and_solution(coin)
+                .expect("should work");
+            side.cradle
+                .report_puzzle_and_solution(coin.clone(), puzzle_and_solution)
+                .await
+                .expect("should work");
+        }
+        CradleEvent::Error(e) => panic!("unexpected error {e:?}"),
+        _ => {}
+    }
+    side.seen.push(event);
+}
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+async fn run_block(
+    allocator: &mut AllocEncoder,
+    simulator: &Simulator,
+    coinset_adapter: &mut FullCoinSetAdapter,
+    farmer: &PuzzleHash,
+    sides: &mut [Side; 2],
+) {
+    simulator.farm_block(farmer);
+    let current_height = simulator.get_current_height();
+    let current_coins = simulator.get_all_coins().expect("should work");
+    let watch_report = coinset_adapter
+        .make_report_from_coin_set_update(current_height as u64, &current_coins)
+        .expect("should work");
+    for side in sides.iter() {
+        side.cradle
+            .new_block(current_height, watch_report.clone())
+            .await
+            .expect("should work");
+    }
+    settle(allocator, simulator, sides).await;
+}
+
+// A cradle whose opening coin was just farmed.
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+async fn spawn_funded_side(
+    allocator: &mut AllocEncoder,
+    rng: &mut ChaCha8Rng,
+    simulator: &Simulator,
+    have_potato: bool,
+    seed: u8,
+) -> Side {
+    let pk: PrivateKey = rng.gen();
+    let identity = ChiaIdentity::new(allocator, pk).expect("should work");
+    simulator.farm_block(&identity.puzzle_hash);
+    let coins = simulator
+        .get_my_coins(&identity.puzzle_hash)
+        .expect("should work");
+    let (coin, _) = simulator
+        .transfer_coin_amount(allocator, &identity, &identity, &coins[0], Amount::new(100))
+        .expect("should work");
+
+    let config = cradle_config(allocator, &identity, have_potato, seed);
+    let (cradle, events, transport, _thread) =
+        AsyncGameCradle::spawn(config).expect("should spawn");
+    cradle.opening_coin(coin).await.expect("should work");
+    Side {
+        cradle,
+        events,
+        transport,
+        seen: Vec::new(),
+    }
+}
+
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+#[tokio::test]
+async fn test_async_cradles_play_and_shut_down() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let simulator = Simulator::default();
+    let mut coinset_adapter = FullCoinSetAdapter::default();
+
+    let farmer_pk: PrivateKey = rng.gen();
+    let farmer = ChiaIdentity::new(&mut allocator, farmer_pk).expect("should work");
+
+    let side_0 = spawn_funded_side(&mut allocator, &mut rng, &simulator, true, 1).await;
+    let side_1 = spawn_funded_side(&mut allocator, &mut rng, &simulator, false, 2).await;
+    let mut sides = [side_0, side_1];
+
+    let mut steps = 0;
+    while !(sides[0]
+        .cradle
+        .handshake_finished()
+        .await
+        .expect("should work")
+        && sides[1]
+            .cradle
+            .handshake_finished()
+            .await
+            .expect("should work"))
+    {
+        steps += 1;
+        assert!(steps < 50, "handshake never finished");
+        run_block(
+            &mut allocator,
+            &simulator,
+            &mut coinset_adapter,
+            &farmer.puzzle_hash,
+            &mut sides,
+        )
+        .await;
+    }
+
+    let calpoker_start = GameStart {
+        amount: Amount::new(200),
+        my_contribution: Amount::new(100),
+        game_type: GameType(b"calpoker".to_vec()),
+        timeout: Timeout::new(10),
+        my_turn: true,
+        parameters: vec![0x80],
+    };
+    let game_ids = sides[0]
+        .cradle
+        .start_games(true, calpoker_start.clone())
+        .await
+        .expect("should start");
+    sides[1]
+        .cradle
+        .start_games(
+            false,
+            GameStart {
+                my_turn: false,
+                ..calpoker_start
+            },
+        )
+        .await
+        .expect("should start");
+    run_block(
+        &mut allocator,
+        &simulator,
+        &mut coinset_adapter,
+        &farmer.puzzle_hash,
+        &mut sides,
+    )
+    .await;
+
+    let readable = if let GameAction::Move(0, readable, _) = test_moves_1(&mut allocator)[0].clone()
+    {
+        readable
+    } else {
+        panic!("alice moves first");
+    };
+    let readable_program = Program::from_nodeptr(&mut allocator, readable).expect("should convert");
+    sides[0]
+        .cradle
+        .make_move(
+            game_ids[0].clone(),
+            readable_program.bytes().to_vec(),
+            rng.gen(),
+        )
+        .await
+        .expect("should move");
+
+    let mut steps = 0;
+    while !sides[1]
+        .has_seen(|e| matches!(e, CradleEvent::OpponentMoved(id, _) if *id == game_ids[0]))
+    {
+        steps += 1;
+        assert!(steps < 10, "the move never arrived");
+        run_block(
+            &mut allocator,
+            &simulator,
+            &mut coinset_adapter,
+            &farmer.puzzle_hash,
+            &mut sides,
+        )
+        .await;
+    }
+
+    sides[0].cradle.shut_down().await.expect("should shut down");
+    let mut steps = 0;
+    while !sides
+        .iter()
+        .all(|s| s.has_seen(|e| matches!(e, CradleEvent::ShutdownComplete(_))))
+    {
+        steps += 1;
+        assert!(steps < 50, "shutdown never completed");
+        run_block(
+            &mut allocator,
+            &simulator,
+            &mut coinset_adapter,
+            &farmer.puzzle_hash,
+            &mut sides,
+        )
+        .await;
+    }
+
+    for side in sides.iter() {
+        assert!(side.has_seen(|e| matches!(e, CradleEvent::HandshakeFinished)));
+        assert!(!side.cradle.is_on_chain().await.expect("should work"));
+    }
+}
+
+#[tokio::test]
+async fn test_async_cradle_stopped() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let pk: PrivateKey = rng.gen();
+    let identity = ChiaIdentity::new(&mut allocator, pk).expect("should work");
+    let config = cradle_config(&mut allocator, &identity, true, 1);
+    let (cradle, mut events, mut transport, thread) =
+        AsyncGameCradle::spawn(config).expect("should spawn");
+
+    // Dropping the last handle stops the cradle, closing both of its outputs.
+    drop(cradle);
+    thread.join().expect("cradle thread should exit");
+    while events.next().await.is_some() {}
+    while transport.next_outbound().await.is_some() {}
+
+    match transport.sink.deliver(vec![]).await {
+        Err(Error::StrErr(e)) => assert!(e.contains("stopped"), "{e}"),
+        other => panic!("expected the cradle to have stopped, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_async_cradle_restores_from_snapshot() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let pk: PrivateKey = rng.gen();
+    let identity = ChiaIdentity::new(&mut allocator, pk).expect("should work");
+    let config = cradle_config(&mut allocator, &identity, true, 1);
+    let (cradle, _events, _transport, _thread) =
+        AsyncGameCradle::spawn(config).expect("should spawn");
+    let snapshot = cradle.snapshot().await.expect("should snapshot");
+    drop(cradle);
+
+    // The restored cradle answers commands and saves the same state again.
+    let (restored, _events, _transport, _thread) =
+        AsyncGameCradle::restore(snapshot.clone(), rng.clone()).expect("should restore");
+    assert!(!restored.handshake_finished().await.expect("should work"));
+    assert!(!restored.is_on_chain().await.expect("should work"));
+    assert_eq!(
+        restored.snapshot().await.expect("should snapshot"),
+        snapshot
+    );
+
+    // A cradle that can't be rebuilt reports why rather than hanging.
+    assert!(AsyncGameCradle::restore(b"not a snapshot".to_vec(), rng.clone()).is_err());
+}
@@ -1,43 +1,43 @@
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use log::debug;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use rand::prelude::*;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use rand_chacha::ChaCha8Rng;
 
 use clvm_traits::{ClvmEncoder, ToClvm};
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::channel_handler::game::Game;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::channel_handler::types::ReadableMove;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::constants::CREATE_COIN;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::standard_coin::ChiaIdentity;
 use crate::common::types::{AllocEncoder, Sha256Input};
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::types::{Amount, PrivateKey};
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::types::{Error, GameID, Hash};
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::games::calpoker::decode_calpoker_readable;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::games::calpoker::decode_readable_card_choices;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::games::calpoker::make_cards;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::games::calpoker::CalpokerResult;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::games::calpoker::{CalpokerHandValue, RawCalpokerHandValue};
 use crate::tests::game::GameAction;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::tests::game::GameActionResult;
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::tests::simenv::SimulatorEnvironment;
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 pub fn load_calpoker(allocator: &mut AllocEncoder, game_id: GameID) -> Result<Game, Error> {
     Game::new(
         allocator,
@@ -46,7 +46,7 @@ pub fn load_calpoker(allocator: &mut AllocEncoder, game_id: GameID) -> Result<Ga
     )
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_load_calpoker() {
     let mut allocator = AllocEncoder::new();
@@ -61,7 +61,7 @@ fn test_load_calpoker() {
         .expect("should get a sim env");
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 fn run_calpoker_play_test(
     allocator: &mut AllocEncoder,
     moves: &[GameAction],
@@ -108,7 +108,7 @@ pub fn test_moves_1(allocator: &mut AllocEncoder) -> [GameAction; 5] {
     ]
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_happy_path() {
     let mut allocator = AllocEncoder::new();
@@ -116,7 +116,7 @@ fn test_play_calpoker_happy_path() {
     let test1 = run_calpoker_play_test(&mut allocator, &moves).expect("should work");
     debug!("play_result {test1:?}");
 }
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_verify_endgame_data() {
     let mut allocator = AllocEncoder::new();
@@ -158,7 +158,7 @@ fn test_verify_endgame_data() {
     };
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 fn extract_info_from_game(game_results: &[GameActionResult]) -> (Hash, ReadableMove, Vec<u8>) {
     if let GameActionResult::MoveResult(_, _, _, entropy) = &game_results[1] {
         game_results.iter().find_map(|x| {
@@ -176,7 +176,7 @@ fn extract_info_from_game(game_results: &[GameActionResult]) -> (Hash, ReadableM
     .unwrap()
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_verify_bob_message() {
     // Ensure the bytes being passed on are structured correctly
@@ -193,7 +193,7 @@ fn test_verify_bob_message() {
     assert_eq!(got, expected);
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_on_chain_after_1_move_p1() {
     let mut allocator = AllocEncoder::new();
@@ -208,7 +208,7 @@ fn test_play_calpoker_on_chain_after_1_move_p1() {
     debug!("play_result {test2:?}");
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_on_chain_after_1_move_p0_lost_message() {
     let mut allocator = AllocEncoder::new();
@@ -220,7 +220,7 @@ fn test_play_calpoker_on_chain_after_1_move_p0_lost_message() {
     debug!("play_result {test3:?}");
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_on_chain_after_1_move_p0() {
     let mut allocator = AllocEncoder::new();
@@ -231,7 +231,7 @@ fn test_play_calpoker_on_chain_after_1_move_p0() {
     debug!("play_result {test3:?}");
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_on_chain_after_2_moves_p0() {
     let mut allocator = AllocEncoder::new();
@@ -243,7 +243,7 @@ fn test_play_calpoker_on_chain_after_2_moves_p0() {
     debug!("play_result {test4:?}");
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_on_chain_after_2_moves_p1() {
     let mut allocator = AllocEncoder::new();
@@ -257,7 +257,7 @@ fn test_play_calpoker_on_chain_after_2_moves_p1() {
     debug!("play_result {test4:?}");
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 #[test]
 fn test_play_calpoker_end_game_reward() {
     let mut allocator = AllocEncoder::new();
@@ -197,6 +197,7 @@ fn test_unroll_can_verify_own_signature() {
         my_balance: Amount::new(0),
         their_balance: Amount::new(100),
         puzzle_hashes_and_amounts: vec![],
+        unroll_timeout: (*DEFAULT_UNROLL_TIME_LOCK).clone(),
     };
 
     let _sig1 = unroll_coin_1
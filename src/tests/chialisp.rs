@@ -2,7 +2,8 @@ use std::rc::Rc;
 
 use crate::channel_handler::game_handler::chia_dialect;
 use crate::common::standard_coin::read_hex_puzzle;
-use crate::common::types::{AllocEncoder, Node, Sha256Input};
+use crate::common::types::{AllocEncoder, Amount, Node, Sha256Input};
+use crate::games::calpoker::make_cards;
 
 use clvm_traits::ToClvm;
 use clvmr::run_program;
@@ -89,6 +90,16 @@ fn test_make_cards() {
     );
 }
 
+#[test]
+fn test_make_cards_zero_amount() {
+    let alice = Sha256Input::Bytes(b"alice").hash();
+    let bob = Sha256Input::Bytes(b"bob").hash();
+    let (alice_cards, bob_cards) = make_cards(alice.bytes(), bob.bytes(), Amount::default());
+    assert_eq!(alice_cards.len(), 8);
+    assert_eq!(bob_cards.len(), 8);
+    assert!(alice_cards.iter().all(|c| !bob_cards.contains(c)));
+}
+
 #[test]
 fn test_mergein() {
     let mut allocator = AllocEncoder::new();
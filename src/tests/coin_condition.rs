@@ -0,0 +1,107 @@
+use clvm_traits::ToClvm;
+
+use clvm_tools_rs::classic::clvm_tools::binutils::assemble;
+
+use crate::common::types::{
+    AllocEncoder, Amount, CoinCondition, CoinID, Hash, PublicKey, PuzzleHash,
+};
+use crate::tests::constants::TEST_PUBLIC_KEY_BYTES;
+
+fn every_condition() -> Vec<CoinCondition> {
+    let pk = PublicKey::from_bytes(*TEST_PUBLIC_KEY_BYTES).expect("should be a public key");
+    let msg = b"message".to_vec();
+    let hash = Hash::new(b"hash");
+    let ph = PuzzleHash::from_hash(Hash::new(b"puzzle"));
+    let coin_id = CoinID::new(Hash::new(b"coin"));
+    vec![
+        CoinCondition::AggSigParent(pk.clone(), msg.clone()),
+        CoinCondition::AggSigPuzzle(pk.clone(), msg.clone()),
+        CoinCondition::AggSigAmount(pk.clone(), msg.clone()),
+        CoinCondition::AggSigPuzzleAmount(pk.clone(), msg.clone()),
+        CoinCondition::AggSigParentAmount(pk.clone(), msg.clone()),
+        CoinCondition::AggSigParentPuzzle(pk.clone(), msg.clone()),
+        CoinCondition::AggSigUnsafe(pk.clone(), msg.clone()),
+        CoinCondition::AggSigMe(pk, msg.clone()),
+        CoinCondition::CreateCoin(ph.clone(), Amount::new(100), vec![]),
+        CoinCondition::CreateCoin(ph.clone(), Amount::new(128), vec![msg.clone(), vec![]]),
+        CoinCondition::ReserveFee(Amount::new(0)),
+        CoinCondition::CreateCoinAnnouncement(msg.clone()),
+        CoinCondition::AssertCoinAnnouncement(hash.clone()),
+        CoinCondition::CreatePuzzleAnnouncement(msg.clone()),
+        CoinCondition::AssertPuzzleAnnouncement(hash),
+        CoinCondition::AssertConcurrentSpend(coin_id.clone()),
+        CoinCondition::AssertConcurrentPuzzle(ph.clone()),
+        CoinCondition::SendMessage(0x3f, msg.clone(), vec![coin_id.bytes().to_vec()]),
+        CoinCondition::ReceiveMessage(0x12, msg.clone(), vec![ph.bytes().to_vec(), vec![1]]),
+        CoinCondition::AssertMyCoinId(coin_id.clone()),
+        CoinCondition::AssertMyParentId(coin_id),
+        CoinCondition::AssertMyPuzzleHash(ph),
+        CoinCondition::AssertMyAmount(Amount::new(u64::MAX)),
+        CoinCondition::AssertMyBirthSeconds(1_700_000_000),
+        CoinCondition::AssertMyBirthHeight(5),
+        CoinCondition::AssertEphemeral,
+        CoinCondition::AssertSecondsRelative(300),
+        CoinCondition::AssertSecondsAbsolute(1_700_000_000),
+        CoinCondition::AssertHeightRelative(5),
+        CoinCondition::AssertHeightAbsolute(1000),
+        CoinCondition::AssertBeforeSecondsRelative(600),
+        CoinCondition::AssertBeforeSecondsAbsolute(1_800_000_000),
+        CoinCondition::AssertBeforeHeightRelative(10),
+        CoinCondition::AssertBeforeHeightAbsolute(2000),
+        CoinCondition::Rem(vec![msg, vec![1, 2, 3]]),
+    ]
+}
+
+#[test]
+fn test_coin_condition_round_trip() {
+    let mut allocator = AllocEncoder::new();
+    let conditions = every_condition();
+    let encoded = conditions.to_clvm(&mut allocator).expect("should encode");
+    let decoded = CoinCondition::from_nodeptr(&mut allocator, encoded);
+    assert_eq!(decoded, conditions);
+}
+
+#[test]
+fn test_coin_condition_parse_chialisp() {
+    let mut allocator = AllocEncoder::new();
+    let conditions = assemble(
+        allocator.allocator(),
+        "((82 5) (83 0x03e8) (73 100) (60 \"hi\") (76) (51 0x7075a7e9e2fc7f64fe1f47cfd7d1e2b5d0e18c4fd5e4c0a0c0b81dbe00e6f0a3 200 (memo)))",
+    )
+    .expect("should assemble");
+    let parsed = CoinCondition::from_nodeptr(&mut allocator, conditions);
+    assert_eq!(
+        parsed[..5],
+        [
+            CoinCondition::AssertHeightRelative(5),
+            CoinCondition::AssertHeightAbsolute(1000),
+            CoinCondition::AssertMyAmount(Amount::new(100)),
+            CoinCondition::CreateCoinAnnouncement(b"hi".to_vec()),
+            CoinCondition::AssertEphemeral,
+        ]
+    );
+    let ph = hex::decode("7075a7e9e2fc7f64fe1f47cfd7d1e2b5d0e18c4fd5e4c0a0c0b81dbe00e6f0a3")
+        .expect("should decode");
+    assert_eq!(
+        parsed[5],
+        CoinCondition::CreateCoin(
+            PuzzleHash::from_hash(Hash::from_slice(&ph)),
+            Amount::new(200),
+            vec![b"memo".to_vec()]
+        )
+    );
+}
+
+#[test]
+fn test_coin_condition_skips_malformed() {
+    let mut allocator = AllocEncoder::new();
+    // A negative timelock, a short puzzle hash, an unknown opcode and a
+    // condition that isn't a list.
+    let conditions = assemble(
+        allocator.allocator(),
+        "((82 -1) (51 0xcafe 100) (99 1) 50 (83 7))",
+    )
+    .expect("should assemble");
+    let parsed = CoinCondition::from_nodeptr(&mut allocator, conditions);
+    assert_eq!(parsed, vec![CoinCondition::AssertHeightAbsolute(7)]);
+}
@@ -1,6 +1,6 @@
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::channel_handler::types::ReadableMove;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::types::Hash;
 use crate::common::types::Timeout;
 
@@ -8,32 +8,32 @@ use crate::common::types::Timeout;
 use clvmr::NodePtr;
 use lazy_static::lazy_static;
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use rand::prelude::*;
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use log::debug;
 
 lazy_static! {
     pub static ref DEFAULT_UNROLL_TIME_LOCK: Timeout = Timeout::new(5);
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::channel_handler::game::Game;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::channel_handler::runner::ChannelHandlerGame;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::channel_handler::types::{ChannelHandlerEnv, PrintableGameStartInfo};
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::standard_coin::{
     private_to_public_key, puzzle_hash_for_synthetic_public_key, ChiaIdentity,
 };
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::types::Amount;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::common::types::{CoinString, Error, IntoErr};
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 use crate::simulator::Simulator;
 
 #[derive(Debug, Clone)]
@@ -46,21 +46,50 @@ pub enum GameAction {
     #[allow(dead_code)]
     Move(usize, NodePtr, bool),
     /// Fake move, just calls receive on the indicated side.
-    #[cfg(feature = "sim-tests")]
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
     FakeMove(usize, NodePtr, Vec<u8>),
+    /// Move (player, clvm readable move, mover share), claiming a mover share
+    /// the game doesn't allow.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    CheatMove(usize, NodePtr, Amount),
     /// Go on chain
-    #[cfg(feature = "sim-tests")]
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
     GoOnChain(usize),
+    /// Go on chain (player, moves back) with the channel state the player held
+    /// that many of its own moves ago.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    GoOnChainStale(usize, usize),
     /// Accept
-    #[cfg(feature = "sim-tests")]
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
     Accept(usize),
     /// Shut down
-    #[cfg(feature = "sim-tests")]
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
     Shutdown(usize, NodePtr),
+    /// Drop the player's next outbound messages as if the connection failed,
+    /// then have both sides reconnect and resume.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    LoseMessage(usize),
+    /// The player stops sending anything to its peer.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    GoSilent(usize),
+    /// The player goes silent once its peer asks for a clean shutdown, never
+    /// broadcasting the spend that finishes it.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    IgnoreShutdown(usize),
+    /// The player adds a coin of this amount to the channel.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    TopUp(usize, Amount),
+    /// The player takes this amount out of the channel.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    Withdraw(usize, Amount),
+    /// The player's cradle is saved to a snapshot and rebuilt from it, as if
+    /// the process restarted.
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
+    Restore(usize),
 }
 
 impl GameAction {
-    #[cfg(feature = "sim-tests")]
+    #[cfg(any(feature = "sim-tests", feature = "native-sim"))]
     pub fn lose(&self) -> GameAction {
         if let GameAction::Move(p, m, _r) = self {
             return GameAction::Move(*p, *m, false);
@@ -71,7 +100,7 @@ impl GameAction {
 }
 
 #[derive(Debug, Clone)]
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 pub enum GameActionResult {
     MoveResult(NodePtr, Vec<u8>, Option<ReadableMove>, Hash),
     BrokenMove,
@@ -80,7 +109,7 @@ pub enum GameActionResult {
     Shutdown,
 }
 
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 pub fn new_channel_handler_game<R: Rng>(
     simulator: &Simulator,
     env: &mut ChannelHandlerEnv<R>,
@@ -1,16 +1,21 @@
 use exec::execvp;
 use std::ffi::OsString;
 
+#[cfg(feature = "server")]
+pub mod async_cradle;
 pub mod calpoker;
 pub mod channel_handler;
 pub mod chialisp;
+pub mod coin_condition;
 pub mod constants;
 pub mod game;
 pub mod game_handler;
 pub mod peer;
 pub mod referee;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 pub mod simenv;
+#[cfg(feature = "native-sim")]
+pub mod simulator;
 pub mod standard_coin;
 
 fn detect_run_as_python(args: &[String]) -> bool {
@@ -1,3 +1,3 @@
 pub mod potato_handler;
-#[cfg(feature = "sim-tests")]
+#[cfg(any(feature = "sim-tests", feature = "native-sim"))]
 pub mod potato_handler_sim;
@@ -8,17 +8,19 @@ use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 
 use crate::channel_handler::runner::channel_handler_env;
-use crate::channel_handler::types::{ChannelHandlerEnv, ChannelHandlerPrivateKeys, ReadableMove};
+use crate::channel_handler::types::{
+    ChannelHandlerEnv, ChannelHandlerPrivateKeys, FlatGameStartInfo, ReadableMove,
+};
 use crate::common::standard_coin::{private_to_public_key, puzzle_hash_for_pk, read_hex_puzzle};
 use crate::common::types::{
-    AllocEncoder, Amount, CoinID, CoinString, Error, GameID, IntoErr, PrivateKey, PuzzleHash,
-    Spend, SpendBundle, Timeout,
+    Aggsig, AllocEncoder, Amount, CoinID, CoinString, Error, GameID, HandshakeError, IntoErr,
+    PrivateKey, ProtocolError, PuzzleHash, Spend, SpendBundle, Timeout,
 };
 use crate::peer_container::{MessagePeerQueue, MessagePipe, WalletBootstrapState};
 use crate::potato_handler::{
-    BootstrapTowardGame, BootstrapTowardWallet, FromLocalUI, GameStart, GameType, PacketSender,
-    PeerEnv, PeerMessage, PotatoHandler, PotatoHandlerInit, SpendWalletReceiver, ToLocalUI,
-    WalletSpendInterface,
+    BootstrapTowardGame, BootstrapTowardWallet, FromLocalUI, GameStart, GameType,
+    GoingOnChainReason, LivenessPolicy, PacketSender, PeerEnv, PeerMessage, PotatoHandler,
+    PotatoHandlerInit, SpendWalletReceiver, ToLocalUI, WalletSpendInterface, WireGameStart,
 };
 
 use crate::common::constants::CREATE_COIN;
@@ -42,6 +44,17 @@ struct Pipe {
     opponent_messages: Vec<(GameID, ReadableMove)>,
     our_moves: Vec<(GameID, Vec<u8>)>,
 
+    // Game negotiation
+    proposals: Vec<WireGameStart>,
+    accepted_proposals: Vec<GameID>,
+    declined_proposals: Vec<GameID>,
+    cancelled_proposals: Vec<GameID>,
+
+    // Game endings
+    finished_games: Vec<(GameID, Amount)>,
+    settlement_offers: Vec<(GameID, Amount)>,
+    declined_settlements: Vec<GameID>,
+
     // Bootstrap info
     channel_puzzle_hash: Option<PuzzleHash>,
 
@@ -50,6 +63,10 @@ struct Pipe {
 
     #[allow(dead_code)]
     bootstrap_state: Option<WalletBootstrapState>,
+
+    // Fuzzed input can provoke replies the normal flow never would, so only
+    // the last message sent is kept instead of asserting on a backlog.
+    unchecked_sends: bool,
 }
 
 impl MessagePeerQueue for Pipe {
@@ -80,6 +97,9 @@ impl PacketSender for MessagePipe {
 
 impl PacketSender for Pipe {
     fn send_message(&mut self, msg: &PeerMessage) -> Result<(), Error> {
+        if self.unchecked_sends {
+            self.message_pipe.queue.clear();
+        }
         self.message_pipe.send_message(msg)
     }
 }
@@ -124,6 +144,10 @@ impl BootstrapTowardWallet for Pipe {
     ) -> Result<(), Error> {
         todo!();
     }
+
+    fn channel_top_up(&mut self, _bundle: &SpendBundle) -> Result<(), Error> {
+        todo!();
+    }
 }
 
 impl ToLocalUI for Pipe {
@@ -155,7 +179,8 @@ impl ToLocalUI for Pipe {
         self.opponent_messages.push((id.clone(), readable));
         Ok(())
     }
-    fn game_finished(&mut self, _id: &GameID, _my_share: Amount) -> Result<(), Error> {
+    fn game_finished(&mut self, id: &GameID, my_share: Amount) -> Result<(), Error> {
+        self.finished_games.push((id.clone(), my_share));
         Ok(())
     }
     fn game_cancelled(&mut self, _id: &GameID) -> Result<(), Error> {
@@ -165,9 +190,33 @@ impl ToLocalUI for Pipe {
     fn shutdown_complete(&mut self, _reward_coin_string: &CoinString) -> Result<(), Error> {
         todo!();
     }
-    fn going_on_chain(&mut self) -> Result<(), Error> {
+    fn going_on_chain(&mut self, _reason: &GoingOnChainReason) -> Result<(), Error> {
         todo!();
     }
+    fn game_proposed(&mut self, proposal: &WireGameStart) -> Result<(), Error> {
+        self.proposals.push(proposal.clone());
+        Ok(())
+    }
+    fn game_proposal_accepted(&mut self, id: &GameID) -> Result<(), Error> {
+        self.accepted_proposals.push(id.clone());
+        Ok(())
+    }
+    fn game_proposal_declined(&mut self, id: &GameID) -> Result<(), Error> {
+        self.declined_proposals.push(id.clone());
+        Ok(())
+    }
+    fn game_proposal_cancelled(&mut self, id: &GameID) -> Result<(), Error> {
+        self.cancelled_proposals.push(id.clone());
+        Ok(())
+    }
+    fn settlement_offered(&mut self, id: &GameID, my_share: &Amount) -> Result<(), Error> {
+        self.settlement_offers.push((id.clone(), my_share.clone()));
+        Ok(())
+    }
+    fn settlement_declined(&mut self, id: &GameID) -> Result<(), Error> {
+        self.declined_settlements.push(id.clone());
+        Ok(())
+    }
 }
 
 pub struct TestPeerEnv<'inputs, G, R>
@@ -388,19 +437,16 @@ where
     Ok(())
 }
 
-#[test]
-fn test_peer_smoke() {
-    let seed: [u8; 32] = [0; 32];
-    let mut rng = ChaCha8Rng::from_seed(seed);
-    let mut allocator = AllocEncoder::new();
-
+fn calpoker_peers_after_handshake(
+    rng: &mut ChaCha8Rng,
+    allocator: &mut AllocEncoder,
+) -> ([PotatoHandler; 2], [Pipe; 2]) {
     let mut pipe_sender: [Pipe; 2] = Default::default();
     pipe_sender[1].message_pipe.my_id = 1;
 
     let mut game_type_map = BTreeMap::new();
-    let calpoker_factory =
-        read_hex_puzzle(&mut allocator, "clsp/calpoker_include_calpoker_factory.hex")
-            .expect("should load");
+    let calpoker_factory = read_hex_puzzle(allocator, "clsp/calpoker_include_calpoker_factory.hex")
+        .expect("should load");
 
     game_type_map.insert(
         GameType(b"calpoker".to_vec()),
@@ -423,24 +469,25 @@ fn test_peer_smoke() {
             channel_timeout: Timeout::new(1000),
             unroll_timeout: Timeout::new(5),
             reward_puzzle_hash: reward_puzzle_hash1.clone(),
+            liveness: LivenessPolicy::default(),
         })
     };
 
     let parent_private_key: PrivateKey = rng.gen();
     let parent_public_key = private_to_public_key(&parent_private_key);
     let parent_puzzle_hash =
-        puzzle_hash_for_pk(&mut allocator, &parent_public_key).expect("should work");
+        puzzle_hash_for_pk(allocator, &parent_public_key).expect("should work");
 
     let parent_coin_id = CoinID::default();
     let parent_coin =
         CoinString::from_parts(&parent_coin_id, &parent_puzzle_hash, &Amount::new(200));
 
-    let p1 = new_peer(&mut allocator, &mut rng, true);
-    let p2 = new_peer(&mut allocator, &mut rng, false);
+    let p1 = new_peer(allocator, rng, true);
+    let p2 = new_peer(allocator, rng, false);
     let mut peers = [p1, p2];
 
     {
-        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut env = channel_handler_env(allocator, rng);
         let mut penv = TestPeerEnv {
             env: &mut env,
             system_interface: &mut pipe_sender[0],
@@ -450,8 +497,8 @@ fn test_peer_smoke() {
 
     // Do handshake for peers.
     handshake(
-        &mut rng,
-        &mut allocator,
+        rng,
+        allocator,
         Amount::new(200),
         &mut peers,
         &mut pipe_sender,
@@ -459,16 +506,75 @@ fn test_peer_smoke() {
     .expect("should work");
 
     quiesce(
-        &mut rng,
-        &mut allocator,
+        rng,
+        allocator,
         Amount::new(200),
         &mut peers,
         &mut pipe_sender,
     )
     .expect("should work");
 
+    (peers, pipe_sender)
+}
+
+// Start the game either with both sides calling start_games or by the second
+// peer proposing it and the first accepting.
+fn run_calpoker_peer_smoke(restore_before_move: Option<usize>, by_proposal: bool) {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let (mut peers, mut pipe_sender) = calpoker_peers_after_handshake(&mut rng, &mut allocator);
+
+    let calpoker_start = GameStart {
+        amount: Amount::new(200),
+        my_contribution: Amount::new(100),
+        game_type: GameType(b"calpoker".to_vec()),
+        timeout: Timeout::new(10),
+        my_turn: true,
+        parameters: vec![0x80],
+    };
+
     // Start a game
-    let game_ids = {
+    let game_ids = if by_proposal {
+        let game_ids = {
+            let mut env = channel_handler_env(&mut allocator, &mut rng);
+            let mut penv = TestPeerEnv {
+                env: &mut env,
+                system_interface: &mut pipe_sender[1],
+            };
+            peers[1]
+                .propose_game(&mut penv, &calpoker_start)
+                .expect("should propose")
+        };
+
+        quiesce(
+            &mut rng,
+            &mut allocator,
+            Amount::new(200),
+            &mut peers,
+            &mut pipe_sender,
+        )
+        .expect("should work");
+
+        let proposal = pipe_sender[0].proposals.pop().expect("should be proposed");
+        assert_eq!(proposal.game_ids, game_ids);
+        assert!(!proposal.start.my_turn);
+        assert_eq!(proposal.start.my_contribution, Amount::new(100));
+
+        {
+            let mut env = channel_handler_env(&mut allocator, &mut rng);
+            let mut penv = TestPeerEnv {
+                env: &mut env,
+                system_interface: &mut pipe_sender[0],
+            };
+            peers[0]
+                .accept_proposal(&mut penv, &game_ids[0])
+                .expect("should accept");
+        }
+
+        game_ids
+    } else {
         let mut env = channel_handler_env(&mut allocator, &mut rng);
         let mut penv = TestPeerEnv {
             env: &mut env,
@@ -476,18 +582,7 @@ fn test_peer_smoke() {
         };
 
         let game_ids = peers[1]
-            .start_games(
-                &mut penv,
-                true,
-                &GameStart {
-                    amount: Amount::new(200),
-                    my_contribution: Amount::new(100),
-                    game_type: GameType(b"calpoker".to_vec()),
-                    timeout: Timeout::new(10),
-                    my_turn: true,
-                    parameters: vec![0x80],
-                },
-            )
+            .start_games(&mut penv, true, &calpoker_start)
             .expect("should run");
 
         peers[0]
@@ -495,12 +590,8 @@ fn test_peer_smoke() {
                 &mut penv,
                 false,
                 &GameStart {
-                    amount: Amount::new(200),
-                    my_contribution: Amount::new(100),
-                    game_type: GameType(b"calpoker".to_vec()),
-                    timeout: Timeout::new(10),
                     my_turn: false,
-                    parameters: vec![0x80],
+                    ..calpoker_start.clone()
                 },
             )
             .expect("should run");
@@ -520,11 +611,26 @@ fn test_peer_smoke() {
     assert!(pipe_sender[0].message_pipe.queue.is_empty());
     assert!(pipe_sender[1].message_pipe.queue.is_empty());
 
-    let moves = test_moves_1(&mut allocator);
+    let mut moves = test_moves_1(&mut allocator);
+
+    for i in 0..moves.len() {
+        if restore_before_move == Some(i) {
+            // Round trip both peers through a snapshot into a fresh allocator
+            // and continue the game with the restored handlers.
+            let mut new_allocator = AllocEncoder::new();
+            for peer in peers.iter_mut() {
+                let snapshot = peer
+                    .to_snapshot_bytes(&mut allocator)
+                    .expect("should snapshot");
+                *peer = PotatoHandler::from_snapshot_bytes(&mut new_allocator, &snapshot)
+                    .expect("should restore");
+            }
+            allocator = new_allocator;
+            moves = test_moves_1(&mut allocator);
+        }
 
-    for this_move in moves.iter() {
-        let (who, what) = if let GameAction::Move(who, what, _) = this_move {
-            (who, what)
+        let (who, what) = if let Some(GameAction::Move(who, what, _)) = moves.get(i) {
+            (*who, *what)
         } else {
             panic!();
         };
@@ -540,7 +646,7 @@ fn test_peer_smoke() {
                 .make_move(
                     &mut penv,
                     &game_ids[0],
-                    &ReadableMove::from_nodeptr(*what),
+                    &ReadableMove::from_nodeptr(what),
                     entropy,
                 )
                 .expect("should work");
@@ -584,3 +690,889 @@ fn test_peer_smoke() {
     assert!(pipe_sender[0].message_pipe.queue.is_empty());
     assert!(pipe_sender[1].message_pipe.queue.is_empty());
 }
+
+#[test]
+fn test_peer_smoke() {
+    run_calpoker_peer_smoke(None, false);
+}
+
+#[test]
+fn test_peer_snapshot_restore_mid_game() {
+    run_calpoker_peer_smoke(Some(2), false);
+}
+
+#[test]
+fn test_peer_snapshot_restore_before_first_move() {
+    run_calpoker_peer_smoke(Some(0), false);
+}
+
+#[test]
+fn test_peer_proposed_game_plays() {
+    run_calpoker_peer_smoke(None, true);
+}
+
+#[test]
+fn test_peer_settlement_decline_and_accept() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let (mut peers, mut pipe_sender) = calpoker_peers_after_handshake(&mut rng, &mut allocator);
+
+    let calpoker_start = GameStart {
+        amount: Amount::new(200),
+        my_contribution: Amount::new(100),
+        game_type: GameType(b"calpoker".to_vec()),
+        timeout: Timeout::new(10),
+        my_turn: true,
+        parameters: vec![0x80],
+    };
+
+    let game_id = {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        let game_ids = peers[1]
+            .start_games(&mut penv, true, &calpoker_start)
+            .expect("should run");
+        peers[0]
+            .start_games(
+                &mut penv,
+                false,
+                &GameStart {
+                    my_turn: false,
+                    ..calpoker_start.clone()
+                },
+            )
+            .expect("should run");
+        game_ids[0].clone()
+    };
+
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+
+    let offer = |peers: &mut [PotatoHandler; 2],
+                 pipe_sender: &mut [Pipe; 2],
+                 rng: &mut ChaCha8Rng,
+                 allocator: &mut AllocEncoder,
+                 my_share: u64| {
+        {
+            let mut env = channel_handler_env(allocator, rng);
+            let mut penv = TestPeerEnv {
+                env: &mut env,
+                system_interface: &mut pipe_sender[0],
+            };
+            peers[0]
+                .offer_settlement(&mut penv, &game_id, &Amount::new(my_share))
+                .expect("should offer");
+            // Only one offer per game may be outstanding.
+            assert!(peers[0]
+                .offer_settlement(&mut penv, &game_id, &Amount::new(my_share))
+                .is_err());
+        }
+        quiesce(rng, allocator, Amount::new(200), peers, pipe_sender).expect("should work");
+    };
+
+    // Declined: the game carries on and the proposer may offer again.
+    offer(&mut peers, &mut pipe_sender, &mut rng, &mut allocator, 150);
+    assert_eq!(
+        pipe_sender[1].settlement_offers,
+        vec![(game_id.clone(), Amount::new(50))]
+    );
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        peers[1]
+            .decline_settlement(&mut penv, &game_id)
+            .expect("should decline");
+        assert!(peers[1].accept_settlement(&mut penv, &game_id).is_err());
+    }
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+    assert_eq!(pipe_sender[0].declined_settlements, vec![game_id.clone()]);
+    assert!(pipe_sender[0].finished_games.is_empty());
+
+    // Accepted: the game ends at the offered split rather than the mover
+    // share.
+    offer(&mut peers, &mut pipe_sender, &mut rng, &mut allocator, 120);
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        peers[1]
+            .accept_settlement(&mut penv, &game_id)
+            .expect("should accept");
+    }
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+
+    assert_eq!(
+        pipe_sender[0].finished_games,
+        vec![(game_id.clone(), Amount::new(120))]
+    );
+    assert_eq!(
+        pipe_sender[1].finished_games,
+        vec![(game_id.clone(), Amount::new(80))]
+    );
+    for (who, (mine, theirs)) in [(0, (120, 80)), (1, (80, 120))] {
+        let ch = peers[who].channel_handler().expect("should have channel");
+        assert!(ch.live_game_ids().is_empty());
+        assert_eq!(
+            ch.out_of_game_balances(),
+            (Amount::new(mine), Amount::new(theirs))
+        );
+    }
+    assert!(pipe_sender[0].message_pipe.queue.is_empty());
+    assert!(pipe_sender[1].message_pipe.queue.is_empty());
+}
+
+#[test]
+fn test_peer_proposal_decline_and_cancel() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let (mut peers, mut pipe_sender) = calpoker_peers_after_handshake(&mut rng, &mut allocator);
+
+    let calpoker_start = GameStart {
+        amount: Amount::new(200),
+        my_contribution: Amount::new(100),
+        game_type: GameType(b"calpoker".to_vec()),
+        timeout: Timeout::new(10),
+        my_turn: true,
+        parameters: vec![0x80],
+    };
+
+    let propose = |peers: &mut [PotatoHandler; 2],
+                   pipe_sender: &mut [Pipe; 2],
+                   rng: &mut ChaCha8Rng,
+                   allocator: &mut AllocEncoder| {
+        let game_ids = {
+            let mut env = channel_handler_env(allocator, rng);
+            let mut penv = TestPeerEnv {
+                env: &mut env,
+                system_interface: &mut pipe_sender[0],
+            };
+            peers[0]
+                .propose_game(&mut penv, &calpoker_start)
+                .expect("should propose")
+        };
+        quiesce(rng, allocator, Amount::new(200), peers, pipe_sender).expect("should work");
+        game_ids[0].clone()
+    };
+
+    // Declined: the proposer hears about it and can't cancel it afterward.
+    let declined = propose(&mut peers, &mut pipe_sender, &mut rng, &mut allocator);
+    assert_eq!(pipe_sender[1].proposals.len(), 1);
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        peers[1]
+            .decline_proposal(&mut penv, &declined)
+            .expect("should decline");
+    }
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+    assert_eq!(pipe_sender[0].declined_proposals, vec![declined.clone()]);
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[0],
+        };
+        assert!(peers[0].cancel_proposal(&mut penv, &declined).is_err());
+    }
+
+    // Cancelled: the peer hears about it and can no longer accept it.
+    let cancelled = propose(&mut peers, &mut pipe_sender, &mut rng, &mut allocator);
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[0],
+        };
+        peers[0]
+            .cancel_proposal(&mut penv, &cancelled)
+            .expect("should cancel");
+    }
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+    assert_eq!(pipe_sender[1].cancelled_proposals, vec![cancelled.clone()]);
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        assert!(peers[1].accept_proposal(&mut penv, &cancelled).is_err());
+    }
+
+    // An accept crossing a cancel leaves neither side waiting on the game.
+    let crossed = propose(&mut peers, &mut pipe_sender, &mut rng, &mut allocator);
+    for (who, peer) in peers.iter_mut().enumerate() {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[who],
+        };
+        if who == 0 {
+            peer.cancel_proposal(&mut penv, &crossed)
+                .expect("should cancel");
+        } else {
+            peer.accept_proposal(&mut penv, &crossed)
+                .expect("should accept");
+        }
+    }
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+    assert!(pipe_sender[0].accepted_proposals.is_empty());
+    assert_eq!(
+        pipe_sender[1].cancelled_proposals,
+        vec![cancelled, crossed.clone()]
+    );
+
+    // The channel still works: a fresh proposal is accepted and started.
+    let started = propose(&mut peers, &mut pipe_sender, &mut rng, &mut allocator);
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        peers[1]
+            .accept_proposal(&mut penv, &started)
+            .expect("should accept");
+    }
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+    assert_eq!(pipe_sender[0].accepted_proposals, vec![started]);
+    assert!(pipe_sender[0].message_pipe.queue.is_empty());
+    assert!(pipe_sender[1].message_pipe.queue.is_empty());
+}
+
+// Accept a calpoker proposal, let tamper alter the games the proposer sends
+// and give back what the accepter makes of them.
+fn start_for_accepted_proposal(
+    tamper: impl FnOnce(&mut Vec<FlatGameStartInfo>),
+) -> Result<(), Error> {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let (mut peers, mut pipe_sender) = calpoker_peers_after_handshake(&mut rng, &mut allocator);
+
+    let calpoker_start = GameStart {
+        amount: Amount::new(200),
+        my_contribution: Amount::new(100),
+        game_type: GameType(b"calpoker".to_vec()),
+        timeout: Timeout::new(10),
+        my_turn: true,
+        parameters: vec![0x80],
+    };
+
+    let game_ids = {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[0],
+        };
+        peers[0]
+            .propose_game(&mut penv, &calpoker_start)
+            .expect("should propose")
+    };
+    quiesce(
+        &mut rng,
+        &mut allocator,
+        Amount::new(200),
+        &mut peers,
+        &mut pipe_sender,
+    )
+    .expect("should work");
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[1],
+        };
+        peers[1]
+            .accept_proposal(&mut penv, &game_ids[0])
+            .expect("should accept");
+    }
+
+    // Pass messages along until the proposer sends the games.
+    let mut step = 0;
+    let (sigs, mut games) = loop {
+        assert!(step < 20, "the proposer never started the games");
+        if let Some(msg) = pipe_sender[0].message_pipe.queue.front() {
+            if let PeerMessage::StartGames(sigs, games) =
+                PeerMessage::from_bytes(msg).expect("should decode")
+            {
+                pipe_sender[0].message_pipe.queue.clear();
+                break (sigs, games);
+            }
+        }
+        let who = step % 2;
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        run_move(
+            &mut env,
+            Amount::new(200),
+            &mut pipe_sender,
+            &mut peers[who],
+            who,
+        )
+        .expect("should work");
+        step += 1;
+    };
+
+    assert_eq!(
+        games.iter().map(|g| g.game_id.clone()).collect::<Vec<_>>(),
+        game_ids
+    );
+    tamper(&mut games);
+
+    let mut env = channel_handler_env(&mut allocator, &mut rng);
+    let mut penv = TestPeerEnv {
+        env: &mut env,
+        system_interface: &mut pipe_sender[1],
+    };
+    peers[1].received_message(&mut penv, {
+        let doc = bson::to_bson(&PeerMessage::StartGames(sigs, games)).expect("should encode");
+        bson::to_vec(&doc).expect("should encode")
+    })
+}
+
+#[test]
+fn test_peer_rejects_start_unlike_accepted_proposal() {
+    let result = start_for_accepted_proposal(|games| {
+        games[0].timeout = Timeout::new(1000);
+    });
+    match result {
+        Err(Error::Protocol(ProtocolError::StateMismatch(e))) => {
+            assert!(e.contains("timeout"), "{e}");
+        }
+        other => panic!("expected a state mismatch, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_peer_rejects_fresh_ids_for_accepted_proposal() {
+    // New game ids mustn't let the games skip the proposal's checks.
+    let result = start_for_accepted_proposal(|games| {
+        for game in games.iter_mut() {
+            let mut id = game.game_id.to_bytes().to_vec();
+            id.push(0xff);
+            game.game_id = GameID::new(id);
+            game.amount = Amount::new(1);
+        }
+    });
+    match result {
+        Err(Error::Protocol(ProtocolError::StateMismatch(e))) => {
+            assert!(e.contains("accepted proposal"), "{e}");
+        }
+        other => panic!("expected a state mismatch, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_peer_handshake_rejects_mismatched_game_factory() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let mut pipe_sender: [Pipe; 2] = Default::default();
+    pipe_sender[1].message_pipe.my_id = 1;
+
+    let calpoker_factory =
+        read_hex_puzzle(&mut allocator, "clsp/calpoker_include_calpoker_factory.hex")
+            .expect("should load");
+
+    // Both sides name the same game, but the second one's factory differs.
+    let factories = [
+        calpoker_factory.to_program(),
+        Program::from_hex("80").expect("should decode"),
+    ];
+
+    let mut new_peer = |have_potato: bool, factory: &Program| {
+        let private_keys: ChannelHandlerPrivateKeys = rng.gen();
+        let reward_private_key: PrivateKey = rng.gen();
+        let reward_public_key = private_to_public_key(&reward_private_key);
+        let reward_puzzle_hash =
+            puzzle_hash_for_pk(&mut allocator, &reward_public_key).expect("should work");
+        let mut game_types = BTreeMap::new();
+        game_types.insert(GameType(b"calpoker".to_vec()), factory.clone());
+
+        PotatoHandler::new(PotatoHandlerInit {
+            have_potato,
+            private_keys,
+            game_types,
+            my_contribution: Amount::new(100),
+            their_contribution: Amount::new(100),
+            channel_timeout: Timeout::new(1000),
+            unroll_timeout: Timeout::new(5),
+            reward_puzzle_hash,
+            liveness: LivenessPolicy::default(),
+        })
+    };
+
+    let p1 = new_peer(true, &factories[0]);
+    let p2 = new_peer(false, &factories[1]);
+    let mut peers = [p1, p2];
+
+    let parent_coin = CoinString::from_parts(
+        &CoinID::default(),
+        &PuzzleHash::default(),
+        &Amount::new(200),
+    );
+
+    {
+        let mut env = channel_handler_env(&mut allocator, &mut rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe_sender[0],
+        };
+        peers[0].start(&mut penv, parent_coin).expect("should work");
+    }
+
+    let mut env = channel_handler_env(&mut allocator, &mut rng);
+    let result = run_move(
+        &mut env,
+        Amount::new(200),
+        &mut pipe_sender,
+        &mut peers[1],
+        1,
+    );
+    assert!(matches!(
+        result,
+        Err(Error::Handshake(HandshakeError::NoCommonGames))
+    ));
+    assert!(pipe_sender[1].message_pipe.queue.is_empty());
+}
+
+// A snapshot of each peer state reached while playing a game, keyed by
+// state name and whether the peer initiated, and every message delivered.
+#[derive(Default)]
+struct FuzzCorpus {
+    states: BTreeMap<(&'static str, bool), Vec<u8>>,
+    messages: Vec<Vec<u8>>,
+}
+
+impl FuzzCorpus {
+    fn observe(&mut self, allocator: &mut AllocEncoder, peers: &[PotatoHandler]) {
+        for peer in peers.iter() {
+            let key = (peer.handshake_state_name(), peer.is_initiator());
+            if !self.states.contains_key(&key) {
+                let snapshot = peer.to_snapshot_bytes(allocator).expect("should snapshot");
+                self.states.insert(key, snapshot);
+            }
+        }
+    }
+}
+
+fn deliver_recording(
+    rng: &mut ChaCha8Rng,
+    allocator: &mut AllocEncoder,
+    peers: &mut [PotatoHandler; 2],
+    pipes: &mut [Pipe; 2],
+    who: usize,
+    corpus: &mut FuzzCorpus,
+) -> Result<bool, Error> {
+    corpus.observe(allocator, peers);
+    let msg = if let Some(msg) = pipes[who ^ 1].message_pipe.queue.pop_front() {
+        msg
+    } else {
+        return Ok(false);
+    };
+    corpus.messages.push(msg.clone());
+
+    let mut env = channel_handler_env(allocator, rng);
+    let mut penv = TestPeerEnv {
+        env: &mut env,
+        system_interface: &mut pipes[who],
+    };
+    peers[who].received_message(&mut penv, msg)?;
+    Ok(true)
+}
+
+fn quiesce_recording(
+    rng: &mut ChaCha8Rng,
+    allocator: &mut AllocEncoder,
+    peers: &mut [PotatoHandler; 2],
+    pipes: &mut [Pipe; 2],
+    corpus: &mut FuzzCorpus,
+) -> Result<(), Error> {
+    loop {
+        let mut msgs = 0;
+        for who in 0..2 {
+            msgs += deliver_recording(rng, allocator, peers, pipes, who, corpus)? as usize;
+        }
+        if msgs == 0 {
+            break;
+        }
+    }
+
+    corpus.observe(allocator, peers);
+    Ok(())
+}
+
+// Play a calpoker game through shutdown, collecting the corpus.
+fn collect_fuzz_corpus(rng: &mut ChaCha8Rng, allocator: &mut AllocEncoder) -> FuzzCorpus {
+    let mut corpus = FuzzCorpus::default();
+    let mut pipes: [Pipe; 2] = Default::default();
+    pipes[1].message_pipe.my_id = 1;
+
+    let calpoker_factory = read_hex_puzzle(allocator, "clsp/calpoker_include_calpoker_factory.hex")
+        .expect("should load");
+    let mut game_types = BTreeMap::new();
+    game_types.insert(
+        GameType(b"calpoker".to_vec()),
+        calpoker_factory.to_program(),
+    );
+
+    let mut new_peer = |have_potato: bool| {
+        let private_keys: ChannelHandlerPrivateKeys = rng.gen();
+        let reward_private_key: PrivateKey = rng.gen();
+        let reward_public_key = private_to_public_key(&reward_private_key);
+        let reward_puzzle_hash =
+            puzzle_hash_for_pk(allocator, &reward_public_key).expect("should work");
+
+        PotatoHandler::new(PotatoHandlerInit {
+            have_potato,
+            private_keys,
+            game_types: game_types.clone(),
+            my_contribution: Amount::new(100),
+            their_contribution: Amount::new(100),
+            channel_timeout: Timeout::new(1000),
+            unroll_timeout: Timeout::new(5),
+            reward_puzzle_hash,
+            liveness: LivenessPolicy::default(),
+        })
+    };
+
+    let p1 = new_peer(true);
+    let p2 = new_peer(false);
+    let mut peers = [p1, p2];
+
+    let parent_coin = CoinString::from_parts(
+        &CoinID::default(),
+        &PuzzleHash::default(),
+        &Amount::new(200),
+    );
+
+    corpus.observe(allocator, &peers);
+    {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipes[0],
+        };
+        peers[0]
+            .start(&mut penv, parent_coin.clone())
+            .expect("should work");
+    }
+
+    // The same steps as handshake, recording as we go.
+    let mut i = 0;
+    while !peers[0].handshake_finished() || !peers[1].handshake_finished() {
+        assert!(i <= 50);
+
+        let who = i % 2;
+        deliver_recording(rng, allocator, &mut peers, &mut pipes, who, &mut corpus)
+            .expect("should send");
+        i += 1;
+
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipes[who],
+        };
+
+        if let Some(ch) = penv.system_interface.get_channel_puzzle_hash() {
+            penv.test_handle_received_channel_puzzle_hash(&mut peers[who], &parent_coin, &ch)
+                .expect("should work");
+            penv.system_interface.set_channel_puzzle_hash(None);
+        }
+
+        if let Some(ufo) = penv.system_interface.get_unfunded_offer() {
+            penv.test_handle_received_unfunded_offer(&mut peers[who], &ufo)
+                .expect("should work");
+        }
+
+        if (10..12).contains(&i) {
+            let channel_coin = get_channel_coin_for_peer(&peers[who]).expect("should work");
+            peers[who]
+                .coin_created(&mut penv, &channel_coin)
+                .expect("should work");
+        }
+    }
+
+    quiesce_recording(rng, allocator, &mut peers, &mut pipes, &mut corpus).expect("should work");
+
+    let game_ids = {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipes[1],
+        };
+        let game_ids = peers[1]
+            .start_games(
+                &mut penv,
+                true,
+                &GameStart {
+                    amount: Amount::new(200),
+                    my_contribution: Amount::new(100),
+                    game_type: GameType(b"calpoker".to_vec()),
+                    timeout: Timeout::new(10),
+                    my_turn: true,
+                    parameters: vec![0x80],
+                },
+            )
+            .expect("should run");
+        peers[0]
+            .start_games(
+                &mut penv,
+                false,
+                &GameStart {
+                    amount: Amount::new(200),
+                    my_contribution: Amount::new(100),
+                    game_type: GameType(b"calpoker".to_vec()),
+                    timeout: Timeout::new(10),
+                    my_turn: false,
+                    parameters: vec![0x80],
+                },
+            )
+            .expect("should run");
+        game_ids
+    };
+
+    quiesce_recording(rng, allocator, &mut peers, &mut pipes, &mut corpus).expect("should work");
+
+    let moves = test_moves_1(allocator);
+    for game_move in moves.iter() {
+        let (who, what) = if let GameAction::Move(who, what, _) = game_move {
+            (*who, *what)
+        } else {
+            panic!();
+        };
+
+        let entropy = rng.gen();
+        {
+            let mut env = channel_handler_env(allocator, rng);
+            let mut penv = TestPeerEnv {
+                env: &mut env,
+                system_interface: &mut pipes[who ^ 1],
+            };
+            peers[who ^ 1]
+                .make_move(
+                    &mut penv,
+                    &game_ids[0],
+                    &ReadableMove::from_nodeptr(what),
+                    entropy,
+                )
+                .expect("should work");
+        }
+
+        quiesce_recording(rng, allocator, &mut peers, &mut pipes, &mut corpus)
+            .expect("should work");
+    }
+
+    let have_potato = if peers[0].has_potato() { 0 } else { 1 };
+    {
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipes[have_potato],
+        };
+        peers[have_potato]
+            .accept(&mut penv, &game_ids[0])
+            .expect("should work");
+    }
+
+    quiesce_recording(rng, allocator, &mut peers, &mut pipes, &mut corpus).expect("should work");
+
+    // A copy of each side takes the channel on chain.
+    for peer in peers.iter() {
+        let snapshot = peer.to_snapshot_bytes(allocator).expect("should snapshot");
+        let mut on_chain_peer =
+            PotatoHandler::from_snapshot_bytes(allocator, &snapshot).expect("should restore");
+        let mut pipe = Pipe::default();
+        let mut env = channel_handler_env(allocator, rng);
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipe,
+        };
+        if on_chain_peer.go_on_chain(&mut penv).is_ok() {
+            corpus.observe(allocator, &[on_chain_peer]);
+        }
+    }
+
+    let have_potato = if peers[0].has_potato() { 0 } else { 1 };
+    {
+        let mut env = channel_handler_env(allocator, rng);
+        let conditions = peers[have_potato]
+            .channel_handler()
+            .expect("should have a channel")
+            .clean_shutdown_conditions(&mut env)
+            .expect("should work");
+        let mut penv = TestPeerEnv {
+            env: &mut env,
+            system_interface: &mut pipes[have_potato],
+        };
+        peers[have_potato]
+            .shut_down(&mut penv, conditions)
+            .expect("should work");
+    }
+
+    quiesce_recording(rng, allocator, &mut peers, &mut pipes, &mut corpus).expect("should work");
+
+    corpus
+}
+
+#[test]
+fn test_peer_fuzz_messages_in_every_state() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let corpus = collect_fuzz_corpus(&mut rng, &mut allocator);
+    assert!(corpus.states.len() > 8);
+
+    let encode = |msg: &PeerMessage| {
+        let doc = bson::to_bson(msg).expect("should encode");
+        bson::to_vec(&doc).expect("should encode")
+    };
+
+    // Real messages, corrupted copies of them, random bytes and a few
+    // messages the normal flow never sends.
+    let mut inputs = corpus.messages.clone();
+    for msg in corpus.messages.iter() {
+        let mut corrupted = msg.clone();
+        for _ in 0..rng.gen_range(1..4) {
+            let at = rng.gen_range(0..corrupted.len());
+            corrupted[at] ^= rng.gen::<u8>() | 1;
+        }
+        inputs.push(corrupted);
+    }
+    for len in [0, 1, 5, 32, 200] {
+        inputs.push((0..len).map(|_| rng.gen()).collect());
+    }
+    let nil = Program::from_hex("80").expect("should decode");
+    // A remark whose argument is a pair rather than an atom.
+    let odd_conditions = Program::from_hex("ffff01ff02ffff03048080").expect("should decode");
+    for msg in [
+        PeerMessage::RequestPotato(()),
+        PeerMessage::Nil(Default::default()),
+        PeerMessage::Shutdown(Aggsig::default(), nil),
+        PeerMessage::Shutdown(Aggsig::default(), odd_conditions),
+        PeerMessage::Message(GameID::default(), vec![1, 2, 3]),
+        PeerMessage::HandshakeE {
+            bundle: SpendBundle { spends: vec![] },
+        },
+        PeerMessage::HandshakeF {
+            bundle: SpendBundle { spends: vec![] },
+        },
+        PeerMessage::StartGames(Default::default(), vec![]),
+        PeerMessage::ProposeGame(WireGameStart {
+            game_ids: vec![],
+            start: GameStart {
+                game_type: GameType(b"calpoker".to_vec()),
+                timeout: Timeout::new(10),
+                amount: Amount::new(200),
+                my_contribution: Amount::new(100),
+                my_turn: true,
+                parameters: vec![0x80],
+            },
+        }),
+        PeerMessage::ProposeGame(WireGameStart {
+            game_ids: vec![GameID::default()],
+            start: GameStart {
+                game_type: GameType(b"calpoker".to_vec()),
+                timeout: Timeout::new(10),
+                amount: Amount::new(100),
+                my_contribution: Amount::new(200),
+                my_turn: true,
+                parameters: vec![0x80],
+            },
+        }),
+        PeerMessage::AcceptProposal(GameID::default()),
+        PeerMessage::DeclineProposal(GameID::default()),
+        PeerMessage::CancelProposal(GameID::default()),
+        PeerMessage::OfferSettlement(GameID::default(), Amount::new(300)),
+        PeerMessage::DeclineSettlement(GameID::default()),
+        PeerMessage::Settle(GameID::default(), Default::default()),
+    ] {
+        inputs.push(encode(&msg));
+    }
+
+    // Any of these may be refused, but none may panic.
+    for ((state, initiator), snapshot) in corpus.states.iter() {
+        let mut allocator = AllocEncoder::new();
+        for _ in 0..24 {
+            let msg = inputs[rng.gen_range(0..inputs.len())].clone();
+            let mut peer = PotatoHandler::from_snapshot_bytes(&mut allocator, snapshot)
+                .expect("should restore");
+            let mut pipe = Pipe {
+                unchecked_sends: true,
+                ..Default::default()
+            };
+            let mut env = channel_handler_env(&mut allocator, &mut rng);
+            let mut penv = TestPeerEnv {
+                env: &mut env,
+                system_interface: &mut pipe,
+            };
+            let result = peer.received_message(&mut penv, msg);
+            debug!("fuzz {state} initiator {initiator}: {result:?}");
+        }
+    }
+}
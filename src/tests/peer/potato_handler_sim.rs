@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use clvm_traits::ToClvm;
 use log::debug;
@@ -7,23 +7,27 @@ use rand_chacha::ChaCha8Rng;
 
 use crate::channel_handler::runner::channel_handler_env;
 use crate::channel_handler::types::{ChannelHandlerEnv, ChannelHandlerPrivateKeys, ReadableMove};
+use crate::channel_manager::{ChannelManager, PeerId};
 use crate::common::constants::CREATE_COIN;
 use crate::common::standard_coin::{
     private_to_public_key, puzzle_hash_for_pk, sign_agg_sig_me, solution_for_conditions,
     standard_solution_partial, ChiaIdentity,
 };
 use crate::common::types::{
-    AllocEncoder, Amount, CoinSpend, CoinString, Error, GameID, IntoErr, PrivateKey, Program,
-    PuzzleHash, Sha256tree, Spend, SpendBundle, Timeout, ToQuotedProgram,
+    usize_from_atom, AllocEncoder, Amount, CoinCondition, CoinSpend, CoinString, Error, GameID,
+    IntoErr, PrivateKey, Program, PuzzleHash, Sha256tree, Spend, SpendBundle, Timeout,
+    ToQuotedProgram,
 };
 use crate::games::poker_collection;
 use crate::peer_container::{
-    report_coin_changes_to_peer, FullCoinSetAdapter, GameCradle, MessagePeerQueue, MessagePipe,
-    SynchronousGameCradle, SynchronousGameCradleConfig, WatchEntry, WatchReport,
+    report_coin_changes_to_peer, CradleEvent, CradleEventCollector, FullCoinSetAdapter, GameCradle,
+    MessagePeerQueue, MessagePipe, SynchronousGameCradle, SynchronousGameCradleConfig, WatchEntry,
+    WatchReport,
 };
 use crate::potato_handler::{
-    BootstrapTowardGame, BootstrapTowardWallet, FromLocalUI, GameStart, GameType, PacketSender,
-    PeerEnv, PeerMessage, PotatoHandler, PotatoHandlerInit, ToLocalUI, WalletSpendInterface,
+    BootstrapTowardGame, BootstrapTowardWallet, FromLocalUI, GameStart, GameType,
+    GoingOnChainReason, HandshakeStepWithSpend, LivenessPolicy, PacketSender, PeerEnv, PeerMessage,
+    PeerWait, PotatoHandler, PotatoHandlerInit, ToLocalUI, WalletSpendInterface, WATCH_PARENT,
 };
 
 use crate::simulator::Simulator;
@@ -177,6 +181,11 @@ impl BootstrapTowardWallet for SimulatedPeer {
         debug!("received channel transaction completion");
         todo!();
     }
+
+    fn channel_top_up(&mut self, _bundle: &SpendBundle) -> Result<(), Error> {
+        debug!("channel top up");
+        todo!();
+    }
 }
 
 impl ToLocalUI for SimulatedPeer {
@@ -213,7 +222,7 @@ impl ToLocalUI for SimulatedPeer {
     fn shutdown_complete(&mut self, _reward_coin_string: &CoinString) -> Result<(), Error> {
         todo!();
     }
-    fn going_on_chain(&mut self) -> Result<(), Error> {
+    fn going_on_chain(&mut self, _reason: &GoingOnChainReason) -> Result<(), Error> {
         todo!();
     }
 }
@@ -486,6 +495,7 @@ fn run_calpoker_test_with_action_list(allocator: &mut AllocEncoder, moves: &[Gam
             channel_timeout: Timeout::new(1000),
             unroll_timeout: Timeout::new(5),
             reward_puzzle_hash: reward_puzzle_hash1.clone(),
+            liveness: LivenessPolicy::default(),
         })
     };
 
@@ -536,7 +546,7 @@ fn run_calpoker_test_with_action_list(allocator: &mut AllocEncoder, moves: &[Gam
         )
         .expect("should work");
     peers[0]
-        .register_coin(&parent_coin_0, &Timeout::new(100), Some("parent"))
+        .register_coin(&parent_coin_0, &Timeout::new(100), Some(WATCH_PARENT))
         .expect("should work");
 
     {
@@ -652,8 +662,15 @@ fn test_peer_in_sim() {
 struct LocalTestUIReceiver {
     shutdown_complete: bool,
     game_finished: Option<Amount>,
+    game_cancelled: bool,
+    opponent_slashed: Option<Amount>,
     opponent_moved: bool,
     go_on_chain: bool,
+    going_on_chain_reason: Option<GoingOnChainReason>,
+    topped_up: Option<(Amount, Amount)>,
+    withdrawn: Option<(Amount, Amount)>,
+    // Every transaction this player pushed, in order.
+    transactions: Vec<SpendBundle>,
 }
 
 impl ToLocalUI for LocalTestUIReceiver {
@@ -682,7 +699,13 @@ impl ToLocalUI for LocalTestUIReceiver {
     }
 
     fn game_cancelled(&mut self, _id: &GameID) -> Result<(), Error> {
-        todo!();
+        self.game_cancelled = true;
+        Ok(())
+    }
+
+    fn opponent_slashed(&mut self, _id: &GameID, my_reward: Amount) -> Result<(), Error> {
+        self.opponent_slashed = Some(my_reward);
+        Ok(())
     }
 
     fn shutdown_complete(&mut self, _reward_coin_string: &CoinString) -> Result<(), Error> {
@@ -690,8 +713,27 @@ impl ToLocalUI for LocalTestUIReceiver {
         Ok(())
     }
 
-    fn going_on_chain(&mut self) -> Result<(), Error> {
+    fn going_on_chain(&mut self, reason: &GoingOnChainReason) -> Result<(), Error> {
         self.go_on_chain = true;
+        self.going_on_chain_reason = Some(reason.clone());
+        Ok(())
+    }
+
+    fn channel_topped_up(
+        &mut self,
+        my_balance: &Amount,
+        their_balance: &Amount,
+    ) -> Result<(), Error> {
+        self.topped_up = Some((my_balance.clone(), their_balance.clone()));
+        Ok(())
+    }
+
+    fn channel_withdrawn(
+        &mut self,
+        my_balance: &Amount,
+        their_balance: &Amount,
+    ) -> Result<(), Error> {
+        self.withdrawn = Some((my_balance.clone(), their_balance.clone()));
         Ok(())
     }
 }
@@ -702,7 +744,30 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
     allocator: &mut AllocEncoder,
     moves: &[GameAction],
     pred: GameRunEarlySuccessPredicate,
-) {
+) -> [LocalTestUIReceiver; 2] {
+    // Only give up on a quiet peer when the test makes one go quiet.
+    let liveness = if moves.iter().any(|m| matches!(m, GameAction::GoSilent(_))) {
+        short_liveness()
+    } else {
+        LivenessPolicy::default()
+    };
+    run_calpoker_container_with_liveness(allocator, moves, pred, liveness)
+}
+
+// Liveness timeouts short enough for a test to run into.
+fn short_liveness() -> LivenessPolicy {
+    LivenessPolicy {
+        potato_timeout: Some(Timeout::new(5)),
+        move_timeout: Some(Timeout::new(5)),
+    }
+}
+
+fn run_calpoker_container_with_liveness(
+    allocator: &mut AllocEncoder,
+    moves: &[GameAction],
+    pred: GameRunEarlySuccessPredicate,
+    liveness: LivenessPolicy,
+) -> [LocalTestUIReceiver; 2] {
     // Coinset adapter for each side.
     let mut rng = ChaCha8Rng::from_seed([0; 32]);
     let game_type_map = poker_collection(allocator);
@@ -764,9 +829,11 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
             identity: &identities[0],
             my_contribution: Amount::new(100),
             their_contribution: Amount::new(100),
-            channel_timeout: Timeout::new(100),
+            channel_timeout: Timeout::new(20),
             unroll_timeout: Timeout::new(5),
             reward_puzzle_hash: id1.puzzle_hash.clone(),
+            liveness: liveness.clone(),
+            validate_outbound: true,
         },
     );
     let cradle2 = SynchronousGameCradle::new(
@@ -777,18 +844,26 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
             identity: &identities[1],
             my_contribution: Amount::new(100),
             their_contribution: Amount::new(100),
-            channel_timeout: Timeout::new(100),
+            channel_timeout: Timeout::new(20),
             unroll_timeout: Timeout::new(5),
             reward_puzzle_hash: id2.puzzle_hash.clone(),
+            liveness,
+            validate_outbound: true,
         },
     );
     let mut cradles = [cradle1, cradle2];
     let mut game_ids = Vec::default();
     let mut handshake_done = false;
     let mut can_move = false;
+    let mut wait_for_on_chain = false;
 
     let mut current_move = moves.iter();
     let mut last_move = 0;
+    // Channel spends each player held when making each of their moves.
+    let mut channel_spends: [Vec<HandshakeStepWithSpend>; 2] = [Vec::new(), Vec::new()];
+    let mut lose_messages = [false, false];
+    let mut silent = [false, false];
+    let mut ignore_shutdown = [false, false];
     let mut num_steps = 0;
 
     // Give coins to the cradles.
@@ -799,8 +874,16 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
         .opening_coin(allocator, &mut rng, parent_coin_1)
         .expect("should work");
 
-    // XXX Move on to shutdown complete.
-    while !local_uis.iter().all(|l| l.game_finished.is_some()) {
+    let wants_shutdown = moves
+        .iter()
+        .any(|m| matches!(m, GameAction::Shutdown(_, _)));
+    while !local_uis.iter().all(|l| {
+        if wants_shutdown {
+            l.shutdown_complete
+        } else {
+            l.game_finished.is_some() || l.opponent_slashed.is_some()
+        }
+    }) {
         num_steps += 1;
 
         assert!(num_steps < 100);
@@ -816,7 +899,7 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
         if let Some(p) = &pred {
             if p(&cradles) {
                 // Success.
-                return;
+                return local_uis;
             }
         }
 
@@ -860,17 +943,39 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
                     }
                 }
 
-                for tx in result.outbound_transactions.iter() {
-                    debug!("PROCESS TX {tx:?}");
-                    let included_result = simulator
-                        .push_tx(allocator, &tx.spends)
-                        .expect("should work");
-                    debug!("included_result {included_result:?}");
-                    assert_eq!(included_result.code, 1);
+                if ignore_shutdown[i] && !result.outbound_transactions.is_empty() {
+                    // The only spend here is the one finishing the shutdown.
+                    debug!("{i} ignores the shutdown");
+                    silent[i] = true;
+                } else {
+                    for tx in result.outbound_transactions.iter() {
+                        debug!("PROCESS TX {tx:?}");
+                        let included_result = simulator
+                            .push_tx(allocator, &tx.spends)
+                            .expect("should work");
+                        debug!("included_result {included_result:?}");
+                        assert_eq!(included_result.code, 1);
+                        local_uis[i].transactions.push(tx.clone());
+                    }
                 }
 
-                for msg in result.outbound_messages.iter() {
-                    cradles[i ^ 1].deliver_message(msg).expect("should work");
+                if silent[i] {
+                    debug!(
+                        "silent: dropping {} messages",
+                        result.outbound_messages.len()
+                    );
+                } else if lose_messages[i] && !result.outbound_messages.is_empty() {
+                    // The connection drops, taking these messages with it.
+                    // Both sides reconnect and resume.
+                    debug!("losing {} messages", result.outbound_messages.len());
+                    lose_messages[i] = false;
+                    for cradle in cradles.iter_mut() {
+                        cradle.resume().expect("should work");
+                    }
+                } else {
+                    for msg in result.outbound_messages.iter() {
+                        cradles[i ^ 1].deliver_message(msg).expect("should work");
+                    }
                 }
 
                 if !result.continue_on {
@@ -916,7 +1021,10 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
                 .expect("should run");
 
             can_move = true;
-        } else if can_move || local_uis.iter().any(|l| l.opponent_moved) {
+        } else if wait_for_on_chain && !cradles.iter().all(|c| c.is_on_chain()) {
+            // Hold further moves until both sides are playing on chain.
+        } else if wait_for_on_chain || can_move || local_uis.iter().any(|l| l.opponent_moved) {
+            wait_for_on_chain = false;
             can_move = false;
             assert!(!game_ids.is_empty());
 
@@ -929,6 +1037,7 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
                 match ga {
                     GameAction::Move(who, readable, _) => {
                         last_move = *who;
+                        channel_spends[*who].extend(cradles[*who].channel_spend());
                         debug!("make move");
                         let readable_program =
                             Program::from_nodeptr(allocator, *readable).expect("should convert");
@@ -944,9 +1053,99 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
                             )
                             .expect("should work");
                     }
-                    GameAction::GoOnChain(_who) => {
+                    GameAction::CheatMove(who, readable, mover_share) => {
+                        last_move = *who;
+                        channel_spends[*who].extend(cradles[*who].channel_spend());
+                        debug!("make cheating move");
+                        let readable_program =
+                            Program::from_nodeptr(allocator, *readable).expect("should convert");
+                        let encoded_readable_move = readable_program.bytes();
+                        let entropy = rng.gen();
+                        cradles[*who]
+                            .cheat_on_next_move(&game_ids[0], mover_share.clone())
+                            .expect("should work");
+                        cradles[*who]
+                            .make_move(
+                                allocator,
+                                &mut rng,
+                                &game_ids[0],
+                                encoded_readable_move.to_vec(),
+                                entropy,
+                            )
+                            .expect("should work");
+                    }
+                    GameAction::GoOnChain(who) => {
                         debug!("go on chain");
-                        todo!();
+                        local_uis[*who].go_on_chain = true;
+                        wait_for_on_chain = true;
+                    }
+                    GameAction::Shutdown(who, _) => {
+                        // The cradle builds conditions paying each side its
+                        // balance.
+                        debug!("shut down");
+                        cradles[*who]
+                            .shut_down(allocator, &mut rng)
+                            .expect("should work");
+                    }
+                    GameAction::GoSilent(who) => {
+                        debug!("{who} goes silent");
+                        silent[*who] = true;
+                        can_move = true;
+                    }
+                    GameAction::IgnoreShutdown(who) => {
+                        debug!("{who} will ignore a shutdown");
+                        ignore_shutdown[*who] = true;
+                        can_move = true;
+                    }
+                    GameAction::LoseMessage(who) => {
+                        debug!("lose next message from {who}");
+                        lose_messages[*who] = true;
+                        can_move = true;
+                    }
+                    GameAction::TopUp(who, amount) => {
+                        debug!("{who} tops up {amount:?}");
+                        let coins = simulator
+                            .get_my_coins(&identities[*who].puzzle_hash)
+                            .expect("should work");
+                        let (top_up_coin, _rest) = simulator
+                            .transfer_coin_amount(
+                                allocator,
+                                &identities[*who],
+                                &identities[*who],
+                                &coins[0],
+                                amount.clone(),
+                            )
+                            .expect("should work");
+                        cradles[*who]
+                            .top_up(allocator, &mut rng, top_up_coin)
+                            .expect("should work");
+                        can_move = true;
+                    }
+                    GameAction::Withdraw(who, amount) => {
+                        debug!("{who} withdraws {amount:?}");
+                        cradles[*who]
+                            .withdraw(allocator, &mut rng, amount)
+                            .expect("should work");
+                        can_move = true;
+                    }
+                    GameAction::Restore(who) => {
+                        debug!("{who} restores from a snapshot");
+                        let snapshot = cradles[*who]
+                            .to_snapshot_bytes(allocator)
+                            .expect("should snapshot");
+                        cradles[*who] =
+                            SynchronousGameCradle::from_snapshot_bytes(allocator, &snapshot)
+                                .expect("should restore");
+                        can_move = true;
+                    }
+                    GameAction::GoOnChainStale(who, moves_back) => {
+                        debug!("go on chain with a stale state");
+                        let spends = &channel_spends[*who];
+                        let spend = spends[spends.len() - moves_back].clone();
+                        cradles[*who]
+                            .go_on_chain_with_stale_state(allocator, &mut rng, spend)
+                            .expect("should work");
+                        wait_for_on_chain = true;
                     }
                     GameAction::FakeMove(who, readable, move_data) => {
                         last_move = *who;
@@ -993,10 +1192,27 @@ fn run_calpoker_container_with_action_list_with_success_predicate(
             }
         }
     }
+
+    local_uis
 }
 
-fn run_calpoker_container_with_action_list(allocator: &mut AllocEncoder, moves: &[GameAction]) {
-    run_calpoker_container_with_action_list_with_success_predicate(allocator, moves, None);
+// The state number an unroll spend announces in its first rem.
+fn rem_state_number(allocator: &mut AllocEncoder, spend: &CoinSpend) -> Option<usize> {
+    let (_, conditions) = spend.conditions(allocator).expect("should run");
+    conditions.iter().find_map(|c| {
+        if let CoinCondition::Rem(data) = c {
+            data.first().and_then(|atom| usize_from_atom(atom))
+        } else {
+            None
+        }
+    })
+}
+
+fn run_calpoker_container_with_action_list(
+    allocator: &mut AllocEncoder,
+    moves: &[GameAction],
+) -> [LocalTestUIReceiver; 2] {
+    run_calpoker_container_with_action_list_with_success_predicate(allocator, moves, None)
 }
 
 #[test]
@@ -1008,6 +1224,263 @@ fn sim_test_with_peer_container() {
     run_calpoker_container_with_action_list(&mut allocator, &moves);
 }
 
+#[test]
+fn sim_test_with_peer_container_liveness_on() {
+    let mut allocator = AllocEncoder::new();
+
+    // Peers that keep talking never trip the liveness timeouts.
+    let moves = test_moves_1(&mut allocator);
+    let local_uis =
+        run_calpoker_container_with_liveness(&mut allocator, &moves, None, short_liveness());
+    assert!(local_uis.iter().all(|l| l.game_finished.is_some()));
+    assert!(local_uis.iter().all(|l| l.going_on_chain_reason.is_none()));
+}
+
+#[test]
+fn sim_test_with_peer_container_on_chain_moves() {
+    let mut allocator = AllocEncoder::new();
+
+    // Alice and bob each move, then alice unrolls and the rest is played on chain.
+    let mut moves = test_moves_1(&mut allocator);
+    moves.insert(2, GameAction::GoOnChain(0));
+    run_calpoker_container_with_action_list(&mut allocator, &moves);
+}
+
+#[test]
+fn sim_test_with_peer_container_slash_cheating_peer() {
+    let mut allocator = AllocEncoder::new();
+
+    // Play on chain and have alice finish by declaring a split that no pair
+    // of hands produces.  Bob's driver spots it and bob slashes the game coin.
+    let mut moves = test_moves_1(&mut allocator);
+    moves.insert(2, GameAction::GoOnChain(0));
+    if let GameAction::Move(player, readable, _) = moves[5].clone() {
+        moves[5] = GameAction::CheatMove(player, readable, Amount::new(199));
+    } else {
+        panic!("no final move to replace");
+    }
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+    assert_eq!(local_uis[1].opponent_slashed, Some(Amount::new(200)));
+    assert_eq!(local_uis[0].game_finished, Some(Amount::default()));
+}
+
+#[test]
+fn sim_test_with_peer_container_clean_shutdown() {
+    let mut allocator = AllocEncoder::new();
+
+    // Play the game out, then alice shuts down.  The game is still live so it
+    // gets accepted before the channel coin is spent to the reward coins.
+    let mut moves = test_moves_1(&mut allocator).to_vec();
+    let conditions = ().to_clvm(&mut allocator).expect("should work");
+    moves.push(GameAction::Shutdown(0, conditions));
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+    assert!(local_uis.iter().all(|l| l.game_finished.is_some()));
+    assert!(local_uis.iter().all(|l| l.shutdown_complete));
+}
+
+#[test]
+fn sim_test_with_peer_container_shutdown_ignored() {
+    let mut allocator = AllocEncoder::new();
+
+    // Alice shuts down but bob never spends the channel coin.  Once the
+    // channel timeout passes alice unrolls instead.
+    let mut moves = test_moves_1(&mut allocator).to_vec();
+    let conditions = ().to_clvm(&mut allocator).expect("should work");
+    moves.push(GameAction::IgnoreShutdown(1));
+    moves.push(GameAction::Shutdown(0, conditions));
+    let local_uis = run_calpoker_container_with_action_list_with_success_predicate(
+        &mut allocator,
+        &moves,
+        Some(&|cradles| cradles[0].is_on_chain()),
+    );
+    assert!(matches!(
+        local_uis[0].going_on_chain_reason,
+        Some(GoingOnChainReason::ShutdownTimeout)
+    ));
+    assert!(!local_uis[0].shutdown_complete);
+}
+
+#[test]
+fn sim_test_with_peer_container_counter_stale_unroll() {
+    let mut allocator = AllocEncoder::new();
+
+    // Bob moves twice, then unrolls with the state he held before his first
+    // move.  Alice holds the state from Bob's last move, supercedes the stale
+    // unroll with it and the game finishes on chain from there.
+    let mut moves = test_moves_1(&mut allocator);
+    moves.insert(4, GameAction::GoOnChainStale(1, 2));
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+
+    // Bob's unroll spends the channel coin and alice's spends the unroll coin
+    // it made, each announcing its state number.
+    let (stale_coin, stale_state) = local_uis[1]
+        .transactions
+        .iter()
+        .flat_map(|tx| tx.spends.iter())
+        .find_map(|spend| {
+            rem_state_number(&mut allocator, spend).map(|n| (spend.coin.to_coin_id(), n))
+        })
+        .expect("bob should have unrolled");
+    let counter_state = local_uis[0]
+        .transactions
+        .iter()
+        .flat_map(|tx| tx.spends.iter())
+        .filter(
+            |spend| matches!(spend.coin.to_parts(), Some((parent, _, _)) if parent == stale_coin),
+        )
+        .find_map(|spend| rem_state_number(&mut allocator, spend))
+        .expect("alice should have superceded the unroll");
+    assert!(
+        counter_state > stale_state,
+        "alice unrolled to state {counter_state}, bob's was {stale_state}"
+    );
+
+    // The cards and picks are the same as when the game is played out off
+    // chain, so the split is too.
+    let baseline_moves = test_moves_1(&mut allocator);
+    let baseline = run_calpoker_container_with_action_list(&mut allocator, &baseline_moves);
+    for (played, expected) in local_uis.iter().zip(baseline.iter()) {
+        assert!(expected.game_finished.is_some());
+        assert_eq!(played.game_finished, expected.game_finished);
+    }
+    let total = local_uis
+        .iter()
+        .filter_map(|l| l.game_finished.clone())
+        .fold(Amount::default(), |a, b| a + b);
+    assert_eq!(total, Amount::new(200));
+}
+
+#[test]
+fn sim_test_with_peer_container_lost_message_resume() {
+    let mut allocator = AllocEncoder::new();
+
+    // Alice's picks never reach bob.  After both sides resume, alice resends
+    // the move bob didn't acknowledge and the game finishes normally.
+    let mut moves = test_moves_1(&mut allocator).to_vec();
+    moves.insert(2, GameAction::LoseMessage(0));
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+    assert!(local_uis.iter().all(|l| l.game_finished.is_some()));
+}
+
+#[test]
+fn sim_test_with_peer_container_restore_mid_game() {
+    let mut allocator = AllocEncoder::new();
+
+    // Bob's cradle is rebuilt from a snapshot after the first two moves and
+    // plays the game out.
+    let mut moves = test_moves_1(&mut allocator).to_vec();
+    moves.insert(2, GameAction::Restore(1));
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+    assert!(local_uis.iter().all(|l| l.game_finished.is_some()));
+}
+
+#[test]
+fn sim_test_with_peer_container_top_up() {
+    let mut allocator = AllocEncoder::new();
+
+    // Alice adds 50 to the channel once both have moved.  The game carries
+    // over to the new channel coin and finishes off chain.
+    let mut moves = test_moves_1(&mut allocator).to_vec();
+    moves.insert(2, GameAction::TopUp(0, Amount::new(50)));
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+    assert_eq!(
+        local_uis[0].topped_up,
+        Some((Amount::new(150), Amount::new(100)))
+    );
+    assert_eq!(
+        local_uis[1].topped_up,
+        Some((Amount::new(100), Amount::new(150)))
+    );
+    assert!(local_uis.iter().all(|l| l.game_finished.is_some()));
+}
+
+#[test]
+fn sim_test_with_peer_container_withdraw() {
+    let mut allocator = AllocEncoder::new();
+
+    // Everything bob put in the channel is in the game, so he tops up by 50
+    // and then takes 30 back to his wallet.  The game carries over both
+    // splices and finishes off chain.
+    let mut moves = test_moves_1(&mut allocator).to_vec();
+    moves.insert(2, GameAction::TopUp(1, Amount::new(50)));
+    moves.insert(3, GameAction::Withdraw(1, Amount::new(30)));
+    let local_uis = run_calpoker_container_with_action_list(&mut allocator, &moves);
+    assert_eq!(
+        local_uis[0].withdrawn,
+        Some((Amount::new(100), Amount::new(120)))
+    );
+    assert_eq!(
+        local_uis[1].withdrawn,
+        Some((Amount::new(120), Amount::new(100)))
+    );
+    assert!(local_uis.iter().all(|l| l.game_finished.is_some()));
+}
+
+#[test]
+fn sim_test_with_peer_container_splice_peer_stalls() {
+    let mut allocator = AllocEncoder::new();
+
+    // Bob starts a splice but alice never countersigns.  Bob gives up on it
+    // and unrolls the channel as it was.
+    let moves = [
+        GameAction::TopUp(1, Amount::new(50)),
+        GameAction::GoSilent(0),
+    ];
+    let local_uis = run_calpoker_container_with_action_list_with_success_predicate(
+        &mut allocator,
+        &moves,
+        Some(&|cradles| cradles[1].is_on_chain()),
+    );
+    assert!(matches!(
+        local_uis[1].going_on_chain_reason,
+        Some(GoingOnChainReason::PeerInactive(PeerWait::Splice, _))
+    ));
+    assert!(local_uis[1].topped_up.is_none());
+}
+
+#[test]
+fn sim_test_with_peer_container_withdraw_peer_stalls() {
+    let mut allocator = AllocEncoder::new();
+
+    // As above for a withdrawal, with no liveness policy at all: the stalled
+    // splice still unrolls once the channel timeout passes.
+    let moves = [
+        GameAction::Withdraw(1, Amount::new(30)),
+        GameAction::GoSilent(0),
+    ];
+    let local_uis = run_calpoker_container_with_liveness(
+        &mut allocator,
+        &moves,
+        Some(&|cradles| cradles[1].is_on_chain()),
+        LivenessPolicy::default(),
+    );
+    assert!(matches!(
+        local_uis[1].going_on_chain_reason,
+        Some(GoingOnChainReason::PeerInactive(PeerWait::Splice, _))
+    ));
+    assert!(local_uis[1].withdrawn.is_none());
+}
+
+#[test]
+fn sim_test_with_peer_container_silent_peer() {
+    let mut allocator = AllocEncoder::new();
+
+    // Bob stops talking once the game has started.  Alice is left waiting on
+    // him, runs out her liveness timeout and takes the channel on chain by
+    // herself.
+    let moves = test_moves_1(&mut allocator);
+    let moves = [GameAction::GoSilent(1), moves[0].clone(), moves[1].clone()];
+    let local_uis = run_calpoker_container_with_action_list_with_success_predicate(
+        &mut allocator,
+        &moves,
+        Some(&|cradles| cradles[0].is_on_chain()),
+    );
+    assert!(matches!(
+        local_uis[0].going_on_chain_reason,
+        Some(GoingOnChainReason::PeerInactive(_, _))
+    ));
+}
+
 #[test]
 fn sim_test_with_peer_container_piss_off_peer() {
     let mut allocator = AllocEncoder::new();
@@ -1024,3 +1497,458 @@ fn sim_test_with_peer_container_piss_off_peer() {
         Some(&|cradles| cradles[0].is_on_chain() && cradles[1].is_on_chain()),
     );
 }
+
+// One block feed and message router for both sides, which each hold a manager
+// with a channel per opponent.  Returns the coins each manager asked to watch.
+fn run_managers_until<F>(
+    allocator: &mut AllocEncoder,
+    rng: &mut ChaCha8Rng,
+    simulator: &Simulator,
+    managers: &mut [ChannelManager<LocalTestUIReceiver>; 2],
+    done: F,
+) -> [HashSet<CoinString>; 2]
+where
+    F: Fn(&[ChannelManager<LocalTestUIReceiver>; 2]) -> bool,
+{
+    let mut coins_to_watch: [HashSet<CoinString>; 2] = Default::default();
+    let neutral_pk: PrivateKey = rng.gen();
+    let neutral_identity = ChiaIdentity::new(allocator, neutral_pk).expect("should work");
+    let mut coinset_adapter = FullCoinSetAdapter::default();
+    let mut num_steps = 0;
+
+    while !done(managers) {
+        num_steps += 1;
+        assert!(num_steps < 100);
+
+        simulator.farm_block(&neutral_identity.puzzle_hash);
+        let current_height = simulator.get_current_height();
+        let current_coins = simulator.get_all_coins().expect("should work");
+        let watch_report = coinset_adapter
+            .make_report_from_coin_set_update(current_height as u64, &current_coins)
+            .expect("should work");
+
+        for i in 0..=1 {
+            let errors = managers[i].new_block(allocator, rng, current_height, &watch_report);
+            assert!(errors.is_empty(), "{errors:?}");
+
+            let result = managers[i].idle(allocator, rng);
+            assert!(result.errors.is_empty(), "{:?}", result.errors);
+            coins_to_watch[i].extend(result.coins_to_watch.iter().cloned());
+
+            for coin in result.coin_solution_requests.iter() {
+                let ps_res = simulator
+                    .get_puzzle_and_solution(coin)
+                    .expect("should work");
+                let errors = managers[i].report_puzzle_and_solution(
+                    allocator,
+                    rng,
+                    coin,
+                    ps_res.as_ref().map(|ps| (&ps.0, &ps.1)),
+                );
+                assert!(errors.is_empty(), "{errors:?}");
+            }
+
+            for tx in result.outbound_transactions.iter() {
+                let included_result = simulator
+                    .push_tx(allocator, &tx.spends)
+                    .expect("should work");
+                assert_eq!(included_result.code, 1);
+            }
+
+            for (peer, msg) in result.outbound_messages.iter() {
+                managers[i ^ 1]
+                    .deliver_message(peer, msg)
+                    .expect("should work");
+            }
+        }
+    }
+
+    coins_to_watch
+}
+
+#[test]
+fn sim_test_channel_manager_two_opponents() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let game_type_map = poker_collection(&mut allocator);
+    let simulator = Simulator::default();
+
+    // The service plays opponents a and b, each over its own channel.  The
+    // opponents' channels share a manager too, keyed by the same names.
+    let mut identities = Vec::new();
+    for _ in 0..3 {
+        let pk: PrivateKey = rng.gen();
+        let identity = ChiaIdentity::new(&mut allocator, pk).expect("should work");
+        simulator.farm_block(&identity.puzzle_hash);
+        simulator.farm_block(&identity.puzzle_hash);
+        identities.push(identity);
+    }
+
+    let peers = [PeerId("a".to_string()), PeerId("b".to_string())];
+    let mut managers: [ChannelManager<LocalTestUIReceiver>; 2] = Default::default();
+    let service_coins = simulator
+        .get_my_coins(&identities[0].puzzle_hash)
+        .expect("should work");
+    for (i, peer) in peers.iter().enumerate() {
+        let opponent = &identities[i + 1];
+        let opponent_coins = simulator
+            .get_my_coins(&opponent.puzzle_hash)
+            .expect("should work");
+        let (service_coin, _) = simulator
+            .transfer_coin_amount(
+                &mut allocator,
+                &identities[0],
+                &identities[0],
+                &service_coins[i],
+                Amount::new(100),
+            )
+            .expect("should work");
+        let (opponent_coin, _) = simulator
+            .transfer_coin_amount(
+                &mut allocator,
+                opponent,
+                opponent,
+                &opponent_coins[0],
+                Amount::new(100),
+            )
+            .expect("should work");
+
+        for (side, (identity, coin)) in [(&identities[0], service_coin), (opponent, opponent_coin)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut cradle = SynchronousGameCradle::new(
+                &mut rng,
+                SynchronousGameCradleConfig {
+                    game_types: game_type_map.clone(),
+                    have_potato: side == 0,
+                    identity,
+                    my_contribution: Amount::new(100),
+                    their_contribution: Amount::new(100),
+                    channel_timeout: Timeout::new(100),
+                    unroll_timeout: Timeout::new(5),
+                    reward_puzzle_hash: identity.puzzle_hash.clone(),
+                    liveness: LivenessPolicy::default(),
+                    validate_outbound: true,
+                },
+            );
+            cradle
+                .opening_coin(&mut allocator, &mut rng, coin)
+                .expect("should work");
+            managers[side]
+                .add_channel(peer.clone(), cradle, LocalTestUIReceiver::default())
+                .expect("should add");
+        }
+    }
+
+    let coins_to_watch = run_managers_until(
+        &mut allocator,
+        &mut rng,
+        &simulator,
+        &mut managers,
+        |managers| {
+            managers
+                .iter()
+                .flat_map(|m| m.channels())
+                .all(|c| c.handshake_finished)
+        },
+    );
+    assert!(!managers[0].watched_coins().is_empty());
+    // Every coin being watched was handed out by idle for the block feed.
+    for (manager, forwarded) in managers.iter().zip(coins_to_watch.iter()) {
+        assert!(manager.watched_coins().is_subset(forwarded));
+    }
+
+    // Closing one channel leaves the other running.
+    managers[0]
+        .close_channel(&mut allocator, &mut rng, &peers[0])
+        .expect("should close");
+    run_managers_until(
+        &mut allocator,
+        &mut rng,
+        &simulator,
+        &mut managers,
+        |managers| {
+            managers
+                .iter()
+                .all(|m| m.local_ui(&peers[0]).expect("have a").shutdown_complete)
+        },
+    );
+    for manager in managers.iter_mut() {
+        assert!(
+            !manager
+                .local_ui(&peers[1])
+                .expect("have b")
+                .shutdown_complete
+        );
+        manager.remove_channel(&peers[0]).expect("should remove");
+        let channels = manager.channels();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].peer, peers[1]);
+        assert!(channels[0].handshake_finished && !channels[0].is_on_chain);
+    }
+}
+
+// Two funded cradles, the first holding the potato.
+fn new_cradle_pair(
+    allocator: &mut AllocEncoder,
+    rng: &mut ChaCha8Rng,
+    simulator: &Simulator,
+) -> Vec<SynchronousGameCradle> {
+    let game_type_map = poker_collection(allocator);
+    let mut cradles = Vec::new();
+    for side in 0..=1 {
+        let pk: PrivateKey = rng.gen();
+        let identity = ChiaIdentity::new(allocator, pk).expect("should work");
+        simulator.farm_block(&identity.puzzle_hash);
+        let coins = simulator
+            .get_my_coins(&identity.puzzle_hash)
+            .expect("should work");
+        let (coin, _) = simulator
+            .transfer_coin_amount(allocator, &identity, &identity, &coins[0], Amount::new(100))
+            .expect("should work");
+        let mut cradle = SynchronousGameCradle::new(
+            rng,
+            SynchronousGameCradleConfig {
+                game_types: game_type_map.clone(),
+                have_potato: side == 0,
+                identity: &identity,
+                my_contribution: Amount::new(100),
+                their_contribution: Amount::new(100),
+                channel_timeout: Timeout::new(100),
+                unroll_timeout: Timeout::new(5),
+                reward_puzzle_hash: identity.puzzle_hash.clone(),
+                liveness: LivenessPolicy::default(),
+                validate_outbound: true,
+            },
+        );
+        cradle
+            .opening_coin(allocator, rng, coin)
+            .expect("should work");
+        cradles.push(cradle);
+    }
+    cradles
+}
+
+// Farm a block, report it to both cradles and act on what idle_events gives
+// back, returning the events.  Transactions are only pushed if asked.
+fn run_idle_events_block(
+    allocator: &mut AllocEncoder,
+    rng: &mut ChaCha8Rng,
+    simulator: &Simulator,
+    coinset_adapter: &mut FullCoinSetAdapter,
+    cradles: &mut [SynchronousGameCradle],
+    push_transactions: bool,
+) -> [Vec<CradleEvent>; 2] {
+    let neutral_pk: PrivateKey = rng.gen();
+    let neutral_identity = ChiaIdentity::new(allocator, neutral_pk).expect("should work");
+    simulator.farm_block(&neutral_identity.puzzle_hash);
+    let current_height = simulator.get_current_height();
+    let current_coins = simulator.get_all_coins().expect("should work");
+    let watch_report = coinset_adapter
+        .make_report_for_block(
+            current_height as u64,
+            &simulator.get_current_block(),
+            &current_coins,
+        )
+        .expect("should work");
+
+    let mut seen: [Vec<CradleEvent>; 2] = Default::default();
+    for i in 0..=1 {
+        cradles[i]
+            .new_block(allocator, rng, current_height, &watch_report)
+            .expect("should work");
+        for event in cradles[i].idle_events(allocator, rng) {
+            match &event {
+                CradleEvent::OutboundMessage(msg) => {
+                    cradles[i ^ 1].deliver_message(msg).expect("should work");
+                }
+                CradleEvent::Transaction(tx) if push_transactions => {
+                    let included_result = simulator
+                        .push_tx(allocator, &tx.spends)
+                        .expect("should work");
+                    assert_eq!(included_result.code, 1);
+                }
+                CradleEvent::PuzzleSolutionRequest(coin) => {
+                    let ps_res = simulator
+                        .get_puzzle_and_solution(coin)
+                        .expect("should work");
+                    cradles[i]
+                        .report_puzzle_and_solution(
+                            allocator,
+                            rng,
+                            coin,
+                            ps_res.as_ref().map(|ps| (&ps.0, &ps.1)),
+                        )
+                        .expect("should work");
+                }
+                CradleEvent::Error(e) => panic!("unexpected error {e:?}"),
+                _ => {}
+            }
+            seen[i].push(event);
+        }
+    }
+    seen
+}
+
+#[test]
+fn sim_test_with_peer_container_idle_events() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let simulator = Simulator::default();
+    let mut cradles = new_cradle_pair(&mut allocator, &mut rng, &simulator);
+
+    // Everything comes through idle_events; no ToLocalUI is involved.
+    let mut seen: [Vec<CradleEvent>; 2] = Default::default();
+    let mut coinset_adapter = FullCoinSetAdapter::default();
+    let mut shutting_down = false;
+    let mut num_steps = 0;
+    while !seen.iter().all(|events| {
+        events
+            .iter()
+            .any(|e| matches!(e, CradleEvent::ShutdownComplete(_)))
+    }) {
+        num_steps += 1;
+        assert!(num_steps < 100);
+
+        if !shutting_down && cradles.iter().all(|c| c.handshake_finished()) {
+            shutting_down = true;
+            cradles[0]
+                .shut_down(&mut allocator, &mut rng)
+                .expect("should work");
+        }
+
+        let events = run_idle_events_block(
+            &mut allocator,
+            &mut rng,
+            &simulator,
+            &mut coinset_adapter,
+            &mut cradles,
+            true,
+        );
+        for (seen, events) in seen.iter_mut().zip(events) {
+            seen.extend(events);
+        }
+    }
+
+    for events in seen.iter() {
+        let position = |f: &dyn Fn(&CradleEvent) -> bool| {
+            let matching: Vec<usize> = (0..events.len()).filter(|i| f(&events[*i])).collect();
+            assert_eq!(matching.len(), 1, "{events:?}");
+            matching[0]
+        };
+        let created = position(&|e| matches!(e, CradleEvent::ChannelCreated(_)));
+        let finished = position(&|e| matches!(e, CradleEvent::HandshakeFinished));
+        let shutdown = position(&|e| matches!(e, CradleEvent::ShutdownComplete(_)));
+        assert!(created <= finished && finished < shutdown);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, CradleEvent::WatchCoin(_))));
+    }
+}
+
+#[test]
+fn sim_test_with_peer_container_idle_events_keep_order() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let simulator = Simulator::default();
+    let mut cradles = new_cradle_pair(&mut allocator, &mut rng, &simulator);
+
+    // A last move, the game finishing and a move in the next game, all in
+    // one pass.
+    let first = GameID::new(vec![1]);
+    let second = GameID::new(vec![2]);
+    let ui = cradles[0].queued_local_ui();
+    ui.self_move(&first, b"last").expect("should work");
+    ui.game_finished(&first, Amount::new(100))
+        .expect("should work");
+    ui.self_move(&second, b"first").expect("should work");
+
+    let events: Vec<CradleEvent> = cradles[0]
+        .idle_events(&mut allocator, &mut rng)
+        .into_iter()
+        .filter(|e| {
+            matches!(
+                e,
+                CradleEvent::SelfMove(_, _) | CradleEvent::GameFinished(_, _)
+            )
+        })
+        .collect();
+    assert!(
+        matches!(
+            &events[..],
+            [
+                CradleEvent::SelfMove(a, _),
+                CradleEvent::GameFinished(b, _),
+                CradleEvent::SelfMove(c, _),
+            ] if *a == first && *b == first && *c == second
+        ),
+        "{events:?}"
+    );
+}
+
+#[test]
+fn sim_test_with_peer_container_reorg_undoes_channel_spend() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let simulator = Simulator::default();
+    let mut cradles = new_cradle_pair(&mut allocator, &mut rng, &simulator);
+    let mut coinset_adapter = FullCoinSetAdapter::default();
+
+    let mut num_steps = 0;
+    while !cradles.iter().all(|c| c.handshake_finished()) {
+        num_steps += 1;
+        assert!(num_steps < 100);
+        run_idle_events_block(
+            &mut allocator,
+            &mut rng,
+            &simulator,
+            &mut coinset_adapter,
+            &mut cradles,
+            true,
+        );
+    }
+
+    // The first player goes on chain.  Find the block where the second sees
+    // the channel coin spent.
+    cradles[0]
+        .go_on_chain(
+            &mut allocator,
+            &mut rng,
+            &mut CradleEventCollector::default(),
+        )
+        .expect("should work");
+    let fork_height = loop {
+        num_steps += 1;
+        assert!(num_steps < 100);
+        let height = simulator.get_current_height();
+        run_idle_events_block(
+            &mut allocator,
+            &mut rng,
+            &simulator,
+            &mut coinset_adapter,
+            &mut cradles,
+            true,
+        );
+        if !cradles[1].handshake_finished() {
+            break height;
+        }
+    };
+
+    // A fork without the spend puts the second player back in the channel,
+    // and the first sends its unroll again.
+    simulator.rewind(fork_height);
+    let events = run_idle_events_block(
+        &mut allocator,
+        &mut rng,
+        &simulator,
+        &mut coinset_adapter,
+        &mut cradles,
+        false,
+    );
+    assert!(cradles[1].handshake_finished());
+    assert!(!cradles[1].is_on_chain());
+    assert!(events[0]
+        .iter()
+        .any(|e| matches!(e, CradleEvent::Transaction(_))));
+}
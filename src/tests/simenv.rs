@@ -12,18 +12,23 @@ use crate::channel_handler::game::Game;
 use crate::channel_handler::game_handler::chia_dialect;
 use crate::channel_handler::runner::{channel_handler_env, ChannelHandlerGame};
 use crate::channel_handler::types::{
-    ChannelHandlerEnv, GameStartInfo, ReadableMove, ValidationProgram,
+    ChannelCoinSpentResult, ChannelHandlerEnv, CoinSpentDisposition, GameStartInfo, ReadableMove,
+    ValidationProgram,
 };
-use crate::common::constants::AGG_SIG_ME_ADDITIONAL_DATA;
+use crate::common::constants::{AGG_SIG_ME_ADDITIONAL_DATA, CREATE_COIN};
 use crate::common::standard_coin::{
-    private_to_public_key, puzzle_for_synthetic_public_key, standard_solution_partial, ChiaIdentity,
+    private_to_public_key, puzzle_for_synthetic_public_key, sign_agg_sig_me,
+    solution_for_conditions, standard_solution_partial, ChiaIdentity,
 };
 use crate::common::types::{
     AllocEncoder, Amount, CoinCondition, CoinSpend, CoinString, Error, GameID, Hash, IntoErr, Node,
-    PrivateKey, Program, PuzzleHash, Sha256tree, Spend, Timeout,
+    PrivateKey, Program, PuzzleHash, Sha256tree, Spend, Timeout, ToQuotedProgram,
 };
 use crate::simulator::Simulator;
-use crate::tests::game::{new_channel_handler_game, GameAction, GameActionResult};
+use crate::tests::calpoker::{load_calpoker, test_moves_1};
+use crate::tests::game::{
+    new_channel_handler_game, GameAction, GameActionResult, DEFAULT_UNROLL_TIME_LOCK,
+};
 use crate::tests::referee::{make_debug_game_handler, RefereeTest};
 
 #[derive(Debug, Clone)]
@@ -212,17 +217,9 @@ impl<'a, R: Rng> SimulatorEnvironment<'a, R> {
 
     fn do_unroll_spend_to_games(
         &mut self,
-        player: usize,
         unroll_coin: CoinString,
+        pre_unroll_data: &ChannelCoinSpentResult,
     ) -> Result<Vec<CoinString>, Error> {
-        let player_ch = &mut self.parties.player(player).ch;
-        let finished_unroll_coin = player_ch.get_finished_unroll_coin();
-        let pre_unroll_data = player_ch.get_create_unroll_coin_transaction(
-            &mut self.env,
-            finished_unroll_coin,
-            true,
-        )?;
-
         let run_puzzle = pre_unroll_data
             .transaction
             .puzzle
@@ -245,6 +242,13 @@ impl<'a, R: Rng> SimulatorEnvironment<'a, R> {
 
         self.simulator.farm_block(&self.identities[0].puzzle_hash);
 
+        // The default conditions assert the unroll timeout.
+        if pre_unroll_data.timeout {
+            for _ in 0..DEFAULT_UNROLL_TIME_LOCK.to_u64() {
+                self.simulator.farm_block(&self.identities[0].puzzle_hash);
+            }
+        }
+
         debug!(
             "private key 1: {:?}",
             self.parties.player(0).ch.unroll_private_key()
@@ -275,7 +279,7 @@ impl<'a, R: Rng> SimulatorEnvironment<'a, R> {
         Ok(condition_list
             .iter()
             .filter_map(|cond| {
-                if let CoinCondition::CreateCoin(ph, amt) = cond {
+                if let CoinCondition::CreateCoin(ph, amt, _) = cond {
                     return Some(CoinString::from_parts(&unroll_coin.to_coin_id(), ph, amt));
                 }
 
@@ -300,11 +304,8 @@ impl<'a, R: Rng> SimulatorEnvironment<'a, R> {
             entropy,
         )?;
         let finished_unroll_coin = player_ch.get_finished_unroll_coin();
-        let post_unroll_data = player_ch.get_create_unroll_coin_transaction(
-            &mut self.env,
-            finished_unroll_coin,
-            true,
-        )?;
+        let post_unroll_data =
+            player_ch.get_create_unroll_coin_transaction(&mut self.env, finished_unroll_coin)?;
         debug!("post_unroll_data {post_unroll_data:?}");
         todo!();
     }
@@ -358,18 +359,25 @@ impl<'a, R: Rng> SimulatorEnvironment<'a, R> {
                 )?;
                 debug!("unroll_coin {unroll_coin:?}");
 
-                let _channel_spent_result_1 = self.parties.player(*player).ch.channel_coin_spent(
+                let channel_spent_result_1 = self.parties.player(*player).ch.channel_coin_spent(
                     &mut self.env,
                     true,
                     channel_coin_conditions,
                 )?;
-                let _channel_spent_result_2 = self
+                let channel_spent_result_2 = self
                     .parties
                     .player(*player ^ 1)
                     .ch
                     .channel_coin_spent(&mut self.env, false, channel_coin_conditions)?;
 
-                let game_coins = self.do_unroll_spend_to_games(*player, unroll_coin)?;
+                // If the other player holds a newer state, they supercede the
+                // unroll rather than waiting for the timeout.
+                let unroll_spend = if channel_spent_result_2.timeout {
+                    channel_spent_result_1
+                } else {
+                    channel_spent_result_2
+                };
+                let game_coins = self.do_unroll_spend_to_games(unroll_coin, &unroll_spend)?;
 
                 debug!(
                     "channel coin conditions {}",
@@ -569,6 +577,71 @@ fn test_simulator_combine_coins() {
     assert_eq!(a1 + a2, amt);
 }
 
+#[test]
+fn test_simulator_rejects_bad_spends() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+    let s = Simulator::default();
+    let private_key: PrivateKey = rng.gen();
+    let identity = ChiaIdentity::new(&mut allocator, private_key).expect("should create");
+    let pk2: PrivateKey = rng.gen();
+    let identity2 = ChiaIdentity::new(&mut allocator, pk2).expect("should create");
+
+    s.farm_block(&identity.puzzle_hash);
+    let coins = s.get_my_coins(&identity.puzzle_hash).expect("got coins");
+    let (_, _, amt) = coins[0].to_parts().expect("should parse");
+
+    let mut spend_to = |signer: &ChiaIdentity, amt: Amount| -> CoinSpend {
+        let conditions = (
+            (CREATE_COIN, (identity2.puzzle_hash.clone(), (amt, ()))),
+            (),
+        )
+            .to_clvm(&mut allocator)
+            .expect("should build");
+        let quoted_conditions = conditions
+            .to_quoted_program(&mut allocator)
+            .expect("should quote");
+        let quoted_conditions_hash = quoted_conditions.sha256tree(&mut allocator);
+        let solution = solution_for_conditions(&mut allocator, conditions).expect("should work");
+        CoinSpend {
+            coin: coins[0].clone(),
+            bundle: Spend {
+                puzzle: identity.puzzle.clone(),
+                solution: Program::from_nodeptr(&mut allocator, solution).expect("should work"),
+                signature: sign_agg_sig_me(
+                    &signer.synthetic_private_key,
+                    quoted_conditions_hash.bytes(),
+                    &coins[0].to_coin_id(),
+                    &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+                ),
+            },
+        }
+    };
+
+    let wrong_signer = spend_to(&identity2, amt.clone());
+    let minting = spend_to(&identity, amt.clone() + Amount::new(1));
+    let good = spend_to(&identity, amt.clone());
+
+    let mut allocator = AllocEncoder::new();
+    let included = s
+        .push_tx(&mut allocator, &[wrong_signer])
+        .expect("should work");
+    assert_eq!(included.code, 3);
+    let included = s.push_tx(&mut allocator, &[minting]).expect("should work");
+    assert_eq!(included.code, 3);
+    let included = s
+        .push_tx(&mut allocator, &[good.clone()])
+        .expect("should work");
+    assert_eq!(included.code, 1);
+
+    s.farm_block(&identity.puzzle_hash);
+    let coins2 = s.get_my_coins(&identity2.puzzle_hash).expect("got coins");
+    assert_eq!(coins2.len(), 1);
+    let included = s.push_tx(&mut allocator, &[good]).expect("should work");
+    assert_eq!(included.code, 3);
+}
+
 #[test]
 fn test_referee_can_slash_on_chain() {
     let seed: [u8; 32] = [0; 32];
@@ -822,3 +895,347 @@ fn test_referee_can_move_on_chain() {
     debug!("included {included:?}");
     assert_eq!(included.code, 1);
 }
+
+#[test]
+fn test_referee_moves_from_the_coin_before_its_move() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+
+    let agg_sig_me_additional_data = Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA);
+
+    // Generate keys and puzzle hashes.
+    let my_private_key: PrivateKey = rng.gen();
+    let my_identity = ChiaIdentity::new(&mut allocator, my_private_key).expect("should generate");
+
+    let their_private_key: PrivateKey = rng.gen();
+    let their_identity =
+        ChiaIdentity::new(&mut allocator, their_private_key).expect("should generate");
+
+    let amount = Amount::new(100);
+    let timeout = Timeout::new(10);
+    let max_move_size = 100;
+
+    let debug_game = make_debug_game_handler(&mut allocator, &my_identity, &amount, &timeout);
+    let init_state = assemble(allocator.allocator(), "(0 . 0)").expect("should assemble");
+
+    let my_validation_program =
+        ValidationProgram::new(&mut allocator, debug_game.my_validation_program);
+
+    let game_start_info = GameStartInfo {
+        game_id: GameID::from_bytes(b"test"),
+        amount: amount.clone(),
+        game_handler: debug_game.my_turn_handler,
+        timeout: timeout.clone(),
+        my_contribution_this_game: Amount::new(50),
+        their_contribution_this_game: Amount::new(50),
+        initial_validation_program: my_validation_program,
+        initial_state: init_state,
+        initial_move: vec![],
+        initial_max_move_size: max_move_size,
+        initial_mover_share: Amount::default(),
+    };
+
+    let _their_validation_program_hash =
+        Node(debug_game.their_validation_program).sha256tree(&mut allocator);
+
+    let mut reftest = RefereeTest::new(
+        &mut allocator,
+        my_identity,
+        their_identity,
+        debug_game.their_turn_handler,
+        &game_start_info,
+    );
+
+    let readable_move = assemble(allocator.allocator(), "(100 . 0)").expect("should assemble");
+    assert_eq!(reftest.my_referee.get_our_current_share(), Amount::new(0));
+
+    // The referee coin waiting for our first move.
+    assert!(reftest.my_referee.is_my_turn_on_chain());
+    let referee_puzzle_hash = reftest
+        .my_referee
+        .on_chain_referee_puzzle_hash(&mut allocator)
+        .expect("should work");
+
+    // Make our first move.
+    let _my_move_wire_data = reftest
+        .my_referee
+        .my_turn_make_move(
+            &mut allocator,
+            &ReadableMove::from_nodeptr(readable_move),
+            rng.gen(),
+        )
+        .expect("should move");
+
+    assert_eq!(reftest.my_referee.get_our_current_share(), Amount::new(100));
+
+    // Make simulator and create referee coin.
+    let s = Simulator::default();
+    s.farm_block(&reftest.my_identity.puzzle_hash);
+
+    let coins = s
+        .get_my_coins(&reftest.my_identity.puzzle_hash)
+        .expect("got coins");
+    assert!(!coins.is_empty());
+
+    // Create the referee coin.
+    let (_, _, amt) = coins[0].to_parts().unwrap();
+    let referee_coins = s
+        .spend_coin_to_puzzle_hash(
+            &mut allocator,
+            &reftest.my_identity,
+            &reftest.my_identity.puzzle,
+            &coins[0],
+            &[(referee_puzzle_hash.clone(), amt.clone())],
+        )
+        .expect("should create referee coin");
+    s.farm_block(&reftest.my_identity.puzzle_hash);
+
+    // Make our move on chain.
+    let move_transaction = reftest
+        .my_referee
+        .get_transaction_for_move(
+            &mut allocator,
+            &referee_coins[0],
+            &agg_sig_me_additional_data,
+        )
+        .expect("should work");
+
+    debug!("move_transaction {move_transaction:?}");
+    let specific = CoinSpend {
+        coin: referee_coins[0].clone(),
+        bundle: move_transaction.bundle.clone(),
+    };
+
+    let included = s.push_tx(&mut allocator, &[specific]).expect("should work");
+    debug!("included {included:?}");
+    assert_eq!(included.code, 1);
+
+    // The move creates the referee coin that waits on their move.
+    s.farm_block(&reftest.my_identity.puzzle_hash);
+    assert!(!reftest.my_referee.is_my_turn_on_chain());
+    let (_, next_puzzle_hash, next_amount) = move_transaction.reward_coin.to_parts().unwrap();
+    assert_eq!(
+        next_puzzle_hash,
+        reftest
+            .my_referee
+            .on_chain_referee_puzzle_hash(&mut allocator)
+            .expect("should work")
+    );
+    assert_eq!(next_amount, amount);
+    let all_coins = s.get_all_coins().expect("should work");
+    assert!(all_coins.contains(&move_transaction.reward_coin));
+
+    // The waiting side recognizes the spend as a move because it makes the
+    // referee coin its remark describes.
+    let move_puzzle = move_transaction
+        .bundle
+        .puzzle
+        .to_clvm(&mut allocator)
+        .expect("should work");
+    let move_solution = move_transaction
+        .bundle
+        .solution
+        .to_clvm(&mut allocator)
+        .expect("should work");
+    let move_conditions = run_program(
+        allocator.allocator(),
+        &chia_dialect(),
+        move_puzzle,
+        move_solution,
+        0,
+    )
+    .expect("should run")
+    .1;
+    assert!(reftest
+        .their_referee
+        .is_move_spend(&mut allocator, move_conditions)
+        .expect("should work"));
+}
+
+#[test]
+fn test_unroll_default_spend_waits_for_timeout() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+    let game_id_data: Hash = rng.gen();
+    let game_id = GameID::new(game_id_data.bytes().to_vec());
+    let calpoker = load_calpoker(&mut allocator, game_id).expect("should load");
+    let moves = test_moves_1(&mut allocator);
+    let contributions = [Amount::new(100), Amount::new(100)];
+    let mut simenv = SimulatorEnvironment::new(&mut allocator, &mut rng, &calpoker, &contributions)
+        .expect("should get a sim env");
+    simenv.play_game(&moves[..2]).expect("should play");
+
+    // Bob unrolls and tries to claim the default conditions right away,
+    // before alice has had the timeout to challenge.
+    let use_unroll = simenv
+        .parties
+        .player(1)
+        .ch
+        .get_finished_unroll_coin()
+        .clone();
+    let unroll_target = simenv
+        .parties
+        .player(1)
+        .ch
+        .get_unroll_target(&mut simenv.env, &use_unroll)
+        .expect("should work");
+    let state_channel_coin = if let OnChainState::OffChain(coin) = simenv.on_chain.clone() {
+        coin
+    } else {
+        panic!("should be off chain");
+    };
+    let (channel_coin_conditions, unroll_coin) = simenv
+        .spend_channel_coin(1, state_channel_coin, &unroll_target.unroll_puzzle_hash)
+        .expect("should spend the channel coin");
+    let default_spend = simenv
+        .parties
+        .player(1)
+        .ch
+        .channel_coin_spent(&mut simenv.env, true, channel_coin_conditions)
+        .expect("should work");
+    assert!(default_spend.timeout);
+
+    let spends = [CoinSpend {
+        bundle: default_spend.transaction.clone(),
+        coin: unroll_coin.clone(),
+    }];
+    let included = simenv
+        .simulator
+        .push_tx(simenv.env.allocator, &spends)
+        .expect("should work");
+    assert_ne!(included.code, 1);
+    simenv
+        .simulator
+        .farm_block(&simenv.identities[0].puzzle_hash);
+    assert!(simenv
+        .simulator
+        .get_puzzle_and_solution(&unroll_coin)
+        .expect("should work")
+        .is_none());
+
+    // Once the timeout has passed the same spend goes through.
+    for _ in 0..DEFAULT_UNROLL_TIME_LOCK.to_u64() {
+        simenv
+            .simulator
+            .farm_block(&simenv.identities[0].puzzle_hash);
+    }
+    simenv
+        .simulator
+        .push_tx(simenv.env.allocator, &spends)
+        .expect("should work");
+    simenv
+        .simulator
+        .farm_block(&simenv.identities[0].puzzle_hash);
+    assert!(simenv
+        .simulator
+        .get_puzzle_and_solution(&unroll_coin)
+        .expect("should work")
+        .is_some());
+}
+
+#[test]
+fn test_uncountersigned_settle_claims_current_share_on_chain() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+    let game_id_data: Hash = rng.gen();
+    let game_id = GameID::new(game_id_data.bytes().to_vec());
+    let calpoker = load_calpoker(&mut allocator, game_id.clone()).expect("should load");
+    let moves = test_moves_1(&mut allocator);
+    let contributions = [Amount::new(100), Amount::new(100)];
+    let mut simenv = SimulatorEnvironment::new(&mut allocator, &mut rng, &calpoker, &contributions)
+        .expect("should get a sim env");
+    simenv.play_game(&moves).expect("should play");
+
+    // Alice's last move declares a split giving bob everything at stake, and
+    // leaves him the potato.
+    let settler = 1;
+    let bob_share = Amount::new(200);
+    let game_id = simenv.parties.game_id.clone();
+    assert!(simenv.parties.player(settler).ch.has_potato());
+
+    // Bob offers to settle for less, which alice never countersigns.
+    simenv
+        .parties
+        .player(settler)
+        .ch
+        .send_potato_settle(&mut simenv.env, &game_id, &Amount::new(50))
+        .expect("should settle");
+    assert!(simenv
+        .parties
+        .player(settler)
+        .ch
+        .send_potato_settle(&mut simenv.env, &game_id, &Amount::new(50))
+        .is_err());
+
+    // The settler unrolls the last state both sides signed.
+    let use_unroll = simenv
+        .parties
+        .player(settler)
+        .ch
+        .get_finished_unroll_coin()
+        .clone();
+    let unroll_target = simenv
+        .parties
+        .player(settler)
+        .ch
+        .get_unroll_target(&mut simenv.env, &use_unroll)
+        .expect("should work");
+    let state_channel_coin = if let OnChainState::OffChain(coin) = simenv.on_chain.clone() {
+        coin
+    } else {
+        panic!("should be off chain");
+    };
+    let (channel_coin_conditions, unroll_coin) = simenv
+        .spend_channel_coin(
+            settler,
+            state_channel_coin,
+            &unroll_target.unroll_puzzle_hash,
+        )
+        .expect("should spend the channel coin");
+    let unroll_spend = simenv
+        .parties
+        .player(settler)
+        .ch
+        .channel_coin_spent(&mut simenv.env, true, channel_coin_conditions)
+        .expect("should work");
+    let unroll_puzzle = unroll_spend
+        .transaction
+        .puzzle
+        .to_clvm(simenv.env.allocator)
+        .expect("should work");
+    let unroll_solution = unroll_spend
+        .transaction
+        .solution
+        .to_clvm(simenv.env.allocator)
+        .expect("should work");
+    let unroll_conditions = run_program(
+        simenv.env.allocator.allocator(),
+        &chia_dialect(),
+        unroll_puzzle,
+        unroll_solution,
+        0,
+    )
+    .expect("should run")
+    .1;
+    simenv
+        .do_unroll_spend_to_games(unroll_coin.clone(), &unroll_spend)
+        .expect("should unroll");
+
+    let spent = simenv
+        .parties
+        .player(settler)
+        .ch
+        .unroll_coin_spent(&mut simenv.env, &unroll_coin, unroll_conditions)
+        .expect("should work");
+    // The chain pays what the game says, not the settlement.
+    if let Some(CoinSpentDisposition::Accept(accepted)) = spent.disposition {
+        assert_eq!(accepted.game_id, game_id);
+        let (_, _, reward) = accepted.reward_coin.to_parts().expect("should parse");
+        assert_eq!(reward, bob_share);
+    } else {
+        panic!("expected the game to be claimed like an accept");
+    }
+}
@@ -0,0 +1,204 @@
+use clvm_traits::ToClvm;
+use clvmr::allocator::NodePtr;
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use crate::common::constants::{
+    AGG_SIG_ME_ADDITIONAL_DATA, ASSERT_HEIGHT_RELATIVE, ASSERT_SECONDS_RELATIVE, CREATE_COIN,
+};
+use crate::common::standard_coin::{standard_solution_partial, ChiaIdentity};
+use crate::common::types::{
+    AllocEncoder, Amount, CoinSpend, CoinString, Hash, PrivateKey, Program, Spend,
+};
+use crate::simulator::Simulator;
+
+// Inclusion status and error codes as chia numbers them.
+const SUCCESS: u32 = 1;
+const PENDING: u32 = 2;
+const FAILED: u32 = 3;
+const BAD_AGGREGATE_SIGNATURE: u32 = 7;
+const ASSERT_HEIGHT_RELATIVE_FAILED: u32 = 13;
+const MINTING_COIN: u32 = 20;
+const ASSERT_SECONDS_RELATIVE_FAILED: u32 = 105;
+
+// How far the native simulator's clock moves with each block.
+const SECONDS_PER_BLOCK: u64 = 20;
+
+fn standard_spend(
+    allocator: &mut AllocEncoder,
+    identity: &ChiaIdentity,
+    coin: &CoinString,
+    conditions: NodePtr,
+) -> CoinSpend {
+    let spend_info = standard_solution_partial(
+        allocator,
+        &identity.synthetic_private_key,
+        &coin.to_coin_id(),
+        conditions,
+        &identity.synthetic_public_key,
+        &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+        false,
+    )
+    .expect("should build");
+    CoinSpend {
+        coin: coin.clone(),
+        bundle: Spend {
+            puzzle: identity.puzzle.clone(),
+            solution: Program::from_nodeptr(allocator, spend_info.solution)
+                .expect("should convert"),
+            signature: spend_info.signature,
+        },
+    }
+}
+
+// A simulator with a confirmed, non reward coin of 100 owned by identity.
+fn funded_simulator(
+    allocator: &mut AllocEncoder,
+    rng: &mut ChaCha8Rng,
+) -> (Simulator, ChiaIdentity, CoinString) {
+    let simulator = Simulator::default();
+    let pk: PrivateKey = rng.gen();
+    let identity = ChiaIdentity::new(allocator, pk).expect("should work");
+    simulator.farm_block(&identity.puzzle_hash);
+    let coins = simulator
+        .get_my_coins(&identity.puzzle_hash)
+        .expect("should work");
+    let (coin, _) = simulator
+        .transfer_coin_amount(allocator, &identity, &identity, &coins[0], Amount::new(100))
+        .expect("should work");
+    simulator.farm_block(&identity.puzzle_hash);
+    (simulator, identity, coin)
+}
+
+fn is_unspent(simulator: &Simulator, identity: &ChiaIdentity, coin: &CoinString) -> bool {
+    simulator
+        .get_my_coins(&identity.puzzle_hash)
+        .expect("should work")
+        .contains(coin)
+}
+
+#[test]
+fn test_native_sim_rejects_bad_signature() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let (simulator, identity, coin) = funded_simulator(&mut allocator, &mut rng);
+
+    let conditions = (
+        (
+            CREATE_COIN,
+            (identity.puzzle_hash.clone(), (Amount::new(100), ())),
+        ),
+        (),
+    )
+        .to_clvm(&mut allocator)
+        .expect("should build");
+    let mut spend = standard_spend(&mut allocator, &identity, &coin, conditions);
+    let other_pk: PrivateKey = rng.gen();
+    spend.bundle.signature = other_pk.sign(b"something else");
+
+    let result = simulator
+        .push_tx(&mut allocator, &[spend])
+        .expect("should run");
+    assert_eq!(result.code, FAILED);
+    assert_eq!(result.e, Some(BAD_AGGREGATE_SIGNATURE));
+    simulator.farm_block(&identity.puzzle_hash);
+    assert!(is_unspent(&simulator, &identity, &coin));
+}
+
+#[test]
+fn test_native_sim_rejects_minting() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let (simulator, identity, coin) = funded_simulator(&mut allocator, &mut rng);
+
+    let conditions = (
+        (
+            CREATE_COIN,
+            (identity.puzzle_hash.clone(), (Amount::new(101), ())),
+        ),
+        (),
+    )
+        .to_clvm(&mut allocator)
+        .expect("should build");
+    let spend = standard_spend(&mut allocator, &identity, &coin, conditions);
+
+    let result = simulator
+        .push_tx(&mut allocator, &[spend])
+        .expect("should run");
+    assert_eq!(result.code, FAILED);
+    assert_eq!(result.e, Some(MINTING_COIN));
+    simulator.farm_block(&identity.puzzle_hash);
+    assert!(is_unspent(&simulator, &identity, &coin));
+}
+
+#[test]
+fn test_native_sim_holds_relative_timelocks() {
+    for (opcode, value, error) in [
+        (ASSERT_HEIGHT_RELATIVE, 3, ASSERT_HEIGHT_RELATIVE_FAILED),
+        (
+            ASSERT_SECONDS_RELATIVE,
+            3 * SECONDS_PER_BLOCK,
+            ASSERT_SECONDS_RELATIVE_FAILED,
+        ),
+    ] {
+        let mut allocator = AllocEncoder::new();
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        let (simulator, identity, coin) = funded_simulator(&mut allocator, &mut rng);
+        let confirmed_height = simulator.get_current_height();
+
+        let conditions = (
+            (opcode, (value, ())),
+            (
+                (
+                    CREATE_COIN,
+                    (identity.puzzle_hash.clone(), (Amount::new(100), ())),
+                ),
+                (),
+            ),
+        )
+            .to_clvm(&mut allocator)
+            .expect("should build");
+        let spend = standard_spend(&mut allocator, &identity, &coin, conditions);
+
+        let result = simulator
+            .push_tx(&mut allocator, &[spend])
+            .expect("should run");
+        assert_eq!(result.code, PENDING, "{opcode}");
+        assert_eq!(result.e, Some(error), "{opcode}");
+
+        // The bundle is held until the lock passes three blocks later, then
+        // goes into the block after that without being pushed again.
+        while simulator.get_current_height() < confirmed_height + 3 {
+            simulator.farm_block(&identity.puzzle_hash);
+            assert!(is_unspent(&simulator, &identity, &coin), "{opcode}");
+        }
+        simulator.farm_block(&identity.puzzle_hash);
+        assert!(!is_unspent(&simulator, &identity, &coin), "{opcode}");
+    }
+}
+
+#[test]
+fn test_native_sim_accepts_a_good_spend() {
+    let mut allocator = AllocEncoder::new();
+    let mut rng = ChaCha8Rng::from_seed([0; 32]);
+    let (simulator, identity, coin) = funded_simulator(&mut allocator, &mut rng);
+
+    let conditions = (
+        (
+            CREATE_COIN,
+            (identity.puzzle_hash.clone(), (Amount::new(100), ())),
+        ),
+        (),
+    )
+        .to_clvm(&mut allocator)
+        .expect("should build");
+    let spend = standard_spend(&mut allocator, &identity, &coin, conditions);
+
+    let result = simulator
+        .push_tx(&mut allocator, &[spend])
+        .expect("should run");
+    assert_eq!(result.code, SUCCESS);
+    simulator.farm_block(&identity.puzzle_hash);
+    assert!(!is_unspent(&simulator, &identity, &coin));
+}
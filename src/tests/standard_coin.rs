@@ -9,15 +9,18 @@ use clvm_tools_rs::classic::clvm_tools::stages::stage_0::{DefaultProgramRunner,
 
 use log::debug;
 
-use crate::common::constants::{DEFAULT_HIDDEN_PUZZLE_HASH, ONE, TWO};
+use crate::common::constants::{
+    AGG_SIG_ME_ADDITIONAL_DATA, AGG_SIG_PARENT, CREATE_COIN, DEFAULT_HIDDEN_PUZZLE_HASH, ONE, TWO,
+};
 use crate::common::standard_coin::{
     calculate_hash_of_quoted_mod_hash, calculate_synthetic_public_key, curry_and_treehash,
     get_standard_coin_puzzle, hex_to_sexp, partial_signer, private_to_public_key, puzzle_for_pk,
-    puzzle_hash_for_pk, standard_solution_unsafe, unsafe_sign_partial,
+    puzzle_hash_for_pk, sign_agg_sig_me, solution_for_conditions, standard_solution_unsafe,
+    unsafe_sign_partial, ChiaIdentity,
 };
 use crate::common::types::{
-    Aggsig, AllocEncoder, Node, PrivateKey, PublicKey, PuzzleHash, Sha256Input, Sha256tree,
-    ToQuotedProgram,
+    Aggsig, AllocEncoder, Amount, CoinID, CoinSpend, CoinString, Hash, Node, PrivateKey, Program,
+    PublicKey, Puzzle, PuzzleHash, Sha256Input, Sha256tree, Spend, SpendBundle, ToQuotedProgram,
 };
 use crate::tests::constants::{
     EXPECTED_PUZZLE_HEX, KEY_PAIR_PARTIAL_SIGNER_TEST_RESULT, KEY_PAIR_PRIVATE, KEY_PAIR_PUBLIC,
@@ -209,3 +212,130 @@ fn test_standard_puzzle_solution_maker() {
         .signature
         .verify(&public_key, quoted_conditions_hash.bytes()));
 }
+
+#[test]
+fn test_spend_bundle_validate() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+    let identity = ChiaIdentity::new(&mut allocator, rng.gen()).expect("should create");
+    let other = ChiaIdentity::new(&mut allocator, rng.gen()).expect("should create");
+    let parent: Hash = rng.gen();
+    let coin = CoinString::from_parts(
+        &CoinID::new(parent),
+        &identity.puzzle_hash,
+        &Amount::new(100),
+    );
+
+    let conditions = (
+        (
+            CREATE_COIN,
+            (other.puzzle_hash.clone(), (Amount::new(100), ())),
+        ),
+        (),
+    )
+        .to_clvm(&mut allocator)
+        .expect("should build");
+    let quoted_conditions_hash = conditions
+        .to_quoted_program(&mut allocator)
+        .expect("should quote")
+        .sha256tree(&mut allocator);
+    let solution = solution_for_conditions(&mut allocator, conditions).expect("should work");
+    let bundle_signed_by = |allocator: &mut AllocEncoder, signer: &ChiaIdentity| SpendBundle {
+        spends: vec![CoinSpend {
+            coin: coin.clone(),
+            bundle: Spend {
+                puzzle: identity.puzzle.clone(),
+                solution: Program::from_nodeptr(allocator, solution).expect("should work"),
+                signature: sign_agg_sig_me(
+                    &signer.synthetic_private_key,
+                    quoted_conditions_hash.bytes(),
+                    &coin.to_coin_id(),
+                    &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+                ),
+            },
+        }],
+    };
+
+    let good = bundle_signed_by(&mut allocator, &identity);
+    let cost = good.validate(&mut allocator).expect("should validate");
+    assert!(cost > 0);
+
+    let wrong_signer = bundle_signed_by(&mut allocator, &other);
+    assert!(wrong_signer.validate(&mut allocator).is_err());
+
+    let mut wrong_puzzle = good.clone();
+    wrong_puzzle.spends[0].bundle.puzzle = other.puzzle.clone();
+    assert!(wrong_puzzle.validate(&mut allocator).is_err());
+
+    assert!(SpendBundle { spends: vec![] }
+        .validate(&mut allocator)
+        .is_err());
+}
+
+#[test]
+fn test_spend_bundle_validate_agg_sig_parent() {
+    let seed: [u8; 32] = [0; 32];
+    let mut rng = ChaCha8Rng::from_seed(seed);
+    let mut allocator = AllocEncoder::new();
+    let signer = ChiaIdentity::new(&mut allocator, rng.gen()).expect("should create");
+    let other = ChiaIdentity::new(&mut allocator, rng.gen()).expect("should create");
+    let msg: Hash = rng.gen();
+    let parent: Hash = rng.gen();
+
+    let conditions = (
+        (
+            AGG_SIG_PARENT,
+            (signer.public_key.clone(), (msg.clone(), ())),
+        ),
+        (),
+    )
+        .to_clvm(&mut allocator)
+        .expect("should build");
+    let puzzle = conditions
+        .to_quoted_program(&mut allocator)
+        .expect("should quote");
+    let coin = CoinString::from_parts(
+        &CoinID::new(parent.clone()),
+        &puzzle.sha256tree(&mut allocator),
+        &Amount::new(100),
+    );
+    let bundle_signed_with = |signature: Aggsig| SpendBundle {
+        spends: vec![CoinSpend {
+            coin: coin.clone(),
+            bundle: Spend {
+                puzzle: Puzzle::from_bytes(puzzle.bytes()),
+                solution: Program::from_bytes(&[0x80]),
+                signature,
+            },
+        }],
+    };
+
+    // AGG_SIG_PARENT covers the message, the parent coin id and additional
+    // data of its own.
+    let additional_data = Sha256Input::Bytes(
+        &[&AGG_SIG_ME_ADDITIONAL_DATA[..], &[AGG_SIG_PARENT as u8][..]].concat(),
+    )
+    .hash();
+    let signed = [
+        &msg.bytes()[..],
+        &parent.bytes()[..],
+        &additional_data.bytes()[..],
+    ]
+    .concat();
+
+    let good = bundle_signed_with(signer.private_key.sign(&signed));
+    good.validate(&mut allocator).expect("should validate");
+
+    let wrong_signer = bundle_signed_with(other.private_key.sign(&signed));
+    assert!(wrong_signer.validate(&mut allocator).is_err());
+
+    // Signed the way AGG_SIG_ME would be instead.
+    let wrong_message = bundle_signed_with(sign_agg_sig_me(
+        &signer.private_key,
+        msg.bytes(),
+        &coin.to_coin_id(),
+        &Hash::from_slice(&AGG_SIG_ME_ADDITIONAL_DATA),
+    ));
+    assert!(wrong_message.validate(&mut allocator).is_err());
+}
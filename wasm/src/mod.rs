@@ -15,12 +15,13 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use chia_gaming::log::wasm_init;
-use chia_gaming::common::types::{AllocEncoder, Amount, CoinSpend, CoinString, GameID, Hash, PrivateKey, Program, PuzzleHash, Sha256Input, Spend, SpendBundle, Timeout, IntoErr};
+use chia_gaming::common::types::{Aggsig, AllocEncoder, Amount, CoinSpend, CoinString, GameID, Hash, PrivateKey, Program, Puzzle, PuzzleHash, Sha256Input, Spend, SpendBundle, Timeout, IntoErr};
 use chia_gaming::common::types;
 use chia_gaming::channel_handler::types::ReadableMove;
-use chia_gaming::potato_handler::{GameStart, GameType, ToLocalUI};
-use chia_gaming::peer_container::{GameCradle, IdleResult, SynchronousGameCradle, SynchronousGameCradleConfig, WatchReport};
+use chia_gaming::potato_handler::{GameStart, GameType, GoingOnChainReason, LivenessPolicy, ToLocalUI, WireGameStart};
+use chia_gaming::peer_container::{BlockId, GameCradle, IdleResult, SynchronousGameCradle, SynchronousGameCradleConfig, WatchReport};
 use chia_gaming::common::standard_coin::{ChiaIdentity, wasm_deposit_file};
+use chia_gaming::games::calpoker::{CalpokerResult, RawCalpokerHandValue, decode_calpoker_readable, decode_readable_card_choices, encode_calpoker_commit, encode_calpoker_picks, make_cards, picks_from_bitmask};
 
 use crate::map_m::map_m;
 
@@ -65,8 +66,11 @@ export type IdleResult = {
     "continue_on": boolean,
     "outbound_transactions": Array<SpendBundle>,
     "outbound_messages": Array<string>,
+    "coin_solution_requests": Array<string>,
+    "coins_to_watch": Array<string>,
     "opponent_move": OpponentMove | undefined,
-    "game_finished": GameFinished | undefined
+    "game_finished": GameFinished | undefined,
+    "receive_error": string | undefined
 };
 
 export type GameCradleConfig = {
@@ -77,18 +81,71 @@ export type GameCradleConfig = {
     "my_contribution": Amount,
     "their_contribution": Amount,
     "channel_timeout": number,
-    "reward_puzzle_hash": string
+    "unroll_timeout": number,
+    "reward_puzzle_hash": string,
+    "potato_timeout": number | undefined,
+    "move_timeout": number | undefined,
+    "validate_outbound": boolean | undefined
+};
+
+export type GameStart = {
+    "game_type": string,
+    "timeout": number,
+    "amount": number,
+    "my_contribution": number,
+    "my_turn": boolean,
+    "parameters": string
+};
+
+export type GameProposal = {
+    "id": string,
+    "game_ids": Array<string>,
+    "start": GameStart
 };
 
 export type IChiaIdentityFun = (seed: string) => IChiaIdentity;
 
+export type CalpokerCard = [number, number];
+export type CalpokerHands = [Array<CalpokerCard>, Array<CalpokerCard>];
+
+export type CalpokerResult = {
+    "raw_alice_selects": number,
+    "raw_bob_picks": number,
+    "raw_alice_picks": number,
+    "bob_hand_value": any,
+    "bob_hand_result": any,
+    "alice_hand_value": any,
+    "alice_hand_result": any,
+    "win_direction": number,
+    "game_amount": number,
+    "your_share": number
+};
+
+export type CalpokerOutcome = {
+    "result": CalpokerResult,
+    "alice_selects": Array<number>,
+    "bob_picks": Array<number>,
+    "alice_picks": Array<number>,
+    "winner": "you" | "opponent" | "tie"
+};
+
 export type IdleCallbacks = {
     "self_move": ((game_id: string, move_hex: string) => void) | undefined,
     "opponent_moved": ((game_id: string, readable_move_hex: string) => void) | undefined,
     "game_message": ((game_id: string, readable_move_hex: string) => void) | undefined,
-    "game_finished": ((game_id: string) => void) | undefined,
+    "game_finished": ((game_id: string, my_share: number) => void) | undefined,
+    "game_cancelled": ((game_id: string) => void) | undefined,
+    "opponent_slashed": ((game_id: string, my_reward: number) => void) | undefined,
     "shutdown_complete": ((coin: string) => void) | undefined,
-    "going_on_chain": (() => void) | undefined
+    "going_on_chain": ((reason: any) => void) | undefined,
+    "game_proposed": ((proposal: GameProposal) => void) | undefined,
+    "game_proposal_accepted": ((id: string) => void) | undefined,
+    "game_proposal_declined": ((id: string) => void) | undefined,
+    "game_proposal_cancelled": ((id: string) => void) | undefined,
+    "channel_topped_up": ((my_balance: number, their_balance: number) => void) | undefined,
+    "channel_withdrawn": ((my_balance: number, their_balance: number) => void) | undefined,
+    "settlement_offered": ((id: string, my_share: number) => void) | undefined,
+    "settlement_declined": ((id: string) => void) | undefined
 };
 "#;
 
@@ -154,6 +211,11 @@ struct JsGameCradleConfig {
     unroll_timeout: i32,
     // hex string for puzzle hash
     reward_puzzle_hash: String,
+    // blocks to wait for the potato or a move before going on chain
+    potato_timeout: Option<i32>,
+    move_timeout: Option<i32>,
+    // check outbound transactions before handing them out
+    validate_outbound: Option<bool>,
 }
 
 fn convert_game_types(collection: &BTreeMap<String, String>) -> Result<BTreeMap<GameType, Program>, JsValue> {
@@ -185,6 +247,11 @@ fn get_game_config<'b>(
         my_contribution: jsconfig.my_contribution.amt.clone(),
         their_contribution: jsconfig.their_contribution.amt.clone(),
         reward_puzzle_hash: PuzzleHash::from_hash(Hash::from_slice(&reward_puzzle_hash_bytes)),
+        liveness: LivenessPolicy {
+            potato_timeout: jsconfig.potato_timeout.map(|t| Timeout::new(t as u64)),
+            move_timeout: jsconfig.move_timeout.map(|t| Timeout::new(t as u64)),
+        },
+        validate_outbound: jsconfig.validate_outbound.unwrap_or(false),
     })
 }
 
@@ -292,18 +359,19 @@ pub fn opening_coin(cid: i32, hex_coinstring: &str) -> Result<(), JsValue> {
     })
 }
 
-fn watch_report_from_params(additions: Vec<String>, removals: Vec<String>, timed_out: Vec<String>) -> Result<WatchReport, types::Error> {
+fn watch_report_from_params(additions: Vec<String>, removals: Vec<String>, timed_out: Vec<String>, block: Option<BlockId>) -> Result<WatchReport, types::Error> {
     Ok(WatchReport {
         created_watched: map_m(|s| hex_to_coinstring(&s), &additions)?.iter().cloned().collect(),
         deleted_watched: map_m(|s| hex_to_coinstring(&s), &removals)?.iter().cloned().collect(),
         timed_out: map_m(|s| hex_to_coinstring(&s), &timed_out)?.iter().cloned().collect(),
+        block,
     })
 }
 
 #[wasm_bindgen]
 pub fn new_block(cid: i32, height: usize, additions: Vec<String>, removals: Vec<String>, timed_out: Vec<String>) -> Result<(), JsValue> {
     with_game(cid, move |cradle: &mut JsCradle| {
-        let watch_report = watch_report_from_params(additions, removals, timed_out)?;
+        let watch_report = watch_report_from_params(additions, removals, timed_out, None)?;
         cradle.cradle.new_block(
             &mut cradle.allocator,
             &mut cradle.rng,
@@ -313,7 +381,34 @@ pub fn new_block(cid: i32, height: usize, additions: Vec<String>, removals: Vec<
     })
 }
 
-#[derive(Deserialize)]
+fn hex_to_hash(hex: &str) -> Result<Hash, types::Error> {
+    let hash_bytes = hex::decode(hex).into_gen()?;
+    if hash_bytes.len() != 32 {
+        return Err(types::Error::StrErr(format!("hash should be 32 bytes, got {}", hash_bytes.len())));
+    }
+    Ok(Hash::from_slice(&hash_bytes))
+}
+
+// Like new_block, but naming the block and its parent so that the cradle can
+// notice reorgs.
+#[wasm_bindgen]
+pub fn new_block_with_id(cid: i32, height: usize, header_hash: &str, parent_hash: &str, additions: Vec<String>, removals: Vec<String>, timed_out: Vec<String>) -> Result<(), JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        let block = BlockId {
+            header_hash: hex_to_hash(header_hash)?,
+            parent_hash: hex_to_hash(parent_hash)?,
+        };
+        let watch_report = watch_report_from_params(additions, removals, timed_out, Some(block))?;
+        cradle.cradle.new_block(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            height,
+            &watch_report
+        )
+    })
+}
+
+#[derive(Serialize, Deserialize)]
 struct JsGameStart {
     // Game name
     game_type: String,
@@ -333,18 +428,48 @@ fn string_to_game_id(id: &str) -> Result<GameID, JsValue> {
     Ok(GameID::from_bytes(&hex::decode(id).into_js()?))
 }
 
+// A proposal from the peer, given from our side.  id names it in
+// accept_proposal and decline_proposal.
+#[derive(Serialize)]
+struct JsGameProposal {
+    id: String,
+    game_ids: Vec<String>,
+    start: JsGameStart,
+}
+
+fn game_start_from_js(game: JsValue) -> Result<GameStart, JsValue> {
+    let js_game_start = serde_wasm_bindgen::from_value::<JsGameStart>(game).into_js()?;
+    Ok(GameStart {
+        game_type: GameType(hex::decode(&js_game_start.game_type).into_js()?),
+        timeout: Timeout::new(js_game_start.timeout),
+        amount: Amount::new(js_game_start.amount),
+        my_contribution: Amount::new(js_game_start.my_contribution),
+        my_turn: js_game_start.my_turn,
+        parameters: hex::decode(&js_game_start.parameters).into_js()?
+    })
+}
+
+fn game_proposal_to_js(proposal: &WireGameStart) -> Result<JsValue, types::Error> {
+    let start = &proposal.start;
+    let js_proposal = JsGameProposal {
+        id: proposal.proposal_id().map(game_id_to_string)?,
+        game_ids: proposal.game_ids.iter().map(game_id_to_string).collect(),
+        start: JsGameStart {
+            game_type: hex::encode(&start.game_type.0),
+            timeout: start.timeout.to_u64(),
+            amount: start.amount.to_u64(),
+            my_contribution: start.my_contribution.to_u64(),
+            my_turn: start.my_turn,
+            parameters: hex::encode(&start.parameters),
+        },
+    };
+    serde_wasm_bindgen::to_value(&js_proposal).map_err(|e| types::Error::StrErr(format!("{e:?}")))
+}
+
 #[wasm_bindgen]
 pub fn start_games(cid: i32, initiator: bool, game: JsValue) -> Result<Vec<String>, JsValue> {
-    let js_game_start = serde_wasm_bindgen::from_value::<JsGameStart>(game.clone()).into_js()?;
+    let game_start = game_start_from_js(game)?;
     let res = with_game(cid, move |cradle: &mut JsCradle| {
-        let game_start = GameStart {
-            game_type: GameType(hex::decode(&js_game_start.game_type).into_gen()?),
-            timeout: Timeout::new(js_game_start.timeout),
-            amount: Amount::new(js_game_start.amount),
-            my_contribution: Amount::new(js_game_start.my_contribution),
-            my_turn: js_game_start.my_turn,
-            parameters: hex::decode(&js_game_start.parameters).into_gen()?
-        };
         cradle.cradle.start_games(
             &mut cradle.allocator,
             &mut cradle.rng,
@@ -399,10 +524,122 @@ pub fn accept(cid: i32, id: &str) -> Result<(), JsValue> {
     })
 }
 
+#[wasm_bindgen]
+pub fn propose_game(cid: i32, game: JsValue) -> Result<Vec<String>, JsValue> {
+    let game_start = game_start_from_js(game)?;
+    let res = with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.propose_game(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_start,
+        )
+    })?;
+
+    Ok(res.iter().map(game_id_to_string).collect())
+}
+
+#[wasm_bindgen]
+pub fn accept_proposal(cid: i32, id: &str) -> Result<(), JsValue> {
+    let game_id = string_to_game_id(id)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.accept_proposal(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_id,
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn decline_proposal(cid: i32, id: &str) -> Result<(), JsValue> {
+    let game_id = string_to_game_id(id)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.decline_proposal(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_id,
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn cancel_proposal(cid: i32, id: &str) -> Result<(), JsValue> {
+    let game_id = string_to_game_id(id)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.cancel_proposal(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_id,
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn top_up(cid: i32, hex_coinstring: &str) -> Result<(), JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.top_up(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            hex_to_coinstring(hex_coinstring)?
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn withdraw(cid: i32, amount: u64) -> Result<(), JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.withdraw(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &Amount::new(amount)
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn offer_settlement(cid: i32, id: &str, my_share: u64) -> Result<(), JsValue> {
+    let game_id = string_to_game_id(id)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.offer_settlement(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_id,
+            &Amount::new(my_share)
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn accept_settlement(cid: i32, id: &str) -> Result<(), JsValue> {
+    let game_id = string_to_game_id(id)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.accept_settlement(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_id,
+        )
+    })
+}
+
+#[wasm_bindgen]
+pub fn decline_settlement(cid: i32, id: &str) -> Result<(), JsValue> {
+    let game_id = string_to_game_id(id)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.decline_settlement(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &game_id,
+        )
+    })
+}
+
 #[wasm_bindgen]
 pub fn shut_down(cid: i32) -> Result<(), JsValue> {
     with_game(cid, move |cradle: &mut JsCradle| {
-        cradle.cradle.shut_down()
+        cradle.cradle.shut_down(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+        )
     })
 }
 
@@ -414,6 +651,34 @@ pub fn deliver_message(cid: i32, inbound_message: &str) -> Result<(), JsValue> {
     })
 }
 
+#[wasm_bindgen]
+pub fn resume(cid: i32) -> Result<(), JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.resume()
+    })
+}
+
+#[wasm_bindgen]
+pub fn handshake_finished(cid: i32) -> Result<bool, JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        Ok(cradle.cradle.handshake_finished())
+    })
+}
+
+#[wasm_bindgen]
+pub fn is_on_chain(cid: i32) -> Result<bool, JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        Ok(cradle.cradle.is_on_chain())
+    })
+}
+
+#[wasm_bindgen]
+pub fn watched_coins(cid: i32) -> Result<Vec<String>, JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        Ok(cradle.cradle.watched_coins().map(coinstring_to_hex).collect())
+    })
+}
+
 #[derive(Default)]
 struct JsLocalUI {
     callbacks: BTreeMap<String, JsValue>
@@ -463,7 +728,7 @@ impl ToLocalUI for JsLocalUI {
     }
 
     fn game_finished(&mut self, game_id: &GameID, amount: Amount) -> Result<(), chia_gaming::common::types::Error> {
-        call_javascript_from_collection(&self.callbacks, "game_message", |args_array| {
+        call_javascript_from_collection(&self.callbacks, "game_finished", |args_array| {
             args_array.set(0, JsValue::from_str(&game_id_to_string(game_id)));
             args_array.set(1, amount.to_u64().into());
             Ok(())
@@ -471,12 +736,20 @@ impl ToLocalUI for JsLocalUI {
     }
 
     fn game_cancelled(&mut self, game_id: &GameID) -> Result<(), chia_gaming::common::types::Error> {
-        call_javascript_from_collection(&self.callbacks, "game_finished", |args_array| {
+        call_javascript_from_collection(&self.callbacks, "game_cancelled", |args_array| {
             args_array.set(0, JsValue::from_str(&game_id_to_string(game_id)));
             Ok(())
         })
     }
 
+    fn opponent_slashed(&mut self, game_id: &GameID, my_reward: Amount) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "opponent_slashed", |args_array| {
+            args_array.set(0, JsValue::from_str(&game_id_to_string(game_id)));
+            args_array.set(1, my_reward.to_u64().into());
+            Ok(())
+        })
+    }
+
     fn shutdown_complete(&mut self, coin: &CoinString) -> Result<(), chia_gaming::common::types::Error> {
         call_javascript_from_collection(&self.callbacks, "shutdown_complete", |args_array| {
             args_array.set(0, JsValue::from_str(&hex::encode(&coin.to_bytes())));
@@ -484,8 +757,69 @@ impl ToLocalUI for JsLocalUI {
         })
     }
 
-    fn going_on_chain(&mut self) -> Result<(), chia_gaming::common::types::Error> {
-        call_javascript_from_collection(&self.callbacks, "going_on_chain", |_args_array| {
+    fn going_on_chain(&mut self, reason: &GoingOnChainReason) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "going_on_chain", |args_array| {
+            let js_reason = serde_wasm_bindgen::to_value(reason).map_err(|e| types::Error::StrErr(format!("{e:?}")))?;
+            args_array.set(0, js_reason);
+            Ok(())
+        })
+    }
+
+    fn game_proposed(&mut self, proposal: &WireGameStart) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "game_proposed", |args_array| {
+            args_array.set(0, game_proposal_to_js(proposal)?);
+            Ok(())
+        })
+    }
+
+    fn game_proposal_accepted(&mut self, id: &GameID) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "game_proposal_accepted", |args_array| {
+            args_array.set(0, JsValue::from_str(&game_id_to_string(id)));
+            Ok(())
+        })
+    }
+
+    fn game_proposal_declined(&mut self, id: &GameID) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "game_proposal_declined", |args_array| {
+            args_array.set(0, JsValue::from_str(&game_id_to_string(id)));
+            Ok(())
+        })
+    }
+
+    fn game_proposal_cancelled(&mut self, id: &GameID) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "game_proposal_cancelled", |args_array| {
+            args_array.set(0, JsValue::from_str(&game_id_to_string(id)));
+            Ok(())
+        })
+    }
+
+    fn channel_topped_up(&mut self, my_balance: &Amount, their_balance: &Amount) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "channel_topped_up", |args_array| {
+            args_array.set(0, my_balance.to_u64().into());
+            args_array.set(1, their_balance.to_u64().into());
+            Ok(())
+        })
+    }
+
+    fn channel_withdrawn(&mut self, my_balance: &Amount, their_balance: &Amount) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "channel_withdrawn", |args_array| {
+            args_array.set(0, my_balance.to_u64().into());
+            args_array.set(1, their_balance.to_u64().into());
+            Ok(())
+        })
+    }
+
+    fn settlement_offered(&mut self, id: &GameID, my_share: &Amount) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "settlement_offered", |args_array| {
+            args_array.set(0, JsValue::from_str(&game_id_to_string(id)));
+            args_array.set(1, my_share.to_u64().into());
+            Ok(())
+        })
+    }
+
+    fn settlement_declined(&mut self, id: &GameID) -> Result<(), chia_gaming::common::types::Error> {
+        call_javascript_from_collection(&self.callbacks, "settlement_declined", |args_array| {
+            args_array.set(0, JsValue::from_str(&game_id_to_string(id)));
             Ok(())
         })
     }
@@ -514,20 +848,20 @@ fn to_local_ui(callbacks: JsValue) -> Result<JsLocalUI, JsValue> {
     Ok(jslocalui)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct JsSpend {
     puzzle: String,
     solution: String,
     signature: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct JsCoinSpend {
     coin: String,
     bundle: JsSpend,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct JsSpendBundle {
     spends: Vec<JsCoinSpend>,
 }
@@ -537,8 +871,11 @@ struct JsIdleResult {
     continue_on: bool,
     outbound_transactions: Vec<JsSpendBundle>,
     outbound_messages: Vec<String>,
+    coin_solution_requests: Vec<String>,
+    coins_to_watch: Vec<String>,
     opponent_move: Option<(String, String)>,
     game_finished: Option<(String, u64)>,
+    receive_error: Option<String>,
 }
 
 fn spend_to_js(spend: &Spend) -> JsSpend {
@@ -562,6 +899,42 @@ fn spend_bundle_to_js(spend_bundle: &SpendBundle) -> JsSpendBundle {
     }
 }
 
+fn spend_from_js(spend: &JsSpend) -> Result<Spend, types::Error> {
+    Ok(Spend {
+        puzzle: Puzzle::from_bytes(&hex::decode(&spend.puzzle).into_gen()?),
+        solution: Program::from_hex(&spend.solution)?,
+        signature: Aggsig::from_slice(&hex::decode(&spend.signature).into_gen()?)?
+    })
+}
+
+fn coin_spend_from_js(spend: &JsCoinSpend) -> Result<CoinSpend, types::Error> {
+    Ok(CoinSpend {
+        coin: hex_to_coinstring(&spend.coin)?,
+        bundle: spend_from_js(&spend.bundle)?
+    })
+}
+
+fn spend_bundle_from_js(js_bundle: JsValue) -> Result<SpendBundle, JsValue> {
+    let js_spend_bundle = serde_wasm_bindgen::from_value::<JsSpendBundle>(js_bundle).into_js()?;
+    Ok(SpendBundle {
+        spends: map_m(coin_spend_from_js, &js_spend_bundle.spends).into_js()?
+    })
+}
+
+// The coins a spend bundle from outbound_transactions would create, so that a
+// block feed can report them without running clvm itself.
+#[wasm_bindgen]
+pub fn spend_bundle_additions(js_bundle: JsValue) -> Result<Vec<String>, JsValue> {
+    let spend_bundle = spend_bundle_from_js(js_bundle)?;
+    let mut allocator = AllocEncoder::new();
+    let mut result = Vec::new();
+    for spend in spend_bundle.spends.iter() {
+        let additions = spend.additions(&mut allocator).into_js()?;
+        result.extend(additions.iter().map(coinstring_to_hex));
+    }
+    Ok(result)
+}
+
 fn readable_move_to_hex(allocator: &mut AllocEncoder, rm: &ReadableMove) -> Result<String, types::Error> {
     let program = Program::from_nodeptr(allocator, rm.to_nodeptr())?;
     Ok(program.to_hex())
@@ -613,8 +986,11 @@ fn idle_result_to_js(
         continue_on: idle_result.continue_on,
         outbound_transactions: idle_result.outbound_transactions.iter().map(spend_bundle_to_js).collect(),
         outbound_messages: idle_result.outbound_messages.iter().map(hex::encode).collect(),
+        coin_solution_requests: idle_result.coin_solution_requests.iter().map(coinstring_to_hex).collect(),
+        coins_to_watch: idle_result.coins_to_watch.iter().map(coinstring_to_hex).collect(),
         opponent_move: opponent_move,
         game_finished: game_finished,
+        receive_error: idle_result.receive_error.as_ref().map(|e| format!("{e:?}")),
     }).into_e()
 }
 
@@ -632,6 +1008,39 @@ pub fn idle(cid: i32, callbacks: JsValue) -> Result<JsValue, JsValue> {
 
 }
 
+#[wasm_bindgen]
+pub fn go_on_chain(cid: i32, callbacks: JsValue) -> Result<(), JsValue> {
+    let mut local_ui = to_local_ui(callbacks)?;
+    with_game(cid, move |cradle: &mut JsCradle| {
+        cradle.cradle.go_on_chain(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &mut local_ui
+        )
+    })
+}
+
+// Answer a coin_solution_requests entry.  puzzle and solution are hex and are
+// both left out if the coin wasn't spent.
+#[wasm_bindgen]
+pub fn report_puzzle_and_solution(cid: i32, hex_coinstring: &str, puzzle: Option<String>, solution: Option<String>) -> Result<(), JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        let coin = hex_to_coinstring(hex_coinstring)?;
+        let puzzle_and_solution =
+            if let (Some(p), Some(s)) = (puzzle, solution) {
+                Some((Program::from_hex(&p)?, Program::from_hex(&s)?))
+            } else {
+                None
+            };
+        cradle.cradle.report_puzzle_and_solution(
+            &mut cradle.allocator,
+            &mut cradle.rng,
+            &coin,
+            puzzle_and_solution.as_ref().map(|(p, s)| (p, s))
+        )
+    })
+}
+
 #[derive(Serialize, Deserialize)]
 struct JsChiaIdentity {
     pub private_key: String,
@@ -666,8 +1075,189 @@ pub fn chia_identity(seed: &str) -> Result<JsValue, JsValue> {
     serde_wasm_bindgen::to_value(&js_identity).into_js()
 }
 
+// Version of the export_cradle format.
+const CRADLE_EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct JsCradleExport {
+    version: u32,
+    // base64 of the bson cradle snapshot.
+    cradle: String,
+    // The rng as it stands, so the restored cradle carries on from the same
+    // point.  word_pos is a decimal string since it's a u128.
+    rng_seed: String,
+    rng_stream: u64,
+    rng_word_pos: String,
+    // hex sha256 of everything above.
+    hash: String,
+}
+
+fn cradle_export_hash(export: &JsCradleExport) -> String {
+    let hash_input = format!(
+        "{}:{}:{}:{}:{}",
+        export.version,
+        export.cradle,
+        export.rng_seed,
+        export.rng_stream,
+        export.rng_word_pos
+    );
+    hex::encode(Sha256Input::Bytes(hash_input.as_bytes()).hash().bytes())
+}
+
+// Serialize everything about a cradle to a string that import_cradle can
+// rebuild it from, for keeping the channel across a page reload.
+#[wasm_bindgen]
+pub fn export_cradle(cid: i32) -> Result<String, JsValue> {
+    with_game(cid, move |cradle: &mut JsCradle| {
+        let snapshot = cradle.cradle.to_snapshot_bytes(&mut cradle.allocator)?;
+        let mut export = JsCradleExport {
+            version: CRADLE_EXPORT_VERSION,
+            cradle: base64::encode(&snapshot),
+            rng_seed: hex::encode(cradle.rng.get_seed()),
+            rng_stream: cradle.rng.get_stream(),
+            rng_word_pos: cradle.rng.get_word_pos().to_string(),
+            hash: String::new(),
+        };
+        export.hash = cradle_export_hash(&export);
+        serde_json::to_string(&export).map_err(|e| types::Error::StrErr(format!("{e:?}")))
+    })
+}
+
+// Rebuild a cradle from export_cradle's output, giving it a new id.
+#[wasm_bindgen]
+pub fn import_cradle(data: &str) -> Result<i32, JsValue> {
+    let export: JsCradleExport = serde_json::from_str(data).map_err(|e| JsValue::from_str(&format!("{e:?}")))?;
+    if export.version != CRADLE_EXPORT_VERSION {
+        return Err(JsValue::from_str(&format!("unsupported cradle export version {} (expected {})", export.version, CRADLE_EXPORT_VERSION)));
+    }
+    if cradle_export_hash(&export) != export.hash {
+        return Err(JsValue::from_str("cradle export hash doesn't match its contents"));
+    }
+
+    let snapshot = base64::decode(&export.cradle).map_err(|e| JsValue::from_str(&format!("{e:?}")))?;
+    let seed_bytes = hex::decode(&export.rng_seed).into_js()?;
+    let mut use_seed: [u8; 32] = [0; 32];
+    if seed_bytes.len() != use_seed.len() {
+        return Err(JsValue::from_str("bad rng seed in cradle export"));
+    }
+    use_seed.copy_from_slice(&seed_bytes);
+    let word_pos: u128 = export.rng_word_pos.parse().map_err(|e| JsValue::from_str(&format!("{e:?}")))?;
+
+    let mut rng = ChaCha8Rng::from_seed(use_seed);
+    rng.set_stream(export.rng_stream);
+    rng.set_word_pos(word_pos);
+    let mut allocator = AllocEncoder::new();
+    let game_cradle = SynchronousGameCradle::from_snapshot_bytes(&mut allocator, &snapshot).into_js()?;
+
+    let new_id = get_next_id();
+    insert_cradle(new_id, JsCradle {
+        allocator,
+        rng,
+        cradle: game_cradle
+    });
+
+    Ok(new_id)
+}
+
 #[wasm_bindgen]
 pub fn sha256bytes(bytes_str: &str) -> Result<JsValue, JsValue> {
     let hashed = Sha256Input::Bytes(bytes_str.as_bytes()).hash();
     serde_wasm_bindgen::to_value(&hashed).into_js()
-}
\ No newline at end of file
+}
+
+fn readable_from_hex(allocator: &mut AllocEncoder, readable: &str) -> Result<ReadableMove, types::Error> {
+    let program = Program::from_hex(readable)?;
+    Ok(ReadableMove::from_nodeptr(program.to_nodeptr(allocator)?))
+}
+
+fn to_js_value<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(value).into_js()
+}
+
+// The cards each player is dealt, given alice's and bob's committed entropy
+// in hex and the game amount.
+#[wasm_bindgen(typescript_type = "CalpokerHands")]
+pub fn calpoker_make_cards(alice_hash: &str, bob_hash: &str, amount: u64) -> Result<JsValue, JsValue> {
+    let alice_bytes = hex::decode(alice_hash).into_js()?;
+    let bob_bytes = hex::decode(bob_hash).into_js()?;
+    if alice_bytes.len() < 16 || bob_bytes.len() < 16 {
+        return Err(JsValue::from_str("calpoker entropy should be at least 16 bytes"));
+    }
+    to_js_value(&make_cards(&alice_bytes, &bob_bytes, Amount::new(amount)))
+}
+
+// Decode the readable move carrying both players' cards, as delivered to
+// opponent_moved or game_message.
+#[wasm_bindgen(typescript_type = "CalpokerHands")]
+pub fn calpoker_decode_card_choices(readable: &str) -> Result<JsValue, JsValue> {
+    let mut allocator = AllocEncoder::new();
+    let readable_move = readable_from_hex(&mut allocator, readable).into_js()?;
+    let hands = decode_readable_card_choices(&mut allocator, readable_move).into_js()?;
+    to_js_value(&hands)
+}
+
+#[derive(Serialize)]
+struct JsCalpokerOutcome {
+    result: CalpokerResult,
+    // The card indices set in each of the result's bitmasks.
+    alice_selects: Vec<usize>,
+    bob_picks: Vec<usize>,
+    alice_picks: Vec<usize>,
+    // "you", "opponent" or "tie", judged from your_share.
+    winner: String,
+}
+
+// Decode the final readable move of a calpoker game from our side.
+#[wasm_bindgen(typescript_type = "CalpokerOutcome")]
+pub fn calpoker_decode_outcome(readable: &str, amount: u64, am_bob: bool) -> Result<JsValue, JsValue> {
+    let mut allocator = AllocEncoder::new();
+    let readable_move = readable_from_hex(&mut allocator, readable).into_js()?;
+    let result = decode_calpoker_readable(
+        &mut allocator,
+        readable_move.to_nodeptr(),
+        Amount::new(amount),
+        am_bob
+    ).into_js()?;
+    let winner =
+        if result.your_share * 2 > result.game_amount {
+            "you"
+        } else if result.your_share * 2 < result.game_amount {
+            "opponent"
+        } else {
+            "tie"
+        };
+    to_js_value(&JsCalpokerOutcome {
+        alice_selects: picks_from_bitmask(result.raw_alice_selects),
+        bob_picks: picks_from_bitmask(result.raw_bob_picks),
+        alice_picks: picks_from_bitmask(result.raw_alice_picks),
+        winner: winner.to_string(),
+        result,
+    })
+}
+
+// The named hand for a raw hand value list as found in a CalpokerResult.
+#[wasm_bindgen]
+pub fn calpoker_hand_value(raw: JsValue) -> Result<JsValue, JsValue> {
+    let raw_list: Vec<usize> = serde_wasm_bindgen::from_value(raw).into_js()?;
+    let hand_value = RawCalpokerHandValue::SimpleList(raw_list).hand_value().into_js()?;
+    to_js_value(&hand_value)
+}
+
+// The readable move (hex) committing to entropy given in hex, for make_move.
+#[wasm_bindgen]
+pub fn calpoker_commit_move(entropy: &str) -> Result<String, JsValue> {
+    let entropy_bytes = hex::decode(entropy).into_js()?;
+    let mut allocator = AllocEncoder::new();
+    let program = encode_calpoker_commit(&mut allocator, &entropy_bytes).into_js()?;
+    Ok(program.to_hex())
+}
+
+// The readable move (hex) keeping the four cards at these indices, for
+// make_move.
+#[wasm_bindgen]
+pub fn calpoker_picks_move(picks: JsValue) -> Result<String, JsValue> {
+    let pick_list: Vec<usize> = serde_wasm_bindgen::from_value(picks).into_js()?;
+    let mut allocator = AllocEncoder::new();
+    let program = encode_calpoker_picks(&mut allocator, &pick_list).into_js()?;
+    Ok(program.to_hex())
+}
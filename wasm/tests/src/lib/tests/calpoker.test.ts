@@ -0,0 +1,29 @@
+import { init, calpoker_commit_move, calpoker_picks_move, calpoker_decode_outcome, calpoker_hand_value, calpoker_make_cards } from '../../../../pkg/chia_gaming_wasm.js';
+import * as assert from 'assert';
+
+it('encodes calpoker moves', async () => {
+    init();
+    assert.equal(calpoker_commit_move('30626f62343536373839616263646566'), '9030626f62343536373839616263646566');
+    assert.equal(calpoker_picks_move([1, 3, 5, 7]), 'ff80ff01ff80ff01ff80ff01ff80ff0180');
+    assert.throws(() => calpoker_picks_move([1, 3, 5]));
+});
+
+it('decodes calpoker results', async () => {
+    init();
+    // (60 59 91 (2 2 1 12 11 8) (2 2 1 14 5 2) -1)
+    let outcome = calpoker_decode_outcome('ff3cff3bff5bffff02ff02ff01ff0cff0bff0880ffff02ff02ff01ff0eff05ff0280ff81ff80', 200, false);
+    assert.deepEqual(outcome.alice_selects, [2, 3, 4, 5]);
+    assert.equal(outcome.result.your_share, 200);
+    assert.equal(outcome.winner, 'you');
+    assert.deepEqual(outcome.result.alice_hand_result, {TwoPair: [12, 11, 8]});
+    assert.deepEqual(calpoker_hand_value([3, 1, 3, 13, 10, 9, 6, 3]), {Flush: [13, 10, 9, 6, 3]});
+
+    let hands = calpoker_make_cards('30616c6963653637383961626364656630616c69636536373839616263646566', '30626f62343536373839616263646566', 200);
+    assert.equal(hands[0].length, 8);
+    assert.equal(hands[1].length, 8);
+
+    let zero_hands = calpoker_make_cards('30616c6963653637383961626364656630616c69636536373839616263646566', '30626f62343536373839616263646566', 0);
+    assert.equal(zero_hands[0].length, 8);
+    assert.equal(zero_hands[1].length, 8);
+    assert.throws(() => calpoker_make_cards('3061', '30626f62343536373839616263646566', 200));
+});
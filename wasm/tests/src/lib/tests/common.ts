@@ -1,3 +1,67 @@
+import { deposit_file, spend_bundle_additions, CoinSpend, SpendBundle } from '../../../../pkg/chia_gaming_wasm.js';
+
+import * as fs from 'fs';
+import { resolve } from 'path';
+
+export function rooted(name: string) {
+    return resolve(__dirname, '../../../../..', name);
+}
+
+export function preset_file(name: string) {
+    deposit_file(name, fs.readFileSync(rooted(name), 'utf8'));
+}
+
+// Stands in for a full node.  Transactions are included in the next block if
+// their coins exist, kept for later if they don't yet and dropped if they were
+// already spent.  Timelocks aren't checked; the cradles time their own coins.
+export class MockBlockFeed {
+    height: number;
+    coins: Set<string>;
+    spent: Map<string, CoinSpend>;
+    pending: Array<SpendBundle>;
+
+    constructor(initial_coins: Array<string>) {
+        this.height = 0;
+        this.coins = new Set(initial_coins);
+        this.spent = new Map();
+        this.pending = [];
+    }
+
+    push_tx(bundle: SpendBundle) {
+        this.pending.push(bundle);
+    }
+
+    farm_block(): [Array<string>, Array<string>] {
+        let additions: Array<string> = [];
+        let removals: Array<string> = [];
+        let still_pending: Array<SpendBundle> = [];
+
+        for (let bundle of this.pending) {
+            let removed = bundle.spends.map((s) => s.coin);
+            if (removed.some((c) => this.spent.has(c))) {
+                continue;
+            }
+            if (!removed.every((c) => this.coins.has(c))) {
+                still_pending.push(bundle);
+                continue;
+            }
+
+            for (let spend of bundle.spends) {
+                this.coins.delete(spend.coin);
+                this.spent.set(spend.coin, spend);
+                removals.push(spend.coin);
+            }
+            for (let coin of spend_bundle_additions(bundle)) {
+                this.coins.add(coin);
+                additions.push(coin);
+            }
+        }
+
+        this.pending = still_pending;
+        this.height += 1;
+        return [additions, removals];
+    }
+}
 
 export function to_hex_string(byteArray: Array<number>) {
   return Array.from(byteArray, function(byte: number) {
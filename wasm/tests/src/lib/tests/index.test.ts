@@ -1,18 +1,11 @@
-import { init, config_scaffold, create_game_cradle, deliver_message, deposit_file, opening_coin, idle, chia_identity, Spend, CoinSpend, SpendBundle, IChiaIdentity, IdleCallbacks, IdleResult } from '../../../../pkg/chia_gaming_wasm.js';
+import { init, config_scaffold, create_game_cradle, deliver_message, opening_coin, idle, chia_identity, Spend, CoinSpend, SpendBundle, IChiaIdentity, IdleCallbacks, IdleResult } from '../../../../pkg/chia_gaming_wasm.js';
+
+import { rooted, preset_file } from './common';
 
 import * as fs from 'fs';
-import { resolve } from 'path';
 import * as assert from 'assert';
 import * as bls_loader from 'bls-signatures';
 
-function rooted(name: string) {
-    return resolve(__dirname, '../../../../..', name);
-}
-
-function preset_file(name: string) {
-    deposit_file(name, fs.readFileSync(rooted(name), 'utf8'));
-}
-
 class ChiaGame {
     waiting_messages: Array<string>;
     private_key: string;
@@ -0,0 +1,188 @@
+import { init, create_game_cradle, deliver_message, opening_coin, idle, new_block, start_games, make_move, accept, handshake_finished, report_puzzle_and_solution, export_cradle, import_cradle, chia_identity, sha256bytes, IChiaIdentity, IdleCallbacks, IdleResult } from '../../../../pkg/chia_gaming_wasm.js';
+import { rooted, preset_file, to_hex_string, MockBlockFeed } from './common';
+
+import * as fs from 'fs';
+import * as assert from 'assert';
+
+class PersistPlayer {
+    cradle: number;
+    waiting_messages: Array<string>;
+    opponent_moved: boolean;
+    game_finished: boolean;
+
+    constructor(env: any, seed: string, identity: IChiaIdentity, have_potato: boolean) {
+        this.waiting_messages = [];
+        this.opponent_moved = false;
+        this.game_finished = false;
+        this.cradle = create_game_cradle({
+            seed: seed,
+            game_types: env.game_types,
+            identity: identity.private_key,
+            have_potato: have_potato,
+            my_contribution: {amt: 100},
+            their_contribution: {amt: 100},
+            channel_timeout: env.timeout,
+            unroll_timeout: env.unroll_timeout,
+            reward_puzzle_hash: identity.puzzle_hash,
+        });
+    }
+
+    // Save the cradle as a page would before unloading and load it back as
+    // the reloaded page would.
+    reload() {
+        let saved = export_cradle(this.cradle);
+        this.cradle = import_cradle(saved);
+    }
+
+    callbacks(): IdleCallbacks {
+        return <IdleCallbacks>{
+            opponent_moved: (game_id: string, readable_move_hex: string) => {
+                this.opponent_moved = true;
+            },
+            game_finished: (game_id: string, my_share: number) => {
+                this.game_finished = true;
+            },
+        };
+    }
+
+    outbound_messages(): Array<string> {
+        let w = this.waiting_messages;
+        this.waiting_messages = [];
+        return w;
+    }
+
+    idle(feed: MockBlockFeed) {
+        while (true) {
+            let result: IdleResult = idle(this.cradle, this.callbacks());
+            assert.equal(result.receive_error, undefined);
+            this.waiting_messages = this.waiting_messages.concat(result.outbound_messages);
+            for (let tx of result.outbound_transactions) {
+                feed.push_tx(tx);
+            }
+            for (let coin of result.coin_solution_requests) {
+                let spend = feed.spent.get(coin);
+                report_puzzle_and_solution(this.cradle, coin, spend?.bundle.puzzle, spend?.bundle.solution);
+            }
+            if (!result.continue_on) {
+                break;
+            }
+        }
+    }
+}
+
+function calpoker_moves(): Array<[number, string]> {
+    let ascii_hex = (s: string) => to_hex_string(Array.from(new TextEncoder().encode(s)));
+    return [
+        [0, 'a0' + to_hex_string(sha256bytes('0alice6789abcdef'))],
+        [1, '90' + ascii_hex('0bob456789abcdef')],
+        [0, 'ff80ff01ff80ff01ff80ff01ff80ff0180'],
+        [1, 'ff01ff80ff01ff80ff01ff80ff01ff8080'],
+        [0, '80'],
+    ];
+}
+
+it('reloads mid-game and finishes', async () => {
+    init();
+    preset_file("resources/p2_delegated_puzzle_or_hidden_puzzle.clsp.hex");
+    preset_file("clsp/unroll/unroll_meta_puzzle.hex");
+    preset_file("clsp/unroll/unroll_puzzle_state_channel_unrolling.hex");
+    preset_file("clsp/onchain/referee.hex");
+    let identity1 = chia_identity('test1');
+    let identity2 = chia_identity('test2');
+
+    let calpoker_hex = fs.readFileSync(rooted('clsp/calpoker_include_calpoker_factory.hex'),'utf8');
+    let env = {
+        game_types: {
+            "calpoker": calpoker_hex
+        },
+        timeout: 99,
+        unroll_timeout: 5
+    };
+
+    let coin1 = identity1.puzzle_hash + identity1.puzzle_hash + '64';
+    let coin2 = identity2.puzzle_hash + identity2.puzzle_hash + '64';
+    let feed = new MockBlockFeed([coin1, coin2]);
+
+    let players = [
+        new PersistPlayer(env, "3579", identity1, true),
+        new PersistPlayer(env, "3589", identity2, false),
+    ];
+    opening_coin(players[0].cradle, coin1);
+    opening_coin(players[1].cradle, coin2);
+
+    let moves = calpoker_moves();
+    let next_move = 0;
+    let last_mover = 0;
+    let can_move = false;
+    let accepted = false;
+    let game_ids: Array<string> = [];
+    let steps = 0;
+    while (!players.every((p) => p.game_finished)) {
+        steps += 1;
+        assert.ok(steps < 100);
+
+        let [additions, removals] = feed.farm_block();
+        for (let p of players) {
+            new_block(p.cradle, feed.height, additions, removals, []);
+            p.idle(feed);
+        }
+        for (let c = 0; c < 2; c++) {
+            for (let msg of players[c].outbound_messages()) {
+                deliver_message(players[c ^ 1].cradle, msg);
+            }
+        }
+
+        if (game_ids.length === 0) {
+            if (players.every((p) => handshake_finished(p.cradle))) {
+                for (let c = 0; c < 2; c++) {
+                    game_ids = start_games(players[c].cradle, c === 0, {
+                        game_type: "63616c706f6b6572",
+                        timeout: 10,
+                        amount: 200,
+                        my_contribution: 100,
+                        my_turn: c === 0,
+                        parameters: "80",
+                    });
+                }
+                can_move = true;
+            }
+        } else if (can_move || players.some((p) => p.opponent_moved)) {
+            can_move = false;
+            for (let p of players) {
+                p.opponent_moved = false;
+            }
+
+            if (next_move === 2) {
+                // Both pages reload between moves.
+                for (let p of players) {
+                    p.reload();
+                }
+            }
+
+            if (next_move < moves.length) {
+                let [who, readable] = moves[next_move];
+                next_move += 1;
+                last_mover = who;
+                make_move(players[who].cradle, game_ids[0], readable);
+            } else if (!accepted) {
+                accepted = true;
+                accept(players[last_mover ^ 1].cradle, game_ids[0]);
+            }
+        }
+    }
+
+    assert.equal(next_move, moves.length);
+});
+
+it('refuses a tampered export', async () => {
+    init();
+    preset_file("resources/p2_delegated_puzzle_or_hidden_puzzle.clsp.hex");
+    let identity = chia_identity('test1');
+    let calpoker_hex = fs.readFileSync(rooted('clsp/calpoker_include_calpoker_factory.hex'),'utf8');
+    let player = new PersistPlayer({ game_types: { "calpoker": calpoker_hex }, timeout: 99, unroll_timeout: 5 }, "3579", identity, true);
+
+    let saved = JSON.parse(export_cradle(player.cradle));
+    assert.equal(saved.version, 1);
+    saved.rng_stream += 1;
+    assert.throws(() => import_cradle(JSON.stringify(saved)));
+});
@@ -0,0 +1,143 @@
+import { init, create_game_cradle, deliver_message, opening_coin, idle, new_block, start_games, go_on_chain, is_on_chain, handshake_finished, report_puzzle_and_solution, chia_identity, IChiaIdentity, IdleCallbacks, IdleResult } from '../../../../pkg/chia_gaming_wasm.js';
+import { rooted, preset_file, MockBlockFeed } from './common';
+
+import * as fs from 'fs';
+import * as assert from 'assert';
+
+class UnrollPlayer {
+    cradle: number;
+    waiting_messages: Array<string>;
+    finished_games: Array<string>;
+    cancelled_games: Array<string>;
+
+    constructor(env: any, seed: string, identity: IChiaIdentity, have_potato: boolean) {
+        this.waiting_messages = [];
+        this.finished_games = [];
+        this.cancelled_games = [];
+        this.cradle = create_game_cradle({
+            seed: seed,
+            game_types: env.game_types,
+            identity: identity.private_key,
+            have_potato: have_potato,
+            my_contribution: {amt: 100},
+            their_contribution: {amt: 100},
+            channel_timeout: env.timeout,
+            unroll_timeout: env.unroll_timeout,
+            reward_puzzle_hash: identity.puzzle_hash,
+        });
+    }
+
+    callbacks(): IdleCallbacks {
+        return <IdleCallbacks>{
+            game_finished: (game_id: string, my_share: number) => {
+                this.finished_games.push(game_id);
+            },
+            game_cancelled: (game_id: string) => {
+                this.cancelled_games.push(game_id);
+            },
+        };
+    }
+
+    outbound_messages(): Array<string> {
+        let w = this.waiting_messages;
+        this.waiting_messages = [];
+        return w;
+    }
+
+    done(): boolean {
+        return this.finished_games.length + this.cancelled_games.length > 0;
+    }
+
+    idle(feed: MockBlockFeed) {
+        while (true) {
+            let result: IdleResult = idle(this.cradle, this.callbacks());
+            assert.equal(result.receive_error, undefined);
+            this.waiting_messages = this.waiting_messages.concat(result.outbound_messages);
+            for (let tx of result.outbound_transactions) {
+                feed.push_tx(tx);
+            }
+            for (let coin of result.coin_solution_requests) {
+                let spend = feed.spent.get(coin);
+                report_puzzle_and_solution(this.cradle, coin, spend?.bundle.puzzle, spend?.bundle.solution);
+            }
+            if (!result.continue_on) {
+                break;
+            }
+        }
+    }
+}
+
+it('survives a forced unroll', async () => {
+    init();
+    preset_file("resources/p2_delegated_puzzle_or_hidden_puzzle.clsp.hex");
+    preset_file("clsp/unroll/unroll_meta_puzzle.hex");
+    preset_file("clsp/unroll/unroll_puzzle_state_channel_unrolling.hex");
+    preset_file("clsp/onchain/referee.hex");
+    let identity1 = chia_identity('test1');
+    let identity2 = chia_identity('test2');
+
+    let calpoker_hex = fs.readFileSync(rooted('clsp/calpoker_include_calpoker_factory.hex'),'utf8');
+    let env = {
+        game_types: {
+            "calpoker": calpoker_hex
+        },
+        timeout: 99,
+        unroll_timeout: 5
+    };
+
+    let coin1 = identity1.puzzle_hash + identity1.puzzle_hash + '64';
+    let coin2 = identity2.puzzle_hash + identity2.puzzle_hash + '64';
+    let feed = new MockBlockFeed([coin1, coin2]);
+
+    let players = [
+        new UnrollPlayer(env, "3579", identity1, true),
+        new UnrollPlayer(env, "3589", identity2, false),
+    ];
+    opening_coin(players[0].cradle, coin1);
+    opening_coin(players[1].cradle, coin2);
+
+    let game_ids: Array<string> = [];
+    let forced = false;
+    let steps = 0;
+    while (!players.every((p) => p.done())) {
+        steps += 1;
+        assert.ok(steps < 100);
+
+        let [additions, removals] = feed.farm_block();
+        for (let p of players) {
+            new_block(p.cradle, feed.height, additions, removals, []);
+            p.idle(feed);
+        }
+        let delivered = 0;
+        for (let c = 0; c < 2; c++) {
+            for (let msg of players[c].outbound_messages()) {
+                deliver_message(players[c ^ 1].cradle, msg);
+                delivered += 1;
+            }
+        }
+
+        let handshake_done = players.every((p) => handshake_finished(p.cradle));
+        if (handshake_done && game_ids.length === 0) {
+            for (let c = 0; c < 2; c++) {
+                game_ids = start_games(players[c].cradle, c === 0, {
+                    game_type: "63616c706f6b6572",
+                    timeout: 10,
+                    amount: 200,
+                    my_contribution: 100,
+                    my_turn: c === 0,
+                    parameters: "80",
+                });
+            }
+        } else if (game_ids.length > 0 && delivered === 0 && !forced) {
+            // Once the games are running, stop cooperating and force the
+            // channel to unroll.
+            forced = true;
+            go_on_chain(players[0].cradle, players[0].callbacks());
+        }
+    }
+
+    assert.ok(players.every((p) => is_on_chain(p.cradle)));
+    for (let p of players) {
+        assert.deepEqual(p.finished_games.concat(p.cancelled_games).sort(), game_ids.slice().sort());
+    }
+});